@@ -99,8 +99,15 @@ macro_rules! new_full_start {
 				import_setup = Some((block_import, grandpa_link, babe_link));
 				Ok(import_queue)
 			})?
-			.with_rpc_extensions(|client, pool, _backend, fetcher, _remote_blockchain| -> Result<RpcExtension, _> {
-				Ok(node_rpc::create(client, pool, node_rpc::LightDeps::none(fetcher)))
+			.with_rpc_extensions_builder(|client, pool, _backend, fetcher, _remote_blockchain| {
+				Ok(move |deny_unsafe: sc_rpc::DenyUnsafe| -> RpcExtension {
+					node_rpc::create(
+						client.clone(),
+						pool.clone(),
+						deny_unsafe,
+						node_rpc::LightDeps::none(fetcher.clone()),
+					)
+				})
 			})?;
 
 		(builder, import_setup, inherent_data_providers)
@@ -368,14 +375,19 @@ pub fn new_light<C: Send + Default + 'static>(config: NodeConfiguration<C>)
 		.with_finality_proof_provider(|client, backend|
 			Ok(Arc::new(GrandpaFinalityProofProvider::new(backend, client)) as _)
 		)?
-		.with_rpc_extensions(|client, pool, _backend, fetcher, remote_blockchain| -> Result<RpcExtension, _> {
+		.with_rpc_extensions_builder(|client, pool, _backend, fetcher, remote_blockchain| {
 			let fetcher = fetcher
 				.ok_or_else(|| "Trying to start node RPC without active fetcher")?;
 			let remote_blockchain = remote_blockchain
 				.ok_or_else(|| "Trying to start node RPC without active remote blockchain")?;
 
-			let light_deps = node_rpc::LightDeps { remote_blockchain, fetcher };
-			Ok(node_rpc::create(client, pool, Some(light_deps)))
+			Ok(move |deny_unsafe: sc_rpc::DenyUnsafe| -> RpcExtension {
+				let light_deps = node_rpc::LightDeps {
+					remote_blockchain: remote_blockchain.clone(),
+					fetcher: fetcher.clone(),
+				};
+				node_rpc::create(client.clone(), pool.clone(), deny_unsafe, Some(light_deps))
+			})
 		})?
 		.build()?;
 
@@ -31,8 +31,9 @@
 
 use std::sync::Arc;
 
-use node_primitives::{Block, AccountId, Index, Balance};
+use node_primitives::{Block, AccountId, Index, Balance, Hash};
 use node_runtime::UncheckedExtrinsic;
+use sc_rpc_api::DenyUnsafe;
 use sp_runtime::traits::ProvideRuntimeApi;
 use txpool_api::TransactionPool;
 
@@ -57,14 +58,20 @@ impl<F> LightDeps<F> {
 /// Instantiate all RPC extensions.
 ///
 /// If you provide `LightDeps`, the system is configured for light client.
+/// The `deny_unsafe` tells whether potentially unsafe RPC methods are denied.
 pub fn create<C, P, M, F>(
 	client: Arc<C>,
 	pool: Arc<P>,
+	deny_unsafe: DenyUnsafe,
 	light_deps: Option<LightDeps<F>>,
 ) -> jsonrpc_core::IoHandler<M> where
 	C: ProvideRuntimeApi,
+	C: sp_api::CallRuntimeAt<Block>,
 	C: client::blockchain::HeaderBackend<Block>,
+	C: substrate_frame_rpc_metadata::StorageAt<Block>,
 	C: Send + Sync + 'static,
+	C::Api: sp_api::Core<Block, Error = <C as sp_api::CallRuntimeAt<Block>>::Error>,
+	C::Api: sp_api::Metadata<Block>,
 	C::Api: substrate_frame_rpc_system::AccountNonceApi<Block, AccountId, Index>,
 	C::Api: pallet_contracts_rpc::ContractsRuntimeApi<Block, AccountId, Balance>,
 	C::Api: pallet_transaction_payment_rpc::TransactionPaymentRuntimeApi<Block, Balance, UncheckedExtrinsic>,
@@ -73,6 +80,7 @@ pub fn create<C, P, M, F>(
 	M: jsonrpc_core::Metadata + Default,
 {
 	use substrate_frame_rpc_system::{FullSystem, LightSystem, SystemApi};
+	use substrate_frame_rpc_metadata::{Metadata, MetadataApi};
 	use pallet_contracts_rpc::{Contracts, ContractsApi};
 	use pallet_transaction_payment_rpc::{TransactionPayment, TransactionPaymentApi};
 
@@ -80,11 +88,13 @@ pub fn create<C, P, M, F>(
 
 	if let Some(LightDeps { remote_blockchain, fetcher }) = light_deps {
 		io.extend_with(
-			SystemApi::<AccountId, Index>::to_delegate(LightSystem::new(client, remote_blockchain, fetcher, pool))
+			SystemApi::<Hash, AccountId, Index>::to_delegate(
+				LightSystem::new(client, remote_blockchain, fetcher, pool)
+			)
 		);
 	} else {
 		io.extend_with(
-			SystemApi::to_delegate(FullSystem::new(client.clone(), pool))
+			SystemApi::to_delegate(FullSystem::new(client.clone(), pool, deny_unsafe))
 		);
 
 		// Making synchronous calls in light client freezes the browser currently,
@@ -94,8 +104,61 @@ pub fn create<C, P, M, F>(
 			ContractsApi::to_delegate(Contracts::new(client.clone()))
 		);
 		io.extend_with(
-			TransactionPaymentApi::to_delegate(TransactionPayment::new(client))
+			TransactionPaymentApi::to_delegate(TransactionPayment::new(client.clone()))
+		);
+		io.extend_with(
+			MetadataApi::to_delegate(Metadata::new(client))
 		);
 	}
 	io
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::{Decode, Encode};
+	use frame_support::{traits::Get, weights::GetDispatchInfo};
+	use jsonrpc_core::futures::Future;
+	use node_runtime::{
+		BalancesCall, Call, CheckedExtrinsic, Event, Runtime, TransferFee, constants::currency::DOLLARS,
+	};
+	use node_testing::{client::TestClientBuilderExt, keyring::*};
+	use substrate_frame_rpc_system::{DryRunOutcome, FullSystem, SystemApi};
+	use system::EventRecord;
+	use txpool::{BasicPool, FullChainApi};
+
+	#[test]
+	fn dry_run_should_report_weight_and_events() {
+		// given
+		let client = Arc::new(node_testing::client::TestClientBuilder::new().build());
+		let pool = Arc::new(BasicPool::new(Default::default(), FullChainApi::new(client.clone())));
+		let genesis_hash = client.info().chain.genesis_hash;
+		let system = FullSystem::new(client, pool, DenyUnsafe::No);
+
+		let call = BalancesCall::transfer::<Runtime>(bob().into(), 69 * DOLLARS);
+		let weight = call.get_dispatch_info().weight;
+		let xt = sign(
+			CheckedExtrinsic {
+				signed: Some((alice(), signed_extra(0, 0))),
+				function: Call::Balances(call),
+			},
+			node_runtime::VERSION.spec_version,
+			genesis_hash.into(),
+		);
+
+		// when
+		let res = SystemApi::<Hash, AccountId, Index>::dry_run(&system, xt.encode().into(), None)
+			.wait()
+			.unwrap();
+
+		// then
+		assert_eq!(res.outcome, DryRunOutcome::Success);
+		assert_eq!(res.weight, Some(weight));
+		let events = res.events.expect("node runtime describes System.Events in its metadata");
+		let events = Vec::<EventRecord<Event, Hash>>::decode(&mut &events[..]).unwrap();
+		let transfer = Event::balances(
+			balances::RawEvent::Transfer(alice().into(), bob().into(), 69 * DOLLARS, TransferFee::get()),
+		);
+		assert!(events.iter().any(|record| record.event == transfer));
+	}
+}
@@ -29,6 +29,7 @@ use crate::{
 		Backend as BlockchainBackend, well_known_cache_keys
 	},
 	light::RemoteBlockchain,
+	client::{UsageInfo, ColumnSize},
 };
 use sp_blockchain;
 use consensus::BlockOrigin;
@@ -261,8 +262,22 @@ pub trait Backend<Block, H>: AuxStore + Send + Sync where
 	/// Returns reference to blockchain backend.
 	fn blockchain(&self) -> &Self::Blockchain;
 
+	/// Returns the usage statistics of the backend, if it keeps them.
+	fn usage_info(&self) -> Option<UsageInfo>;
+
 	/// Returns the used state cache, if existent.
-	fn used_state_cache_size(&self) -> Option<usize>;
+	#[deprecated(note = "Use `usage_info` instead")]
+	fn used_state_cache_size(&self) -> Option<usize> {
+		self.usage_info().map(|usage| usage.state_cache.used_size)
+	}
+
+	/// Returns the number of keys and the size of each column of the database, if the backend
+	/// has one.
+	///
+	/// This goes through the whole database and may take a long time.
+	fn column_sizes(&self) -> sp_blockchain::Result<Option<Vec<ColumnSize>>> {
+		Ok(None)
+	}
 
 	/// Returns reference to changes trie storage.
 	fn changes_trie_storage(&self) -> Option<&Self::ChangesTrieStorage>;
@@ -311,6 +326,12 @@ pub trait Backend<Block, H>: AuxStore + Send + Sync where
 	/// something that the import of a block would interfere with, e.g. importing
 	/// a new block or calculating the best head.
 	fn get_import_lock(&self) -> &RwLock<()>;
+
+	/// Enables or disables the bulk import mode, in which the backend may trade the durability of
+	/// the latest imports for the import speed while catching up with the chain.
+	fn set_bulk_import(&self, _enabled: bool) -> sp_blockchain::Result<()> {
+		Ok(())
+	}
 }
 
 /// Changes trie storage that supports pruning.
@@ -88,6 +88,20 @@ where
 	/// No changes are made.
 	fn runtime_version(&self, id: &BlockId<B>) -> Result<RuntimeVersion, sp_blockchain::Error>;
 
+	/// Execute a call to the given runtime `code` in Wasm on top of state in a block of given
+	/// hash, instead of the runtime of the block.
+	///
+	/// The changes made by the call are recorded in `overlay`, so that the following calls with the
+	/// same `overlay` execute on top of them. No changes are made to the state of the block.
+	fn call_with_code(
+		&self,
+		id: &BlockId<B>,
+		code: &[u8],
+		overlay: &mut OverlayedChanges,
+		method: &str,
+		call_data: &[u8],
+	) -> Result<Vec<u8>, sp_blockchain::Error>;
+
 	/// Execute a call to a contract on top of given state.
 	///
 	/// No changes are made.
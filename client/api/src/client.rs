@@ -16,7 +16,7 @@
 
 //! A set of APIs supported by the client along with their primitives.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 use futures::channel::mpsc;
 use primitives::storage::StorageKey;
 use sp_runtime::{
@@ -24,6 +24,8 @@ use sp_runtime::{
     generic::BlockId
 };
 use consensus::BlockOrigin;
+use executor::RuntimeVersion;
+use inherents::InherentData;
 
 use crate::blockchain::Info;
 use crate::notifications::StorageEventStream;
@@ -74,6 +76,53 @@ pub trait BlockBody<Block: BlockT> {
 	) -> sp_blockchain::Result<Option<Vec<<Block as BlockT>::Extrinsic>>>;
 }
 
+/// Interface for fetching information about the usage of the backend.
+pub trait UsageProvider<Block: BlockT> {
+	/// Get the client info, usage statistics included.
+	fn usage_info(&self) -> ClientInfo<Block>;
+
+	/// Get the number of keys and the size of each column of the database, if there is one.
+	///
+	/// This goes through the whole database and may take a long time.
+	fn column_sizes(&self) -> sp_blockchain::Result<Option<Vec<ColumnSize>>>;
+}
+
+/// Interface for switching the backend to the bulk import mode.
+pub trait BulkImport {
+	/// Enables or disables the bulk import mode of the backend, used while catching up with the
+	/// chain.
+	fn set_bulk_import(&self, enabled: bool) -> sp_blockchain::Result<()>;
+}
+
+/// Interface for checking a new runtime before upgrading to it.
+pub trait CheckRuntimeUpgrade<Block: BlockT> {
+	/// Dry run the upgrade to the runtime `code` on top of the best block, without changing the
+	/// state.
+	///
+	/// The new runtime must be instantiable with the host functions of the node, it must have the
+	/// `spec_name` of the current runtime and a greater `spec_version`. It must then initialize a
+	/// block, apply the inherent extrinsics it creates from `inherent_data` and finalize the block
+	/// without failing.
+	fn check_runtime_upgrade(
+		&self,
+		code: &[u8],
+		inherent_data: InherentData,
+	) -> sp_blockchain::Result<RuntimeUpgradeCheck<Block>>;
+}
+
+/// The outcome of a successful dry run of a runtime upgrade.
+#[derive(Debug, Clone)]
+pub struct RuntimeUpgradeCheck<Block: BlockT> {
+	/// The hash of the best block the upgrade was checked on.
+	pub best_hash: Block::Hash,
+	/// The version of the runtime of the best block.
+	pub current_version: RuntimeVersion,
+	/// The version of the new runtime.
+	pub new_version: RuntimeVersion,
+	/// The number of inherent extrinsics applied by the new runtime.
+	pub inherent_extrinsics: usize,
+}
+
 /// Provide a list of potential uncle headers for a given block.
 pub trait ProvideUncles<Block: BlockT> {
 	/// Gets the uncles of the block with `target_hash` going back `max_generation` ancestors.
@@ -86,8 +135,103 @@ pub trait ProvideUncles<Block: BlockT> {
 pub struct ClientInfo<Block: BlockT> {
 	/// Best block hash.
 	pub chain: Info<Block>,
-	/// State Cache Size currently used by the backend
-	pub used_state_cache_size: Option<usize>,
+	/// Usage statistics of the backend, if it keeps them.
+	pub usage: Option<UsageInfo>,
+}
+
+/// Accesses to a database column.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IoStats {
+	/// Number of reads.
+	pub reads: u64,
+	/// Number of bytes read.
+	pub bytes_read: u64,
+	/// Number of writes, deletions included.
+	pub writes: u64,
+	/// Number of bytes written.
+	pub bytes_written: u64,
+}
+
+/// Usage of a database column.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnUsage {
+	/// Name of the column.
+	pub name: &'static str,
+	/// Accesses since the database has been opened.
+	pub total: IoStats,
+	/// Accesses per second, averaged over the last completed measurement period.
+	pub per_second: IoStats,
+}
+
+/// Histogram of the durations of the database commits.
+#[derive(Debug, Clone, Default)]
+pub struct CommitLatency {
+	/// Upper bound of each bucket in milliseconds, and the number of commits that fall in it.
+	/// The last bucket has no upper bound.
+	pub buckets: Vec<(Option<u64>, u64)>,
+	/// Number of commits.
+	pub commits: u64,
+	/// Total duration of the commits.
+	pub total: Duration,
+}
+
+/// Usage of a cache of the state.
+#[derive(Debug, Clone, Default)]
+pub struct StateCacheUsage {
+	/// Memory used by the cache, in bytes.
+	pub used_size: usize,
+	/// Number of reads served by the cache.
+	pub hits: u64,
+	/// Number of reads which had to go to the database.
+	pub misses: u64,
+}
+
+impl StateCacheUsage {
+	/// Ratio of the reads served by the cache, or `None` if there has been no read yet.
+	pub fn hit_ratio(&self) -> Option<f64> {
+		let reads = self.hits + self.misses;
+		if reads == 0 {
+			None
+		} else {
+			Some(self.hits as f64 / reads as f64)
+		}
+	}
+}
+
+/// Usage statistics of a backend.
+#[derive(Debug, Clone, Default)]
+pub struct UsageInfo {
+	/// Accesses to each column of the database.
+	pub columns: Vec<ColumnUsage>,
+	/// Durations of the database commits.
+	pub commit_latency: CommitLatency,
+	/// Usage of the state cache.
+	pub state_cache: StateCacheUsage,
+	/// Usage of the trie node cache.
+	pub trie_cache: StateCacheUsage,
+}
+
+impl UsageInfo {
+	/// Sum of the accesses per second to all the columns.
+	pub fn per_second(&self) -> IoStats {
+		self.columns.iter().fold(IoStats::default(), |sum, column| IoStats {
+			reads: sum.reads + column.per_second.reads,
+			bytes_read: sum.bytes_read + column.per_second.bytes_read,
+			writes: sum.writes + column.per_second.writes,
+			bytes_written: sum.bytes_written + column.per_second.bytes_written,
+		})
+	}
+}
+
+/// Size of a database column.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnSize {
+	/// Name of the column.
+	pub name: &'static str,
+	/// Number of keys.
+	pub keys: u64,
+	/// Size of the keys and values, in bytes.
+	pub bytes: u64,
 }
 
 /// Summary of an imported block
@@ -20,18 +20,20 @@
 //! strategy for the runtime calls and provide the right `Externalities`
 //! extensions to support APIs for particular execution context & capabilities.
 
-use std::sync::{Weak, Arc};
+use std::{path::PathBuf, sync::{Weak, Arc}};
 use codec::Decode;
 use primitives::{
 	ExecutionContext,
 	offchain::{self, OffchainExt, TransactionPoolExt},
-	traits::{BareCryptoStorePtr, KeystoreExt},
+	traits::{BareCryptoStorePtr, KeystoreExt, FuelLimitExt, OffchainCallExt, WasmProfileExt},
 };
 use sp_runtime::{
 	generic::BlockId,
 	traits,
 };
-use state_machine::{ExecutionStrategy, ExecutionManager, DefaultHandler};
+use state_machine::{
+	ExecutionStrategy, ExecutionManager, DefaultHandler, DivergenceReportExt, DivergenceReporter,
+};
 use externalities::Extensions;
 use parking_lot::RwLock;
 
@@ -62,6 +64,31 @@ impl Default for ExecutionStrategies {
 	}
 }
 
+/// Default maximum number of instructions executed in Wasm by the calls which are limited.
+pub const DEFAULT_FUEL_LIMIT: u64 = 10_000_000_000;
+
+/// Maximum number of instructions executed in Wasm by the offchain workers and the `state_call`
+/// RPC. `None` if unlimited.
+///
+/// The limited calls are executed in Wasm whatever their execution strategy. The other calls,
+/// e.g. importing blocks or validating transactions, are never limited.
+#[derive(Debug, Clone)]
+pub struct ExecutionFuelLimits {
+	/// Fuel limit of the offchain workers.
+	pub offchain_worker: Option<u64>,
+	/// Fuel limit of the calls made through the `state_call` RPC.
+	pub rpc: Option<u64>,
+}
+
+impl Default for ExecutionFuelLimits {
+	fn default() -> ExecutionFuelLimits {
+		ExecutionFuelLimits {
+			offchain_worker: Some(DEFAULT_FUEL_LIMIT),
+			rpc: Some(DEFAULT_FUEL_LIMIT),
+		}
+	}
+}
+
 /// A producer of execution extensions for offchain calls.
 ///
 /// This crate aggregates extensions available for the offchain calls
@@ -69,6 +96,9 @@ impl Default for ExecutionStrategies {
 /// for each call, based on required `Capabilities`.
 pub struct ExecutionExtensions<Block: traits::Block> {
 	strategies: ExecutionStrategies,
+	fuel_limits: ExecutionFuelLimits,
+	divergence_reports: Option<PathBuf>,
+	wasm_profiles: Option<PathBuf>,
 	keystore: Option<BareCryptoStorePtr>,
 	transaction_pool: RwLock<Option<Weak<dyn txpool_api::OffchainSubmitTransaction<Block>>>>,
 }
@@ -77,6 +107,9 @@ impl<Block: traits::Block> Default for ExecutionExtensions<Block> {
 	fn default() -> Self {
 		Self {
 			strategies: Default::default(),
+			fuel_limits: Default::default(),
+			divergence_reports: None,
+			wasm_profiles: None,
 			keystore: None,
 			transaction_pool: RwLock::new(None),
 		}
@@ -90,7 +123,38 @@ impl<Block: traits::Block> ExecutionExtensions<Block> {
 		keystore: Option<BareCryptoStorePtr>,
 	) -> Self {
 		let transaction_pool = RwLock::new(None);
-		Self { strategies, keystore, transaction_pool }
+		let fuel_limits = Default::default();
+		let divergence_reports = None;
+		let wasm_profiles = None;
+		Self {
+			strategies,
+			fuel_limits,
+			divergence_reports,
+			wasm_profiles,
+			keystore,
+			transaction_pool,
+		}
+	}
+
+	/// Set the fuel limits of the runtime calls.
+	pub fn with_fuel_limits(mut self, fuel_limits: ExecutionFuelLimits) -> Self {
+		self.fuel_limits = fuel_limits;
+		self
+	}
+
+	/// Write the reports of the divergences between the native and Wasm executions of the calls
+	/// executed with `ExecutionStrategy::Both` to the given directory.
+	pub fn with_divergence_reports(mut self, directory: PathBuf) -> Self {
+		self.divergence_reports = Some(directory);
+		self
+	}
+
+	/// Profile the Wasm execution of the imported blocks, and write the profile of each block to
+	/// the given directory, in the collapsed stacks format. The profiled blocks are executed in
+	/// Wasm whatever the importing and syncing strategies.
+	pub fn with_wasm_profiles(mut self, directory: PathBuf) -> Self {
+		self.wasm_profiles = Some(directory);
+		self
 	}
 
 	/// Get a reference to the execution strategies.
@@ -98,6 +162,45 @@ impl<Block: traits::Block> ExecutionExtensions<Block> {
 		&self.strategies
 	}
 
+	/// Get a reference to the fuel limits.
+	pub fn fuel_limits(&self) -> &ExecutionFuelLimits {
+		&self.fuel_limits
+	}
+
+	/// Create `Extensions` for a call made through the `state_call` RPC.
+	pub fn call_extensions(&self) -> Extensions {
+		let mut extensions = Extensions::new();
+		extensions.register(OffchainCallExt(()));
+		if let Some(fuel) = self.fuel_limits.rpc {
+			extensions.register(FuelLimitExt(fuel));
+		}
+		extensions
+	}
+
+	/// Create `Extensions` for the execution of a block being imported.
+	pub fn block_execution_extensions(
+		&self,
+		number: traits::NumberFor<Block>,
+		hash: Block::Hash,
+	) -> Extensions {
+		let mut extensions = Extensions::new();
+		self.register_divergence_report(&mut extensions, format!("block #{} ({})", number, hash));
+		if let Some(ref directory) = self.wasm_profiles {
+			extensions.register(WasmProfileExt(
+				directory.join(format!("block-{}-{:?}.folded", number, hash)),
+			));
+		}
+		extensions
+	}
+
+	fn register_divergence_report(&self, extensions: &mut Extensions, block: String) {
+		if let Some(ref directory) = self.divergence_reports {
+			extensions.register(DivergenceReportExt(
+				DivergenceReporter::new(directory.clone(), block),
+			));
+		}
+	}
+
 	/// Register transaction pool extension.
 	///
 	/// To break retain cycle between `Client` and `TransactionPool` we require this
@@ -133,10 +236,23 @@ impl<Block: traits::Block> ExecutionExtensions<Block> {
 				self.strategies.other.get_manager(),
 		};
 
+		// the runtime API calls without a context, e.g. validating transactions, aren't limited.
+		let fuel_limit = match context {
+			ExecutionContext::OffchainCall(Some((_, capabilities))) if capabilities.has_all() =>
+				self.fuel_limits.offchain_worker,
+			_ => None,
+		};
+
 		let capabilities = context.capabilities();
 
 		let mut extensions = Extensions::new();
 
+		if let Some(fuel) = fuel_limit {
+			extensions.register(FuelLimitExt(fuel));
+		}
+
+		self.register_divergence_report(&mut extensions, at.to_string());
+
 		if capabilities.has(offchain::Capability::Keystore) {
 			if let Some(keystore) = self.keystore.as_ref() {
 				extensions.register(KeystoreExt(keystore.clone()));
@@ -153,6 +269,11 @@ impl<Block: traits::Block> ExecutionExtensions<Block> {
 		}
 
 		if let ExecutionContext::OffchainCall(Some(ext)) = context {
+			// only the offchain workers are marked, the other calls with an offchain context may
+			// be part of the consensus, e.g. validating transactions.
+			if capabilities.has_all() {
+				extensions.register(OffchainCallExt(()));
+			}
 			extensions.register(
 				OffchainExt::new(offchain::LimitedExternalities::new(capabilities, ext.0))
 			)
@@ -181,3 +302,58 @@ impl<Block: traits::Block> offchain::TransactionPool for TransactionPoolAdapter<
 		self.pool.submit_at(&self.at, xt)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::any::TypeId;
+	use primitives::offchain::testing::TestOffchainExt;
+	use test_primitives::Block;
+
+	fn fuel_limit(mut extensions: Extensions) -> Option<u64> {
+		extensions.get_mut(TypeId::of::<FuelLimitExt>())
+			.and_then(|ext| ext.downcast_ref::<FuelLimitExt>())
+			.map(|limit| limit.0)
+	}
+
+	#[test]
+	fn only_offchain_workers_and_rpc_calls_are_limited() {
+		let extensions = ExecutionExtensions::<Block>::default().with_fuel_limits(ExecutionFuelLimits {
+			offchain_worker: Some(1),
+			rpc: Some(2),
+		});
+		let context_limit = |context| fuel_limit(
+			extensions.manager_and_extensions::<(), ()>(&BlockId::Number(0), context).1
+		);
+
+		let (offchain, _) = TestOffchainExt::new();
+		let worker = ExecutionContext::OffchainCall(Some((Box::new(offchain), offchain::Capabilities::all())));
+		assert_eq!(context_limit(worker), Some(1));
+		assert_eq!(context_limit(ExecutionContext::OffchainCall(None)), None);
+		assert_eq!(context_limit(ExecutionContext::Importing), None);
+		assert_eq!(fuel_limit(extensions.call_extensions()), Some(2));
+	}
+
+	#[test]
+	fn only_offchain_workers_and_rpc_calls_are_offchain_calls() {
+		let extensions = ExecutionExtensions::<Block>::default();
+		let is_offchain_call = |mut extensions: Extensions| {
+			extensions.get_mut(TypeId::of::<OffchainCallExt>()).is_some()
+		};
+		let context_is_offchain_call = |context| is_offchain_call(
+			extensions.manager_and_extensions::<(), ()>(&BlockId::Number(0), context).1
+		);
+
+		let (offchain, _) = TestOffchainExt::new();
+		let worker = ExecutionContext::OffchainCall(Some((Box::new(offchain), offchain::Capabilities::all())));
+		assert!(context_is_offchain_call(worker));
+		let (offchain, _) = TestOffchainExt::new();
+		let keystore_only = ExecutionContext::OffchainCall(Some((
+			Box::new(offchain),
+			offchain::Capabilities::from(&[offchain::Capability::Keystore][..]),
+		)));
+		assert!(!context_is_offchain_call(keystore_only));
+		assert!(!context_is_offchain_call(ExecutionContext::OffchainCall(None)));
+		assert!(is_offchain_call(extensions.call_extensions()));
+	}
+}
@@ -15,8 +15,8 @@
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
 use ansi_term::Colour;
-use client_api::ClientInfo;
-use log::info;
+use client_api::{ClientInfo, StateCacheUsage};
+use log::{info, debug};
 use network::SyncState;
 use sp_runtime::traits::{Block as BlockT, CheckedDiv, NumberFor, Zero, Saturating};
 use service::NetworkStatus;
@@ -79,9 +79,39 @@ impl<B: BlockT> InformantDisplay<B> {
 			TransferRateFormat(net_status.average_download_per_sec),
 			TransferRateFormat(net_status.average_upload_per_sec),
 		);
+
+		if let Some(usage) = &info.usage {
+			let io = usage.per_second();
+			let latency = &usage.commit_latency;
+			let average_commit_ms = if latency.commits == 0 {
+				0
+			} else {
+				latency.total.as_millis() / u128::from(latency.commits)
+			};
+			debug!(
+				target: "substrate",
+				"Database: {} reads/s ({}), {} writes/s ({}), {} commits ({} ms avg), state cache {} hits ({}), \
+				trie cache {} hits ({})",
+				io.reads,
+				TransferRateFormat(io.bytes_read),
+				io.writes,
+				TransferRateFormat(io.bytes_written),
+				latency.commits,
+				average_commit_ms,
+				hit_ratio(&usage.state_cache),
+				SizeFormat(usage.state_cache.used_size as u64),
+				hit_ratio(&usage.trie_cache),
+				SizeFormat(usage.trie_cache.used_size as u64),
+			);
+		}
 	}
 }
 
+/// Formats the ratio of the reads served by the cache as a percentage.
+fn hit_ratio(usage: &StateCacheUsage) -> String {
+	usage.hit_ratio().map_or_else(|| "-".into(), |ratio| format!("{:.1}%", ratio * 100.0))
+}
+
 /// Calculates `(best_number - last_number) / (now - last_update)` and returns a `String`
 /// representing the speed of import.
 fn speed<B: BlockT>(
@@ -147,3 +177,20 @@ impl fmt::Display for TransferRateFormat {
 		write!(f, "{:.1}MiB/s", self.0 as f64 / (1024.0 * 1024.0))
 	}
 }
+
+/// Contains a number of bytes. Implements `fmt::Display` and shows this number of bytes in a nice
+/// way.
+struct SizeFormat(u64);
+impl fmt::Display for SizeFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.0 < 1024 {
+			return write!(f, "{} B", self.0)
+		}
+
+		if self.0 < 1024 * 1024 {
+			return write!(f, "{:.1}kiB", self.0 as f64 / 1024.0)
+		}
+
+		write!(f, "{:.1}MiB", self.0 as f64 / (1024.0 * 1024.0))
+	}
+}
@@ -30,13 +30,14 @@ use client_api::execution_extensions::ExecutionStrategies;
 use service::{
 	config::{Configuration, DatabaseConfig},
 	ServiceBuilderCommand,
-	RuntimeGenesis, ChainSpecExtension, PruningMode, ChainSpec,
+	RuntimeGenesis, ChainSpecExtension, PruningMode, BlockPruning, StatePinning, HeaderPruning, ChainSpec,
 };
 use network::{
 	self,
 	multiaddr::Protocol,
 	config::{
-		NetworkConfiguration, TransportConfig, NonReservedPeerMode, NodeKeyConfig, build_multiaddr
+		NetworkConfiguration, TransportConfig, NonReservedPeerMode, NodeKeyConfig, AllowedPeers,
+		BandwidthLimits, build_multiaddr,
 	},
 };
 use primitives::H256;
@@ -52,7 +53,8 @@ use structopt::{StructOpt, clap::AppSettings};
 #[doc(hidden)]
 pub use structopt::clap::App;
 use params::{
-	RunCmd, PurgeChainCmd, RevertCmd, ImportBlocksCmd, ExportBlocksCmd, BuildSpecCmd,
+	RunCmd, PurgeChainCmd, RevertCmd, ImportBlocksCmd, ExportBlocksCmd, BuildSpecCmd, MigrateDbCmd,
+	CheckDbCmd, CheckRuntimeUpgradeCmd,
 	NetworkConfigurationParams, MergeParameters, TransactionPoolParams,
 	NodeKeyParams, NodeKeyType, Cors, CheckBlockCmd,
 };
@@ -71,8 +73,12 @@ use sp_runtime::traits::Block as BlockT;
 const DEFAULT_NETWORK_CONFIG_PATH : &'static str = "network";
 /// default sub directory to store database
 const DEFAULT_DB_CONFIG_PATH : &'static str = "db";
+/// default sub directory to store the sled database
+const DEFAULT_SLED_DB_CONFIG_PATH : &'static str = "sled";
 /// default sub directory for the key store
 const DEFAULT_KEYSTORE_CONFIG_PATH : &'static str =  "keystore";
+/// default sub directory for the cache of the compiled runtimes
+const DEFAULT_WASMTIME_CACHE_CONFIG_PATH : &'static str = "wasmtime-cache";
 
 /// The maximum number of characters for a node name.
 const NODE_NAME_MAX_LENGTH: usize = 32;
@@ -242,6 +248,15 @@ where
 		params::CoreParams::Revert(params) => ParseAndPrepare::RevertChain(
 			ParseAndPrepareRevert { params, version }
 		),
+		params::CoreParams::CheckDb(params) => ParseAndPrepare::CheckDb(
+			ParseAndPrepareCheckDb { params, version }
+		),
+		params::CoreParams::MigrateDb(params) => ParseAndPrepare::MigrateDb(
+			ParseAndPrepareMigrate { params, version }
+		),
+		params::CoreParams::CheckRuntimeUpgrade(params) => ParseAndPrepare::CheckRuntimeUpgrade(
+			ParseAndPrepareCheckRuntimeUpgrade { params, version }
+		),
 		params::CoreParams::Custom(params) => ParseAndPrepare::CustomCommand(params),
 	}
 }
@@ -274,6 +289,12 @@ pub enum ParseAndPrepare<'a, CC, RP> {
 	PurgeChain(ParseAndPreparePurge<'a>),
 	/// Command ready to revert the chain.
 	RevertChain(ParseAndPrepareRevert<'a>),
+	/// Command ready to check the database.
+	CheckDb(ParseAndPrepareCheckDb<'a>),
+	/// Command ready to migrate the database.
+	MigrateDb(ParseAndPrepareMigrate<'a>),
+	/// Command ready to dry run a runtime upgrade.
+	CheckRuntimeUpgrade(ParseAndPrepareCheckRuntimeUpgrade<'a>),
 	/// An additional custom command passed to `parse_and_prepare`.
 	CustomCommand(CC),
 }
@@ -608,6 +629,113 @@ impl<'a> ParseAndPrepareRevert<'a> {
 	}
 }
 
+/// Command ready to dry run a runtime upgrade.
+pub struct ParseAndPrepareCheckRuntimeUpgrade<'a> {
+	params: CheckRuntimeUpgradeCmd,
+	version: &'a VersionInfo,
+}
+
+impl<'a> ParseAndPrepareCheckRuntimeUpgrade<'a> {
+	/// Runs the command and dry runs the upgrade to the runtime read from the input file.
+	pub fn run_with_builder<C, G, E, F, B, S>(
+		self,
+		builder: F,
+		spec_factory: S
+	) -> error::Result<()> where
+		S: FnOnce(&str) -> Result<Option<ChainSpec<G, E>>, String>,
+		F: FnOnce(Configuration<C, G, E>) -> Result<B, error::Error>,
+		B: ServiceBuilderCommand,
+		C: Default,
+		G: RuntimeGenesis,
+		E: ChainSpecExtension,
+	{
+		let mut config = create_config_with_db_path(
+			spec_factory, &self.params.shared_params, self.version
+		)?;
+		fill_import_params(&mut config, &self.params.import_params, service::Roles::FULL)?;
+
+		let code = fs::read(&self.params.input)?;
+		builder(config)?.check_runtime_upgrade(&code)?;
+		Ok(())
+	}
+}
+
+/// Command ready to check the database.
+pub struct ParseAndPrepareCheckDb<'a> {
+	params: CheckDbCmd,
+	version: &'a VersionInfo,
+}
+
+impl<'a> ParseAndPrepareCheckDb<'a> {
+	/// Runs the command and checks the database of a chain made of `Block`s.
+	pub fn run<Block, G, E, S>(
+		self,
+		spec_factory: S
+	) -> error::Result<()> where
+		S: FnOnce(&str) -> Result<Option<ChainSpec<G, E>>, String>,
+		Block: BlockT<Hash = H256>,
+		G: RuntimeGenesis,
+		E: ChainSpecExtension,
+	{
+		let mut config = create_config_with_db_path::<(), _, _, _>(
+			spec_factory, &self.params.shared_params, self.version
+		)?;
+		fill_import_params(&mut config, &self.params.import_params, service::Roles::FULL)?;
+
+		let start = std::time::Instant::now();
+		let consistent = service::chain_ops::check_database::<Block, _, _, _>(&config, self.params.repair)?;
+		println!("Completed in {} ms.", start.elapsed().as_millis());
+		if consistent {
+			Ok(())
+		} else {
+			Err(error::Error::Other("The database is inconsistent".into()))
+		}
+	}
+}
+
+/// Command ready to migrate the database.
+pub struct ParseAndPrepareMigrate<'a> {
+	params: MigrateDbCmd,
+	version: &'a VersionInfo,
+}
+
+impl<'a> ParseAndPrepareMigrate<'a> {
+	/// Runs the command and copies the database into the other backend.
+	pub fn run<G, E, S>(
+		self,
+		spec_factory: S
+	) -> error::Result<()> where
+		S: FnOnce(&str) -> Result<Option<ChainSpec<G, E>>, String>,
+		G: RuntimeGenesis,
+		E: ChainSpecExtension,
+	{
+		let config = create_config_with_db_path::<(), _, _, _>(
+			spec_factory, &self.params.shared_params, self.version
+		)?;
+		let to = match config.database {
+			DatabaseConfig::Path { ref path, .. } if self.params.to != self.params.shared_params.database =>
+				DatabaseConfig::Path {
+					path: path.with_file_name(db_config_path(self.params.to)),
+					cache_size: None,
+					kind: self.params.to.into(),
+				},
+			_ => return Err(error::Error::Input("Cannot migrate the database into itself".to_string())),
+		};
+
+		let start = std::time::Instant::now();
+		service::chain_ops::migrate_database(&config.database, &to)?;
+		if let DatabaseConfig::Path { path, .. } = to {
+			println!(
+				"Database copied to {:?} in {} ms. Start the node with `--database {}` to use it.",
+				path,
+				start.elapsed().as_millis(),
+				self.params.to,
+			);
+		}
+		Ok(())
+	}
+}
+
 /// Create a `NodeKeyConfig` from the given `NodeKeyParams` in the context
 /// of an optional network config storage directory.
 fn node_key_config<P>(params: NodeKeyParams, net_config_dir: &Option<P>)
@@ -714,6 +842,22 @@ fn fill_network_configuration(
 
 	config.max_parallel_downloads = cli.max_parallel_downloads;
 
+	config.allowed_peers = match cli.allowed_peers {
+		Some(path) => Some(AllowedPeers::from_file(&path)?),
+		None if cli.private_network => Some(AllowedPeers::default()),
+		None => None,
+	};
+
+	let bytes_per_sec = |kib_per_sec: Option<u64>| kib_per_sec.map(|rate| rate.saturating_mul(1024));
+	config.bandwidth_limits = BandwidthLimits {
+		upload: bytes_per_sec(cli.max_upload),
+		download: bytes_per_sec(cli.max_download),
+		peer_upload: bytes_per_sec(cli.max_peer_upload),
+		peer_download: bytes_per_sec(cli.max_peer_download),
+	};
+	config.sync_mode = cli.sync.into();
+	config.persist_peers = cli.persist_peers;
+
 	Ok(())
 }
 
@@ -758,6 +902,7 @@ pub fn fill_import_params<C, G, E>(
 	}
 
 	config.state_cache_size = cli.state_cache_size;
+	config.trie_cache_size = cli.trie_cache_size;
 
 	// by default we disable pruning if the node is an authority (i.e.
 	// `ArchiveAll`), otherwise we keep state for the last 256 blocks. if the
@@ -781,7 +926,52 @@ pub fn fill_import_params<C, G, E>(
 		},
 	};
 
+	if let Some(age) = cli.pruning_age {
+		match config.pruning {
+			PruningMode::Constrained(ref mut constraints) => constraints.max_age = Some(age),
+			_ => return Err(error::Error::Input(
+				"`--pruning-age` requires state pruning to be enabled".to_string()
+			)),
+		}
+	}
+
+	config.state_pinning = match cli.pin_state_every {
+		Some(blocks) => StatePinning::EveryNth(blocks),
+		None => StatePinning::None,
+	};
+
+	config.block_pruning = match &cli.blocks_pruning {
+		None => BlockPruning::ArchiveAll,
+		Some(ref s) if s == "archive" => BlockPruning::ArchiveAll,
+		Some(s) => BlockPruning::KeepFinalized {
+			blocks: s.parse()
+				.map_err(|_| error::Error::Input("Invalid blocks pruning mode specified".to_string()))?,
+			keep_justifications: cli.keep_justifications,
+		},
+	};
+
+	config.header_pruning = if cli.keep_headers {
+		HeaderPruning::ArchiveAll
+	} else {
+		HeaderPruning::ReplaceWithCht
+	};
+
 	config.wasm_method = cli.wasm_method.into();
+	config.allocation_strategy = cli.heap_allocation_strategy.into();
+	if let Some(max_runtimes) = cli.max_runtimes {
+		config.max_runtimes = max_runtimes;
+	}
+	if let Some(max_runtime_instances) = cli.max_runtime_instances {
+		config.max_runtime_instances = max_runtime_instances;
+	}
+
+	let fuel_limit = |limit: u64| if limit == 0 { None } else { Some(limit) };
+	if let Some(limit) = cli.fuel_limit_offchain_worker {
+		config.execution_fuel_limits.offchain_worker = fuel_limit(limit);
+	}
+	if let Some(limit) = cli.fuel_limit_rpc {
+		config.execution_fuel_limits.rpc = fuel_limit(limit);
+	}
 
 	let exec = &cli.execution_strategies;
 	let exec_all_or = |strat: ExecutionStrategy| exec.execution.unwrap_or(strat).into();
@@ -792,6 +982,7 @@ pub fn fill_import_params<C, G, E>(
 		offchain_worker: exec_all_or(exec.execution_offchain_worker),
 		other: exec_all_or(exec.execution_other),
 	};
+	config.divergence_reports_path = exec.divergence_reports.clone();
 	Ok(())
 }
 
@@ -887,6 +1078,7 @@ where
 	);
 
 	config.rpc_ws_max_connections = cli.ws_max_connections;
+	config.rpc_methods = cli.rpc_methods.into();
 	config.rpc_cors = cli.rpc_cors.unwrap_or_else(|| if is_dev {
 		log::warn!("Running in --dev mode, RPC CORS has been disabled.");
 		Cors::All
@@ -910,6 +1102,7 @@ where
 
 	config.tracing_targets = cli.tracing_targets.into();
 	config.tracing_receiver = cli.tracing_receiver.into();
+	config.wasm_profiles_path = cli.wasm_profiles.clone();
 
 	// Imply forced authoring on --dev
 	config.force_authoring = cli.shared_params.dev || cli.force_authoring;
@@ -936,13 +1129,23 @@ where
 	);
 
 	config.database = DatabaseConfig::Path {
-		path: config.in_chain_config_dir(DEFAULT_DB_CONFIG_PATH).expect("We provided a base_path."),
+		path: config.in_chain_config_dir(db_config_path(cli.database)).expect("We provided a base_path."),
 		cache_size: None,
+		kind: cli.database.into(),
 	};
+	config.wasmtime_cache_path = config.in_chain_config_dir(DEFAULT_WASMTIME_CACHE_CONFIG_PATH);
 
 	Ok(config)
 }
 
+/// Sub directory of the chain data where the database of the given kind is stored.
+fn db_config_path(database: params::Database) -> &'static str {
+	match database {
+		params::Database::RocksDb => DEFAULT_DB_CONFIG_PATH,
+		params::Database::Sled => DEFAULT_SLED_DB_CONFIG_PATH,
+	}
+}
+
 /// Internal trait used to cast to a dynamic type that implements Read and Seek.
 trait ReadPlusSeek: Read + Seek {}
 
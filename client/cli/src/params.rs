@@ -80,6 +80,50 @@ impl Into<service::config::WasmExecutionMethod> for WasmExecutionMethod {
 	}
 }
 
+arg_enum! {
+	/// How to serve the allocations of the Wasm runtime when the free items of their size are
+	/// exhausted.
+	#[allow(missing_docs)]
+	#[derive(Debug, Clone, Copy)]
+	pub enum HeapAllocationStrategy {
+		// Take more space from the heap.
+		Bump,
+		// Take more space from the heap, or a larger free item if the heap is exhausted.
+		FallbackToLarger,
+	}
+}
+
+impl Into<service::config::AllocationStrategy> for HeapAllocationStrategy {
+	fn into(self) -> service::config::AllocationStrategy {
+		match self {
+			HeapAllocationStrategy::Bump => service::config::AllocationStrategy::Bump,
+			HeapAllocationStrategy::FallbackToLarger =>
+				service::config::AllocationStrategy::FallbackToLarger,
+		}
+	}
+}
+
+arg_enum! {
+	/// How to synchronize with the chain.
+	#[allow(missing_docs)]
+	#[derive(Debug, Clone, Copy)]
+	pub enum SyncMode {
+		// Download and execute all the blocks.
+		Full,
+		// Download the headers and the state of a recent block, then continue with a full sync.
+		Warp,
+	}
+}
+
+impl Into<network::config::SyncMode> for SyncMode {
+	fn into(self) -> network::config::SyncMode {
+		match self {
+			SyncMode::Full => network::config::SyncMode::Full,
+			SyncMode::Warp => network::config::SyncMode::Warp,
+		}
+	}
+}
+
 arg_enum! {
 	/// Whether off-chain workers are enabled.
 	#[allow(missing_docs)]
@@ -91,6 +135,52 @@ arg_enum! {
 	}
 }
 
+arg_enum! {
+	/// Available RPC methods.
+	#[allow(missing_docs)]
+	#[derive(Debug, Copy, Clone, PartialEq)]
+	pub enum RpcMethods {
+		// Expose every RPC method only when RPC is listening on `localhost`,
+		// otherwise serve only safe RPC methods.
+		Auto,
+		// Allow only a safe subset of RPC methods.
+		Safe,
+		// Expose every RPC method (even potentially unsafe ones).
+		Unsafe,
+	}
+}
+
+impl Into<service::config::RpcMethods> for RpcMethods {
+	fn into(self) -> service::config::RpcMethods {
+		match self {
+			RpcMethods::Auto => service::config::RpcMethods::Auto,
+			RpcMethods::Safe => service::config::RpcMethods::Safe,
+			RpcMethods::Unsafe => service::config::RpcMethods::Unsafe,
+		}
+	}
+}
+
+arg_enum! {
+	/// Kind of database.
+	#[allow(missing_docs)]
+	#[derive(Debug, Clone, Copy, PartialEq)]
+	pub enum Database {
+		// RocksDB.
+		RocksDb,
+		// Sled, tuned for the trie nodes workload.
+		Sled,
+	}
+}
+
+impl Into<service::config::DatabaseKind> for Database {
+	fn into(self) -> service::config::DatabaseKind {
+		match self {
+			Database::RocksDb => service::config::DatabaseKind::RocksDb,
+			Database::Sled => service::config::DatabaseKind::Sled,
+		}
+	}
+}
+
 /// Shared parameters used by all `CoreParams`.
 #[derive(Debug, StructOpt, Clone)]
 pub struct SharedParams {
@@ -109,6 +199,20 @@ pub struct SharedParams {
 	/// Sets a custom logging filter.
 	#[structopt(short = "l", long = "log", value_name = "LOG_PATTERN")]
 	pub log: Option<String>,
+
+	/// Select the database backend.
+	///
+	/// Each backend keeps its database in its own directory of the chain data. Use the
+	/// `migrate-db` subcommand to switch an existing node to another backend. `Sled` requires the
+	/// node to be built with the `sled` feature.
+	#[structopt(
+		long = "database",
+		value_name = "DB",
+		possible_values = &Database::variants(),
+		case_insensitive = true,
+		default_value = "RocksDb"
+	)]
+	pub database: Database,
 }
 
 /// Parameters for block import.
@@ -130,6 +234,33 @@ pub struct ImportParams {
 	#[structopt(long = "unsafe-pruning")]
 	pub unsafe_pruning: bool,
 
+	/// Keep the state of the finalized blocks for at least the given number of seconds, even
+	/// if they are out of the state pruning window.
+	#[structopt(long = "pruning-age", value_name = "SECONDS")]
+	pub pruning_age: Option<u64>,
+
+	/// Archive the state of every block whose number is a multiple of the given value, so that
+	/// it is never pruned.
+	#[structopt(long = "pin-state-every", value_name = "BLOCKS")]
+	pub pin_state_every: Option<u32>,
+
+	/// Specify the block body pruning mode, a number of finalized blocks to keep or 'archive'.
+	///
+	/// Default is to keep the bodies of all the blocks (i.e. 'archive'). Headers are always kept.
+	#[structopt(long = "blocks-pruning", value_name = "PRUNING_MODE")]
+	pub blocks_pruning: Option<String>,
+
+	/// Keep the justifications of the blocks whose bodies are pruned.
+	#[structopt(long = "keep-justifications")]
+	pub keep_justifications: bool,
+
+	/// Keep all the headers on a light client.
+	///
+	/// By default the finalized headers are replaced with the root of the CHT covering them, except
+	/// for the headers changing the authority set, and are fetched from the network when needed.
+	#[structopt(long = "keep-headers")]
+	pub keep_headers: bool,
+
 	/// Method for executing Wasm runtime code.
 	#[structopt(
 		long = "wasm-execution",
@@ -140,6 +271,40 @@ pub struct ImportParams {
 	)]
 	pub wasm_method: WasmExecutionMethod,
 
+	/// Allocation strategy of the heap of the Wasm runtime in the offchain workers and RPC calls.
+	///
+	/// The strategy decides which allocations fail when the heap is exhausted, so importing and
+	/// constructing blocks always uses the `Bump` strategy.
+	#[structopt(
+		long = "heap-allocation-strategy",
+		value_name = "STRATEGY",
+		possible_values = &HeapAllocationStrategy::variants(),
+		case_insensitive = true,
+		default_value = "Bump"
+	)]
+	pub heap_allocation_strategy: HeapAllocationStrategy,
+
+	/// Specify the maximum number of runtimes kept in the runtimes cache.
+	#[structopt(long = "max-runtimes", value_name = "COUNT")]
+	pub max_runtimes: Option<usize>,
+
+	/// Specify the maximum number of instances of each runtime, i.e. of calls into a runtime that
+	/// can be executed in parallel.
+	#[structopt(long = "max-runtime-instances", value_name = "COUNT")]
+	pub max_runtime_instances: Option<usize>,
+
+	/// Specify the maximum number of instructions executed in Wasm by an offchain worker.
+	///
+	/// 0 disables the limit.
+	#[structopt(long = "fuel-limit-offchain-worker", value_name = "COUNT")]
+	pub fuel_limit_offchain_worker: Option<u64>,
+
+	/// Specify the maximum number of instructions executed in Wasm by a `state_call` RPC.
+	///
+	/// 0 disables the limit.
+	#[structopt(long = "fuel-limit-rpc", value_name = "COUNT")]
+	pub fuel_limit_rpc: Option<u64>,
+
 	#[allow(missing_docs)]
 	#[structopt(flatten)]
 	pub execution_strategies: ExecutionStrategies,
@@ -151,6 +316,12 @@ pub struct ImportParams {
 	/// Specify the state cache size.
 	#[structopt(long = "state-cache-size", value_name = "Bytes", default_value = "67108864")]
 	pub state_cache_size: usize,
+
+	/// Specify the size of the trie node cache, shared by the states of all the blocks.
+	///
+	/// A size of 0 disables the cache.
+	#[structopt(long = "trie-cache-size", value_name = "Bytes", default_value = "67108864")]
+	pub trie_cache_size: usize,
 }
 
 impl GetLogFilter for SharedParams {
@@ -215,6 +386,60 @@ pub struct NetworkConfigurationParams {
 	#[structopt(long = "max-parallel-downloads", value_name = "COUNT", default_value = "5")]
 	pub max_parallel_downloads: u32,
 
+	/// How to synchronize with the chain.
+	///
+	/// `Warp` downloads the headers and then the state of a recent finalized block from the peers
+	/// instead of executing all the blocks. It is only used when the node has no state yet.
+	#[structopt(
+		long = "sync",
+		value_name = "MODE",
+		possible_values = &SyncMode::variants(),
+		case_insensitive = true,
+		default_value = "Full"
+	)]
+	pub sync: SyncMode,
+
+	/// Run the node as part of a private network.
+	///
+	/// Only the peers of the allow-list may connect, and peer discovery is disabled. The
+	/// allow-list is made of the peers given with `--allowed-peers` and of the peers
+	/// provided by the runtime, if it supports it. The node refuses to start if the allow-list
+	/// is empty.
+	#[structopt(long = "private-network")]
+	pub private_network: bool,
+
+	/// Specify a file with the allow-list of a private network, one peer id per line.
+	///
+	/// Implies `--private-network`.
+	#[structopt(long = "allowed-peers", value_name = "PATH", parse(from_os_str))]
+	pub allowed_peers: Option<PathBuf>,
+
+	/// Save the known peers, their addresses and reputations, and restore them on startup.
+	///
+	/// Peers which misbehaved before a restart remain disliked, and the node doesn't need to
+	/// bootstrap from the boot nodes again.
+	#[structopt(long = "persist-peers")]
+	pub persist_peers: bool,
+
+	/// Maximum upload rate of the node, in KiB/s.
+	///
+	/// When the upload rate gets close to the limit, gossip messages are held back so that the
+	/// remaining bandwidth is used by the sync. Unlimited by default.
+	#[structopt(long = "max-upload", value_name = "KIB_PER_SEC")]
+	pub max_upload: Option<u64>,
+
+	/// Maximum download rate of the node, in KiB/s. Unlimited by default.
+	#[structopt(long = "max-download", value_name = "KIB_PER_SEC")]
+	pub max_download: Option<u64>,
+
+	/// Maximum upload rate to each peer, in KiB/s. Unlimited by default.
+	#[structopt(long = "max-peer-upload", value_name = "KIB_PER_SEC")]
+	pub max_peer_upload: Option<u64>,
+
+	/// Maximum download rate from each peer, in KiB/s. Unlimited by default.
+	#[structopt(long = "max-peer-download", value_name = "KIB_PER_SEC")]
+	pub max_peer_download: Option<u64>,
+
 	#[allow(missing_docs)]
 	#[structopt(flatten)]
 	pub node_key_params: NodeKeyParams
@@ -374,6 +599,13 @@ pub struct ExecutionStrategies {
 	)]
 	pub execution_other: ExecutionStrategy,
 
+	/// Directory to write a report to whenever the native and Wasm executions of a call diverge.
+	///
+	/// Only calls executed with the `Both` strategy are checked. The report contains both return
+	/// values and the storage changes which differ.
+	#[structopt(long = "divergence-reports", value_name = "PATH", parse(from_os_str))]
+	pub divergence_reports: Option<PathBuf>,
+
 	/// The execution strategy that should be used by all execution contexts.
 	#[structopt(
 		long = "execution",
@@ -440,6 +672,21 @@ pub struct RunCmd {
 	#[structopt(long = "ws-external")]
 	pub ws_external: bool,
 
+	/// RPC methods to expose.
+	///
+	/// - `Unsafe`: Exposes every RPC method.
+	/// - `Safe`: Exposes only a safe subset of RPC methods, denying unsafe RPC methods.
+	/// - `Auto`: Acts as `Safe` if RPC is served externally, e.g. when `--{rpc,ws}-external` is
+	///   passed, otherwise acts as `Unsafe`.
+	#[structopt(
+		long = "rpc-methods",
+		value_name = "METHOD SET",
+		possible_values = &RpcMethods::variants(),
+		case_insensitive = true,
+		default_value = "Auto"
+	)]
+	pub rpc_methods: RpcMethods,
+
 	/// Listen to all Grafana data source interfaces.
 	///
 	/// Default is local.
@@ -543,6 +790,15 @@ pub struct RunCmd {
 	)]
 	pub tracing_receiver: TracingReceiver,
 
+	/// Profile the Wasm execution of the imported blocks, and write the profile of each block to
+	/// the given directory.
+	///
+	/// The profiles are written in the collapsed stacks format, which can be turned into
+	/// flamegraphs. The imported blocks are always executed in Wasm when profiled, whatever the
+	/// execution strategy.
+	#[structopt(long = "wasm-profiles", value_name = "PATH", parse(from_os_str))]
+	pub wasm_profiles: Option<PathBuf>,
+
 	/// Specify custom keystore path.
 	#[structopt(long = "keystore-path", value_name = "PATH", parse(from_os_str))]
 	pub keystore_path: Option<PathBuf>,
@@ -832,6 +1088,63 @@ pub struct PurgeChainCmd {
 
 impl_get_log_filter!(PurgeChainCmd);
 
+/// The `check-db` command used to check the integrity of the database.
+#[derive(Debug, StructOpt, Clone)]
+pub struct CheckDbCmd {
+	/// Revert the chain to the last consistent block if the database is inconsistent.
+	///
+	/// Finalized blocks are never reverted.
+	#[structopt(long = "repair")]
+	pub repair: bool,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub import_params: ImportParams,
+}
+
+impl_get_log_filter!(CheckDbCmd);
+
+/// The `migrate-db` command used to copy the database into another database backend.
+#[derive(Debug, StructOpt, Clone)]
+pub struct MigrateDbCmd {
+	/// Database backend to copy the database into. The source is selected with `--database`.
+	#[structopt(
+		long = "to",
+		value_name = "DB",
+		possible_values = &Database::variants(),
+		case_insensitive = true
+	)]
+	pub to: Database,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+impl_get_log_filter!(MigrateDbCmd);
+
+/// The `check-runtime-upgrade` command used to dry run the upgrade to a new runtime.
+#[derive(Debug, StructOpt, Clone)]
+pub struct CheckRuntimeUpgradeCmd {
+	/// Wasm runtime code file.
+	#[structopt(parse(from_os_str))]
+	pub input: PathBuf,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub import_params: ImportParams,
+}
+
+impl_get_log_filter!(CheckRuntimeUpgradeCmd);
+
 /// All core commands that are provided by default.
 ///
 /// The core commands are split into multiple subcommands and `Run` is the default subcommand. From
@@ -860,6 +1173,15 @@ pub enum CoreParams<CC, RP> {
 	/// Remove the whole chain data.
 	PurgeChain(PurgeChainCmd),
 
+	/// Check the integrity of the database.
+	CheckDb(CheckDbCmd),
+
+	/// Copy the database into another database backend.
+	MigrateDb(MigrateDbCmd),
+
+	/// Dry run the upgrade to a new runtime on top of the best block.
+	CheckRuntimeUpgrade(CheckRuntimeUpgradeCmd),
+
 	/// Further custom subcommands.
 	Custom(CC),
 }
@@ -899,6 +1221,18 @@ impl<CC, RP> StructOpt for CoreParams<CC, RP> where
 			PurgeChainCmd::augment_clap(SubCommand::with_name("purge-chain"))
 				.about("Remove the whole chain data.")
 		)
+		.subcommand(
+			CheckDbCmd::augment_clap(SubCommand::with_name("check-db"))
+				.about("Check the integrity of the database, and optionally repair it.")
+		)
+		.subcommand(
+			MigrateDbCmd::augment_clap(SubCommand::with_name("migrate-db"))
+				.about("Copy the database into another database backend.")
+		)
+		.subcommand(
+			CheckRuntimeUpgradeCmd::augment_clap(SubCommand::with_name("check-runtime-upgrade"))
+				.about("Dry run the upgrade to a new runtime on top of the best block.")
+		)
 	}
 
 	fn from_clap(matches: &::structopt::clap::ArgMatches) -> Self {
@@ -914,6 +1248,12 @@ impl<CC, RP> StructOpt for CoreParams<CC, RP> where
 			("revert", Some(matches)) => CoreParams::Revert(RevertCmd::from_clap(matches)),
 			("purge-chain", Some(matches)) =>
 				CoreParams::PurgeChain(PurgeChainCmd::from_clap(matches)),
+			("check-db", Some(matches)) =>
+				CoreParams::CheckDb(CheckDbCmd::from_clap(matches)),
+			("migrate-db", Some(matches)) =>
+				CoreParams::MigrateDb(MigrateDbCmd::from_clap(matches)),
+			("check-runtime-upgrade", Some(matches)) =>
+				CoreParams::CheckRuntimeUpgrade(CheckRuntimeUpgradeCmd::from_clap(matches)),
 			(_, None) => CoreParams::Run(MergeParameters::from_clap(matches)),
 			_ => CoreParams::Custom(CC::from_clap(matches)),
 		}
@@ -929,6 +1269,9 @@ impl<CC, RP> GetLogFilter for CoreParams<CC, RP> where CC: GetLogFilter {
 			CoreParams::ImportBlocks(c) => c.get_log_filter(),
 			CoreParams::CheckBlock(c) => c.get_log_filter(),
 			CoreParams::PurgeChain(c) => c.get_log_filter(),
+			CoreParams::CheckDb(c) => c.get_log_filter(),
+			CoreParams::MigrateDb(c) => c.get_log_filter(),
+			CoreParams::CheckRuntimeUpgrade(c) => c.get_log_filter(),
 			CoreParams::Revert(c) => c.get_log_filter(),
 			CoreParams::Custom(c) => c.get_log_filter(),
 		}
@@ -0,0 +1,346 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Archiving of the state of selected blocks.
+//!
+//! The state of an archived block is never pruned, so that it may be queried at any time. When a
+//! block is selected, its state is pinned in the state database, then all of its nodes are copied
+//! in the background to the state meta column, where they are stored by hash under
+//! `state_db::archived_node_key`. Once the copy is complete the block is registered as archived and
+//! unpinned.
+//!
+//! The blocks being copied are marked in the meta column, in the same transaction as their
+//! canonicalization. A copy interrupted by a restart is resumed when the backend is opened again:
+//! the block is still in the pruning window, as pruning stops at the pinned blocks and the pins are
+//! taken again before any further canonicalization. Archived states are read from the copied nodes
+//! only.
+
+use std::sync::Arc;
+use std::io;
+use std::thread;
+use parking_lot::{Mutex, Condvar};
+use kvdb::DBTransaction;
+use codec::{Decode, Encode};
+use hash_db::Prefix;
+use primitives::{H256, Blake2Hasher};
+use primitives::storage::well_known_keys;
+use sp_runtime::traits::Block as BlockT;
+use state_machine::{DBValue, backend::Backend as StateBackend};
+use log::{debug, info, warn};
+use crate::{StorageDb, DbState, apply_state_commit, columns};
+use crate::utils::meta_keys;
+
+/// Number of copied nodes written to the database at once.
+const COPY_BATCH_SIZE: usize = 1024;
+
+/// Selects the blocks whose state is archived when they are canonicalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatePinning {
+	/// Only the blocks passed to `Backend::pin_state` are archived.
+	None,
+	/// Archive the state of every block whose number is a multiple of the given value.
+	EveryNth(u32),
+}
+
+impl StatePinning {
+	/// Does the policy select the block with the given number?
+	pub fn selects(&self, number: u64) -> bool {
+		match *self {
+			StatePinning::None | StatePinning::EveryNth(0) => false,
+			StatePinning::EveryNth(n) => number % u64::from(n) == 0,
+		}
+	}
+}
+
+impl Default for StatePinning {
+	fn default() -> Self {
+		StatePinning::None
+	}
+}
+
+/// A block whose state is to be archived.
+struct Job<Hash> {
+	hash: Hash,
+	number: u64,
+	state_root: H256,
+}
+
+/// Copies the state of the selected blocks and registers them as archived.
+pub struct StateArchiver<Block: BlockT> {
+	policy: StatePinning,
+	/// Pinned blocks waiting for the commit of their canonicalization.
+	pending: Mutex<Vec<Job<Block::Hash>>>,
+	/// Number of copies in progress.
+	in_progress: Arc<(Mutex<usize>, Condvar)>,
+	/// Serializes the updates of the archived blocks registry.
+	register_lock: Arc<Mutex<()>>,
+}
+
+impl<Block: BlockT<Hash=H256>> StateArchiver<Block> {
+	pub fn new(policy: StatePinning) -> Self {
+		StateArchiver {
+			policy,
+			pending: Mutex::new(Vec::new()),
+			in_progress: Arc::new((Mutex::new(0), Condvar::new())),
+			register_lock: Arc::new(Mutex::new(())),
+		}
+	}
+
+	/// Does the policy select the block with the given number?
+	pub fn selects(&self, number: u64) -> bool {
+		self.policy.selects(number)
+	}
+
+	/// Pins the state of a block which is about to be canonicalized in `transaction`, and marks it
+	/// as being archived. The copy starts with `start_pending` once the canonicalization is
+	/// committed.
+	pub fn schedule(
+		&self,
+		storage: &StorageDb<Block>,
+		transaction: &mut DBTransaction,
+		hash: Block::Hash,
+		number: u64,
+		state_root: H256,
+	) {
+		match storage.state_db.pin(&hash) {
+			Ok(()) => {
+				transaction.put(columns::META, &archiving_key(&hash), &(number, state_root).encode());
+				self.pending.lock().push(Job { hash, number, state_root });
+			},
+			Err(_) => debug!(target: "db", "Not archiving state of #{} ({:?}): state is unknown", number, hash),
+		}
+	}
+
+	/// Resumes the copies interrupted by a restart. Must be called before the first
+	/// canonicalization.
+	pub fn resume(&self, storage: &Arc<StorageDb<Block>>) {
+		let jobs: Vec<_> = storage.db.iter_from_prefix(columns::META, meta_keys::ARCHIVING_PREFIX)
+			.take_while(|(key, _)| key.starts_with(meta_keys::ARCHIVING_PREFIX))
+			.filter_map(|(key, value)| {
+				let hash = Block::Hash::decode(&mut &key[meta_keys::ARCHIVING_PREFIX.len()..]).ok()?;
+				let (number, state_root) = <(u64, H256)>::decode(&mut &value[..]).ok()?;
+				Some(Job { hash, number, state_root })
+			})
+			.collect();
+		for job in jobs {
+			if storage.state_db.is_archived(&job.hash) {
+				// the block was archived again, e.g. with `Backend::pin_state`.
+				self.unmark(storage, &job);
+				continue;
+			}
+			match storage.state_db.pin(&job.hash) {
+				Ok(()) => {
+					info!(target: "db", "Resuming archiving of state of #{} ({:?})", job.number, job.hash);
+					self.start(storage, job);
+				},
+				Err(_) => {
+					// the canonicalization marking the block has been reverted.
+					debug!(target: "db", "Not archiving state of #{} ({:?}): state is unknown", job.number, job.hash);
+					self.unmark(storage, &job);
+				},
+			}
+		}
+	}
+
+	fn unmark(&self, storage: &StorageDb<Block>, job: &Job<Block::Hash>) {
+		let mut transaction = DBTransaction::new();
+		transaction.delete(columns::META, &archiving_key(&job.hash));
+		if let Err(e) = storage.db.write(transaction) {
+			warn!(target: "db", "Failed to unmark state of #{} ({:?}) as archived: {:?}", job.number, job.hash, e);
+		}
+	}
+
+	/// Starts copying the state of the blocks scheduled since the last commit.
+	pub fn start_pending(&self, storage: &Arc<StorageDb<Block>>) {
+		let pending = std::mem::replace(&mut *self.pending.lock(), Vec::new());
+		for job in pending {
+			self.start(storage, job);
+		}
+	}
+
+	/// Unpins the blocks scheduled since the last commit, which has failed.
+	pub fn discard_pending(&self, storage: &StorageDb<Block>) {
+		for job in self.pending.lock().drain(..) {
+			storage.state_db.unpin(&job.hash);
+		}
+	}
+
+	/// Pins the state of the given block and starts copying it.
+	pub fn archive(
+		&self,
+		storage: &Arc<StorageDb<Block>>,
+		hash: Block::Hash,
+		number: u64,
+		state_root: H256,
+	) -> Result<(), String> {
+		storage.state_db.pin(&hash)
+			.map_err(|_| format!("State already discarded for #{} ({:?})", number, hash))?;
+		let mut transaction = DBTransaction::new();
+		transaction.put(columns::META, &archiving_key(&hash), &(number, state_root).encode());
+		if let Err(e) = storage.db.write(transaction) {
+			storage.state_db.unpin(&hash);
+			return Err(format!("Database backend error: {:?}", e));
+		}
+		self.start(storage, Job { hash, number, state_root });
+		Ok(())
+	}
+
+	/// Waits until all the copies in progress are complete.
+	#[cfg(test)]
+	pub fn wait(&self) {
+		let (count, condvar) = &*self.in_progress;
+		let mut count = count.lock();
+		while *count != 0 {
+			condvar.wait(&mut count);
+		}
+	}
+
+	fn start(&self, storage: &Arc<StorageDb<Block>>, job: Job<Block::Hash>) {
+		*self.in_progress.0.lock() += 1;
+		let storage = storage.clone();
+		let in_progress = self.in_progress.clone();
+		let register_lock = self.register_lock.clone();
+		let spawned = thread::Builder::new()
+			.name("state-archive".into())
+			.spawn(move || {
+				run(&storage, &register_lock, &job);
+				storage.state_db.unpin(&job.hash);
+				let (count, condvar) = &*in_progress;
+				*count.lock() -= 1;
+				condvar.notify_all();
+			});
+		if let Err(e) = spawned {
+			warn!(target: "db", "Failed to spawn the state archiving thread: {:?}", e);
+			*self.in_progress.0.lock() -= 1;
+		}
+	}
+}
+
+fn run<Block: BlockT<Hash=H256>>(storage: &Arc<StorageDb<Block>>, register_lock: &Mutex<()>, job: &Job<Block::Hash>) {
+	let result = copy_state(storage, job.state_root).and_then(|nodes| {
+		let _lock = register_lock.lock();
+		let mut transaction = DBTransaction::new();
+		apply_state_commit(&mut transaction, storage.state_db.note_archived(&job.hash, job.number));
+		transaction.delete(columns::META, &archiving_key(&job.hash));
+		storage.db.write(transaction).map_err(|e| format!("Database backend error: {:?}", e))?;
+		Ok(nodes)
+	});
+	match result {
+		Ok(nodes) => info!(target: "db", "Archived state of #{} ({:?}): {} nodes", job.number, job.hash, nodes),
+		Err(e) => warn!(target: "db", "Failed to archive state of #{} ({:?}): {}", job.number, job.hash, e),
+	}
+}
+
+fn archiving_key<H: AsRef<[u8]>>(hash: &H) -> Vec<u8> {
+	let mut key = meta_keys::ARCHIVING_PREFIX.to_vec();
+	key.extend_from_slice(hash.as_ref());
+	key
+}
+
+/// Copies all the nodes of the state with the given root, including child tries. Returns the
+/// number of copied nodes.
+fn copy_state<Block: BlockT<Hash=H256>>(storage: &Arc<StorageDb<Block>>, state_root: H256) -> Result<usize, String> {
+	let copying = Arc::new(CopyingStorage {
+		storage: storage.clone(),
+		batch: Mutex::new((DBTransaction::new(), 0)),
+		copied: Mutex::new(0),
+		error: Mutex::new(None),
+	});
+	let state = DbState::new(copying.clone() as Arc<dyn state_machine::Storage<Blake2Hasher>>, state_root);
+
+	let mut child_storage_keys = Vec::new();
+	state.for_keys_with_prefix(&[], |key| if key.starts_with(well_known_keys::CHILD_STORAGE_KEY_PREFIX) {
+		child_storage_keys.push(key.to_vec());
+	});
+	for storage_key in child_storage_keys {
+		state.for_keys_in_child_storage(&storage_key, |_| {});
+	}
+
+	// the walks ignore the errors of the storage.
+	if let Some(e) = copying.error.lock().take() {
+		return Err(e);
+	}
+	copying.flush().map_err(|e| format!("Database backend error: {:?}", e))?;
+	let copied = *copying.copied.lock();
+	Ok(copied)
+}
+
+/// Storage copying the nodes it returns.
+struct CopyingStorage<Block: BlockT> {
+	storage: Arc<StorageDb<Block>>,
+	/// Copied nodes waiting to be written, and their count.
+	batch: Mutex<(DBTransaction, usize)>,
+	copied: Mutex<usize>,
+	/// First error met.
+	error: Mutex<Option<String>>,
+}
+
+impl<Block: BlockT<Hash=H256>> CopyingStorage<Block> {
+	fn flush(&self) -> io::Result<()> {
+		let transaction = std::mem::replace(&mut *self.batch.lock(), (DBTransaction::new(), 0)).0;
+		self.storage.db.write(transaction)
+	}
+
+	fn note_error(&self, error: String) {
+		let mut first = self.error.lock();
+		if first.is_none() {
+			*first = Some(error);
+		}
+	}
+}
+
+impl<Block: BlockT<Hash=H256>> state_machine::Storage<Blake2Hasher> for CopyingStorage<Block> {
+	fn get(&self, key: &H256, prefix: Prefix) -> Result<Option<DBValue>, String> {
+		let value = <StorageDb<Block> as state_machine::Storage<Blake2Hasher>>::get(&self.storage, key, prefix);
+		match value {
+			Ok(Some(ref node)) => {
+				let full = {
+					let mut batch = self.batch.lock();
+					batch.0.put(columns::STATE_META, &state_db::archived_node_key(key.as_ref()), node);
+					batch.1 += 1;
+					batch.1 >= COPY_BATCH_SIZE
+				};
+				*self.copied.lock() += 1;
+				if full {
+					if let Err(e) = self.flush() {
+						self.note_error(format!("Database backend error: {:?}", e));
+					}
+				}
+			},
+			Ok(None) => self.note_error(format!("Missing trie node {:?}", key)),
+			Err(ref e) => self.note_error(e.clone()),
+		}
+		value
+	}
+}
+
+/// Storage of the archived states, reading the copied nodes only.
+pub(crate) struct ArchivedStorage<Block: BlockT>(pub(crate) Arc<StorageDb<Block>>);
+
+impl<Block: BlockT> state_machine::Storage<Blake2Hasher> for ArchivedStorage<Block> {
+	fn get(&self, key: &H256, _prefix: Prefix) -> Result<Option<DBValue>, String> {
+		if let Some(node) = self.0.node_cache.get(key) {
+			return Ok(Some(DBValue::from_slice(&node)));
+		}
+		let node = self.0.db.get(columns::STATE_META, &state_db::archived_node_key(key.as_ref()))
+			.map_err(|e| format!("Database backend error: {:?}", e))?
+			.map(|node| DBValue::from_slice(&node));
+		if let Some(ref node) = node {
+			self.0.node_cache.insert(*key, node);
+		}
+		Ok(node)
+	}
+}
@@ -0,0 +1,242 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Integrity check of the database of a full node.
+//!
+//! The check walks the canonical chain, the leaves and the non-canonical journal of the state
+//! database, and verifies that the state of every block which has not been pruned is present.
+//! Inconsistencies usually come from a crash in the middle of a commit, and are repaired by
+//! reverting the chain to the last block below them.
+
+use std::fmt;
+use hash_db::EMPTY_PREFIX;
+use primitives::{H256, Blake2Hasher};
+use sp_blockchain::{Result as ClientResult, HeaderBackend};
+use sp_runtime::generic::BlockId;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, NumberFor, Zero, One, SaturatedConversion};
+use client::blockchain::Backend as _;
+use client_api::backend::Backend as _;
+use log::info;
+use crate::{Backend, DbGenesisStorage, StorageDb};
+
+/// Inconsistency found in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency<N, H> {
+	/// The header of the block that the metadata refers to as `what` is missing.
+	MissingMetaBlock {
+		/// Which block of the metadata.
+		what: &'static str,
+		/// Hash of the block.
+		hash: H,
+	},
+	/// There is no canonical block at this number, or it isn't the child of the previous one.
+	InvalidCanonicalBlock(N),
+	/// The header of a leaf is missing.
+	MissingLeaf(H),
+	/// The state of a block which hasn't been pruned is missing.
+	MissingState(N, H),
+	/// A block of the non-canonical journal of the state database is unknown, or its parent
+	/// doesn't match the one of its header.
+	InvalidJournalRecord(N, H),
+}
+
+impl<N: fmt::Display, H: fmt::Debug> fmt::Display for Inconsistency<N, H> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Inconsistency::MissingMetaBlock { what, hash } =>
+				write!(f, "Header of the {} block {:?} is missing", what, hash),
+			Inconsistency::InvalidCanonicalBlock(number) =>
+				write!(f, "Canonical block #{} is missing or not the child of the previous one", number),
+			Inconsistency::MissingLeaf(hash) =>
+				write!(f, "Header of the leaf {:?} is missing", hash),
+			Inconsistency::MissingState(number, hash) =>
+				write!(f, "State of block #{} ({:?}) is missing", number, hash),
+			Inconsistency::InvalidJournalRecord(number, hash) =>
+				write!(f, "State journal record of block #{} ({:?}) doesn't match the chain", number, hash),
+		}
+	}
+}
+
+/// Result of a database check.
+#[derive(Debug)]
+pub struct CheckReport<N, H> {
+	/// The inconsistencies found.
+	pub inconsistencies: Vec<Inconsistency<N, H>>,
+	/// Highest block of the canonical chain such that the database is consistent up to it.
+	pub last_consistent: N,
+	/// Best block number.
+	pub best_number: N,
+}
+
+impl<Block: BlockT<Hash=H256>> Backend<Block> {
+	/// Checks the integrity of the database.
+	pub fn check_integrity(&self) -> ClientResult<CheckReport<NumberFor<Block>, Block::Hash>> {
+		let info = self.blockchain.info();
+		let mut inconsistencies = Vec::new();
+		let mut last_consistent = info.best_number;
+
+		for (what, hash) in &[("best", info.best_hash), ("finalized", info.finalized_hash)] {
+			if self.blockchain.header(BlockId::Hash(*hash))?.is_none() {
+				inconsistencies.push(Inconsistency::MissingMetaBlock { what: *what, hash: *hash });
+			}
+		}
+
+		// the canonical chain: every block must be the child of the previous one.
+		let mut parent_hash = None;
+		let mut number = Zero::zero();
+		while number <= info.best_number {
+			let header = self.blockchain.header(BlockId::Number(number))?;
+			let valid = match (&header, parent_hash) {
+				(Some(header), Some(parent_hash)) => *header.parent_hash() == parent_hash,
+				(Some(_), None) => true,
+				(None, _) => false,
+			};
+			if !valid {
+				inconsistencies.push(Inconsistency::InvalidCanonicalBlock(number));
+				if number <= last_consistent {
+					last_consistent = number.saturating_sub(One::one());
+				}
+				break;
+			}
+			parent_hash = header.map(|header| header.hash());
+			number += One::one();
+		}
+		info!("Checked the canonical chain up to #{}", number.saturating_sub(One::one()));
+
+		for leaf in self.blockchain.leaves()? {
+			if self.blockchain.header(BlockId::Hash(leaf))?.is_none() {
+				inconsistencies.push(Inconsistency::MissingLeaf(leaf));
+			}
+		}
+
+		// the state of the canonical blocks, down to the first pruned one.
+		let empty_root = DbGenesisStorage::new().0;
+		let mut number = info.best_number;
+		loop {
+			let header = match self.blockchain.header(BlockId::Number(number))? {
+				Some(header) => header,
+				None => break,
+			};
+			let hash = header.hash();
+			if self.storage.state_db.is_pruned(&hash, number.saturated_into::<u64>()) {
+				break;
+			}
+			if *header.state_root() != empty_root && !self.has_state(header.state_root())? {
+				inconsistencies.push(Inconsistency::MissingState(number, hash));
+				if number <= last_consistent {
+					last_consistent = number.saturating_sub(One::one());
+				}
+			}
+			if number.is_zero() {
+				break;
+			}
+			number -= One::one();
+		}
+		info!("Checked the state of the canonical blocks down to #{}", number);
+
+		// the non-canonical journal of the state database.
+		for (number, hash, parent_hash) in self.storage.state_db.noncanonical_blocks() {
+			let number: NumberFor<Block> = number.saturated_into();
+			let valid = match self.blockchain.header(BlockId::Hash(hash))? {
+				Some(header) => *header.number() == number && *header.parent_hash() == parent_hash,
+				None => false,
+			};
+			if !valid {
+				inconsistencies.push(Inconsistency::InvalidJournalRecord(number, hash));
+				if number <= last_consistent {
+					last_consistent = number.saturating_sub(One::one());
+				}
+			}
+		}
+
+		Ok(CheckReport { inconsistencies, last_consistent, best_number: info.best_number })
+	}
+
+	/// Repairs the database by reverting the chain to the last consistent block of the report.
+	///
+	/// Finalized blocks are never reverted. Returns the number of reverted blocks.
+	pub fn repair(&self, report: &CheckReport<NumberFor<Block>, Block::Hash>) -> ClientResult<NumberFor<Block>> {
+		let finalized = self.blockchain.info().finalized_number;
+		if report.last_consistent < finalized {
+			return Err(sp_blockchain::Error::Backend(format!(
+				"Cannot revert below the finalized block #{}, the database is only consistent up to #{}",
+				finalized,
+				report.last_consistent,
+			)));
+		}
+		self.revert(report.best_number - report.last_consistent)
+	}
+
+	fn has_state(&self, root: &H256) -> ClientResult<bool> {
+		<StorageDb<Block> as state_machine::Storage<Blake2Hasher>>::get(&self.storage, root, EMPTY_PREFIX)
+			.map(|node| node.is_some())
+			.map_err(|e| sp_blockchain::Error::Backend(e))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use kvdb::DBTransaction;
+	use primitives::H256;
+	use client_api::backend::{Backend as _, BlockImportOperation as _, NewBlockState};
+	use sp_runtime::testing::{Header, Block as RawBlock, ExtrinsicWrapper};
+	use sp_runtime::traits::{Hash, BlakeTwo256};
+	use sp_runtime::generic::BlockId;
+	use crate::{Backend, columns, utils};
+	use super::Inconsistency;
+
+	type Block = RawBlock<ExtrinsicWrapper<u64>>;
+
+	#[test]
+	fn detects_and_repairs_inconsistencies() {
+		let backend = Backend::<Block>::new_test(2, 10);
+		let mut hashes: Vec<H256> = Vec::new();
+		for i in 0..5u64 {
+			let mut op = backend.begin_operation().unwrap();
+			let parent = if i == 0 { BlockId::Hash(Default::default()) } else { BlockId::Number(i - 1) };
+			backend.begin_state_operation(&mut op, parent).unwrap();
+			let header = Header {
+				number: i,
+				parent_hash: hashes.last().cloned().unwrap_or_default(),
+				// the state of the last block is missing, as if a crash happened in the middle of its commit.
+				state_root: if i == 4 { H256::random() } else { BlakeTwo256::trie_root(Vec::new()) },
+				digest: Default::default(),
+				extrinsics_root: Default::default(),
+			};
+			hashes.push(header.hash());
+			op.set_block_data(header, Some(Vec::new()), None, NewBlockState::Best).unwrap();
+			backend.commit_operation(op).unwrap();
+		}
+
+		let report = backend.check_integrity().unwrap();
+		assert_eq!(report.inconsistencies, vec![Inconsistency::MissingState(4, hashes[4])]);
+		assert_eq!(report.last_consistent, 3);
+
+		assert_eq!(backend.repair(&report).unwrap(), 1);
+		let report = backend.check_integrity().unwrap();
+		assert!(report.inconsistencies.is_empty());
+		assert_eq!(report.best_number, 3);
+
+		// lose the canonical mapping of block #2.
+		let mut transaction = DBTransaction::new();
+		utils::remove_number_to_key_mapping(&mut transaction, columns::KEY_LOOKUP, 2u64).unwrap();
+		backend.storage.db.write(transaction).unwrap();
+
+		let report = backend.check_integrity().unwrap();
+		assert_eq!(report.inconsistencies, vec![Inconsistency::InvalidCanonicalBlock(2)]);
+		assert_eq!(report.last_consistent, 1);
+	}
+}
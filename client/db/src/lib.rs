@@ -29,17 +29,25 @@
 pub mod light;
 pub mod offchain;
 
+mod archive;
 mod children;
+mod node_refs;
 mod cache;
+mod check;
+mod stats;
 mod storage_cache;
 mod utils;
+#[cfg(feature = "sled")]
+mod sled_db;
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::path::PathBuf;
 use std::io;
 use std::collections::{HashMap, HashSet};
 
 use client_api::{execution_extensions::ExecutionExtensions, ForkBlocks};
+use client_api::{UsageInfo, StateCacheUsage, ColumnSize};
 use client_api::backend::NewBlockState;
 use client_api::backend::{StorageCollection, ChildStorageCollection};
 use sp_blockchain::{
@@ -69,15 +77,25 @@ use crate::utils::{Meta, db_err, meta_keys, read_db, read_meta};
 use client::leaves::{LeafSet, FinalizationDisplaced};
 use state_db::StateDb;
 use sp_blockchain::{CachedHeaderMetadata, HeaderMetadata, HeaderMetadataCache};
-use crate::storage_cache::{CachingState, SharedCache, new_shared_cache};
-use log::{trace, debug, warn};
+use crate::storage_cache::{CachingState, SharedCache, NodeCache, new_shared_cache};
+use crate::stats::StatsDb;
+use crate::archive::{StateArchiver, ArchivedStorage};
+use log::{trace, debug, info, warn, error};
 pub use state_db::PruningMode;
+pub use check::{Inconsistency, CheckReport};
+pub use archive::StatePinning;
+pub use light::HeaderPruning;
 
 #[cfg(feature = "test-helpers")]
 use client::in_mem::Backend as InMemoryBackend;
 
 const CANONICALIZATION_DELAY: u64 = 4096;
 const MIN_BLOCKS_TO_KEEP_CHANGES_TRIES_FOR: u32 = 32768;
+/// Maximum number of block bodies pruned on each finalization, so that enabling the block pruning
+/// on a database with a long history doesn't prune all of it in a single transaction.
+const MAX_PRUNED_BODIES_PER_FINALIZATION: u32 = 1024;
+/// Number of transactions buffered in the bulk import mode before they are flushed to disk.
+const BULK_IMPORT_FLUSH_INTERVAL: u32 = 64;
 
 /// Default value for storage cache child ratio.
 const DEFAULT_CHILD_RATIO: (usize, usize) = (1, 10);
@@ -206,12 +224,41 @@ pub struct DatabaseSettings {
 	pub state_cache_size: usize,
 	/// Ratio of cache size dedicated to child tries.
 	pub state_cache_child_ratio: Option<(usize, usize)>,
+	/// Size of the trie node cache, shared by the states of all the blocks.
+	pub trie_cache_size: usize,
 	/// Pruning mode.
 	pub pruning: PruningMode,
+	/// Block body pruning mode.
+	pub block_pruning: BlockPruning,
+	/// Selects the blocks whose state is archived, and thus never pruned.
+	pub state_pinning: StatePinning,
+	/// Header pruning mode of the light client.
+	pub header_pruning: HeaderPruning,
 	/// Where to find the database.
 	pub source: DatabaseSettingsSrc,
 }
 
+/// Block body pruning mode, independent of the state pruning mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockPruning {
+	/// Keep the bodies and justifications of all the blocks.
+	ArchiveAll,
+	/// Keep the bodies of the last `blocks` finalized blocks only. The headers are always kept,
+	/// and the justifications too if `keep_justifications` is set.
+	KeepFinalized {
+		/// Number of finalized blocks whose bodies are kept.
+		blocks: u32,
+		/// Keep the justifications of the blocks whose bodies are pruned.
+		keep_justifications: bool,
+	},
+}
+
+impl Default for BlockPruning {
+	fn default() -> Self {
+		BlockPruning::ArchiveAll
+	}
+}
+
 /// Where to find the database..
 pub enum DatabaseSettingsSrc {
 	/// Load a database from a given path. Recommended for most uses.
@@ -220,12 +267,64 @@ pub enum DatabaseSettingsSrc {
 		path: PathBuf,
 		/// Cache size in bytes. If `None` default is used.
 		cache_size: Option<usize>,
+		/// Kind of the database.
+		kind: DatabaseKind,
 	},
 
 	/// Use a custom already-open database.
 	Custom(Arc<dyn KeyValueDB>),
 }
 
+/// Kind of embedded database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseKind {
+	/// RocksDB.
+	RocksDb,
+	/// Sled, which suits the hash-keyed and append-mostly trie nodes workload.
+	Sled,
+}
+
+impl Default for DatabaseKind {
+	fn default() -> Self {
+		DatabaseKind::RocksDb
+	}
+}
+
+/// Number of entries copied at once by `migrate_database`.
+const MIGRATION_BATCH_SIZE: usize = 10_000;
+
+/// Copies the content of a database into another one, column by column.
+///
+/// The destination database is expected to be empty. `progress` is called after each batch of
+/// copied entries with the column and the number of entries of the column copied so far.
+pub fn migrate_database(
+	from: &DatabaseSettingsSrc,
+	to: &DatabaseSettingsSrc,
+	mut progress: impl FnMut(u32, usize),
+) -> ClientResult<()> {
+	let from = crate::utils::open_kvdb(from)?;
+	let to = crate::utils::open_kvdb(to)?;
+	if to.iter(columns::META).next().is_some() {
+		return Err(sp_blockchain::Error::Backend("The destination database is not empty".into()));
+	}
+
+	for col in 0..crate::utils::NUM_COLUMNS {
+		let mut transaction = DBTransaction::new();
+		let mut copied = 0;
+		for (key, value) in from.iter(Some(col)) {
+			transaction.put_vec(Some(col), &key, value.into_vec());
+			copied += 1;
+			if copied % MIGRATION_BATCH_SIZE == 0 {
+				to.write(std::mem::replace(&mut transaction, DBTransaction::new())).map_err(db_err)?;
+				progress(col, copied);
+			}
+		}
+		to.write(transaction).map_err(db_err)?;
+		progress(col, copied);
+	}
+	to.flush().map_err(db_err)
+}
+
 /// Create an instance of db-backed client.
 pub fn new_client<E, S, Block, RA>(
 	settings: DatabaseSettings,
@@ -270,6 +369,12 @@ pub(crate) mod columns {
 	pub const AUX: Option<u32> = Some(8);
 	/// Offchain workers local storage
 	pub const OFFCHAIN: Option<u32> = Some(9);
+
+	/// Names of the columns, by index.
+	pub const NAMES: &[&str] = &[
+		"meta", "state", "state_meta", "key_lookup", "header", "body", "justification",
+		"changes_trie", "aux", "offchain",
+	];
 }
 
 struct PendingBlock<Block: BlockT> {
@@ -465,6 +570,8 @@ pub struct BlockImportOperation<Block: BlockT, H: Hasher> {
 	finalized_blocks: Vec<(BlockId<Block>, Option<Justification>)>,
 	set_head: Option<BlockId<Block>>,
 	commit_state: bool,
+	/// The state has been reset rather than built on top of the state of the parent block.
+	reset_storage: bool,
 }
 
 impl<Block: BlockT, H: Hasher> BlockImportOperation<Block, H> {
@@ -540,6 +647,7 @@ impl<Block> client_api::backend::BlockImportOperation<Block, Blake2Hasher>
 
 		self.db_updates = transaction;
 		self.commit_state = true;
+		self.reset_storage = true;
 		Ok(root)
 	}
 
@@ -584,13 +692,23 @@ impl<Block> client_api::backend::BlockImportOperation<Block, Blake2Hasher>
 struct StorageDb<Block: BlockT> {
 	pub db: Arc<dyn KeyValueDB>,
 	pub state_db: StateDb<Block::Hash, Vec<u8>>,
+	pub node_cache: NodeCache,
 }
 
 impl<Block: BlockT> state_machine::Storage<Blake2Hasher> for StorageDb<Block> {
 	fn get(&self, key: &H256, prefix: Prefix) -> Result<Option<DBValue>, String> {
-		let key = prefixed_key::<Blake2Hasher>(key, prefix);
-		self.state_db.get(&key, self).map(|r| r.map(|v| DBValue::from_slice(&v)))
-			.map_err(|e| format!("Database backend error: {:?}", e))
+		// nodes are identified by their hash, so the cache is shared by all the prefixes.
+		if let Some(node) = self.node_cache.get(key) {
+			return Ok(Some(DBValue::from_slice(&node)));
+		}
+		let prefixed = prefixed_key::<Blake2Hasher>(key, prefix);
+		let node = self.state_db.get(&prefixed, self)
+			.map_err(|e| format!("Database backend error: {:?}", e))?
+			.map(|node| DBValue::from_slice(&node));
+		if let Some(ref node) = node {
+			self.node_cache.insert(*key, node);
+		}
+		Ok(node)
 	}
 }
 
@@ -793,6 +911,21 @@ pub struct Backend<Block: BlockT> {
 	shared_cache: SharedCache<Block, Blake2Hasher>,
 	import_lock: RwLock<()>,
 	is_archive: bool,
+	block_pruning: BlockPruning,
+	/// Number of the last block whose body has been pruned.
+	last_pruned_body: Mutex<Option<NumberFor<Block>>>,
+	/// Number of the last block whose body is pruned by the transaction being committed.
+	pending_pruned_body: Mutex<Option<NumberFor<Block>>>,
+	stats: Arc<StatsDb>,
+	archiver: StateArchiver<Block>,
+	/// Reference counts of the state nodes, unused in archive mode.
+	node_refs: node_refs::NodeRefs,
+	/// Number of transactions buffered since the last flush, `None` outside of the bulk import
+	/// mode.
+	bulk_import: Mutex<Option<u32>>,
+	/// A flush of the bulk import mode has failed. The blocks buffered since the previous flush
+	/// are then only known in memory, so that no further transaction is written.
+	bulk_import_failed: AtomicBool,
 }
 
 impl<Block: BlockT<Hash=H256>> Backend<Block> {
@@ -801,7 +934,10 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 	/// The pruning window is how old a block must be before the state is pruned.
 	pub fn new(config: DatabaseSettings, canonicalization_delay: u64) -> ClientResult<Self> {
 		let db = crate::utils::open_database(&config, columns::META, "full")?;
-		Self::from_kvdb(db as Arc<_>, canonicalization_delay, &config)
+		node_refs::check_database(&*db).map_err(db_err)?;
+		let backend = Self::from_kvdb(db as Arc<_>, canonicalization_delay, &config)?;
+		backend.archiver.resume(&backend.storage);
+		Ok(backend)
 	}
 
 	/// Create new memory-backed client backend for tests.
@@ -811,7 +947,11 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		let db_setting = DatabaseSettings {
 			state_cache_size: 16777216,
 			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
 			pruning: PruningMode::keep_blocks(keep_blocks),
+			block_pruning: BlockPruning::ArchiveAll,
+			state_pinning: StatePinning::None,
+			header_pruning: HeaderPruning::default(),
 			source: DatabaseSettingsSrc::Custom(db),
 		};
 
@@ -823,6 +963,8 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		canonicalization_delay: u64,
 		config: &DatabaseSettings
 	) -> ClientResult<Self> {
+		let stats = Arc::new(StatsDb::new(db, columns::NAMES));
+		let db: Arc<dyn KeyValueDB> = stats.clone();
 		let is_archive_pruning = config.pruning.is_archive();
 		let blockchain = BlockchainDb::new(db.clone())?;
 		let meta = blockchain.meta.clone();
@@ -831,7 +973,12 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		let storage_db = StorageDb {
 			db: db.clone(),
 			state_db,
+			node_cache: NodeCache::new(config.trie_cache_size),
 		};
+		let last_pruned_body = db.get(columns::META, meta_keys::LAST_PRUNED_BODY)
+			.map_err(db_err)?
+			.and_then(|number| Decode::decode(&mut &number[..]).ok());
+		let node_refs = node_refs::NodeRefs::load(&*db).map_err(db_err)?;
 		let offchain_storage = offchain::LocalStorage::new(db.clone());
 		let changes_tries_storage = DbChangesTrieStorage {
 			db,
@@ -854,6 +1001,14 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 			),
 			import_lock: Default::default(),
 			is_archive: is_archive_pruning,
+			block_pruning: config.block_pruning,
+			last_pruned_body: Mutex::new(last_pruned_body),
+			pending_pruned_body: Mutex::new(None),
+			stats,
+			archiver: StateArchiver::new(config.state_pinning),
+			node_refs,
+			bulk_import: Mutex::new(None),
+			bulk_import_failed: AtomicBool::new(false),
 		})
 	}
 
@@ -911,6 +1066,50 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		self.blockchain.db.iter(columns::HEADER).count() as u64
 	}
 
+	/// Archives the state of the given block, so that it is never pruned, e.g. at an era boundary.
+	///
+	/// The state is copied in the background; it is kept from pruning in the meantime.
+	pub fn pin_state(&self, block: BlockId<Block>) -> ClientResult<()> {
+		if self.is_archive {
+			return Ok(())
+		}
+		let header = self.blockchain.expect_header(block)?;
+		let hash = header.hash();
+		let number = (*header.number()).saturated_into::<u64>();
+		let state_root = H256::from_slice(header.state_root().as_ref());
+		self.archiver.archive(&self.storage, hash, number, state_root)
+			.map_err(sp_blockchain::Error::UnknownBlock)
+	}
+
+	/// Enables or disables the bulk import mode, meant for catching up with the chain.
+	///
+	/// In this mode the transactions are buffered in memory and written to disk in a single batch
+	/// every `BULK_IMPORT_FLUSH_INTERVAL` transactions, rather than being written one by one. The
+	/// buffered transactions are visible to the reads, but are lost on a crash. Disabling the mode
+	/// flushes them. If a flush fails, the buffered blocks are already imported in memory, so that
+	/// every later write fails until the backend is reopened from the database.
+	///
+	/// The mode only batches the writes. The write-ahead log of RocksDB is kept, as `kvdb-rocksdb`
+	/// has no option to disable it, but it is never synced to disk one by one, in or out of the
+	/// mode. No compaction is triggered when leaving the mode either, as neither `kvdb-rocksdb` nor
+	/// sled expose a manual compaction: the imported data is compacted in the background.
+	pub fn set_bulk_import(&self, enabled: bool) -> ClientResult<()> {
+		let mut bulk_import = self.bulk_import.lock();
+		match (enabled, bulk_import.is_some()) {
+			(true, false) => {
+				info!(target: "db", "Entering bulk import mode");
+				*bulk_import = Some(0);
+			},
+			(false, true) => {
+				*bulk_import = None;
+				self.flush_bulk_import()?;
+				info!(target: "db", "Leaving bulk import mode");
+			},
+			_ => {},
+		}
+		Ok(())
+	}
+
 	/// Read (from storage or cache) changes trie config.
 	///
 	/// Currently changes tries configuration is set up once (at genesis) and could not
@@ -1044,6 +1243,12 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		Ok((*hash, number, false, true))
 	}
 
+	// blocks imported without state (e.g. during the warp sync) are unknown to the state database.
+	// They are marked until they are finalized, reverted, or their state is imported.
+	fn is_imported_without_state(&self, hash: &Block::Hash) -> ClientResult<bool> {
+		Ok(self.storage.db.get(columns::META, &no_state_key(hash)).map_err(db_err)?.is_some())
+	}
+
 	// performs forced canonicaliziation with a delay after importing a non-finalized block.
 	fn force_delayed_canonicalize(
 		&self,
@@ -1057,7 +1262,9 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		if number_u64 > self.canonicalization_delay {
 			let new_canonical = number_u64 - self.canonicalization_delay;
 
-			if new_canonical <= self.storage.state_db.best_canonical().unwrap_or(0) {
+			// finalized blocks are canonicalized, unless they have no state.
+			let finalized = self.blockchain.meta.read().finalized_number.saturated_into::<u64>();
+			if new_canonical <= std::cmp::max(self.storage.state_db.best_canonical().unwrap_or(0), finalized) {
 				return Ok(())
 			}
 
@@ -1069,6 +1276,16 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 						implies existence of blocks with all numbers before it; qed")
 			};
 
+			if self.is_imported_without_state(&hash)? {
+				return Ok(())
+			}
+
+			if !self.is_archive && self.archiver.selects(new_canonical) {
+				let header = self.blockchain.expect_header(BlockId::Hash(hash))?;
+				let state_root = H256::from_slice(header.state_root().as_ref());
+				self.archiver.schedule(&self.storage, transaction, hash, new_canonical, state_root);
+			}
+
 			trace!(target: "db", "Canonicalize block #{} ({:?})", new_canonical, hash);
 			let commit = self.storage.state_db.canonicalize_block(&hash)
 				.map_err(|e: state_db::Error<io::Error>| sp_blockchain::Error::from(format!("State database error: {:?}", e)))?;
@@ -1078,6 +1295,57 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		Ok(())
 	}
 
+	/// Writes the transaction to the database. The state nodes changes it contains are reference
+	/// counted, unless the nodes are never deleted.
+	///
+	/// In the bulk import mode the transaction is only buffered, and the error of a flush is
+	/// returned for the transaction which triggered it.
+	fn write_transaction(&self, mut transaction: DBTransaction) -> ClientResult<()> {
+		// the lock also serializes the reads of the reference counts and the writes.
+		let mut bulk_import = self.bulk_import.lock();
+		if self.bulk_import_failed.load(Ordering::SeqCst) {
+			return Err(ClientError::Backend(
+				"A bulk import flush has failed, the backend must be reopened".into()
+			));
+		}
+		let refs_update = if self.is_archive {
+			None
+		} else {
+			Some(self.node_refs.resolve(&*self.storage.db, &mut transaction).map_err(db_err)?)
+		};
+		match *bulk_import {
+			// a failed flush prevents any further write, so that the counts may be updated
+			// before the transaction is flushed.
+			Some(ref mut buffered) => {
+				self.storage.db.write_buffered(transaction);
+				if let Some(update) = refs_update {
+					self.node_refs.apply(update);
+				}
+				*buffered += 1;
+				if *buffered >= BULK_IMPORT_FLUSH_INTERVAL {
+					*buffered = 0;
+					self.flush_bulk_import()?;
+				}
+				Ok(())
+			},
+			None => {
+				self.storage.db.write(transaction).map_err(db_err)?;
+				if let Some(update) = refs_update {
+					self.node_refs.apply(update);
+				}
+				Ok(())
+			},
+		}
+	}
+
+	fn flush_bulk_import(&self) -> ClientResult<()> {
+		self.storage.db.flush().map_err(|e| {
+			error!(target: "db", "Failed to flush the blocks of the bulk import: {:?}", e);
+			self.bulk_import_failed.store(true, Ordering::SeqCst);
+			db_err(e)
+		})
+	}
+
 	fn try_commit_operation(&self, mut operation: BlockImportOperation<Block, Blake2Hasher>)
 		-> ClientResult<()>
 	{
@@ -1112,6 +1380,9 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 			// blocks are keyed by number + hash.
 			let lookup_key = utils::number_and_hash_to_lookup_key(number, hash)?;
 
+			// the block is already known when its state is imported after the header.
+			let is_existing = ::client::blockchain::HeaderBackend::header(&self.blockchain, BlockId::Hash(hash))?.is_some();
+
 			let (enacted, retracted) = if pending_block.leaf_state.is_best() {
 				self.set_head_with_transaction(&mut transaction, parent_hash, (number, hash))?
 			} else {
@@ -1146,22 +1417,43 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 
 			let finalized = if operation.commit_state {
 				let mut changeset: state_db::ChangeSet<Vec<u8>> = state_db::ChangeSet::default();
+				// nodes shared by several tries are listed once for each reference.
 				for (key, (val, rc)) in operation.db_updates.drain() {
-					if rc > 0 {
-						changeset.inserted.push((key, val.to_vec()));
-					} else if rc < 0 {
-						changeset.deleted.push(key);
+					for _ in 0..rc {
+						changeset.inserted.push((key.clone(), val.to_vec()));
+					}
+					for _ in rc..0 {
+						changeset.deleted.push(key.clone());
 					}
 				}
 				let number_u64 = number.saturated_into::<u64>();
+				if operation.reset_storage && number_u64 > 0 {
+					// the state of the ancestors is unknown to the state database, so the
+					// imported state starts a new canonical chain.
+					let commit = self.storage.state_db.reset_last_canonicalized(&parent_hash, number_u64 - 1)
+						.map_err(|e: state_db::Error<io::Error>| sp_blockchain::Error::from(format!("State database error: {:?}", e)))?;
+					apply_state_commit(&mut transaction, commit);
+				}
 				let commit = self.storage.state_db.insert_block(&hash, number_u64, &pending_block.header.parent_hash(), changeset)
 					.map_err(|e: state_db::Error<io::Error>| sp_blockchain::Error::from(format!("State database error: {:?}", e)))?;
 				apply_state_commit(&mut transaction, commit);
+				if is_existing {
+					transaction.delete(columns::META, &no_state_key(&hash));
+					// the state of an already finalized block is canonical right away.
+					if number <= self.blockchain.meta.read().finalized_number {
+						let commit = self.storage.state_db.canonicalize_block(&hash)
+							.map_err(|e: state_db::Error<io::Error>| sp_blockchain::Error::from(format!("State database error: {:?}", e)))?;
+						apply_state_commit(&mut transaction, commit);
+					}
+				}
 
 				// Check if need to finalize. Genesis is always finalized instantly.
 				let finalized = number_u64 == 0 || pending_block.leaf_state.is_final();
 				finalized
 			} else {
+				if !is_existing {
+					transaction.put(columns::META, &no_state_key(&hash), &[]);
+				}
 				false
 			};
 
@@ -1188,7 +1480,9 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 
 			debug!(target: "db", "DB Commit {:?} ({}), best = {}", hash, number, is_best);
 
-			let displaced_leaf = {
+			let displaced_leaf = if is_existing {
+				None
+			} else {
 				let mut leaves = self.blockchain.leaves.write();
 				let displaced_leaf = leaves.import(hash, number, parent_hash);
 				leaves.prepare_transaction(&mut transaction, columns::META, meta_keys::LEAF_PREFIX);
@@ -1196,9 +1490,11 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 				displaced_leaf
 			};
 
-			let mut children = children::read_children(&*self.storage.db, columns::META, meta_keys::CHILDREN_PREFIX, parent_hash)?;
-			children.push(hash);
-			children::write_children(&mut transaction, columns::META, meta_keys::CHILDREN_PREFIX, parent_hash, children);
+			if !is_existing {
+				let mut children = children::read_children(&*self.storage.db, columns::META, meta_keys::CHILDREN_PREFIX, parent_hash)?;
+				children.push(hash);
+				children::write_children(&mut transaction, columns::META, meta_keys::CHILDREN_PREFIX, parent_hash, children);
+			}
 
 			meta_updates.push((hash, number, pending_block.leaf_state.is_best(), finalized));
 
@@ -1226,7 +1522,7 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 			None
 		};
 
-		let write_result = self.storage.db.write(transaction).map_err(db_err);
+		let write_result = self.write_transaction(transaction);
 
 		if let Some(changes_trie_cache_update) = operation.changes_trie_cache_update {
 			self.changes_tries_storage.commit_cache(changes_trie_cache_update);
@@ -1290,9 +1586,18 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 			let lookup_key = utils::number_and_hash_to_lookup_key(f_num, f_hash.clone())?;
 			transaction.put(columns::META, meta_keys::FINALIZED_BLOCK, &lookup_key);
 
-			let commit = self.storage.state_db.canonicalize_block(&f_hash)
-				.map_err(|e: state_db::Error<io::Error>| sp_blockchain::Error::from(format!("State database error: {:?}", e)))?;
-			apply_state_commit(transaction, commit);
+			if self.is_imported_without_state(&f_hash)? {
+				transaction.delete(columns::META, &no_state_key(&f_hash));
+			} else {
+				let number = f_num.saturated_into::<u64>();
+				if !self.is_archive && self.archiver.selects(number) {
+					let state_root = H256::from_slice(f_header.state_root().as_ref());
+					self.archiver.schedule(&self.storage, transaction, f_hash, number, state_root);
+				}
+				let commit = self.storage.state_db.canonicalize_block(&f_hash)
+					.map_err(|e: state_db::Error<io::Error>| sp_blockchain::Error::from(format!("State database error: {:?}", e)))?;
+				apply_state_commit(transaction, commit);
+			}
 
 			let changes_trie_config = self.changes_trie_config(parent_hash)?;
 			if let Some(changes_trie_config) = changes_trie_config {
@@ -1301,6 +1606,7 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 		}
 
 		let new_displaced = self.blockchain.leaves.write().finalize_height(f_num);
+		self.prune_blocks(transaction, f_num, &new_displaced)?;
 		match displaced {
 			x @ &mut None => *x = Some(new_displaced),
 			&mut Some(ref mut displaced) => displaced.merge(new_displaced),
@@ -1308,6 +1614,92 @@ impl<Block: BlockT<Hash=H256>> Backend<Block> {
 
 		Ok(())
 	}
+
+	// removes the bodies of the blocks which are out of the block pruning window once a new block is
+	// finalized, along with the bodies of the forks displaced by the finalization.
+	fn prune_blocks(
+		&self,
+		transaction: &mut DBTransaction,
+		f_num: NumberFor<Block>,
+		displaced: &FinalizationDisplaced<Block::Hash, NumberFor<Block>>,
+	) -> ClientResult<()> {
+		let (blocks, keep_justifications) = match self.block_pruning {
+			BlockPruning::ArchiveAll => return Ok(()),
+			BlockPruning::KeepFinalized { blocks, keep_justifications } => (blocks, keep_justifications),
+		};
+
+		// the body of the last finalized block is always kept. The bodies left since the last
+		// pruning are pruned too, e.g. when the pruning has just been enabled, a bounded number of
+		// them on each finalization.
+		let keep: NumberFor<Block> = std::cmp::max(blocks, 1).saturated_into();
+		if f_num >= keep {
+			let mut pending_pruned = self.pending_pruned_body.lock();
+			let last_pruned = pending_pruned.or(*self.last_pruned_body.lock());
+			let mut number = last_pruned.map_or_else(Zero::zero, |n| n + One::one());
+			let max_pruned: NumberFor<Block> = MAX_PRUNED_BODIES_PER_FINALIZATION.saturated_into();
+			let last = std::cmp::min(f_num - keep, number + max_pruned - One::one());
+			if number <= last {
+				while number <= last {
+					if let Some(hash) = ::client::blockchain::HeaderBackend::hash(&self.blockchain, number)? {
+						self.prune_block(transaction, number, hash, keep_justifications)?;
+					}
+					number += One::one();
+				}
+				transaction.put(columns::META, meta_keys::LAST_PRUNED_BODY, &last.encode());
+				// the cached number is only updated once the transaction is committed.
+				*pending_pruned = Some(last);
+			}
+		}
+
+		for leaf in displaced.leaves() {
+			// displaced forks are followed back until they join the canonical chain.
+			let mut hash = leaf.clone();
+			while let Some(header) = ::client::blockchain::HeaderBackend::header(&self.blockchain, BlockId::Hash(hash.clone()))? {
+				let number = *header.number();
+				if ::client::blockchain::HeaderBackend::hash(&self.blockchain, number)? == Some(hash.clone()) {
+					break;
+				}
+				self.prune_block(transaction, number, hash, keep_justifications)?;
+				hash = header.parent_hash().clone();
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Notes that the bodies pruned by the last transaction have been committed.
+	fn apply_pending_pruning(&self) {
+		if let Some(last) = self.pending_pruned_body.lock().take() {
+			*self.last_pruned_body.lock() = Some(last);
+		}
+	}
+
+	/// Forgets the bodies pruned by the last transaction, which failed to be committed.
+	fn discard_pending_pruning(&self) {
+		self.pending_pruned_body.lock().take();
+	}
+
+	fn prune_block(
+		&self,
+		transaction: &mut DBTransaction,
+		number: NumberFor<Block>,
+		hash: Block::Hash,
+		keep_justifications: bool,
+	) -> ClientResult<()> {
+		trace!(target: "db", "Pruning body of block #{} ({:?})", number, hash);
+		let lookup_key = utils::number_and_hash_to_lookup_key(number, hash)?;
+		transaction.delete(columns::BODY, &lookup_key);
+		if !keep_justifications {
+			transaction.delete(columns::JUSTIFICATION, &lookup_key);
+		}
+		Ok(())
+	}
+}
+
+fn no_state_key<H: AsRef<[u8]>>(hash: &H) -> Vec<u8> {
+	let mut key = meta_keys::NO_STATE_PREFIX.to_vec();
+	key.extend_from_slice(hash.as_ref());
+	key
 }
 
 fn apply_state_commit(transaction: &mut DBTransaction, commit: state_db::CommitSet<Vec<u8>>) {
@@ -1340,8 +1732,7 @@ impl<Block> client_api::backend::AuxStore for Backend<Block> where Block: BlockT
 		for k in delete {
 			transaction.delete(columns::AUX, k);
 		}
-		self.storage.db.write(transaction).map_err(db_err)?;
-		Ok(())
+		self.write_transaction(transaction)
 	}
 
 	fn get_aux(&self, key: &[u8]) -> ClientResult<Option<Vec<u8>>> {
@@ -1370,6 +1761,7 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 			finalized_blocks: Vec::new(),
 			set_head: None,
 			commit_state: false,
+			reset_storage: false,
 		})
 	}
 
@@ -1386,13 +1778,20 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 	fn commit_operation(&self, operation: Self::BlockImportOperation)
 		-> ClientResult<()>
 	{
-		match self.try_commit_operation(operation) {
+		let started = std::time::Instant::now();
+		let result = self.try_commit_operation(operation);
+		self.stats.note_commit(started.elapsed());
+		match result {
 			Ok(_) => {
 				self.storage.state_db.apply_pending();
+				self.archiver.start_pending(&self.storage);
+				self.apply_pending_pruning();
 				Ok(())
 			},
 			e @ Err(_) => {
 				self.storage.state_db.revert_pending();
+				self.archiver.discard_pending(&self.storage);
+				self.discard_pending_pruning();
 				e
 			}
 		}
@@ -1414,14 +1813,20 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 				justification,
 				displaced,
 			)?;
-			self.storage.db.write(transaction).map_err(db_err)?;
+			self.write_transaction(transaction)?;
 			self.blockchain.update_meta(hash, number, is_best, is_finalized);
 			Ok(())
 		};
 		match commit(&mut displaced) {
-			Ok(()) => self.storage.state_db.apply_pending(),
+			Ok(()) => {
+				self.storage.state_db.apply_pending();
+				self.archiver.start_pending(&self.storage);
+				self.apply_pending_pruning();
+			},
 			e @ Err(_) => {
 				self.storage.state_db.revert_pending();
+				self.archiver.discard_pending(&self.storage);
+				self.discard_pending_pruning();
 				if let Some(displaced) = displaced {
 					self.blockchain.leaves.write().undo().undo_finalization(displaced);
 				}
@@ -1450,12 +1855,18 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 				return Ok(c.saturated_into::<NumberFor<Block>>())
 			}
 			let mut transaction = DBTransaction::new();
-			match self.storage.state_db.revert_one() {
+			let removed = self.blockchain.header(BlockId::Number(best))?.ok_or_else(
+				|| sp_blockchain::Error::UnknownBlock(
+					format!("Error reverting to {}. Block hash not found.", best)))?;
+			let commit = if self.is_imported_without_state(&removed.hash())? {
+				transaction.delete(columns::META, &no_state_key(&removed.hash()));
+				Some(Default::default())
+			} else {
+				self.storage.state_db.revert_one()
+			};
+			match commit {
 				Some(commit) => {
 					apply_state_commit(&mut transaction, commit);
-					let removed = self.blockchain.header(BlockId::Number(best))?.ok_or_else(
-						|| sp_blockchain::Error::UnknownBlock(
-							format!("Error reverting to {}. Block hash not found.", best)))?;
 
 					best -= One::one();	// prev block
 					let hash = self.blockchain.hash(best)?.ok_or_else(
@@ -1465,7 +1876,7 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 					transaction.put(columns::META, meta_keys::BEST_BLOCK, &key);
 					transaction.delete(columns::KEY_LOOKUP, removed.hash().as_ref());
 					children::remove_children(&mut transaction, columns::META, meta_keys::CHILDREN_PREFIX, hash);
-					self.storage.db.write(transaction).map_err(db_err)?;
+					self.write_transaction(transaction)?;
 					self.blockchain.update_meta(hash, best, true, false);
 					self.blockchain.leaves.write().revert(removed.hash().clone(), removed.number().clone(), removed.parent_hash().clone());
 				}
@@ -1479,9 +1890,26 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 		&self.blockchain
 	}
 
-	fn used_state_cache_size(&self) -> Option<usize> {
-		let used = (*&self.shared_cache).lock().used_storage_cache_size();
-		Some(used)
+	fn usage_info(&self) -> Option<UsageInfo> {
+		let (used_size, (hits, misses)) = {
+			let cache = self.shared_cache.lock();
+			(cache.used_storage_cache_size(), cache.hits_and_misses())
+		};
+		let (trie_hits, trie_misses) = self.storage.node_cache.hits_and_misses();
+		Some(UsageInfo {
+			columns: self.stats.column_usage(),
+			commit_latency: self.stats.commit_latency(),
+			state_cache: StateCacheUsage { used_size, hits, misses },
+			trie_cache: StateCacheUsage {
+				used_size: self.storage.node_cache.used_size(),
+				hits: trie_hits,
+				misses: trie_misses,
+			},
+		})
+	}
+
+	fn column_sizes(&self) -> ClientResult<Option<Vec<ColumnSize>>> {
+		Ok(Some(self.stats.column_sizes()))
 	}
 
 	fn state_at(&self, block: BlockId<Block>) -> ClientResult<Self::State> {
@@ -1507,7 +1935,11 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 				}
 				if let Ok(()) = self.storage.state_db.pin(&hash) {
 					let root = H256::from_slice(hdr.state_root().as_ref());
-					let db_state = DbState::new(self.storage.clone(), root);
+					let db_state = if self.storage.state_db.is_archived(&hash) {
+						DbState::new(Arc::new(ArchivedStorage(self.storage.clone())), root)
+					} else {
+						DbState::new(self.storage.clone(), root)
+					};
 					let state = RefTrackingState::new(db_state, self.storage.clone(), Some(hash.clone()));
 					Ok(CachingState::new(state, self.shared_cache.clone(), Some(hash)))
 				} else {
@@ -1543,6 +1975,10 @@ impl<Block> client_api::backend::Backend<Block, Blake2Hasher> for Backend<Block>
 	fn get_import_lock(&self) -> &RwLock<()> {
 		&self.import_lock
 	}
+
+	fn set_bulk_import(&self, enabled: bool) -> ClientResult<()> {
+		Backend::set_bulk_import(self, enabled)
+	}
 }
 
 impl<Block> client_api::backend::LocalBackend<Block, Blake2Hasher> for Backend<Block>
@@ -1623,6 +2059,81 @@ mod tests {
 		header_hash
 	}
 
+	fn assert_same_content(a: &dyn KeyValueDB, b: &dyn KeyValueDB) {
+		for col in 0..crate::utils::NUM_COLUMNS {
+			let a = a.iter(Some(col)).collect::<Vec<_>>();
+			let b = b.iter(Some(col)).collect::<Vec<_>>();
+			assert_eq!(a, b, "column {} differs", col);
+		}
+	}
+
+	#[test]
+	fn migrate_database_round_trip() {
+		let backend = Backend::<Block>::new_test(2, 0);
+		let mut parent_hash = Default::default();
+		for i in 0..5 {
+			parent_hash = insert_header(&backend, i, parent_hash, vec![(vec![i as u8], vec![i as u8])], Default::default());
+		}
+		let original = backend.storage.db.clone();
+
+		let copy: Arc<dyn KeyValueDB> = Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS));
+		let round_trip: Arc<dyn KeyValueDB> = Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS));
+		migrate_database(
+			&DatabaseSettingsSrc::Custom(original.clone()),
+			&DatabaseSettingsSrc::Custom(copy.clone()),
+			|_, _| {},
+		).unwrap();
+		migrate_database(
+			&DatabaseSettingsSrc::Custom(copy.clone()),
+			&DatabaseSettingsSrc::Custom(round_trip.clone()),
+			|_, _| {},
+		).unwrap();
+		assert_same_content(&*original, &*round_trip);
+
+		// the destination must be empty.
+		assert!(migrate_database(
+			&DatabaseSettingsSrc::Custom(original.clone()),
+			&DatabaseSettingsSrc::Custom(copy),
+			|_, _| {},
+		).is_err());
+
+		// the migrated database is usable.
+		let migrated = Backend::<Block>::new(DatabaseSettings {
+			state_cache_size: 16777216,
+			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
+			pruning: PruningMode::keep_blocks(2),
+			block_pruning: BlockPruning::ArchiveAll,
+			state_pinning: StatePinning::None,
+			header_pruning: HeaderPruning::default(),
+			source: DatabaseSettingsSrc::Custom(round_trip),
+		}, 0).unwrap();
+		assert_eq!(migrated.blockchain().info().best_hash, parent_hash);
+		assert_eq!(migrated.blockchain().info().best_number, 4);
+	}
+
+	#[cfg(feature = "sled")]
+	#[test]
+	fn migrate_database_to_sled_and_back() {
+		let backend = Backend::<Block>::new_test(2, 0);
+		let mut parent_hash = Default::default();
+		for i in 0..5 {
+			parent_hash = insert_header(&backend, i, parent_hash, Vec::new(), Default::default());
+		}
+		let original = backend.storage.db.clone();
+
+		let dir = tempfile::tempdir().unwrap();
+		let sled = DatabaseSettingsSrc::Path {
+			path: dir.path().to_owned(),
+			cache_size: None,
+			kind: DatabaseKind::Sled,
+		};
+		let round_trip: Arc<dyn KeyValueDB> = Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS));
+		migrate_database(&DatabaseSettingsSrc::Custom(original.clone()), &sled, |_, _| {}).unwrap();
+		migrate_database(&sled, &DatabaseSettingsSrc::Custom(round_trip.clone()), |_, _| {}).unwrap();
+		assert_same_content(&*original, &*round_trip);
+	}
+
 	#[test]
 	fn block_hash_inserted_correctly() {
 		let backing = {
@@ -1668,7 +2179,11 @@ mod tests {
 		let backend = Backend::<Block>::new(DatabaseSettings {
 			state_cache_size: 16777216,
 			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
 			pruning: PruningMode::keep_blocks(1),
+			block_pruning: BlockPruning::ArchiveAll,
+			state_pinning: StatePinning::None,
+			header_pruning: HeaderPruning::default(),
 			source: DatabaseSettingsSrc::Custom(backing),
 		}, 0).unwrap();
 		assert_eq!(backend.blockchain().info().best_number, 9);
@@ -1677,6 +2192,177 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn prunes_block_bodies() {
+		let prune_with = |keep_justifications| {
+			let backend = Backend::<Block>::new(DatabaseSettings {
+				state_cache_size: 16777216,
+				state_cache_child_ratio: Some((50, 100)),
+				trie_cache_size: 16777216,
+				pruning: PruningMode::keep_blocks(1),
+				block_pruning: BlockPruning::KeepFinalized { blocks: 3, keep_justifications },
+				state_pinning: StatePinning::None,
+				header_pruning: HeaderPruning::default(),
+				source: DatabaseSettingsSrc::Custom(Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS))),
+			}, 0).unwrap();
+
+			let mut parent_hash = Default::default();
+			for i in 0..10 {
+				let id = if i == 0 {
+					BlockId::Hash(Default::default())
+				} else {
+					BlockId::Number(i - 1)
+				};
+				let mut op = backend.begin_operation().unwrap();
+				backend.begin_state_operation(&mut op, id).unwrap();
+				let header = Header {
+					number: i,
+					parent_hash,
+					state_root: Default::default(),
+					digest: Default::default(),
+					extrinsics_root: Default::default(),
+				};
+				parent_hash = header.hash();
+				op.set_block_data(
+					header,
+					Some(vec![i.into()]),
+					Some(vec![i as u8]),
+					NewBlockState::Final,
+				).unwrap();
+				backend.commit_operation(op).unwrap();
+			}
+			backend
+		};
+
+		let backend = prune_with(false);
+		let blockchain = backend.blockchain();
+		for i in 0..7 {
+			assert_eq!(blockchain.body(BlockId::Number(i)).unwrap(), None);
+			assert_eq!(blockchain.justification(BlockId::Number(i)).unwrap(), None);
+			assert!(blockchain.header(BlockId::Number(i)).unwrap().is_some());
+		}
+		for i in 7..10 {
+			assert_eq!(blockchain.body(BlockId::Number(i)).unwrap(), Some(vec![i.into()]));
+			assert_eq!(blockchain.justification(BlockId::Number(i)).unwrap(), Some(vec![i as u8]));
+		}
+
+		let backend = prune_with(true);
+		let blockchain = backend.blockchain();
+		assert_eq!(blockchain.body(BlockId::Number(0)).unwrap(), None);
+		assert_eq!(blockchain.justification(BlockId::Number(0)).unwrap(), Some(vec![0]));
+	}
+
+	#[test]
+	fn prunes_block_bodies_kept_before_pruning_was_enabled() {
+		let db = Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS));
+		let open = |block_pruning| Backend::<Block>::new(DatabaseSettings {
+			state_cache_size: 16777216,
+			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
+			pruning: PruningMode::keep_blocks(1),
+			block_pruning,
+			state_pinning: StatePinning::None,
+			header_pruning: HeaderPruning::default(),
+			source: DatabaseSettingsSrc::Custom(db.clone()),
+		}, 0).unwrap();
+		let insert_block = |backend: &Backend<Block>, number: u64, parent_hash| {
+			let id = if number == 0 {
+				BlockId::Hash(Default::default())
+			} else {
+				BlockId::Number(number - 1)
+			};
+			let mut op = backend.begin_operation().unwrap();
+			backend.begin_state_operation(&mut op, id).unwrap();
+			let header = Header {
+				number,
+				parent_hash,
+				state_root: Default::default(),
+				digest: Default::default(),
+				extrinsics_root: Default::default(),
+			};
+			let hash = header.hash();
+			op.set_block_data(header, Some(vec![number.into()]), None, NewBlockState::Final).unwrap();
+			backend.commit_operation(op).unwrap();
+			hash
+		};
+
+		let mut parent_hash = Default::default();
+		{
+			let backend = open(BlockPruning::ArchiveAll);
+			for i in 0..10 {
+				parent_hash = insert_block(&backend, i, parent_hash);
+			}
+		}
+
+		let backend = open(BlockPruning::KeepFinalized { blocks: 3, keep_justifications: false });
+		insert_block(&backend, 10, parent_hash);
+		let blockchain = backend.blockchain();
+		for i in 0..8 {
+			assert_eq!(blockchain.body(BlockId::Number(i)).unwrap(), None);
+		}
+		for i in 8..11 {
+			assert_eq!(blockchain.body(BlockId::Number(i)).unwrap(), Some(vec![i.into()]));
+		}
+	}
+
+	#[test]
+	fn prunes_a_bounded_number_of_bodies_per_finalization() {
+		let db = Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS));
+		let open = |block_pruning| Backend::<Block>::new(DatabaseSettings {
+			state_cache_size: 16777216,
+			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
+			pruning: PruningMode::keep_blocks(1),
+			block_pruning,
+			state_pinning: StatePinning::None,
+			header_pruning: HeaderPruning::default(),
+			source: DatabaseSettingsSrc::Custom(db.clone()),
+		}, 0).unwrap();
+		let insert_block = |backend: &Backend<Block>, number: u64, parent_hash| {
+			let id = if number == 0 {
+				BlockId::Hash(Default::default())
+			} else {
+				BlockId::Number(number - 1)
+			};
+			let mut op = backend.begin_operation().unwrap();
+			backend.begin_state_operation(&mut op, id).unwrap();
+			let header = Header {
+				number,
+				parent_hash,
+				state_root: Default::default(),
+				digest: Default::default(),
+				extrinsics_root: Default::default(),
+			};
+			let hash = header.hash();
+			op.set_block_data(header, Some(vec![number.into()]), None, NewBlockState::Final).unwrap();
+			backend.commit_operation(op).unwrap();
+			hash
+		};
+
+		let max = MAX_PRUNED_BODIES_PER_FINALIZATION as u64;
+		let mut parent_hash = Default::default();
+		{
+			let backend = open(BlockPruning::ArchiveAll);
+			for i in 0..max + 10 {
+				parent_hash = insert_block(&backend, i, parent_hash);
+			}
+		}
+
+		let backend = open(BlockPruning::KeepFinalized { blocks: 3, keep_justifications: false });
+		parent_hash = insert_block(&backend, max + 10, parent_hash);
+		let blockchain = backend.blockchain();
+		assert_eq!(blockchain.body(BlockId::Number(max - 1)).unwrap(), None);
+		assert_eq!(blockchain.body(BlockId::Number(max)).unwrap(), Some(vec![max.into()]));
+
+		// the bodies left are pruned on the next finalization.
+		insert_block(&backend, max + 11, parent_hash);
+		let blockchain = backend.blockchain();
+		for i in max..max + 9 {
+			assert_eq!(blockchain.body(BlockId::Number(i)).unwrap(), None);
+		}
+		assert_eq!(blockchain.body(BlockId::Number(max + 9)).unwrap(), Some(vec![(max + 9).into()]));
+	}
+
 	#[test]
 	fn set_state_data() {
 		let db = Backend::<Block>::new_test(2, 0);
@@ -1759,6 +2445,294 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn archives_pinned_states() {
+		let backend = Backend::<Block>::new(DatabaseSettings {
+			state_cache_size: 16777216,
+			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
+			pruning: PruningMode::keep_blocks(1),
+			block_pruning: BlockPruning::ArchiveAll,
+			state_pinning: StatePinning::EveryNth(2),
+			header_pruning: HeaderPruning::default(),
+			source: DatabaseSettingsSrc::Custom(Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS))),
+		}, 0).unwrap();
+
+		let mut parent_hash = Default::default();
+		for i in 0..7u64 {
+			let id = if i == 0 {
+				BlockId::Hash(Default::default())
+			} else {
+				BlockId::Number(i - 1)
+			};
+			let mut op = backend.begin_operation().unwrap();
+			backend.begin_state_operation(&mut op, id).unwrap();
+			let mut header = Header {
+				number: i,
+				parent_hash,
+				state_root: Default::default(),
+				digest: Default::default(),
+				extrinsics_root: Default::default(),
+			};
+			let storage = vec![(vec![1], Some(vec![i as u8]))];
+			let (root, overlay) = op.old_state.storage_root(storage.iter().cloned());
+			if i == 0 {
+				op.reset_storage(vec![(vec![1], vec![0])].into_iter().collect(), Default::default()).unwrap();
+			} else {
+				op.update_db_storage(overlay).unwrap();
+			}
+			header.state_root = root.into();
+			parent_hash = header.hash();
+			op.set_block_data(header, Some(vec![]), None, NewBlockState::Final).unwrap();
+			backend.commit_operation(op).unwrap();
+			// pruning stops at the blocks being archived.
+			backend.archiver.wait();
+		}
+
+		for i in 0..7u64 {
+			let state = backend.state_at(BlockId::Number(i));
+			if i % 2 == 0 || i == 6 {
+				assert_eq!(state.unwrap().storage(&[1]).unwrap(), Some(vec![i as u8]));
+			} else if i < 5 {
+				assert!(state.is_err());
+			}
+		}
+		assert_eq!(
+			backend.storage.state_db.archived_blocks().into_iter().map(|(_, n)| n).collect::<Vec<_>>(),
+			vec![0, 2, 4, 6],
+		);
+	}
+
+	#[test]
+	fn resumes_interrupted_state_archiving() {
+		let db = Arc::new(kvdb_memorydb::create(crate::utils::NUM_COLUMNS));
+		let settings = || DatabaseSettings {
+			state_cache_size: 16777216,
+			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
+			pruning: PruningMode::keep_blocks(1),
+			block_pruning: BlockPruning::ArchiveAll,
+			state_pinning: StatePinning::None,
+			header_pruning: HeaderPruning::default(),
+			source: DatabaseSettingsSrc::Custom(db.clone()),
+		};
+
+		let mut parent_hash = Default::default();
+		let mut headers = Vec::new();
+		{
+			let backend = Backend::<Block>::new(settings(), 2).unwrap();
+			for i in 0..3u64 {
+				let id = if i == 0 {
+					BlockId::Hash(Default::default())
+				} else {
+					BlockId::Number(i - 1)
+				};
+				let mut op = backend.begin_operation().unwrap();
+				backend.begin_state_operation(&mut op, id).unwrap();
+				let mut header = Header {
+					number: i,
+					parent_hash,
+					state_root: Default::default(),
+					digest: Default::default(),
+					extrinsics_root: Default::default(),
+				};
+				let storage = vec![(vec![1], Some(vec![i as u8]))];
+				let (root, overlay) = op.old_state.storage_root(storage.iter().cloned());
+				if i == 0 {
+					op.reset_storage(vec![(vec![1], vec![0])].into_iter().collect(), Default::default()).unwrap();
+				} else {
+					op.update_db_storage(overlay).unwrap();
+				}
+				header.state_root = root.into();
+				parent_hash = header.hash();
+				op.set_block_data(header.clone(), Some(vec![]), None, NewBlockState::Normal).unwrap();
+				backend.commit_operation(op).unwrap();
+				headers.push(header);
+			}
+		}
+
+		// the node stopped right after marking block #1, which is not canonicalized yet.
+		let mut transaction = DBTransaction::new();
+		transaction.put(
+			columns::META,
+			&[&meta_keys::ARCHIVING_PREFIX[..], headers[1].hash().as_ref()].concat(),
+			&(1u64, headers[1].state_root).encode(),
+		);
+		db.write(transaction).unwrap();
+
+		let backend = Backend::<Block>::new(settings(), 2).unwrap();
+		backend.archiver.wait();
+		assert_eq!(
+			backend.storage.state_db.archived_blocks(),
+			vec![(headers[1].hash(), 1)],
+		);
+		assert!(db.iter_from_prefix(columns::META, meta_keys::ARCHIVING_PREFIX)
+			.all(|(key, _)| !key.starts_with(meta_keys::ARCHIVING_PREFIX)));
+
+		// the state is kept once the block is pruned.
+		for i in 0..3u64 {
+			backend.finalize_block(BlockId::Number(i), None).unwrap();
+		}
+		assert!(backend.state_at(BlockId::Number(0)).is_err());
+		assert_eq!(backend.state_at(BlockId::Number(1)).unwrap().storage(&[1]).unwrap(), Some(vec![1]));
+	}
+
+	#[test]
+	fn prunes_shared_child_trie_nodes_with_last_reference() {
+		let backend = Backend::<Block>::new_test(1, 0);
+		let child_a = b":child_storage:default:a".to_vec();
+		let child_b = b":child_storage:default:b".to_vec();
+
+		// imports a finalized block, killing the given child trie.
+		let import = |number: u64, killed: Option<&Vec<u8>>| {
+			let mut op = backend.begin_operation().unwrap();
+			let (parent, parent_hash) = if number == 0 {
+				(BlockId::Hash(Default::default()), Default::default())
+			} else {
+				(BlockId::Number(number - 1), backend.blockchain().hash(number - 1).unwrap().unwrap())
+			};
+			backend.begin_state_operation(&mut op, parent).unwrap();
+			let root = if number == 0 {
+				// the child tries share all their nodes.
+				let mut children = HashMap::new();
+				for child in &[&child_a, &child_b] {
+					children.insert(child.to_vec(), vec![(b"key".to_vec(), b"value".to_vec())].into_iter().collect());
+				}
+				op.reset_storage(Default::default(), children).unwrap()
+			} else {
+				let (root, transaction) = op.old_state.full_storage_root(
+					std::iter::empty(),
+					killed.map(|child| (child.clone(), vec![(b"key".to_vec(), None)])),
+				);
+				op.update_db_storage(transaction).unwrap();
+				root
+			};
+			let header = Header {
+				number,
+				parent_hash,
+				state_root: root,
+				digest: Default::default(),
+				extrinsics_root: Default::default(),
+			};
+			op.set_block_data(header, Some(vec![]), None, NewBlockState::Final).unwrap();
+			backend.commit_operation(op).unwrap();
+		};
+
+		import(0, None);
+		let child_root = {
+			let state = backend.state_at(BlockId::Number(0)).unwrap();
+			H256::decode(&mut &state.storage(&child_b).unwrap().unwrap()[..]).unwrap()
+		};
+		let child_root_key = trie::prefixed_key::<Blake2Hasher>(&child_root, EMPTY_PREFIX);
+
+		import(1, Some(&child_a));
+		import(2, None);
+		assert!(backend.state_at(BlockId::Number(1)).is_err());
+		let state = backend.state_at(BlockId::Number(2)).unwrap();
+		assert_eq!(state.child_storage(&child_a, b"key").unwrap(), None);
+		assert_eq!(state.child_storage(&child_b, b"key").unwrap(), Some(b"value".to_vec()));
+		drop(state);
+
+		import(3, Some(&child_b));
+		import(4, None);
+		assert!(backend.storage.db.get(columns::STATE, &child_root_key).unwrap().is_none());
+	}
+
+	#[test]
+	fn bulk_import_flushes_buffered_commits() {
+		let backend = Backend::<Block>::new_test(1, 0);
+		backend.set_bulk_import(true).unwrap();
+
+		let mut parent_hash = Default::default();
+		for i in 0..70 {
+			parent_hash = insert_header(&backend, i, parent_hash, Vec::new(), Default::default());
+		}
+		assert_eq!(*backend.bulk_import.lock(), Some(70 - BULK_IMPORT_FLUSH_INTERVAL));
+		assert_eq!(backend.blockchain().info().best_number, 69);
+		assert_eq!(backend.blockchain().hash(69).unwrap(), Some(parent_hash));
+
+		backend.set_bulk_import(false).unwrap();
+		assert_eq!(*backend.bulk_import.lock(), None);
+		insert_header(&backend, 70, parent_hash, Vec::new(), Default::default());
+		assert_eq!(*backend.bulk_import.lock(), None);
+	}
+
+	#[test]
+	fn bulk_import_stops_writing_after_failed_flush() {
+		struct FailingFlushDb(kvdb_memorydb::InMemory);
+
+		impl KeyValueDB for FailingFlushDb {
+			fn get(&self, col: Option<u32>, key: &[u8]) -> io::Result<Option<kvdb::DBValue>> {
+				self.0.get(col, key)
+			}
+
+			fn get_by_prefix(&self, col: Option<u32>, prefix: &[u8]) -> Option<Box<[u8]>> {
+				self.0.get_by_prefix(col, prefix)
+			}
+
+			fn write_buffered(&self, transaction: DBTransaction) {
+				self.0.write_buffered(transaction)
+			}
+
+			fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+				self.0.write(transaction)
+			}
+
+			fn flush(&self) -> io::Result<()> {
+				Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+			}
+
+			fn iter<'a>(&'a self, col: Option<u32>) -> Box<dyn Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+				self.0.iter(col)
+			}
+
+			fn iter_from_prefix<'a>(
+				&'a self,
+				col: Option<u32>,
+				prefix: &'a [u8],
+			) -> Box<dyn Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+				self.0.iter_from_prefix(col, prefix)
+			}
+
+			fn restore(&self, new_db: &str) -> io::Result<()> {
+				self.0.restore(new_db)
+			}
+		}
+
+		let backend = Backend::<Block>::new(DatabaseSettings {
+			state_cache_size: 16777216,
+			state_cache_child_ratio: Some((50, 100)),
+			trie_cache_size: 16777216,
+			pruning: PruningMode::keep_blocks(1),
+			block_pruning: BlockPruning::ArchiveAll,
+			state_pinning: StatePinning::None,
+			header_pruning: HeaderPruning::default(),
+			source: DatabaseSettingsSrc::Custom(Arc::new(
+				FailingFlushDb(kvdb_memorydb::create(crate::utils::NUM_COLUMNS))
+			)),
+		}, 0).unwrap();
+		backend.set_bulk_import(true).unwrap();
+
+		let mut parent_hash = Default::default();
+		for i in 0..u64::from(BULK_IMPORT_FLUSH_INTERVAL) - 1 {
+			parent_hash = insert_header(&backend, i, parent_hash, Vec::new(), Default::default());
+		}
+		let mut op = backend.begin_operation().unwrap();
+		backend.begin_state_operation(&mut op, BlockId::Hash(parent_hash)).unwrap();
+		let header = Header {
+			number: u64::from(BULK_IMPORT_FLUSH_INTERVAL) - 1,
+			parent_hash,
+			state_root: Default::default(),
+			digest: Default::default(),
+			extrinsics_root: Default::default(),
+		};
+		op.set_block_data(header, Some(vec![]), None, NewBlockState::Best).unwrap();
+		assert!(backend.commit_operation(op).is_err());
+
+		assert!(backend.insert_aux(&[(&b"test"[..], &b"hello"[..])], &[]).is_err());
+		assert!(backend.set_bulk_import(false).is_err());
+	}
+
 	#[test]
 	fn delete_only_when_negative_rc() {
 		let _ = ::env_logger::try_init();
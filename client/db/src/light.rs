@@ -56,6 +56,25 @@ pub(crate) mod columns {
 const HEADER_CHT_PREFIX: u8 = 0;
 /// Prefix for changes tries roots CHT.
 const CHANGES_TRIE_CHT_PREFIX: u8 = 1;
+/// Prefix of the keys marking the headers which change the authority set.
+const AUTHORITIES_CHANGE_PREFIX: &[u8] = b"auth_change";
+
+/// Header pruning mode of the light client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderPruning {
+	/// Keep all the headers.
+	ArchiveAll,
+	/// Delete the finalized headers once the CHT covering them is built, except for the headers
+	/// at which the authority set changes. Deleted headers are fetched from the network when
+	/// needed, and checked against the CHT root.
+	ReplaceWithCht,
+}
+
+impl Default for HeaderPruning {
+	fn default() -> Self {
+		HeaderPruning::ReplaceWithCht
+	}
+}
 
 /// Light blockchain storage. Stores most recent headers + CHTs for older headers.
 /// Locks order: meta, cache.
@@ -64,6 +83,7 @@ pub struct LightStorage<Block: BlockT> {
 	meta: RwLock<Meta<NumberFor<Block>, Block::Hash>>,
 	cache: Arc<DbCacheSync<Block>>,
 	header_metadata_cache: HeaderMetadataCache<Block>,
+	header_pruning: HeaderPruning,
 }
 
 impl<Block> LightStorage<Block>
@@ -73,7 +93,7 @@ impl<Block> LightStorage<Block>
 	/// Create new storage with given settings.
 	pub fn new(config: DatabaseSettings) -> ClientResult<Self> {
 		let db = crate::utils::open_database(&config, columns::META, "light")?;
-		Self::from_kvdb(db as Arc<_>)
+		Self::from_kvdb(db as Arc<_>, config.header_pruning)
 	}
 
 	/// Create new memory-backed `LightStorage` for tests.
@@ -83,10 +103,10 @@ impl<Block> LightStorage<Block>
 
 		let db = Arc::new(::kvdb_memorydb::create(NUM_COLUMNS));
 
-		Self::from_kvdb(db as Arc<_>).expect("failed to create test-db")
+		Self::from_kvdb(db as Arc<_>, HeaderPruning::default()).expect("failed to create test-db")
 	}
 
-	fn from_kvdb(db: Arc<dyn KeyValueDB>) -> ClientResult<Self> {
+	fn from_kvdb(db: Arc<dyn KeyValueDB>, header_pruning: HeaderPruning) -> ClientResult<Self> {
 		let meta = read_meta::<Block>(&*db, columns::META, columns::HEADER)?;
 		let cache = DbCache::new(
 			db.clone(),
@@ -102,6 +122,7 @@ impl<Block> LightStorage<Block>
 			meta: RwLock::new(meta),
 			cache: Arc::new(DbCacheSync(RwLock::new(cache))),
 			header_metadata_cache: HeaderMetadataCache::default(),
+			header_pruning,
 		})
 	}
 
@@ -332,6 +353,11 @@ impl<Block: BlockT> LightStorage<Block> {
 				);
 			}
 
+			// all the headers are kept, the CHT roots only serve to check the remote proofs.
+			if self.header_pruning == HeaderPruning::ArchiveAll {
+				return Ok(());
+			}
+
 			// prune headers that are replaced with CHT
 			let mut prune_block = new_cht_start;
 			let new_cht_end = cht::end_number(cht::size(), new_cht_number);
@@ -339,16 +365,31 @@ impl<Block: BlockT> LightStorage<Block> {
 				new_cht_start, new_cht_end, new_cht_number);
 
 			while prune_block <= new_cht_end {
+				// the markers of the authority set changes are only needed until the headers are
+				// pruned, including the markers of the headers of forks.
+				let markers_prefix = authorities_change_key(&utils::number_index_key(prune_block)?);
+				let markers: Vec<_> = self.db.iter_from_prefix(columns::META, &markers_prefix)
+					.take_while(|(key, _)| key.starts_with(&markers_prefix))
+					.map(|(key, _)| key)
+					.collect();
+				for marker in &markers {
+					transaction.delete(columns::META, marker);
+				}
+
 				if let Some(hash) = self.hash(prune_block)? {
 					let lookup_key = block_id_to_lookup_key::<Block>(&*self.db, columns::KEY_LOOKUP, BlockId::Number(prune_block))?
 						.expect("retrieved hash for `prune_block` right above. therefore retrieving lookup key must succeed. q.e.d.");
-					utils::remove_key_mappings(
-						transaction,
-						columns::KEY_LOOKUP,
-						prune_block,
-						hash
-					)?;
-					transaction.delete(columns::HEADER, &lookup_key);
+					let marker = authorities_change_key(&lookup_key);
+					let changes_authorities = markers.iter().any(|key| key[..] == marker[..]);
+					if !changes_authorities {
+						utils::remove_key_mappings(
+							transaction,
+							columns::KEY_LOOKUP,
+							prune_block,
+							hash
+						)?;
+						transaction.delete(columns::HEADER, &lookup_key);
+					}
 				}
 				prune_block += One::one();
 			}
@@ -437,6 +478,13 @@ impl<Block> LightBlockchainStorage<Block> for LightStorage<Block>
 		)?;
 		transaction.put(columns::HEADER, &lookup_key, &header.encode());
 
+		// the headers changing the authority set are not replaced with CHT.
+		if self.header_pruning == HeaderPruning::ReplaceWithCht
+			&& cache_at.contains_key(&well_known_cache_keys::AUTHORITIES)
+		{
+			transaction.put(columns::META, &authorities_change_key(&lookup_key), &[]);
+		}
+
 		let header_metadata = CachedHeaderMetadata::from(&header);
 		self.header_metadata_cache.insert_header_metadata(
 			header.hash().clone(),
@@ -550,6 +598,13 @@ impl<Block> LightBlockchainStorage<Block> for LightStorage<Block>
 	}
 }
 
+/// Build the key marking the header with given lookup key as changing the authority set.
+fn authorities_change_key(lookup_key: &[u8]) -> Vec<u8> {
+	let mut key = AUTHORITIES_CHANGE_PREFIX.to_vec();
+	key.extend_from_slice(lookup_key);
+	key
+}
+
 /// Build the key for inserting header-CHT at given block.
 fn cht_key<N: TryInto<u32>>(cht_type: u8, block: N) -> ClientResult<[u8; 5]> {
 	let mut key = [cht_type; 5];
@@ -755,6 +810,44 @@ pub(crate) mod tests {
 		assert!(db.changes_trie_cht_root(cht_size, cht_size + cht_size / 2).is_err());
 	}
 
+	#[test]
+	fn header_pruning_keeps_requested_headers() {
+		fn insert_headers(header_pruning: HeaderPruning) -> LightStorage<Block> {
+			let db = Arc::new(::kvdb_memorydb::create(utils::NUM_COLUMNS));
+			let db = LightStorage::from_kvdb(db as Arc<_>, header_pruning).unwrap();
+			let mut prev_hash = insert_final_block(&db, HashMap::new(), || default_header(&Default::default(), 0));
+			for number in 1..(2 * cht::size::<u64>() + 2) {
+				// the authority set changes at block #10
+				let mut cache = HashMap::new();
+				if number == 10 {
+					cache.insert(well_known_cache_keys::AUTHORITIES, vec![AuthorityId::from_raw([1u8; 32])].encode());
+				}
+				prev_hash = insert_final_block(&db, cache, || default_header(&prev_hash, number));
+			}
+			db
+		}
+
+		fn markers_count(db: &LightStorage<Block>) -> usize {
+			db.db.iter_from_prefix(columns::META, AUTHORITIES_CHANGE_PREFIX)
+				.take_while(|(key, _)| key.starts_with(AUTHORITIES_CHANGE_PREFIX))
+				.count()
+		}
+
+		let cht_size: u64 = cht::size();
+
+		let db = insert_headers(HeaderPruning::ReplaceWithCht);
+		assert_eq!(db.db.iter(columns::CHT).count(), 1);
+		assert_eq!(db.db.iter(columns::HEADER).count(), (1 + 1 + cht_size + 1) as usize);
+		assert!(db.header(BlockId::Number(9)).unwrap().is_none());
+		assert!(db.header(BlockId::Number(10)).unwrap().is_some());
+		assert_eq!(markers_count(&db), 0);
+
+		let db = insert_headers(HeaderPruning::ArchiveAll);
+		assert_eq!(db.db.iter(columns::CHT).count(), 1);
+		assert_eq!(db.db.iter(columns::HEADER).count(), (2 * cht_size + 2) as usize);
+		assert_eq!(markers_count(&db), 0);
+	}
+
 	#[test]
 	fn get_cht_fails_for_genesis_block() {
 		assert!(LightStorage::<Block>::new_test().header_cht_root(cht::size(), 0).is_err());
@@ -1068,7 +1161,7 @@ pub(crate) mod tests {
 		assert_eq!(db.header(BlockId::Hash(hash0)).unwrap().unwrap().hash(), hash0);
 
 		let db = db.db;
-		let db = LightStorage::from_kvdb(db).unwrap();
+		let db = LightStorage::from_kvdb(db, HeaderPruning::default()).unwrap();
 		assert_eq!(db.info().best_hash, hash0);
 		assert_eq!(db.header(BlockId::Hash::<Block>(hash0)).unwrap().unwrap().hash(), hash0);
 	}
@@ -0,0 +1,258 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reference counting of the state trie nodes.
+//!
+//! The top trie and the child tries share the node keyspace, so the same node may be referenced
+//! by several tries, e.g. by the tries of two contracts with the same storage. Each node insertion
+//! of the state database adds a reference to the node and each deletion removes one, the node
+//! being removed with its last reference.
+//!
+//! The number of references is only stored for the nodes referenced more than once, in the state
+//! meta column. A node without a stored count is referenced once. The stored counts are loaded
+//! when the database is opened and then kept in memory, so that only the nodes inserted without a
+//! count are looked up when committing, to know whether they are already referenced.
+//!
+//! The counts are only complete in the databases created since the references are counted, which
+//! are marked as such. In older databases, a node shared by several tries before the upgrade has
+//! no count, and is still removed with the first of its references: `check_database` warns about
+//! it the first time the database is opened.
+
+use std::collections::HashMap;
+use std::io;
+use codec::{Decode, Encode};
+use kvdb::{KeyValueDB, DBTransaction, DBOp, DBValue};
+use log::{debug, warn};
+use parking_lot::Mutex;
+use crate::columns;
+use crate::utils::meta_keys;
+
+const NODE_REFS: &[u8] = b"node_refs";
+
+/// Marks the databases whose nodes have been counted since their creation.
+const NODE_REFS_COMPLETE: &[u8] = b"node_refs_complete";
+
+/// Marks the databases written before the references were counted, once the user has been
+/// warned about it.
+const NODE_REFS_INCOMPLETE: &[u8] = b"incomplete_node_refs";
+
+fn refs_key(key: &[u8]) -> Vec<u8> {
+	let mut refs_key = NODE_REFS.to_vec();
+	refs_key.extend_from_slice(key);
+	refs_key
+}
+
+fn decode_refs(refs: &[u8]) -> io::Result<u32> {
+	u32::decode(&mut &refs[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.what()))
+}
+
+/// Marks a new database as having complete reference counts, and warns once if an existing
+/// database was written before the references were counted. Returns whether the counts are
+/// complete.
+pub fn check_database(db: &dyn KeyValueDB) -> io::Result<bool> {
+	if db.get(columns::STATE_META, NODE_REFS_COMPLETE)?.is_some() {
+		return Ok(true);
+	}
+	if db.get(columns::META, meta_keys::GENESIS_HASH)?.is_none() {
+		let mut transaction = DBTransaction::new();
+		transaction.put(columns::STATE_META, NODE_REFS_COMPLETE, &[]);
+		db.write(transaction)?;
+		return Ok(true);
+	}
+	if db.get(columns::STATE_META, NODE_REFS_INCOMPLETE)?.is_some() {
+		debug!(target: "db", "The state database has incomplete trie node reference counts");
+		return Ok(false);
+	}
+	warn!(
+		target: "db",
+		"The state database was created before the trie nodes were reference counted: the nodes \
+		shared by several tries may be pruned while still referenced. Resync to fix it."
+	);
+	let mut transaction = DBTransaction::new();
+	transaction.put(columns::STATE_META, NODE_REFS_INCOMPLETE, &[]);
+	db.write(transaction)?;
+	Ok(false)
+}
+
+/// The reference counts of the nodes referenced more than once.
+pub struct NodeRefs {
+	counts: Mutex<HashMap<Vec<u8>, u32>>,
+}
+
+/// The reference counts changed by a transaction, a count of zero or one not being stored.
+pub struct RefsUpdate(Vec<(Vec<u8>, u32)>);
+
+impl NodeRefs {
+	/// Loads the counts stored in the database.
+	pub fn load(db: &dyn KeyValueDB) -> io::Result<Self> {
+		let mut counts = HashMap::new();
+		for (key, refs) in db.iter_from_prefix(columns::STATE_META, NODE_REFS) {
+			if !key.starts_with(NODE_REFS) {
+				break;
+			}
+			if &key[..] == NODE_REFS_COMPLETE {
+				continue;
+			}
+			counts.insert(key[NODE_REFS.len()..].to_vec(), decode_refs(&refs)?);
+		}
+		Ok(NodeRefs { counts: Mutex::new(counts) })
+	}
+
+	/// Turns the insertions and deletions of state nodes in the transaction into reference count
+	/// changes. The returned update must be applied once the transaction is written, and the
+	/// transactions must be resolved and written one at a time.
+	pub fn resolve(&self, db: &dyn KeyValueDB, transaction: &mut DBTransaction) -> io::Result<RefsUpdate> {
+		let mut changes: HashMap<Vec<u8>, (Option<DBValue>, i64)> = HashMap::new();
+		for op in std::mem::replace(&mut transaction.ops, Vec::new()) {
+			match op {
+				DBOp::Insert { col, key, value } if col == columns::STATE => {
+					let change = changes.entry(key.to_vec()).or_insert((None, 0));
+					change.0 = Some(value);
+					change.1 += 1;
+				},
+				DBOp::Delete { col, key } if col == columns::STATE =>
+					changes.entry(key.to_vec()).or_insert((None, 0)).1 -= 1,
+				op => transaction.ops.push(op),
+			}
+		}
+
+		let counts = self.counts.lock();
+		let mut update = Vec::new();
+		for (key, (value, delta)) in changes {
+			if delta == 0 {
+				continue;
+			}
+			let stored = counts.get(&key).cloned();
+			let refs = match stored {
+				Some(refs) => refs as i64,
+				// a node being removed is referenced once at most.
+				None if delta < 0 => 1,
+				None => db.get(columns::STATE, &key)?.map_or(0, |_| 1),
+			};
+			let new_refs = refs + delta;
+			if new_refs <= 0 {
+				transaction.delete(columns::STATE, &key);
+			} else if refs == 0 {
+				if let Some(value) = value {
+					transaction.put(columns::STATE, &key, &value);
+				}
+			}
+			let refs_key = refs_key(&key);
+			if new_refs > 1 {
+				transaction.put(columns::STATE_META, &refs_key, &(new_refs as u32).encode());
+			} else if stored.is_some() {
+				transaction.delete(columns::STATE_META, &refs_key);
+			} else {
+				continue;
+			}
+			update.push((key, new_refs.max(0) as u32));
+		}
+		Ok(RefsUpdate(update))
+	}
+
+	/// Applies the counts changed by a written transaction.
+	pub fn apply(&self, update: RefsUpdate) {
+		let mut counts = self.counts.lock();
+		for (key, refs) in update.0 {
+			if refs > 1 {
+				counts.insert(key, refs);
+			} else {
+				counts.remove(&key);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn write(refs: &NodeRefs, db: &dyn KeyValueDB, inserted: &[&[u8]], deleted: &[&[u8]]) {
+		let mut transaction = DBTransaction::new();
+		for key in inserted {
+			transaction.put(columns::STATE, key, b"node");
+		}
+		for key in deleted {
+			transaction.delete(columns::STATE, key);
+		}
+		let update = refs.resolve(db, &mut transaction).unwrap();
+		db.write(transaction).unwrap();
+		refs.apply(update);
+	}
+
+	#[test]
+	fn removes_node_with_last_reference() {
+		let db = kvdb_memorydb::create(crate::utils::NUM_COLUMNS);
+		let refs = NodeRefs::load(&db).unwrap();
+		write(&refs, &db, &[b"one", b"shared", b"shared"], &[]);
+		write(&refs, &db, &[b"shared"], &[]);
+		assert!(db.get(columns::STATE_META, &refs_key(b"one")).unwrap().is_none());
+		assert_eq!(db.get(columns::STATE_META, &refs_key(b"shared")).unwrap().unwrap(), 3u32.encode());
+
+		write(&refs, &db, &[], &[b"one", b"shared", b"shared"]);
+		assert!(db.get(columns::STATE, b"one").unwrap().is_none());
+		assert!(db.get(columns::STATE, b"shared").unwrap().is_some());
+		assert!(db.get(columns::STATE_META, &refs_key(b"shared")).unwrap().is_none());
+
+		write(&refs, &db, &[], &[b"shared"]);
+		assert!(db.get(columns::STATE, b"shared").unwrap().is_none());
+	}
+
+	#[test]
+	fn loads_stored_counts() {
+		let db = kvdb_memorydb::create(crate::utils::NUM_COLUMNS);
+		assert!(check_database(&db).unwrap());
+		let refs = NodeRefs::load(&db).unwrap();
+		write(&refs, &db, &[b"shared", b"shared", b"shared"], &[]);
+
+		let refs = NodeRefs::load(&db).unwrap();
+		assert_eq!(refs.counts.lock().get(&b"shared"[..]), Some(&3));
+		write(&refs, &db, &[], &[b"shared", b"shared"]);
+		assert!(db.get(columns::STATE, b"shared").unwrap().is_some());
+		write(&refs, &db, &[], &[b"shared"]);
+		assert!(db.get(columns::STATE, b"shared").unwrap().is_none());
+	}
+
+	#[test]
+	fn only_marks_new_databases_as_counted() {
+		let db = kvdb_memorydb::create(crate::utils::NUM_COLUMNS);
+		assert!(check_database(&db).unwrap());
+		assert!(check_database(&db).unwrap());
+
+		let db = kvdb_memorydb::create(crate::utils::NUM_COLUMNS);
+		let mut transaction = DBTransaction::new();
+		transaction.put(columns::META, meta_keys::GENESIS_HASH, b"genesis");
+		db.write(transaction).unwrap();
+		assert!(!check_database(&db).unwrap());
+		assert!(db.get(columns::STATE_META, NODE_REFS_INCOMPLETE).unwrap().is_some());
+		assert!(!check_database(&db).unwrap());
+	}
+
+	#[test]
+	fn node_without_count_is_referenced_once() {
+		let db = kvdb_memorydb::create(crate::utils::NUM_COLUMNS);
+		let mut transaction = DBTransaction::new();
+		transaction.put(columns::STATE, b"node", b"node");
+		db.write(transaction).unwrap();
+		let refs = NodeRefs::load(&db).unwrap();
+
+		write(&refs, &db, &[b"node"], &[]);
+		write(&refs, &db, &[], &[b"node"]);
+		assert!(db.get(columns::STATE, b"node").unwrap().is_some());
+		write(&refs, &db, &[], &[b"node"]);
+		assert!(db.get(columns::STATE, b"node").unwrap().is_none());
+	}
+}
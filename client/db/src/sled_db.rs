@@ -0,0 +1,181 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! `KeyValueDB` implementation backed by sled.
+//!
+//! All the columns are stored in a single sled tree, keys being prefixed with the column, so that
+//! transactions touching several columns are applied atomically. Sled is log-structured, which
+//! suits the hash-keyed, append-mostly writes of trie nodes.
+
+use std::{io, path::Path};
+use kvdb::{KeyValueDB, DBTransaction, DBOp, DBValue};
+use log::warn;
+use parking_lot::Mutex;
+
+/// Interval between the flushes of the write buffers, in milliseconds. Flushing less often than
+/// the sled default (500ms) groups the many small writes of trie nodes made by block imports. The
+/// flushes requested through `KeyValueDB::flush` are not delayed.
+const FLUSH_EVERY_MS: u64 = 2000;
+
+/// Number of operations between the snapshots of the page table. Each trie node is a page, so the
+/// page table is large and the sled default (1M operations) snapshots it every few blocks during
+/// a sync. Recovery replays at most this many operations.
+const SNAPSHOT_AFTER_OPS: u64 = 10_000_000;
+
+/// Proportion of live pages, in percent, below which a segment of the log is rewritten. State
+/// pruning deletes most trie nodes soon after they are written, so the segments are reclaimed
+/// earlier than with the sled default (40%) to bound the space amplification.
+const SEGMENT_CLEANUP_THRESHOLD: u8 = 60;
+
+/// Cache size used when none is configured, in MiB.
+const DEFAULT_CACHE_SIZE: usize = 128;
+
+/// Sled database.
+pub struct SledDb {
+	db: sled::Db,
+	/// First error of a buffered write since the last flush, returned by the next flush.
+	write_error: Mutex<Option<io::Error>>,
+}
+
+fn sled_err(err: sled::Error) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, format!("Sled error: {}", err))
+}
+
+/// Prefix of the keys of a column.
+fn column_prefix(col: Option<u32>) -> u8 {
+	col.map(|col| col as u8 + 1).unwrap_or(0)
+}
+
+fn column_key(col: Option<u32>, key: &[u8]) -> Vec<u8> {
+	let mut column_key = Vec::with_capacity(key.len() + 1);
+	column_key.push(column_prefix(col));
+	column_key.extend_from_slice(key);
+	column_key
+}
+
+impl SledDb {
+	/// Opens the database at the given path, creating it if it doesn't exist. The cache size is in
+	/// MiB, like the memory budget of RocksDB.
+	pub fn open(path: &Path, cache_size: Option<usize>) -> io::Result<Self> {
+		// trie nodes are hashes, which don't compress: compression stays disabled.
+		let config = sled::Config::default()
+			.path(path)
+			.cache_capacity(cache_size.unwrap_or(DEFAULT_CACHE_SIZE) as u64 * 1024 * 1024)
+			.flush_every_ms(Some(FLUSH_EVERY_MS))
+			.snapshot_after_ops(SNAPSHOT_AFTER_OPS)
+			.segment_cleanup_threshold(SEGMENT_CLEANUP_THRESHOLD)
+			.use_compression(false);
+		Ok(SledDb {
+			db: config.open().map_err(sled_err)?,
+			write_error: Mutex::new(None),
+		})
+	}
+
+	fn prefix_iter<'a>(
+		&'a self,
+		col: Option<u32>,
+		prefix: &[u8],
+	) -> impl Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a {
+		self.db.scan_prefix(column_key(col, prefix)).filter_map(|item| match item {
+			Ok((key, value)) => Some((key[1..].to_vec().into_boxed_slice(), value.to_vec().into_boxed_slice())),
+			Err(err) => {
+				warn!(target: "db", "Error iterating over the database: {}", err);
+				None
+			},
+		})
+	}
+}
+
+impl KeyValueDB for SledDb {
+	fn get(&self, col: Option<u32>, key: &[u8]) -> io::Result<Option<DBValue>> {
+		self.db.get(column_key(col, key))
+			.map(|value| value.map(|value| DBValue::from_slice(&value)))
+			.map_err(sled_err)
+	}
+
+	fn get_by_prefix(&self, col: Option<u32>, prefix: &[u8]) -> Option<Box<[u8]>> {
+		self.prefix_iter(col, prefix).next().map(|(_, value)| value)
+	}
+
+	fn write_buffered(&self, transaction: DBTransaction) {
+		if let Err(err) = self.write(transaction) {
+			warn!(target: "db", "Failed to write to the database: {}", err);
+			self.write_error.lock().get_or_insert(err);
+		}
+	}
+
+	fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+		let mut batch = sled::Batch::default();
+		for op in transaction.ops {
+			match op {
+				DBOp::Insert { col, key, value } => batch.insert(column_key(col, &key), value.to_vec()),
+				DBOp::Delete { col, key } => batch.remove(column_key(col, &key)),
+			}
+		}
+		self.db.apply_batch(batch).map_err(sled_err)
+	}
+
+	fn flush(&self) -> io::Result<()> {
+		if let Some(err) = self.write_error.lock().take() {
+			return Err(err);
+		}
+		self.db.flush().map(|_| ()).map_err(sled_err)
+	}
+
+	fn iter<'a>(&'a self, col: Option<u32>) -> Box<dyn Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(self.prefix_iter(col, &[]))
+	}
+
+	fn iter_from_prefix<'a>(
+		&'a self,
+		col: Option<u32>,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(self.prefix_iter(col, prefix))
+	}
+
+	fn restore(&self, _new_db: &str) -> io::Result<()> {
+		Err(io::Error::new(io::ErrorKind::Other, "Restoring is not supported by the sled database"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn columns_are_separate() {
+		let dir = tempfile::tempdir().unwrap();
+		let db = SledDb::open(dir.path(), None).unwrap();
+
+		let mut transaction = DBTransaction::new();
+		transaction.put(Some(1), b"key", b"one");
+		transaction.put(Some(2), b"key", b"two");
+		transaction.put(Some(2), b"other", b"three");
+		db.write(transaction).unwrap();
+
+		assert_eq!(db.get(Some(1), b"key").unwrap().unwrap().to_vec(), b"one".to_vec());
+		assert_eq!(db.get(Some(2), b"key").unwrap().unwrap().to_vec(), b"two".to_vec());
+		assert_eq!(db.iter(Some(2)).count(), 2);
+		assert_eq!(db.get_by_prefix(Some(2), b"oth").unwrap().to_vec(), b"three".to_vec());
+
+		let mut transaction = DBTransaction::new();
+		transaction.delete(Some(1), b"key");
+		db.write(transaction).unwrap();
+		assert!(db.get(Some(1), b"key").unwrap().is_none());
+		assert_eq!(db.iter(Some(2)).count(), 2);
+	}
+}
@@ -0,0 +1,275 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Usage statistics of the database.
+//!
+//! `StatsDb` wraps a `KeyValueDB` and counts the reads and writes of each column. The rates are
+//! computed over measurement periods of `PERIOD`, a period being completed when the statistics
+//! are read after it has elapsed.
+
+use std::{io, sync::{Arc, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
+use kvdb::{KeyValueDB, DBTransaction, DBOp, DBValue};
+use parking_lot::Mutex;
+use client_api::{IoStats, ColumnUsage, ColumnSize, CommitLatency};
+
+/// Minimal duration of a measurement period.
+const PERIOD: Duration = Duration::from_secs(5);
+
+/// Upper bounds of the buckets of the commit latency histogram, in milliseconds.
+const LATENCY_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+#[derive(Default)]
+struct ColumnCounters {
+	reads: AtomicU64,
+	bytes_read: AtomicU64,
+	writes: AtomicU64,
+	bytes_written: AtomicU64,
+}
+
+impl ColumnCounters {
+	fn note_read(&self, bytes: usize) {
+		self.reads.fetch_add(1, Ordering::Relaxed);
+		self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+
+	fn note_write(&self, bytes: usize) {
+		self.writes.fetch_add(1, Ordering::Relaxed);
+		self.bytes_written.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+
+	fn get(&self) -> IoStats {
+		IoStats {
+			reads: self.reads.load(Ordering::Relaxed),
+			bytes_read: self.bytes_read.load(Ordering::Relaxed),
+			writes: self.writes.load(Ordering::Relaxed),
+			bytes_written: self.bytes_written.load(Ordering::Relaxed),
+		}
+	}
+}
+
+struct Period {
+	/// When the current period has started.
+	start: Instant,
+	/// Totals of each column at the start of the current period.
+	start_totals: Vec<IoStats>,
+	/// Rates of each column over the last completed period.
+	per_second: Vec<IoStats>,
+}
+
+/// Database counting the accesses to its columns.
+pub struct StatsDb {
+	db: Arc<dyn KeyValueDB>,
+	names: &'static [&'static str],
+	columns: Vec<ColumnCounters>,
+	period: Mutex<Period>,
+	commit_latency: Mutex<CommitLatency>,
+}
+
+impl StatsDb {
+	/// Wraps the given database, whose columns have the given names.
+	pub fn new(db: Arc<dyn KeyValueDB>, names: &'static [&'static str]) -> Self {
+		StatsDb {
+			db,
+			names,
+			columns: names.iter().map(|_| Default::default()).collect(),
+			period: Mutex::new(Period {
+				start: Instant::now(),
+				start_totals: vec![Default::default(); names.len()],
+				per_second: vec![Default::default(); names.len()],
+			}),
+			commit_latency: Mutex::new(CommitLatency {
+				buckets: LATENCY_BUCKETS_MS.iter().map(|bound| Some(*bound))
+					.chain(std::iter::once(None))
+					.map(|bound| (bound, 0))
+					.collect(),
+				commits: 0,
+				total: Duration::default(),
+			}),
+		}
+	}
+
+	fn column(&self, col: Option<u32>) -> Option<&ColumnCounters> {
+		col.and_then(|col| self.columns.get(col as usize))
+	}
+
+	fn note_read(&self, col: Option<u32>, bytes: usize) {
+		if let Some(column) = self.column(col) {
+			column.note_read(bytes);
+		}
+	}
+
+	fn note_transaction(&self, transaction: &DBTransaction) {
+		for op in &transaction.ops {
+			let (col, bytes) = match op {
+				DBOp::Insert { col, key, value } => (*col, key.len() + value.len()),
+				DBOp::Delete { col, key } => (*col, key.len()),
+			};
+			if let Some(column) = self.column(col) {
+				column.note_write(bytes);
+			}
+		}
+	}
+
+	/// Records the duration of a commit.
+	pub fn note_commit(&self, duration: Duration) {
+		let millis = duration.as_millis();
+		let mut latency = self.commit_latency.lock();
+		latency.commits += 1;
+		latency.total += duration;
+		if let Some(bucket) = latency.buckets.iter_mut()
+			.find(|(bound, _)| bound.map_or(true, |bound| millis <= u128::from(bound)))
+		{
+			bucket.1 += 1;
+		}
+	}
+
+	/// Returns the commit latency histogram.
+	pub fn commit_latency(&self) -> CommitLatency {
+		self.commit_latency.lock().clone()
+	}
+
+	/// Returns the usage of each column, and completes the measurement period if it has elapsed.
+	pub fn column_usage(&self) -> Vec<ColumnUsage> {
+		let totals = self.columns.iter().map(|column| column.get()).collect::<Vec<_>>();
+		let mut period = self.period.lock();
+		let elapsed = period.start.elapsed();
+		if elapsed >= PERIOD {
+			let millis = elapsed.as_millis() as u64;
+			let rate = |now: u64, then: u64| now.saturating_sub(then) * 1000 / millis;
+			period.per_second = totals.iter().zip(period.start_totals.iter())
+				.map(|(now, then)| IoStats {
+					reads: rate(now.reads, then.reads),
+					bytes_read: rate(now.bytes_read, then.bytes_read),
+					writes: rate(now.writes, then.writes),
+					bytes_written: rate(now.bytes_written, then.bytes_written),
+				})
+				.collect();
+			period.start = Instant::now();
+			period.start_totals = totals.clone();
+		}
+
+		self.names.iter().zip(totals.into_iter()).zip(period.per_second.iter())
+			.map(|((name, total), per_second)| ColumnUsage { name: *name, total, per_second: *per_second })
+			.collect()
+	}
+
+	/// Counts the keys and the bytes of each column. Goes through the whole database.
+	///
+	/// The accesses made to count them are not recorded.
+	pub fn column_sizes(&self) -> Vec<ColumnSize> {
+		self.names.iter().enumerate().map(|(col, name)| {
+			let mut size = ColumnSize { name: *name, keys: 0, bytes: 0 };
+			for (key, value) in self.db.iter(Some(col as u32)) {
+				size.keys += 1;
+				size.bytes += (key.len() + value.len()) as u64;
+			}
+			size
+		}).collect()
+	}
+}
+
+impl KeyValueDB for StatsDb {
+	fn get(&self, col: Option<u32>, key: &[u8]) -> io::Result<Option<DBValue>> {
+		let value = self.db.get(col, key)?;
+		self.note_read(col, value.as_ref().map_or(0, |value| value.len()));
+		Ok(value)
+	}
+
+	fn get_by_prefix(&self, col: Option<u32>, prefix: &[u8]) -> Option<Box<[u8]>> {
+		let value = self.db.get_by_prefix(col, prefix);
+		self.note_read(col, value.as_ref().map_or(0, |value| value.len()));
+		value
+	}
+
+	fn write_buffered(&self, transaction: DBTransaction) {
+		self.note_transaction(&transaction);
+		self.db.write_buffered(transaction)
+	}
+
+	fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+		self.note_transaction(&transaction);
+		self.db.write(transaction)
+	}
+
+	fn flush(&self) -> io::Result<()> {
+		self.db.flush()
+	}
+
+	fn iter<'a>(&'a self, col: Option<u32>) -> Box<dyn Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(self.db.iter(col).inspect(move |(key, value)| self.note_read(col, key.len() + value.len())))
+	}
+
+	fn iter_from_prefix<'a>(
+		&'a self,
+		col: Option<u32>,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(
+			self.db.iter_from_prefix(col, prefix)
+				.inspect(move |(key, value)| self.note_read(col, key.len() + value.len()))
+		)
+	}
+
+	fn restore(&self, new_db: &str) -> io::Result<()> {
+		self.db.restore(new_db)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counts_accesses() {
+		let db = StatsDb::new(Arc::new(kvdb_memorydb::create(2)), &["zero", "one"]);
+
+		let mut transaction = DBTransaction::new();
+		transaction.put(Some(1), b"key", b"value");
+		transaction.put(Some(1), b"other", b"value");
+		transaction.delete(Some(0), b"key");
+		db.write(transaction).unwrap();
+		assert!(db.get(Some(1), b"key").unwrap().is_some());
+		assert_eq!(db.iter(Some(1)).count(), 2);
+
+		let usage = db.column_usage();
+		assert_eq!(usage[0].total, IoStats { reads: 0, bytes_read: 0, writes: 1, bytes_written: 3 });
+		assert_eq!(usage[1].total, IoStats { reads: 3, bytes_read: 23, writes: 2, bytes_written: 18 });
+
+		assert_eq!(
+			db.column_sizes(),
+			vec![
+				ColumnSize { name: "zero", keys: 0, bytes: 0 },
+				ColumnSize { name: "one", keys: 2, bytes: 18 },
+			],
+		);
+		// counting the sizes isn't recorded.
+		assert_eq!(db.column_usage()[1].total.reads, 3);
+	}
+
+	#[test]
+	fn records_commit_latency() {
+		let db = StatsDb::new(Arc::new(kvdb_memorydb::create(1)), &["zero"]);
+		db.note_commit(Duration::from_millis(3));
+		db.note_commit(Duration::from_millis(5));
+		db.note_commit(Duration::from_secs(10));
+
+		let latency = db.commit_latency();
+		assert_eq!(latency.commits, 3);
+		assert_eq!(latency.total, Duration::from_millis(10_008));
+		assert_eq!(latency.buckets[1], (Some(5), 2));
+		assert_eq!(latency.buckets[8], (None, 1));
+	}
+}
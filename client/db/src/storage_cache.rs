@@ -18,10 +18,12 @@
 
 use std::collections::{VecDeque, HashSet, HashMap};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard};
 use linked_hash_map::{LinkedHashMap, Entry};
 use hash_db::Hasher;
 use sp_runtime::traits::{Block as BlockT, Header};
+use primitives::H256;
 use primitives::hexdisplay::HexDisplay;
 use state_machine::{backend::Backend as StateBackend, TrieBackend};
 use log::trace;
@@ -45,6 +47,25 @@ pub struct Cache<B: BlockT, H: Hasher> {
 	/// Information on the modifications in recently committed blocks; specifically which keys
 	/// changed in which block. Ordered by block number.
 	modifications: VecDeque<BlockChanges<B::Header>>,
+	/// Hits and misses of the states using this cache.
+	stats: Arc<CacheStats>,
+}
+
+/// Number of state reads served by the cache, local or shared, and of the ones which weren't.
+#[derive(Default)]
+struct CacheStats {
+	hits: AtomicU64,
+	misses: AtomicU64,
+}
+
+impl CacheStats {
+	fn note_hit(&self) {
+		self.hits.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn note_miss(&self) {
+		self.misses.fetch_add(1, Ordering::Relaxed);
+	}
 }
 
 struct LRUMap<K, V>(LinkedHashMap<K, V>, usize, usize);
@@ -73,6 +94,12 @@ impl EstimateSize for Option<Vec<u8>> {
 	}
 }
 
+impl EstimateSize for H256 {
+	fn estimate_size(&self) -> usize {
+		self.as_bytes().len()
+	}
+}
+
 struct OptionHOut<T: AsRef<[u8]>>(Option<T>);
 
 impl<T: AsRef<[u8]>> EstimateSize for OptionHOut<T> {
@@ -146,6 +173,62 @@ impl<K: EstimateSize + Eq + StdHash, V: EstimateSize> LRUMap<K, V> {
 
 }
 
+/// Cache of trie nodes, shared by the states of all the blocks.
+///
+/// Nodes are identified by their hash, so a cached node is valid whatever the block and its fork,
+/// and the cache never needs to be invalidated.
+pub struct NodeCache {
+	lru: Mutex<LRUMap<H256, Vec<u8>>>,
+	stats: CacheStats,
+}
+
+impl NodeCache {
+	/// Creates a cache using at most `size` bytes. A cache of size 0 is disabled.
+	pub fn new(size: usize) -> Self {
+		NodeCache {
+			lru: Mutex::new(LRUMap(LinkedHashMap::new(), 0, size)),
+			stats: Default::default(),
+		}
+	}
+
+	/// Returns the node with the given hash, if cached.
+	pub fn get(&self, hash: &H256) -> Option<Vec<u8>> {
+		let mut lru = self.lru.lock();
+		if lru.2 == 0 {
+			return None;
+		}
+		match lru.get(hash) {
+			Some(node) => {
+				self.stats.note_hit();
+				Some(node.clone())
+			},
+			None => {
+				self.stats.note_miss();
+				None
+			},
+		}
+	}
+
+	/// Caches the node with the given hash.
+	pub fn insert(&self, hash: H256, node: &[u8]) {
+		let mut lru = self.lru.lock();
+		if lru.2 != 0 {
+			lru.add(hash, node.to_vec());
+		}
+	}
+
+	/// Returns the used memory size of the cache in bytes.
+	pub fn used_size(&self) -> usize {
+		self.lru.lock().used_size()
+	}
+
+	/// Returns the number of node reads served by the cache and the number of the ones which
+	/// weren't.
+	pub fn hits_and_misses(&self) -> (u64, u64) {
+		(self.stats.hits.load(Ordering::Relaxed), self.stats.misses.load(Ordering::Relaxed))
+	}
+}
+
 impl<B: BlockT, H: Hasher> Cache<B, H> {
 	/// Returns the used memory size of the storage cache in bytes.
 	pub fn used_storage_cache_size(&self) -> usize {
@@ -154,6 +237,12 @@ impl<B: BlockT, H: Hasher> Cache<B, H> {
 			//  ignore small hashes storage and self.lru_hashes.used_size()
 	}
 
+	/// Returns the number of state reads served by the cache and the number of the ones which
+	/// weren't.
+	pub fn hits_and_misses(&self) -> (u64, u64) {
+		(self.stats.hits.load(Ordering::Relaxed), self.stats.misses.load(Ordering::Relaxed))
+	}
+
 	/// Synchronize the shared cache with the best block state.
 	///
 	/// This function updates the shared cache by removing entries
@@ -232,6 +321,7 @@ pub fn new_shared_cache<B: BlockT, H: Hasher>(
 		lru_child_storage: LRUMap(LinkedHashMap::new(), 0,
 			shared_cache_size * child_ratio.0 / child_ratio.1),
 		modifications: VecDeque::new(),
+		stats: Default::default(),
 	}))
 }
 
@@ -274,6 +364,8 @@ pub struct CacheChanges<H: Hasher, B: BlockT> {
 	shared_cache: SharedCache<B, H>,
 	/// Local cache of values for this state.
 	local_cache: RwLock<LocalCache<H>>,
+	/// Hits and misses of the shared cache.
+	stats: Arc<CacheStats>,
 	/// Hash of the block on top of which this instance was created or
 	/// `None` if cache is disabled
 	pub parent_hash: Option<B::Hash>,
@@ -407,10 +499,12 @@ impl<H: Hasher, B: BlockT> CacheChanges<H, B> {
 impl<H: Hasher, S: StateBackend<H>, B: BlockT> CachingState<H, S, B> {
 	/// Create a new instance wrapping generic State and shared cache.
 	pub fn new(state: S, shared_cache: SharedCache<B, H>, parent_hash: Option<B::Hash>) -> CachingState<H, S, B> {
+		let stats = shared_cache.lock().stats.clone();
 		CachingState {
 			state,
 			cache: CacheChanges {
 				shared_cache,
+				stats,
 				local_cache: RwLock::new(LocalCache {
 					storage: Default::default(),
 					hashes: Default::default(),
@@ -482,16 +576,19 @@ impl<H: Hasher, S: StateBackend<H>, B: BlockT> StateBackend<H> for CachingState<
 		// Note that local cache makes that lru is not refreshed
 		if let Some(entry) = local_cache.storage.get(key).cloned() {
 			trace!("Found in local cache: {:?}", HexDisplay::from(&key));
+			self.cache.stats.note_hit();
 			return Ok(entry)
 		}
 		let mut cache = self.cache.shared_cache.lock();
 		if Self::is_allowed(Some(key), None, &self.cache.parent_hash, &cache.modifications) {
 			if let Some(entry) = cache.lru_storage.get(key).map(|a| a.clone()) {
 				trace!("Found in shared cache: {:?}", HexDisplay::from(&key));
+				self.cache.stats.note_hit();
 				return Ok(entry)
 			}
 		}
 		trace!("Cache miss: {:?}", HexDisplay::from(&key));
+		self.cache.stats.note_miss();
 		let value = self.state.storage(key)?;
 		RwLockUpgradableReadGuard::upgrade(local_cache).storage.insert(key.to_vec(), value.clone());
 		Ok(value)
@@ -501,16 +598,19 @@ impl<H: Hasher, S: StateBackend<H>, B: BlockT> StateBackend<H> for CachingState<
 		let local_cache = self.cache.local_cache.upgradable_read();
 		if let Some(entry) = local_cache.hashes.get(key).cloned() {
 			trace!("Found hash in local cache: {:?}", HexDisplay::from(&key));
+			self.cache.stats.note_hit();
 			return Ok(entry)
 		}
 		let mut cache = self.cache.shared_cache.lock();
 		if Self::is_allowed(Some(key), None, &self.cache.parent_hash, &cache.modifications) {
 			if let Some(entry) = cache.lru_hashes.get(key).map(|a| a.0.clone()) {
 				trace!("Found hash in shared cache: {:?}", HexDisplay::from(&key));
+				self.cache.stats.note_hit();
 				return Ok(entry)
 			}
 		}
 		trace!("Cache hash miss: {:?}", HexDisplay::from(&key));
+		self.cache.stats.note_miss();
 		let hash = self.state.storage_hash(key)?;
 		RwLockUpgradableReadGuard::upgrade(local_cache).hashes.insert(key.to_vec(), hash);
 		Ok(hash)
@@ -521,16 +621,19 @@ impl<H: Hasher, S: StateBackend<H>, B: BlockT> StateBackend<H> for CachingState<
 		let local_cache = self.cache.local_cache.upgradable_read();
 		if let Some(entry) = local_cache.child_storage.get(&key).cloned() {
 			trace!("Found in local cache: {:?}", key);
+			self.cache.stats.note_hit();
 			return Ok(entry)
 		}
 		let mut cache = self.cache.shared_cache.lock();
 		if Self::is_allowed(None, Some(&key), &self.cache.parent_hash, &cache.modifications) {
 			if let Some(entry) = cache.lru_child_storage.get(&key).map(|a| a.clone()) {
 				trace!("Found in shared cache: {:?}", key);
+				self.cache.stats.note_hit();
 				return Ok(entry)
 			}
 		}
 		trace!("Cache miss: {:?}", key);
+		self.cache.stats.note_miss();
 		let value = self.state.child_storage(storage_key, &key.1[..])?;
 		RwLockUpgradableReadGuard::upgrade(local_cache).child_storage.insert(key, value.clone());
 		Ok(value)
@@ -694,6 +797,44 @@ mod tests {
 		assert_eq!(s.storage(&key).unwrap().unwrap(), vec![2]);
 	}
 
+	#[test]
+	fn counts_hits_and_misses() {
+		let root_parent = H256::random();
+		let key = H256::random()[..].to_vec();
+		let h0 = H256::random();
+
+		let shared = new_shared_cache::<Block, Blake2Hasher>(256*1024, (0,1));
+		let mut s = CachingState::new(InMemory::<Blake2Hasher>::default(), shared.clone(), Some(root_parent));
+		s.cache.sync_cache(&[], &[], vec![(key.clone(), Some(vec![2]))], vec![], Some(h0), Some(0), true);
+
+		let s = CachingState::new(InMemory::<Blake2Hasher>::default(), shared.clone(), Some(h0));
+		// shared cache hit.
+		assert_eq!(s.storage(&key).unwrap(), Some(vec![2]));
+		// miss, then local cache hit.
+		assert_eq!(s.storage(&[1, 2, 3]).unwrap(), None);
+		assert_eq!(s.storage(&[1, 2, 3]).unwrap(), None);
+		assert_eq!(shared.lock().hits_and_misses(), (2, 1));
+	}
+
+	#[test]
+	fn node_cache_is_bounded() {
+		let cache = NodeCache::new(100);
+		let (first, second) = (H256::random(), H256::random());
+		cache.insert(first, &[1; 50]);
+		assert_eq!(cache.get(&first), Some(vec![1; 50]));
+		assert_eq!(cache.used_size(), 82);
+		// the least recently used node is evicted.
+		cache.insert(second, &[2; 50]);
+		assert_eq!(cache.get(&first), None);
+		assert_eq!(cache.get(&second), Some(vec![2; 50]));
+		assert_eq!(cache.hits_and_misses(), (2, 1));
+
+		let disabled = NodeCache::new(0);
+		disabled.insert(first, &[1]);
+		assert_eq!(disabled.get(&first), None);
+		assert_eq!(disabled.used_size(), 0);
+	}
+
 	#[test]
 	fn double_fork() {
 		let root_parent = H256::random();
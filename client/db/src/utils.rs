@@ -32,7 +32,7 @@ use sp_runtime::traits::{
 	Block as BlockT, Header as HeaderT, Zero,
 	UniqueSaturatedFrom, UniqueSaturatedInto,
 };
-use crate::{DatabaseSettings, DatabaseSettingsSrc};
+use crate::{DatabaseSettings, DatabaseSettingsSrc, DatabaseKind};
 
 /// Number of columns in the db. Must be the same for both full && light dbs.
 /// Otherwise RocksDb will fail to open database && check its type.
@@ -56,6 +56,12 @@ pub mod meta_keys {
 	pub const LEAF_PREFIX: &[u8; 4] = b"leaf";
 	/// Children prefix list key.
 	pub const CHILDREN_PREFIX: &[u8; 8] = b"children";
+	/// Prefix of the keys marking the blocks imported without state.
+	pub const NO_STATE_PREFIX: &[u8; 7] = b"nostate";
+	/// Number of the last block whose body has been pruned.
+	pub const LAST_PRUNED_BODY: &[u8; 10] = b"prunedbody";
+	/// Prefix of the keys marking the blocks whose state is being archived.
+	pub const ARCHIVING_PREFIX: &[u8; 9] = b"archiving";
 }
 
 /// Database metadata.
@@ -202,15 +208,11 @@ pub fn db_err(err: io::Error) -> sp_blockchain::Error {
 	sp_blockchain::Error::Backend(format!("{}", err))
 }
 
-/// Open RocksDB database.
-pub fn open_database(
-	config: &DatabaseSettings,
-	col_meta: Option<u32>,
-	db_type: &str
-) -> sp_blockchain::Result<Arc<dyn KeyValueDB>> {
-	let db: Arc<dyn KeyValueDB> = match &config.source {
+/// Open the database, without checking its type.
+pub fn open_kvdb(source: &DatabaseSettingsSrc) -> sp_blockchain::Result<Arc<dyn KeyValueDB>> {
+	let db: Arc<dyn KeyValueDB> = match source {
 		#[cfg(feature = "kvdb-rocksdb")]
-		DatabaseSettingsSrc::Path { path, cache_size } => {
+		DatabaseSettingsSrc::Path { path, cache_size, kind: DatabaseKind::RocksDb } => {
 			let mut db_config = DatabaseConfig::with_columns(Some(NUM_COLUMNS));
 
 			if let Some(cache_size) = cache_size {
@@ -233,12 +235,30 @@ pub fn open_database(
 			Arc::new(Database::open(&db_config, &path).map_err(db_err)?)
 		},
 		#[cfg(not(feature = "kvdb-rocksdb"))]
-		DatabaseSettingsSrc::Path { .. } => {
+		DatabaseSettingsSrc::Path { kind: DatabaseKind::RocksDb, .. } => {
 			let msg = "Try to open RocksDB database with RocksDB disabled".into();
 			return Err(sp_blockchain::Error::Backend(msg));
 		},
+		#[cfg(feature = "sled")]
+		DatabaseSettingsSrc::Path { path, cache_size, kind: DatabaseKind::Sled } =>
+			Arc::new(crate::sled_db::SledDb::open(path, *cache_size).map_err(db_err)?),
+		#[cfg(not(feature = "sled"))]
+		DatabaseSettingsSrc::Path { kind: DatabaseKind::Sled, .. } => {
+			let msg = "Try to open sled database with sled disabled".into();
+			return Err(sp_blockchain::Error::Backend(msg));
+		},
 		DatabaseSettingsSrc::Custom(db) => db.clone(),
 	};
+	Ok(db)
+}
+
+/// Open the database and check its type.
+pub fn open_database(
+	config: &DatabaseSettings,
+	col_meta: Option<u32>,
+	db_type: &str
+) -> sp_blockchain::Result<Arc<dyn KeyValueDB>> {
+	let db = open_kvdb(&config.source)?;
 
 	// check database type
 	match db.get(col_meta, meta_keys::TYPE).map_err(db_err)? {
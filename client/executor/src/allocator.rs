@@ -45,10 +45,19 @@
 //!
 //! To deallocate we use the preceding 8 bytes of the allocation to knit
 //! back the allocation into the linked list from the head.
+//!
+//! With `AllocationStrategy::FallbackToLarger`, an allocation which would
+//! exhaust the heap when bumping is instead served from the linked list of
+//! the smallest larger item size which has a free item. The item keeps the
+//! identity of its linked list, so it returns to it when deallocated.
+//!
+//! The allocator keeps `AllocationStats` of its usage, which are logged on
+//! the `wasm-heap` target after each runtime call.
 
 use crate::error::{Error, Result};
 use log::trace;
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::ops::Range;
 use wasm_interface::{Pointer, WordSize};
 
@@ -69,11 +78,96 @@ const MIN_POSSIBLE_ALLOCATION: u32 = 8;
 // to which it belongs.
 const PREFIX_SIZE: u32 = 8;
 
+// The size of a page of the Wasm linear memory.
+const WASM_PAGE_SIZE: u32 = 65536;
+
+/// The strategy used to serve an allocation when the linked list of its item size is empty.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum AllocationStrategy {
+	/// Bump the heap, failing if it is exhausted.
+	Bump,
+	/// Bump the heap, or take a free item of a larger size if the heap is exhausted.
+	///
+	/// This makes fewer allocations fail, but the allocations which fail depend on the strategy:
+	/// the runtime may behave differently than on nodes using the `Bump` strategy. It must not be
+	/// used by the calls which are part of the consensus, e.g. importing or constructing blocks.
+	FallbackToLarger,
+}
+
+impl Default for AllocationStrategy {
+	fn default() -> Self {
+		AllocationStrategy::Bump
+	}
+}
+
+/// Statistics of the usage of a heap.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AllocationStats {
+	/// Number of bytes in use, including the prefixes.
+	pub size: u32,
+	/// Highest number of bytes in use, including the prefixes.
+	pub peak_size: u32,
+	/// Number of bytes taken from the heap by bumping.
+	pub bumped_size: u32,
+	/// Number of allocations of each item size, from 8 bytes to 16 MiB.
+	pub allocations: [u32; N],
+	/// Total number of bytes requested by the allocations.
+	pub requested_bytes: u64,
+	/// Total number of bytes of the items given to the allocations.
+	pub item_bytes: u64,
+}
+
+impl AllocationStats {
+	/// The share of the bumped heap which is in the linked lists of free items.
+	pub fn fragmentation(&self) -> f64 {
+		if self.bumped_size == 0 {
+			0.0
+		} else {
+			f64::from(self.bumped_size - self.size) / f64::from(self.bumped_size)
+		}
+	}
+
+	/// The share of the bytes of the items given to the allocations which were not requested.
+	pub fn rounding_waste(&self) -> f64 {
+		if self.item_bytes == 0 {
+			0.0
+		} else {
+			(self.item_bytes - self.requested_bytes) as f64 / self.item_bytes as f64
+		}
+	}
+}
+
+impl fmt::Display for AllocationStats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"peak {} bytes, in use {} bytes, bumped {} bytes, {:.1}% fragmented, \
+			{:.1}% lost to rounding, allocations per item size:",
+			self.peak_size,
+			self.size,
+			self.bumped_size,
+			self.fragmentation() * 100.0,
+			self.rounding_waste() * 100.0,
+		)?;
+		for (index, count) in self.allocations.iter().enumerate().filter(|(_, count)| **count > 0) {
+			write!(
+				f,
+				" {}: {}",
+				FreeingBumpHeapAllocator::get_item_size_from_index(index),
+				count,
+			)?;
+		}
+		Ok(())
+	}
+}
+
 pub struct FreeingBumpHeapAllocator {
 	bumper: u32,
 	heads: [u32; N],
 	ptr_offset: u32,
 	total_size: u32,
+	strategy: AllocationStrategy,
+	stats: AllocationStats,
 }
 
 /// Create an allocator error.
@@ -97,9 +191,22 @@ impl FreeingBumpHeapAllocator {
 			heads: [0; N],
 			ptr_offset,
 			total_size: 0,
+			strategy: AllocationStrategy::default(),
+			stats: AllocationStats::default(),
 		}
 	}
 
+	/// Sets the strategy used when the linked list of the item size of an allocation is empty.
+	pub fn with_strategy(mut self, strategy: AllocationStrategy) -> Self {
+		self.strategy = strategy;
+		self
+	}
+
+	/// Returns the statistics of the usage of the heap.
+	pub fn stats(&self) -> &AllocationStats {
+		&self.stats
+	}
+
 	/// Gets requested number of bytes to allocate and returns a pointer.
 	/// The maximum size which can be allocated at once is 16 MiB.
 	/// There is no minimum size, but whatever size is passed into
@@ -114,18 +221,32 @@ impl FreeingBumpHeapAllocator {
 		let mem_size = u32::try_from(mem.len())
 			.expect("size of Wasm linear memory is <2^32");
 		let max_heap_size = mem_size - self.ptr_offset;
+		let out_of_space = || Error::AllocatorOutOfSpace {
+			pages: mem_size / WASM_PAGE_SIZE,
+			requested: size,
+		};
 
 		if size > MAX_POSSIBLE_ALLOCATION {
 			return Err(Error::RequestedAllocationTooLarge);
 		}
 
+		let requested_size = size;
 		let size = size.max(MIN_POSSIBLE_ALLOCATION);
 		let item_size = size.next_power_of_two();
 		if item_size + PREFIX_SIZE + self.total_size > max_heap_size {
-			return Err(Error::AllocatorOutOfSpace);
+			return Err(out_of_space());
+		}
+
+		let mut list_index = (item_size.trailing_zeros() - 3) as usize;
+		if self.heads[list_index] == 0 && self.strategy == AllocationStrategy::FallbackToLarger
+			&& self.bumper + PREFIX_SIZE + item_size > max_heap_size
+		{
+			if let Some(larger_index) = (list_index + 1..N).find(|index| self.heads[*index] != 0) {
+				list_index = larger_index;
+			}
 		}
+		let item_size = Self::get_item_size_from_index(list_index) as u32;
 
-		let list_index = (item_size.trailing_zeros() - 3) as usize;
 		let ptr: u32 = if self.heads[list_index] != 0 {
 			// Something from the free list
 			let item = self.heads[list_index];
@@ -142,7 +263,7 @@ impl FreeingBumpHeapAllocator {
 			ptr
 		} else {
 			// Nothing to be freed. Bump.
-			self.bump(item_size, max_heap_size)? + PREFIX_SIZE
+			self.bump(item_size, max_heap_size).ok_or_else(out_of_space)? + PREFIX_SIZE
 		};
 
 		self.set_heap_u64(mem, ptr - PREFIX_SIZE, list_index as u64)?;
@@ -150,6 +271,13 @@ impl FreeingBumpHeapAllocator {
 		self.total_size = self.total_size + item_size + PREFIX_SIZE;
 		trace!(target: "wasm-heap", "Heap size is {} bytes after allocation", self.total_size);
 
+		self.stats.size = self.total_size;
+		self.stats.peak_size = self.stats.peak_size.max(self.total_size);
+		self.stats.bumped_size = self.bumper;
+		self.stats.allocations[list_index] += 1;
+		self.stats.requested_bytes += u64::from(requested_size);
+		self.stats.item_bytes += u64::from(item_size);
+
 		Ok(Pointer::new(self.ptr_offset + ptr))
 	}
 
@@ -178,6 +306,7 @@ impl FreeingBumpHeapAllocator {
 		self.total_size = self.total_size.checked_sub(item_size as u32 + PREFIX_SIZE)
 			.ok_or_else(|| error("Unable to subtract from total heap size without overflow"))?;
 		trace!(target: "wasm-heap", "Heap size is {} bytes after deallocation", self.total_size);
+		self.stats.size = self.total_size;
 
 		Ok(())
 	}
@@ -185,16 +314,15 @@ impl FreeingBumpHeapAllocator {
 	/// Increases the `bumper` by `item_size + PREFIX_SIZE`.
 	///
 	/// Returns the `bumper` from before the increase.
-	/// Returns an `Error::AllocatorOutOfSpace` if the operation
-	/// would exhaust the heap.
-	fn bump(&mut self, item_size: u32, max_heap_size: u32) -> Result<u32> {
+	/// Returns `None` if the operation would exhaust the heap.
+	fn bump(&mut self, item_size: u32, max_heap_size: u32) -> Option<u32> {
 		if self.bumper + PREFIX_SIZE + item_size > max_heap_size {
-			return Err(Error::AllocatorOutOfSpace);
+			return None;
 		}
 
 		let res = self.bumper;
 		self.bumper += item_size + PREFIX_SIZE;
-		Ok(res)
+		Some(res)
 	}
 
 	fn get_item_size_from_index(index: usize) -> usize {
@@ -383,7 +511,7 @@ mod tests {
 
 		// then
 		match ptr.unwrap_err() {
-			Error::AllocatorOutOfSpace => {},
+			Error::AllocatorOutOfSpace { .. } => {},
 			e => panic!("Expected allocator out of space error, got: {:?}", e),
 		}
 	}
@@ -402,7 +530,7 @@ mod tests {
 		// then
 		// there is no room for another half page incl. its 8 byte prefix
 		match ptr2.unwrap_err() {
-			Error::AllocatorOutOfSpace => {},
+			Error::AllocatorOutOfSpace { .. } => {},
 			e => panic!("Expected allocator out of space error, got: {:?}", e),
 		}
 	}
@@ -463,7 +591,7 @@ mod tests {
 
 		// then
 		match ptr.unwrap_err() {
-			Error::AllocatorOutOfSpace => {},
+			Error::AllocatorOutOfSpace { .. } => {},
 			e => panic!("Expected allocator out of space error, got: {:?}", e),
 		}
 	}
@@ -551,4 +679,81 @@ mod tests {
 		assert_eq!(item_size as u32, MAX_POSSIBLE_ALLOCATION);
 	}
 
+	#[test]
+	fn should_report_exhausted_pages_and_requested_size() {
+		// given
+		let mut mem = [0u8; PAGE_SIZE as usize];
+		let mut heap = FreeingBumpHeapAllocator::new(0);
+
+		// when
+		let ptr = heap.allocate(&mut mem[..], PAGE_SIZE);
+
+		// then
+		match ptr.unwrap_err() {
+			Error::AllocatorOutOfSpace { pages: 1, requested: PAGE_SIZE } => {},
+			e => panic!("Expected allocator out of space error, got: {:?}", e),
+		}
+	}
+
+	#[test]
+	fn should_track_allocation_stats() {
+		// given
+		let mut mem = [0u8; PAGE_SIZE as usize];
+		let mut heap = FreeingBumpHeapAllocator::new(0);
+
+		// when
+		let ptr1 = heap.allocate(&mut mem[..], 1).unwrap();
+		let ptr2 = heap.allocate(&mut mem[..], 12).unwrap();
+		heap.allocate(&mut mem[..], 16).unwrap();
+		heap.deallocate(&mut mem[..], ptr2).unwrap();
+		heap.deallocate(&mut mem[..], ptr1).unwrap();
+
+		// then
+		let stats = heap.stats();
+		assert_eq!(stats.size, 16 + PREFIX_SIZE);
+		assert_eq!(stats.peak_size, 8 + 16 + 16 + 3 * PREFIX_SIZE);
+		assert_eq!(stats.bumped_size, 8 + 16 + 16 + 3 * PREFIX_SIZE);
+		assert_eq!(&stats.allocations[..3], &[1, 2, 0]);
+		assert_eq!(stats.requested_bytes, 1 + 12 + 16);
+		assert_eq!(stats.item_bytes, 8 + 16 + 16);
+		assert_eq!(stats.fragmentation(), 0.625);
+	}
+
+	#[test]
+	fn should_fall_back_to_larger_items_when_exhausted() {
+		// given
+		let exhausted_heap = |mem: &mut [u8], strategy| {
+			let mut heap = FreeingBumpHeapAllocator::new(0).with_strategy(strategy);
+			heap.allocate(mem, 8).unwrap();
+			let ptr = heap.allocate(mem, 32).unwrap();
+			heap.deallocate(mem, ptr).unwrap();
+			heap.allocate(mem, 8).unwrap();
+			heap
+		};
+		let mut bump_mem = [0u8; 72];
+		let mut bump_heap = exhausted_heap(&mut bump_mem[..], AllocationStrategy::Bump);
+		let mut fallback_mem = [0u8; 72];
+		let mut fallback_heap = exhausted_heap(
+			&mut fallback_mem[..],
+			AllocationStrategy::FallbackToLarger,
+		);
+
+		// when
+		let bump_ptr = bump_heap.allocate(&mut bump_mem[..], 8);
+		let fallback_ptr = fallback_heap.allocate(&mut fallback_mem[..], 8);
+
+		// then
+		match bump_ptr.unwrap_err() {
+			Error::AllocatorOutOfSpace { .. } => {},
+			e => panic!("Expected allocator out of space error, got: {:?}", e),
+		}
+		// the free item of 32 bytes is used, and returns to its list when deallocated
+		let fallback_ptr = fallback_ptr.unwrap();
+		assert_eq!(fallback_ptr, to_pointer(16 + PREFIX_SIZE));
+		assert_eq!(fallback_heap.total_size, 72);
+		assert_eq!(fallback_heap.stats().allocations[2], 2);
+		fallback_heap.deallocate(&mut fallback_mem[..], fallback_ptr).unwrap();
+		assert_eq!(fallback_heap.heads[2], 16);
+	}
+
 }
@@ -79,14 +79,23 @@ pub enum Error {
 	#[display(fmt="Error in allocator: {}", _0)]
 	Allocator(&'static str),
 	/// The allocator ran out of space.
-	#[display(fmt="Allocator ran out of space")]
-	AllocatorOutOfSpace,
+	#[from(ignore)]
+	#[display(fmt="Heap exhausted: {} pages of memory, requested {} bytes", pages, requested)]
+	AllocatorOutOfSpace {
+		/// The number of pages of the linear memory.
+		pages: u32,
+		/// The number of bytes requested by the allocation which failed.
+		requested: u32,
+	},
 	/// Someone tried to allocate more memory than the allowed maximum per allocation.
 	#[display(fmt="Requested allocation size is too large")]
 	RequestedAllocationTooLarge,
 	/// Execution of a host function failed.
 	#[display(fmt="Host function {} execution failed with: {}", _0, _1)]
 	FunctionExecution(String, String),
+	/// The runtime executed more instructions than allowed.
+	#[display(fmt="Runtime ran out of fuel after executing {} instructions", _0)]
+	FuelExhausted(u64),
 }
 
 impl std::error::Error for Error {
@@ -141,4 +150,7 @@ pub enum WasmError {
 	/// Wasmtime setup error.
 	#[cfg(feature = "wasmtime")]
 	WasmtimeSetup(SetupError),
+	/// The cache of the compiled runtimes could not be set up.
+	#[cfg(feature = "wasmtime")]
+	CompilationCache(String),
 }
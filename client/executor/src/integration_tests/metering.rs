@@ -0,0 +1,65 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::TestExternalities;
+use crate::{
+	AllocationStrategy, WasmExecutionMethod, error::Error, metering,
+	wasm_runtime::create_wasm_runtime_with_code,
+};
+
+use assert_matches::assert_matches;
+use test_case::test_case;
+use wabt;
+
+const CODE: &str = r#"
+	(module
+		(memory (export "memory") 1)
+		(global (export "__heap_base") i32 (i32.const 1024))
+		(func (export "test_loop") (param i32 i32) (result i64)
+			(loop $loop
+				(br $loop)
+			)
+			(i64.const 0)
+		)
+		(func (export "test_return") (param i32 i32) (result i64)
+			(i64.const 0)
+		)
+	)
+"#;
+
+#[test_case(WasmExecutionMethod::Interpreted)]
+#[cfg_attr(feature = "wasmtime", test_case(WasmExecutionMethod::Compiled))]
+fn metered_runtime_runs_out_of_fuel(wasm_method: WasmExecutionMethod) {
+	let mut ext = TestExternalities::default();
+	let mut ext = ext.ext();
+	let code = metering::instrument(&wabt::wat2wasm(CODE).unwrap()).unwrap();
+	let mut runtime = create_wasm_runtime_with_code(
+		wasm_method,
+		8,
+		AllocationStrategy::default(),
+		&code,
+		vec![metering::GAS_FUNCTION],
+	).unwrap();
+
+	assert_eq!(
+		metering::with_fuel(1000, || runtime.call(&mut ext, "test_return", &[])).unwrap(),
+		Vec::<u8>::new(),
+	);
+	assert_matches!(
+		metering::with_fuel(1000, || runtime.call(&mut ext, "test_loop", &[])),
+		Err(Error::FuelExhausted(1000))
+	);
+}
@@ -0,0 +1,91 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::TestExternalities;
+use crate::{
+	AllocationStrategy, WasmExecutionMethod, profiling,
+	wasm_runtime::create_wasm_runtime_with_code,
+};
+
+use std::collections::HashSet;
+use test_case::test_case;
+use wabt;
+
+const CODE: &str = r#"
+	(module
+		(memory (export "memory") 1)
+		(global (export "__heap_base") i32 (i32.const 1024))
+		(func $inner (result i32)
+			(return (i32.const 1))
+		)
+		(func $outer (result i32)
+			(call $inner)
+		)
+		(func (export "test_profile") (param i32 i32) (result i64)
+			(drop (call $outer))
+			(drop (call $inner))
+			(i64.const 0)
+		)
+	)
+"#;
+
+#[test_case(WasmExecutionMethod::Interpreted)]
+#[cfg_attr(feature = "wasmtime", test_case(WasmExecutionMethod::Compiled))]
+fn profiled_runtime_writes_collapsed_stacks(wasm_method: WasmExecutionMethod) {
+	let mut ext = TestExternalities::default();
+	let mut ext = ext.ext();
+	let code = wabt::Wat2Wasm::new()
+		.write_debug_names(true)
+		.convert(CODE)
+		.unwrap()
+		.as_ref()
+		.to_vec();
+	let mut runtime = create_wasm_runtime_with_code(
+		wasm_method,
+		8,
+		AllocationStrategy::default(),
+		&profiling::instrument(&code).unwrap(),
+		profiling::HOST_FUNCTIONS.to_vec(),
+	).unwrap();
+
+	let (result, profile) = profiling::with_profile(
+		"test_profile",
+		|| runtime.call(&mut ext, "test_profile", &[]),
+	);
+	assert_eq!(result.unwrap(), Vec::<u8>::new());
+
+	let directory = tempfile::tempdir().unwrap();
+	let path = directory.path().join("profile.folded");
+	profile.append_collapsed_stacks(&path, &profiling::function_names(&code)).unwrap();
+
+	// The unnamed exported function is named by its index.
+	let expected = [
+		"test_profile;func[2]",
+		"test_profile;func[2];outer",
+		"test_profile;func[2];outer;inner",
+		"test_profile;func[2];inner",
+	].iter().map(|stack| stack.to_string()).collect::<HashSet<_>>();
+	let stacks = std::fs::read_to_string(&path).unwrap()
+		.lines()
+		.map(|line| {
+			let (stack, nanos) = line.split_at(line.rfind(' ').unwrap());
+			assert!(nanos.trim().parse::<u128>().unwrap() > 0);
+			stack.replace('$', "")
+		})
+		.collect::<HashSet<_>>();
+	assert!(stacks.is_subset(&expected), "unexpected stacks: {:?}", stacks);
+	assert!(stacks.contains("test_profile;func[2];outer;inner"));
+}
@@ -36,6 +36,8 @@ mod wasmi_execution;
 mod native_executor;
 mod sandbox;
 mod allocator;
+mod metering;
+mod profiling;
 pub mod deprecated_host_interface;
 mod wasm_runtime;
 #[cfg(feature = "wasmtime")]
@@ -52,7 +54,10 @@ pub use codec::Codec;
 pub use primitives::traits::Externalities;
 #[doc(hidden)]
 pub use wasm_interface;
-pub use wasm_runtime::WasmExecutionMethod;
+pub use wasm_runtime::{WasmExecutionMethod, DEFAULT_MAX_RUNTIMES, DEFAULT_MAX_RUNTIME_INSTANCES};
+pub use allocator::{AllocationStrategy, AllocationStats};
+#[cfg(feature = "wasmtime")]
+pub use crate::wasmtime::init_compilation_cache;
 
 /// Call the given `function` in the given wasm `code`.
 ///
@@ -75,6 +80,7 @@ pub fn call_in_wasm<E: Externalities, HF: wasm_interface::HostFunctions>(
 	let mut instance = wasm_runtime::create_wasm_runtime_with_code(
 		execution_method,
 		heap_pages,
+		AllocationStrategy::default(),
 		code,
 		HF::host_functions(),
 	)?;
@@ -88,6 +94,20 @@ pub trait RuntimeInfo {
 
 	/// Extract RuntimeVersion of given :code block
 	fn runtime_version<E: Externalities> (&self, ext: &mut E) -> error::Result<RuntimeVersion>;
+
+	/// Call `method` of the given runtime `code` in Wasm, instead of the runtime in the storage of
+	/// `ext`, e.g. to check a runtime before upgrading to it.
+	///
+	/// The runtime is instantiated with the host functions of the executor, and
+	/// `Error::InvalidCode` is returned if this fails. The runtime is kept in the runtimes cache,
+	/// so that it is instantiated once for all the calls checking it.
+	fn call_with_code<E: Externalities>(
+		&self,
+		ext: &mut E,
+		code: &[u8],
+		method: &str,
+		data: &[u8],
+	) -> error::Result<Vec<u8>>;
 }
 
 #[cfg(test)]
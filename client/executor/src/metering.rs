@@ -0,0 +1,103 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Deterministic metering of the runtime execution.
+//!
+//! The code of a metered runtime is instrumented to call the `gas` host function at the start of
+//! each block of instructions, with the number of instructions of the block. Each instruction
+//! consumes one unit of fuel, so the metering doesn't depend on the Wasm execution method.
+//!
+//! The fuel available for a call is set with [`with_fuel`]. When the runtime calls `gas` with more
+//! instructions than the remaining fuel, the execution is aborted and the call returns
+//! `Error::FuelExhausted`. A metered runtime executed without fuel set is not limited.
+
+use crate::error::{Error, WasmError};
+use parity_wasm::elements::Module;
+use std::cell::Cell;
+use wasm_interface::{Function, FunctionContext, Signature, Value, ValueType};
+
+/// Name of the host function consuming fuel, imported by the metered runtimes.
+const GAS_FUNCTION_NAME: &str = "gas";
+
+thread_local! {
+	/// The fuel remaining for the current call, if it is limited.
+	static REMAINING_FUEL: Cell<Option<u64>> = Cell::new(None);
+	/// Whether the current call ran out of fuel.
+	static FUEL_EXHAUSTED: Cell<bool> = Cell::new(false);
+}
+
+/// The host function consuming fuel.
+pub static GAS_FUNCTION: &(dyn Function + 'static) = &GasFunction;
+
+/// Instrument the given runtime `code` to consume fuel while executing.
+pub fn instrument(code: &[u8]) -> Result<Vec<u8>, WasmError> {
+	let module: Module = parity_wasm::deserialize_buffer(code)
+		.map_err(|_| WasmError::CantDeserializeWasm)?;
+	let module = pwasm_utils::inject_gas_counter(module, &pwasm_utils::rules::Set::default())
+		.map_err(|_| WasmError::Instantiation("failed to inject the gas counter".into()))?;
+	parity_wasm::serialize(module)
+		.map_err(|_| WasmError::Instantiation("failed to serialize the metered module".into()))
+}
+
+/// Execute `f`, which calls into a metered runtime, with the given amount of fuel.
+pub fn with_fuel<R>(fuel: u64, f: impl FnOnce() -> Result<R, Error>) -> Result<R, Error> {
+	let previous_fuel = REMAINING_FUEL.with(|remaining| remaining.replace(Some(fuel)));
+	let previously_exhausted = FUEL_EXHAUSTED.with(|exhausted| exhausted.replace(false));
+
+	let result = f();
+
+	REMAINING_FUEL.with(|remaining| remaining.set(previous_fuel));
+	if FUEL_EXHAUSTED.with(|exhausted| exhausted.replace(previously_exhausted)) {
+		return Err(Error::FuelExhausted(fuel));
+	}
+	result
+}
+
+struct GasFunction;
+
+impl Function for GasFunction {
+	fn name(&self) -> &str {
+		GAS_FUNCTION_NAME
+	}
+
+	fn signature(&self) -> Signature {
+		Signature::new(&[ValueType::I32][..], None)
+	}
+
+	fn execute(
+		&self,
+		_context: &mut dyn FunctionContext,
+		args: &mut dyn Iterator<Item = Value>,
+	) -> wasm_interface::Result<Option<Value>> {
+		let instructions = match args.next() {
+			Some(Value::I32(instructions)) => instructions as u32 as u64,
+			_ => return Err("`gas` expects a single `i32` argument".into()),
+		};
+
+		REMAINING_FUEL.with(|remaining| match remaining.get() {
+			None => Ok(None),
+			Some(fuel) if fuel >= instructions => {
+				remaining.set(Some(fuel - instructions));
+				Ok(None)
+			},
+			Some(_) => {
+				remaining.set(Some(0));
+				FUEL_EXHAUSTED.with(|exhausted| exhausted.set(true));
+				Err("Runtime ran out of fuel".into())
+			},
+		})
+	}
+}
@@ -15,26 +15,32 @@
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::{
-	RuntimeInfo, error::{Error, Result},
-	wasm_runtime::{RuntimesCache, WasmExecutionMethod, WasmRuntime},
+	RuntimeInfo, metering, profiling, allocator::AllocationStrategy, error::{Error, Result},
+	wasm_runtime::{
+		Instrumentation, RuntimesCache, VersionedRuntime, WasmExecutionMethod, WasmRuntime,
+		DEFAULT_MAX_RUNTIMES, DEFAULT_MAX_RUNTIME_INSTANCES,
+	},
 };
 
 use runtime_version::{NativeVersion, RuntimeVersion};
 
 use codec::{Decode, Encode};
 
-use primitives::{NativeOrEncoded, traits::{CodeExecutor, Externalities}};
+use primitives::{
+	NativeOrEncoded,
+	traits::{
+		CodeExecutor, Externalities, ExternalitiesExt, FuelLimitExt, OffchainCallExt, WasmProfileExt,
+	},
+};
 
-use log::trace;
+use log::{trace, warn};
 
-use std::{result, cell::RefCell, panic::{UnwindSafe, AssertUnwindSafe}};
+use std::{
+	collections::HashMap, result, sync::Arc, path::PathBuf, panic::{UnwindSafe, AssertUnwindSafe},
+};
 
 use wasm_interface::{HostFunctions, Function};
 
-thread_local! {
-	static RUNTIMES_CACHE: RefCell<RuntimesCache> = RefCell::new(RuntimesCache::new());
-}
-
 /// Default num of pages for the heap
 const DEFAULT_HEAP_PAGES: u64 = 1024;
 
@@ -84,8 +90,12 @@ pub struct NativeExecutor<D> {
 	native_version: NativeVersion,
 	/// The number of 64KB pages to allocate for Wasm execution.
 	default_heap_pages: u64,
+	/// The allocation strategy of the heap of the Wasm execution of the offchain calls.
+	allocation_strategy: AllocationStrategy,
 	/// The host functions registered with this instance.
 	host_functions: Vec<&'static dyn Function>,
+	/// The runtimes cache, shared by the clones of this instance.
+	runtimes_cache: Arc<RuntimesCache>,
 }
 
 impl<D: NativeExecutionDispatch> NativeExecutor<D> {
@@ -112,51 +122,122 @@ impl<D: NativeExecutionDispatch> NativeExecutor<D> {
 			fallback_method,
 			native_version: D::native_version(),
 			default_heap_pages: default_heap_pages.unwrap_or(DEFAULT_HEAP_PAGES),
+			allocation_strategy: AllocationStrategy::default(),
 			host_functions,
+			runtimes_cache: Arc::new(RuntimesCache::new(
+				DEFAULT_MAX_RUNTIMES,
+				DEFAULT_MAX_RUNTIME_INSTANCES,
+			)),
+		}
+	}
+
+	/// Set the limits of the runtimes cache.
+	///
+	/// `max_runtimes` - Maximum number of runtimes kept in the cache, the least recently used
+	/// runtime is evicted when it is exceeded.
+	///
+	/// `max_instances` - Maximum number of instances of each runtime, which is the maximum number
+	/// of calls into a runtime executed in parallel.
+	pub fn with_runtimes_cache_limits(mut self, max_runtimes: usize, max_instances: usize) -> Self {
+		self.runtimes_cache = Arc::new(RuntimesCache::new(max_runtimes, max_instances));
+		self
+	}
+
+	/// Set the allocation strategy of the heap of the Wasm execution of the offchain calls, i.e.
+	/// the calls marked with `OffchainCallExt`, made by the offchain workers and through the
+	/// `state_call` RPC.
+	///
+	/// The strategy decides which allocations fail when the heap is exhausted, so the other calls,
+	/// which are part of the consensus, always use `AllocationStrategy::Bump`.
+	pub fn with_allocation_strategy(mut self, allocation_strategy: AllocationStrategy) -> Self {
+		self.allocation_strategy = allocation_strategy;
+		self
+	}
+
+	/// The allocation strategy of the heap of the Wasm execution of a call with the given `ext`.
+	fn allocation_strategy<E: Externalities>(&self, ext: &mut E) -> AllocationStrategy {
+		let ext: &mut dyn Externalities = &mut *ext;
+		if ext.extension::<OffchainCallExt>().is_some() {
+			self.allocation_strategy
+		} else {
+			AllocationStrategy::Bump
 		}
 	}
 
 	/// Execute the given closure `f` with the latest runtime (based on the `CODE` key in `ext`).
 	///
 	/// The closure `f` is expected to return `Err(_)` when there happened a `panic!` in native code
-	/// while executing the runtime in Wasm. If a `panic!` occurred, the runtime is invalidated to
-	/// prevent any poisoned state. Native runtime execution does not need to report back
-	/// any `panic!`.
+	/// while executing the runtime in Wasm. If a `panic!` occurred, the runtime instance is
+	/// discarded to prevent any poisoned state. Native runtime execution does not need to report
+	/// back any `panic!`.
 	///
 	/// # Safety
 	///
 	/// `runtime` and `ext` are given as `AssertUnwindSafe` to the closure. As described above, the
-	/// runtime is invalidated on any `panic!` to prevent a poisoned state. `ext` is already
+	/// runtime instance is discarded on any `panic!` to prevent a poisoned state. `ext` is already
 	/// implicitly handled as unwind safe, as we store it in a global variable while executing the
 	/// native runtime.
 	fn with_runtime<E, R>(
 		&self,
 		ext: &mut E,
+		allocation_strategy: AllocationStrategy,
+		instrumentation: Instrumentation,
 		f: impl for<'a> FnOnce(
 			AssertUnwindSafe<&'a mut (dyn WasmRuntime + 'static)>,
-			&'a RuntimeVersion,
+			&'a VersionedRuntime,
 			AssertUnwindSafe<&'a mut E>,
 		) -> Result<Result<R>>,
 	) -> Result<R> where E: Externalities {
-		RUNTIMES_CACHE.with(|cache| {
-			let mut cache = cache.borrow_mut();
-			let (runtime, version, code_hash) = cache.fetch_runtime(
-				ext,
-				self.fallback_method,
-				self.default_heap_pages,
-				&self.host_functions,
-			)?;
-
-			let runtime = AssertUnwindSafe(runtime);
-			let ext = AssertUnwindSafe(ext);
-
-			match f(runtime, version, ext) {
-				Ok(res) => res,
-				Err(e) => {
-					cache.invalidate_runtime(self.fallback_method, code_hash);
-					Err(e)
-				}
+		self.runtimes_cache.with_instance(
+			ext,
+			None,
+			self.fallback_method,
+			self.default_heap_pages,
+			allocation_strategy,
+			instrumentation,
+			&self.host_functions,
+			f,
+		)
+	}
+
+	/// Call `method` of the runtime in Wasm.
+	///
+	/// `runtime` must be instrumented for the given `fuel_limit` and `profile_path`. With a fuel
+	/// limit, the call fails with `Error::FuelExhausted` when it executes more instructions than
+	/// the limit. With a profile path, the profile of the call is appended to the file, naming the
+	/// functions according to `function_names`.
+	fn call_in_wasm<E: Externalities>(
+		&self,
+		mut runtime: AssertUnwindSafe<&mut (dyn WasmRuntime + 'static)>,
+		function_names: &HashMap<u32, String>,
+		mut ext: AssertUnwindSafe<&mut E>,
+		fuel_limit: Option<u64>,
+		profile_path: Option<PathBuf>,
+		method: &str,
+		data: &[u8],
+	) -> Result<Result<Vec<u8>>> {
+		safe_call(move || {
+			let mut call = || match fuel_limit {
+				Some(fuel) =>
+					metering::with_fuel(fuel, || runtime.call(&mut **ext, method, data)),
+				None => runtime.call(&mut **ext, method, data),
+			};
+			let path = match profile_path {
+				Some(path) => path,
+				None => return call(),
+			};
+
+			let (result, profile) = profiling::with_profile(method, call);
+			if let Err(e) = profile.append_collapsed_stacks(&path, function_names) {
+				warn!(
+					target: "executor",
+					"Failed to write the profile of `{}` to {}: {}",
+					method,
+					path.display(),
+					e,
+				);
 			}
+			result
 		})
 	}
 }
@@ -168,7 +249,9 @@ impl<D: NativeExecutionDispatch> Clone for NativeExecutor<D> {
 			fallback_method: self.fallback_method,
 			native_version: D::native_version(),
 			default_heap_pages: self.default_heap_pages,
+			allocation_strategy: self.allocation_strategy,
 			host_functions: self.host_functions.clone(),
+			runtimes_cache: self.runtimes_cache.clone(),
 		}
 	}
 }
@@ -182,7 +265,32 @@ impl<D: NativeExecutionDispatch> RuntimeInfo for NativeExecutor<D> {
 		&self,
 		ext: &mut E,
 	) -> Result<RuntimeVersion> {
-		self.with_runtime(ext, |_runtime, version, _ext| Ok(Ok(version.clone())))
+		self.with_runtime(
+			ext,
+			AllocationStrategy::Bump,
+			Instrumentation::default(),
+			|_runtime, versioned, _ext| Ok(Ok(versioned.version.clone())),
+		)
+	}
+
+	fn call_with_code<E: Externalities>(
+		&self,
+		ext: &mut E,
+		code: &[u8],
+		method: &str,
+		data: &[u8],
+	) -> Result<Vec<u8>> {
+		let allocation_strategy = self.allocation_strategy(ext);
+		self.runtimes_cache.with_instance(
+			ext,
+			Some(code),
+			self.fallback_method,
+			self.default_heap_pages,
+			allocation_strategy,
+			Instrumentation::default(),
+			&self.host_functions,
+			|mut runtime, _versioned, mut ext| safe_call(move || runtime.call(&mut **ext, method, data)),
+		)
 	}
 }
 
@@ -203,7 +311,23 @@ impl<D: NativeExecutionDispatch> CodeExecutor for NativeExecutor<D> {
 		native_call: Option<NC>,
 	) -> (Result<NativeOrEncoded<R>>, bool){
 		let mut used_native = false;
-		let result = self.with_runtime(ext, |mut runtime, onchain_version, mut ext| {
+		let (fuel_limit, profile_path) = {
+			let mut ext: &mut dyn Externalities = &mut *ext;
+			(
+				ext.extension::<FuelLimitExt>().map(|limit| limit.0),
+				ext.extension::<WasmProfileExt>().map(|path| path.0.clone()),
+			)
+		};
+		let instrumentation = Instrumentation {
+			metered: fuel_limit.is_some(),
+			profiled: profile_path.is_some(),
+		};
+		let allocation_strategy = self.allocation_strategy(ext);
+		// the fuel is only metered and the profiles only recorded in Wasm, so the limited and
+		// profiled calls are never executed natively.
+		let use_native = use_native && fuel_limit.is_none() && profile_path.is_none();
+		let result = self.with_runtime(ext, allocation_strategy, instrumentation, |runtime, versioned, mut ext| {
+			let onchain_version = &versioned.version;
 			match (
 				use_native,
 				onchain_version.can_call_with(&self.native_version.runtime_version),
@@ -217,14 +341,26 @@ impl<D: NativeExecutionDispatch> CodeExecutor for NativeExecutor<D> {
 						onchain_version,
 					);
 
-					safe_call(
-						move || runtime.call(&mut **ext, method, data).map(NativeOrEncoded::Encoded)
-					)
+					self.call_in_wasm(
+						runtime,
+						&versioned.function_names,
+						ext,
+						fuel_limit,
+						profile_path,
+						method,
+						data,
+					).map(|res| res.map(NativeOrEncoded::Encoded))
 				}
 				(false, _, _) => {
-					safe_call(
-						move || runtime.call(&mut **ext, method, data).map(NativeOrEncoded::Encoded)
-					)
+					self.call_in_wasm(
+						runtime,
+						&versioned.function_names,
+						ext,
+						fuel_limit,
+						profile_path,
+						method,
+						data,
+					).map(|res| res.map(NativeOrEncoded::Encoded))
 				},
 				(true, true, Some(call)) => {
 					trace!(
@@ -363,4 +499,94 @@ mod tests {
 			);
 		});
 	}
+
+	#[test]
+	fn calls_with_fuel_limit_are_executed_in_wasm() {
+		let executor = NativeExecutor::<MyExecutor>::new(WasmExecutionMethod::Interpreted, None);
+		let mut ext = runtime_io::TestExternalities::new_with_code(
+			test_runtime::WASM_BINARY,
+			Default::default(),
+		);
+		let mut call = |fuel| {
+			ext.register_extension(FuelLimitExt(fuel));
+			executor.call::<_, primitives::NeverNativeValue, fn() -> _>(
+				&mut ext.ext(),
+				"Core_version",
+				&[],
+				true,
+				None,
+			)
+		};
+
+		match call(1) {
+			(Err(Error::FuelExhausted(1)), false) => {},
+			(res, used_native) => panic!("Expected `FuelExhausted` in Wasm, got {:?} ({})", res.map(|_| ()), used_native),
+		}
+		match call(client_api::execution_extensions::DEFAULT_FUEL_LIMIT) {
+			(Ok(NativeOrEncoded::Encoded(version)), false) =>
+				assert_eq!(RuntimeVersion::decode(&mut &version[..]).unwrap(), test_runtime::VERSION),
+			(res, used_native) => panic!("Expected a Wasm execution, got {:?} ({})", res.map(|_| ()), used_native),
+		}
+	}
+
+	#[test]
+	fn profiled_calls_are_executed_in_wasm() {
+		let executor = NativeExecutor::<MyExecutor>::new(WasmExecutionMethod::Interpreted, None);
+		let directory = tempfile::tempdir().unwrap();
+		let path = directory.path().join("profile.folded");
+		let mut ext = runtime_io::TestExternalities::new_with_code(
+			test_runtime::WASM_BINARY,
+			Default::default(),
+		);
+		ext.register_extension(WasmProfileExt(path.clone()));
+
+		match executor.call::<_, primitives::NeverNativeValue, fn() -> _>(
+			&mut ext.ext(),
+			"Core_version",
+			&[],
+			true,
+			None,
+		) {
+			(Ok(NativeOrEncoded::Encoded(_)), false) => {},
+			(res, used_native) => panic!("Expected a Wasm execution, got {:?} ({})", res.map(|_| ()), used_native),
+		}
+		let profile = std::fs::read_to_string(&path).unwrap();
+		assert!(!profile.is_empty());
+		assert!(profile.lines().all(|line| line.starts_with("Core_version")));
+	}
+
+	#[test]
+	fn only_offchain_calls_use_the_configured_allocation_strategy() {
+		let executor = NativeExecutor::<MyExecutor>::new(WasmExecutionMethod::Interpreted, None)
+			.with_allocation_strategy(AllocationStrategy::FallbackToLarger);
+		let mut ext = runtime_io::TestExternalities::default();
+
+		assert_eq!(executor.allocation_strategy(&mut ext.ext()), AllocationStrategy::Bump);
+
+		ext.register_extension(OffchainCallExt(()));
+		assert_eq!(
+			executor.allocation_strategy(&mut ext.ext()),
+			AllocationStrategy::FallbackToLarger,
+		);
+	}
+
+	#[test]
+	fn call_with_code_uses_the_given_code() {
+		let executor = NativeExecutor::<MyExecutor>::new(WasmExecutionMethod::Interpreted, None);
+		let mut ext = runtime_io::TestExternalities::default();
+		let mut ext = ext.ext();
+
+		let res = executor.call_with_code(
+			&mut ext,
+			&runtime_test::WASM_BINARY[..],
+			"test_empty_return",
+			&[],
+		).unwrap();
+		assert_eq!(res, vec![0u8; 0]);
+
+		match executor.call_with_code(&mut ext, &[1, 2, 3], "test_empty_return", &[]) {
+			Err(Error::InvalidCode(_)) => {},
+			res => panic!("Expected `InvalidCode`, got {:?}", res),
+		}
+	}
 }
@@ -0,0 +1,339 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Profiling of the runtime execution.
+//!
+//! The code of a profiled runtime is instrumented to call the `profile_enter` host function with
+//! the index of the function at its entry, and the `profile_exit` host function at its exits. The
+//! time spent in each stack of functions, excluding the functions it calls, is measured during
+//! the calls executed with [`with_profile`].
+//!
+//! The profile is written in the collapsed stacks format, one stack per line with the names of the
+//! functions from the name section of the runtime, which can be turned into a flamegraph with
+//! `flamegraph.pl` or `inferno-flamegraph`.
+
+use crate::error::WasmError;
+use parity_wasm::{builder, elements::{
+	BlockType, ImportCountType, Instruction, Internal, Module, Type, ValueType,
+}};
+use std::{
+	cell::RefCell, collections::HashMap, fs::{self, OpenOptions}, io::{self, Write}, path::Path,
+	time::{Duration, Instant},
+};
+use wasm_interface::{Function, FunctionContext, Signature, Value};
+
+/// Name of the host function called at the entry of the functions of the profiled runtimes.
+const ENTER_FUNCTION_NAME: &str = "profile_enter";
+
+/// Name of the host function called at the exits of the functions of the profiled runtimes.
+const EXIT_FUNCTION_NAME: &str = "profile_exit";
+
+thread_local! {
+	/// The profiler of the current call, if it is profiled.
+	static PROFILER: RefCell<Option<Profiler>> = RefCell::new(None);
+}
+
+/// The host functions called by the profiled runtimes.
+pub static HOST_FUNCTIONS: [&(dyn Function + 'static); 2] = [&EnterFunction, &ExitFunction];
+
+/// Instrument the given runtime `code` to report the entries and exits of its functions.
+pub fn instrument(code: &[u8]) -> Result<Vec<u8>, WasmError> {
+	let module: Module = parity_wasm::deserialize_buffer(code)
+		.map_err(|_| WasmError::CantDeserializeWasm)?;
+	let imported_functions = module.import_count(ImportCountType::Function) as u32;
+	let return_types = function_return_types(&module)?;
+
+	let mut module_builder = builder::from_module(module);
+	let enter_signature = module_builder.push_signature(
+		builder::signature().with_param(ValueType::I32).build_sig()
+	);
+	let exit_signature = module_builder.push_signature(builder::signature().build_sig());
+	module_builder.push_import(
+		builder::import()
+			.module("env")
+			.field(ENTER_FUNCTION_NAME)
+			.external()
+			.func(enter_signature)
+			.build()
+	);
+	module_builder.push_import(
+		builder::import()
+			.module("env")
+			.field(EXIT_FUNCTION_NAME)
+			.external()
+			.func(exit_signature)
+			.build()
+	);
+	let mut module = module_builder.build();
+
+	// The new imported functions are placed after the previous ones, so the indices of the
+	// functions defined by the module are shifted.
+	let enter_function = imported_functions;
+	let exit_function = imported_functions + 1;
+	let shift = |index: &mut u32| if *index >= imported_functions {
+		*index += 2;
+	};
+
+	if let Some(code_section) = module.code_section_mut() {
+		for (defined_index, body) in code_section.bodies_mut().iter_mut().enumerate() {
+			let function_index = imported_functions + defined_index as u32;
+			let instructions = body.code_mut().elements_mut();
+			for instruction in instructions.iter_mut() {
+				if let Instruction::Call(index) = instruction {
+					shift(index);
+				}
+			}
+
+			// Wrap the body in a block, so that the branches exiting the function exit the block
+			// instead, and are followed by the call to `profile_exit`.
+			let block_type = match return_types[defined_index] {
+				Some(value_type) => BlockType::Value(value_type),
+				None => BlockType::NoResult,
+			};
+			let mut profiled = Vec::with_capacity(instructions.len() + 8);
+			profiled.push(Instruction::I32Const(function_index as i32));
+			profiled.push(Instruction::Call(enter_function));
+			profiled.push(Instruction::Block(block_type));
+			for instruction in instructions.drain(..) {
+				if let Instruction::Return = instruction {
+					profiled.push(Instruction::Call(exit_function));
+				}
+				profiled.push(instruction);
+			}
+			// The last instruction of the body ends the block, and the function is ended after
+			// the call to `profile_exit`.
+			profiled.push(Instruction::Call(exit_function));
+			profiled.push(Instruction::End);
+			*instructions = profiled;
+		}
+	}
+
+	if let Some(export_section) = module.export_section_mut() {
+		for export in export_section.entries_mut() {
+			if let Internal::Function(index) = export.internal_mut() {
+				shift(index);
+			}
+		}
+	}
+
+	if let Some(elements_section) = module.elements_section_mut() {
+		for segment in elements_section.entries_mut() {
+			segment.members_mut().iter_mut().for_each(shift);
+		}
+	}
+
+	if let Some(mut start) = module.start_section() {
+		shift(&mut start);
+		module.set_start_section(start);
+	}
+
+	parity_wasm::serialize(module)
+		.map_err(|_| WasmError::Instantiation("failed to serialize the profiled module".into()))
+}
+
+/// The return types of the functions defined by the given module.
+fn function_return_types(module: &Module) -> Result<Vec<Option<ValueType>>, WasmError> {
+	let types = module.type_section().map(|section| section.types()).unwrap_or(&[]);
+	let functions = module.function_section().map(|section| section.entries()).unwrap_or(&[]);
+	functions.iter()
+		.map(|function| match types.get(function.type_ref() as usize) {
+			Some(Type::Function(function_type)) => Ok(function_type.return_type()),
+			None => Err(WasmError::InvalidModule),
+		})
+		.collect()
+}
+
+/// The names of the functions of the given runtime `code`, according to its name section.
+pub fn function_names(code: &[u8]) -> HashMap<u32, String> {
+	let module: Module = match parity_wasm::deserialize_buffer(code) {
+		Ok(module) => module,
+		Err(_) => return HashMap::new(),
+	};
+	let module = module.parse_names().unwrap_or_else(|(_, module)| module);
+	module.names_section()
+		.and_then(|section| section.functions())
+		.map(|functions| functions.names()
+			.iter()
+			.map(|(index, name)| (index, name.clone()))
+			.collect()
+		)
+		.unwrap_or_default()
+}
+
+/// Execute `f`, which calls `method` of a profiled runtime, and return its profile.
+pub fn with_profile<R>(method: &str, f: impl FnOnce() -> R) -> (R, Profile) {
+	let previous = PROFILER.with(|profiler| profiler.replace(Some(Profiler::new())));
+
+	let result = f();
+
+	let profiler = PROFILER.with(|profiler| profiler.replace(previous))
+		.expect("the profiler is only removed by `with_profile`; qed");
+	(result, profiler.finish(method.into()))
+}
+
+/// The time spent in each stack of functions of a runtime call.
+pub struct Profile {
+	/// The method called.
+	method: String,
+	/// The time spent in each stack of functions, excluding the functions it calls.
+	stacks: HashMap<Vec<u32>, Duration>,
+}
+
+impl Profile {
+	/// Append the profile to the file at `path`, in the collapsed stacks format.
+	///
+	/// The stacks are rooted at the method called, and the time is counted in nanoseconds. The
+	/// functions are named according to `names`, or by their index if they aren't named.
+	pub fn append_collapsed_stacks(
+		&self,
+		path: &Path,
+		names: &HashMap<u32, String>,
+	) -> io::Result<()> {
+		let mut collapsed = String::new();
+		for (stack, duration) in &self.stacks {
+			let nanos = duration.as_nanos();
+			if nanos == 0 {
+				continue;
+			}
+			collapsed.push_str(&self.method);
+			for function in stack {
+				// `;` separates the functions of a stack.
+				match names.get(function) {
+					Some(name) => collapsed.push_str(&format!(";{}", name.replace(';', ","))),
+					None => collapsed.push_str(&format!(";func[{}]", function)),
+				}
+			}
+			collapsed.push_str(&format!(" {}\n", nanos));
+		}
+
+		if let Some(directory) = path.parent() {
+			fs::create_dir_all(directory)?;
+		}
+		OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)?
+			.write_all(collapsed.as_bytes())
+	}
+}
+
+/// A function being executed.
+struct Frame {
+	/// The moment the function was entered.
+	entered: Instant,
+	/// The time spent in the functions it called.
+	children: Duration,
+}
+
+/// Measures the time spent in the stacks of functions of a runtime call.
+struct Profiler {
+	/// The functions being executed, the innermost last.
+	stack: Vec<u32>,
+	/// The frames of the functions of `stack`.
+	frames: Vec<Frame>,
+	/// The time spent in each stack of functions, excluding the functions it calls.
+	stacks: HashMap<Vec<u32>, Duration>,
+}
+
+impl Profiler {
+	fn new() -> Self {
+		Profiler { stack: Vec::new(), frames: Vec::new(), stacks: HashMap::new() }
+	}
+
+	fn enter(&mut self, function: u32) {
+		self.stack.push(function);
+		self.frames.push(Frame { entered: Instant::now(), children: Duration::default() });
+	}
+
+	fn exit(&mut self) {
+		let frame = match self.frames.pop() {
+			Some(frame) => frame,
+			None => return,
+		};
+		let elapsed = frame.entered.elapsed();
+		let own = elapsed.checked_sub(frame.children).unwrap_or_default();
+		match self.stacks.get_mut(&self.stack[..]) {
+			Some(duration) => *duration += own,
+			None => {
+				self.stacks.insert(self.stack.clone(), own);
+			},
+		}
+		self.stack.pop();
+		if let Some(parent) = self.frames.last_mut() {
+			parent.children += elapsed;
+		}
+	}
+
+	/// Exits the functions which are still being executed, e.g. after a trap, and returns the
+	/// profile.
+	fn finish(mut self, method: String) -> Profile {
+		while !self.frames.is_empty() {
+			self.exit();
+		}
+		Profile { method, stacks: self.stacks }
+	}
+}
+
+struct EnterFunction;
+
+impl Function for EnterFunction {
+	fn name(&self) -> &str {
+		ENTER_FUNCTION_NAME
+	}
+
+	fn signature(&self) -> Signature {
+		Signature::new_with_args(&[wasm_interface::ValueType::I32][..])
+	}
+
+	fn execute(
+		&self,
+		_context: &mut dyn FunctionContext,
+		args: &mut dyn Iterator<Item = Value>,
+	) -> wasm_interface::Result<Option<Value>> {
+		let function = match args.next() {
+			Some(Value::I32(function)) => function as u32,
+			_ => return Err("`profile_enter` expects a single `i32` argument".into()),
+		};
+
+		PROFILER.with(|profiler| if let Some(profiler) = profiler.borrow_mut().as_mut() {
+			profiler.enter(function);
+		});
+		Ok(None)
+	}
+}
+
+struct ExitFunction;
+
+impl Function for ExitFunction {
+	fn name(&self) -> &str {
+		EXIT_FUNCTION_NAME
+	}
+
+	fn signature(&self) -> Signature {
+		Signature::new_with_args(Vec::new())
+	}
+
+	fn execute(
+		&self,
+		_context: &mut dyn FunctionContext,
+		_args: &mut dyn Iterator<Item = Value>,
+	) -> wasm_interface::Result<Option<Value>> {
+		PROFILER.with(|profiler| if let Some(profiler) = profiler.borrow_mut().as_mut() {
+			profiler.exit();
+		});
+		Ok(None)
+	}
+}
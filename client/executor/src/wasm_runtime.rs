@@ -19,22 +19,27 @@
 //! The primary means of accessing the runtimes is through a cache which saves the reusable
 //! components of the runtime that are expensive to initialize.
 
-use crate::{wasmi_execution, error::{Error, WasmError}};
+use crate::{
+	wasmi_execution, metering, profiling, allocator::AllocationStrategy, error::{Error, WasmError},
+};
 #[cfg(feature = "wasmtime")]
 use crate::wasmtime;
 use log::{trace, warn};
 
 use codec::Decode;
 
-use primitives::{storage::well_known_keys, traits::Externalities};
+use primitives::{blake2_256, storage::well_known_keys, traits::Externalities};
 
 use runtime_version::RuntimeVersion;
-use std::{collections::hash_map::{Entry, HashMap}, panic::AssertUnwindSafe};
+use std::{collections::HashMap, sync::Arc, panic::AssertUnwindSafe};
+use parking_lot::{Mutex, Condvar};
 
 use wasm_interface::Function;
 
 /// The Substrate Wasm runtime.
-pub trait WasmRuntime {
+///
+/// The runtimes are sent to the threads executing them, but are only used by one at a time.
+pub trait WasmRuntime: Send {
 	/// Attempt to update the number of heap pages available during execution.
 	///
 	/// Returns false if the update cannot be applied. The function is guaranteed to return true if
@@ -59,149 +64,289 @@ pub enum WasmExecutionMethod {
 	Compiled,
 }
 
-/// A Wasm runtime object along with its cached runtime version.
-struct VersionedRuntime {
-	runtime: Box<dyn WasmRuntime>,
+/// Default maximum number of runtimes kept in the cache.
+///
+/// Besides the current runtime, the cache holds its variants with a different instrumentation or
+/// allocation strategy, e.g. for the offchain workers, and the runtimes checked or enacted by an
+/// upgrade, so that it needs room for several of them not to recompile them over and over.
+pub const DEFAULT_MAX_RUNTIMES: usize = 8;
+
+/// Default maximum number of instances of each runtime.
+pub const DEFAULT_MAX_RUNTIME_INSTANCES: usize = 8;
+
+/// The instrumentation of the code of a runtime.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct Instrumentation {
+	/// Whether the runtime consumes fuel, see the `metering` module.
+	pub metered: bool,
+	/// Whether the runtime reports the entries and exits of its functions, see the `profiling`
+	/// module.
+	pub profiled: bool,
+}
+
+/// Identifies a runtime in the cache: the Wasm execution method, the hash of the code, the
+/// number of heap pages, the allocation strategy of the heap and the instrumentation of the code.
+type RuntimeKey = (WasmExecutionMethod, Vec<u8>, u64, AllocationStrategy, Instrumentation);
+
+/// A runtime in the cache.
+struct CachedRuntime {
+	key: RuntimeKey,
+	/// The host functions the runtime is created with.
+	host_functions: Vec<&'static dyn Function>,
+	/// The runtime, or the error met when creating it. `None` until it is created by the first
+	/// call needing it.
+	runtime: Mutex<Option<Result<Arc<VersionedRuntime>, String>>>,
+}
+
+/// The instances of a runtime along with its cached runtime version.
+pub struct VersionedRuntime {
 	/// Runtime version according to `Core_version`.
-	version: RuntimeVersion,
+	pub version: RuntimeVersion,
+	/// The names of the functions of the code, by index. Only parsed for the profiled runtimes,
+	/// empty otherwise.
+	pub function_names: HashMap<u32, String>,
+	/// The pool of instances.
+	pool: Mutex<InstancePool>,
+	/// Notified when an instance is returned to the pool, or discarded.
+	released: Condvar,
+}
+
+struct InstancePool {
+	/// The instances which are not in use.
+	idle: Vec<Box<dyn WasmRuntime>>,
+	/// The number of instances, in use or not.
+	count: usize,
 }
 
 /// Cache for the runtimes.
 ///
-/// When an instance is requested for the first time it is added to this cache. Metadata is kept
-/// with the instance so that it can be efficiently reinitialized.
+/// When a runtime is requested for the first time it is instantiated and added to this cache,
+/// evicting the least recently used runtime if the cache is full. The cache keeps a pool of
+/// instances of each runtime, so that several calls may be executed in parallel: a call takes an
+/// idle instance, creates a new one if all of them are in use, or waits for one to be released
+/// if the runtime already has the maximum number of instances.
 ///
-/// When using the Wasmi interpreter execution method, the metadata includes the initial memory and
-/// values of mutable globals. Follow-up requests to fetch a runtime return this one instance with
-/// the memory reset to the initial memory. So, one runtime instance is reused for every fetch
-/// request.
+/// When using the Wasmi interpreter execution method, an instance keeps the initial memory and
+/// values of mutable globals, and is reset to them before each call.
 ///
-/// For now the cache grows indefinitely, but that should be fine for now since runtimes can only be
-/// upgraded rarely and there are no other ways to make the node to execute some other runtime.
+/// A runtime is created without holding the lock of the cache, so that the calls into the other
+/// runtimes are not held up while it is compiled. The failures to create a runtime are cached as
+/// well. A runtime requested with other host functions than the cached one is created again.
 pub struct RuntimesCache {
-	/// A cache of runtime instances along with metadata, ready to be reused.
-	///
-	/// Instances are keyed by the Wasm execution method and the hash of their code.
-	instances: HashMap<(WasmExecutionMethod, Vec<u8>), Result<VersionedRuntime, WasmError>>,
+	/// The cached runtimes, the most recently used first.
+	runtimes: Mutex<Vec<Arc<CachedRuntime>>>,
+	/// The maximum number of cached runtimes.
+	max_runtimes: usize,
+	/// The maximum number of instances of each runtime.
+	max_instances: usize,
 }
 
 impl RuntimesCache {
-	/// Creates a new instance of a runtimes cache.
-	pub fn new() -> RuntimesCache {
+	/// Creates a new instance of a runtimes cache, keeping at most `max_runtimes` runtimes with
+	/// at most `max_instances` instances each.
+	pub fn new(max_runtimes: usize, max_instances: usize) -> RuntimesCache {
 		RuntimesCache {
-			instances: HashMap::new(),
+			runtimes: Mutex::new(Vec::new()),
+			max_runtimes: max_runtimes.max(1),
+			max_instances: max_instances.max(1),
 		}
 	}
 
-	/// Fetches an instance of the runtime.
+	/// Executes `f` with an instance of the runtime with the given `code`, or whose code is in the
+	/// storage of `ext` if `code` is `None`.
 	///
-	/// On first use we create a new runtime instance, save it to the cache
-	/// and persist its initial memory.
-	///
-	/// Each subsequent request will return this instance, with its memory restored
-	/// to the persisted initial memory. Thus, we reuse one single runtime instance
-	/// for every `fetch_runtime` invocation.
+	/// The instance is taken from the pool of the runtime, and returned to it once `f` is done.
+	/// `f` is expected to return `Err(_)` when there happened a `panic!` while executing the
+	/// runtime, in which case the instance is discarded rather than returned to the pool, as it may
+	/// have been left in a poisoned state.
 	///
 	/// # Parameters
 	///
 	/// `ext` - Externalities to use for the runtime. This is used for setting
-	/// up an initial runtime instance.
+	/// up the runtime instances.
 	///
-	/// `default_heap_pages` - Number of 64KB pages to allocate for Wasm execution.
+	/// `code` - The code of the runtime, e.g. of a runtime checked before upgrading to it. `None`
+	/// to use the code in the storage of `ext`.
 	///
-	/// `host_functions` - The host functions that should be registered for the Wasm runtime.
+	/// `default_heap_pages` - Number of 64KB pages to allocate for Wasm execution.
 	///
-	/// # Return value
+	/// `allocation_strategy` - The allocation strategy of the heap of the Wasm execution.
 	///
-	/// If no error occurred a tuple `(&mut WasmRuntime, H256)` is
-	/// returned. `H256` is the hash of the runtime code.
+	/// `instrumentation` - The instrumentation of the code of the runtime.
 	///
-	/// In case of failure one of two errors can be returned:
+	/// `host_functions` - The host functions that should be registered for the Wasm runtime.
 	///
-	/// `Err::InvalidCode` is returned for runtime code issues.
+	/// # Errors
 	///
-	/// `Error::InvalidMemoryReference` is returned if no memory export with the
-	/// identifier `memory` can be found in the runtime.
-	pub fn fetch_runtime<E: Externalities>(
-		&mut self,
+	/// `Err::InvalidCode` is returned for runtime code issues, including a failure to instantiate
+	/// the runtime.
+	pub fn with_instance<E, R>(
+		&self,
 		ext: &mut E,
+		code: Option<&[u8]>,
 		wasm_method: WasmExecutionMethod,
 		default_heap_pages: u64,
+		allocation_strategy: AllocationStrategy,
+		instrumentation: Instrumentation,
 		host_functions: &[&'static dyn Function],
-	) -> Result<(&mut (dyn WasmRuntime + 'static), &RuntimeVersion, Vec<u8>), Error> {
-		let code_hash = ext
-			.original_storage_hash(well_known_keys::CODE)
-			.ok_or(Error::InvalidCode("`CODE` not found in storage.".into()))?;
+		f: impl for<'a> FnOnce(
+			AssertUnwindSafe<&'a mut (dyn WasmRuntime + 'static)>,
+			&'a VersionedRuntime,
+			AssertUnwindSafe<&'a mut E>,
+		) -> Result<Result<R, Error>, Error>,
+	) -> Result<R, Error> where E: Externalities {
+		let code_hash = match code {
+			// The storage is hashed with Blake2 as well, so the same code gets the same key.
+			Some(code) => blake2_256(code).to_vec(),
+			None => ext
+				.original_storage_hash(well_known_keys::CODE)
+				.ok_or(Error::InvalidCode("`CODE` not found in storage.".into()))?,
+		};
 
 		let heap_pages = ext
 			.storage(well_known_keys::HEAP_PAGES)
 			.and_then(|pages| u64::decode(&mut &pages[..]).ok())
 			.unwrap_or(default_heap_pages);
 
-		let result = match self.instances.entry((wasm_method, code_hash.clone())) {
-			Entry::Occupied(o) => {
-				let result = o.into_mut();
-				if let Ok(ref mut cached_runtime) = result {
-					let heap_pages_changed = !cached_runtime.runtime.update_heap_pages(heap_pages);
-					let host_functions_changed = cached_runtime.runtime.host_functions()
-						!= host_functions;
-					if heap_pages_changed || host_functions_changed {
-						let changed = if heap_pages_changed {
-							"heap_pages"
-						} else {
-							"host functions"
-						};
-
-						trace!(
-							target: "runtimes_cache",
-							"{} were changed. Reinstantiating the instance",
-							changed,
-						);
-						*result = create_versioned_wasm_runtime(
-							ext,
-							wasm_method,
-							heap_pages,
-							host_functions.into(),
-						);
-						if let Err(ref err) = result {
-							warn!(target: "runtimes_cache", "cannot create a runtime: {:?}", err);
-						}
-					}
-				}
-				result
-			},
-			Entry::Vacant(v) => {
-				trace!(target: "runtimes_cache", "no instance found in cache, creating now.");
-				let result = create_versioned_wasm_runtime(
+		let (runtime, first_instance) = self.fetch_runtime(
+			ext,
+			code,
+			(wasm_method, code_hash, heap_pages, allocation_strategy, instrumentation),
+			host_functions,
+		)?;
+
+		let mut instance = match first_instance {
+			Some(instance) => instance,
+			None => runtime.acquire(self.max_instances, || {
+				trace!(target: "runtimes_cache", "all instances in use, creating a new one.");
+				create_wasm_runtime(
 					ext,
+					code,
 					wasm_method,
 					heap_pages,
-					host_functions.into(),
-				);
-				if let Err(ref err) = result {
-					warn!(target: "runtimes_cache", "cannot create a runtime: {:?}", err);
-				}
-				v.insert(result)
+					allocation_strategy,
+					instrumentation,
+					host_functions,
+				)
+			}).map_err(|e| Error::InvalidCode(format!("{:?}", e)))?,
+		};
+
+		let result = f(AssertUnwindSafe(instance.as_mut()), &*runtime, AssertUnwindSafe(ext));
+		match result {
+			Ok(res) => {
+				runtime.release(Some(instance));
+				res
+			},
+			Err(e) => {
+				runtime.release(None);
+				Err(e)
+			},
+		}
+	}
+
+	/// Fetches the runtime with the given key, creating it if it isn't cached yet. The instance
+	/// created along with the runtime is returned as well, in use.
+	fn fetch_runtime<E: Externalities>(
+		&self,
+		ext: &mut E,
+		code: Option<&[u8]>,
+		key: RuntimeKey,
+		host_functions: &[&'static dyn Function],
+	) -> Result<(Arc<VersionedRuntime>, Option<Box<dyn WasmRuntime>>), Error> {
+		let cached = {
+			let mut runtimes = self.runtimes.lock();
+			let cached = match runtimes.iter().position(|cached| cached.key == key) {
+				Some(position) if &runtimes[position].host_functions[..] == host_functions =>
+					runtimes.remove(position),
+				position => {
+					if let Some(position) = position {
+						trace!(target: "runtimes_cache", "host functions were changed, creating the runtime again.");
+						runtimes.remove(position);
+					}
+					Arc::new(CachedRuntime {
+						key,
+						host_functions: host_functions.to_vec(),
+						runtime: Mutex::new(None),
+					})
+				},
+			};
+			runtimes.insert(0, cached.clone());
+			if runtimes.len() > self.max_runtimes {
+				let evicted = runtimes.pop().map(|cached| cached.key.clone());
+				trace!(target: "runtimes_cache", "evicting runtime {:?}", evicted);
 			}
+			cached
+		};
+
+		// the other calls into this runtime wait until it is created.
+		let mut runtime = cached.runtime.lock();
+		let first_instance = match *runtime {
+			Some(_) => None,
+			None => {
+				trace!(target: "runtimes_cache", "no runtime found in cache, creating now.");
+				let (created, first_instance) = match create_versioned_wasm_runtime(
+					ext,
+					code,
+					cached.key.0,
+					cached.key.2,
+					cached.key.3,
+					cached.key.4,
+					host_functions,
+				) {
+					Ok((runtime, instance)) => (Ok(Arc::new(runtime)), Some(instance)),
+					Err(err) => {
+						warn!(target: "runtimes_cache", "cannot create a runtime: {:?}", err);
+						(Err(format!("{:?}", err)), None)
+					},
+				};
+				*runtime = Some(created);
+				first_instance
+			},
 		};
 
-		result.as_mut()
-			.map(|entry| (entry.runtime.as_mut(), &entry.version, code_hash))
-			.map_err(|ref e| Error::InvalidCode(format!("{:?}", e)))
+		runtime.clone()
+			.expect("the runtime is created above if it wasn't yet; qed")
+			.map(|runtime| (runtime, first_instance))
+			.map_err(Error::InvalidCode)
+	}
+}
+
+impl VersionedRuntime {
+	/// Takes an idle instance, or creates a new one with `create` if there are less than
+	/// `max_instances` instances. Otherwise waits for an instance to be released.
+	fn acquire(
+		&self,
+		max_instances: usize,
+		create: impl FnOnce() -> Result<Box<dyn WasmRuntime>, WasmError>,
+	) -> Result<Box<dyn WasmRuntime>, WasmError> {
+		let mut pool = self.pool.lock();
+		loop {
+			if let Some(instance) = pool.idle.pop() {
+				return Ok(instance);
+			}
+			if pool.count < max_instances {
+				break;
+			}
+			self.released.wait(&mut pool);
+		}
+		pool.count += 1;
+		drop(pool);
+
+		let instance = create();
+		if instance.is_err() {
+			self.release(None);
+		}
+		instance
 	}
 
-	/// Invalidate the runtime for the given `wasm_method` and `code_hash`.
-	///
-	/// Invalidation of a runtime is useful when there was a `panic!` in native while executing it.
-	/// The `panic!` maybe have brought the runtime into a poisoned state and so, it is better to
-	/// invalidate this runtime instance.
-	pub fn invalidate_runtime(
-		&mut self,
-		wasm_method: WasmExecutionMethod,
-		code_hash: Vec<u8>,
-	) {
-		// Just remove the instance, it will be re-created the next time it is requested.
-		self.instances.remove(&(wasm_method, code_hash));
+	/// Returns an instance to the pool, or notes that it is discarded.
+	fn release(&self, instance: Option<Box<dyn WasmRuntime>>) {
+		let mut pool = self.pool.lock();
+		match instance {
+			Some(instance) => pool.idle.push(instance),
+			None => pool.count -= 1,
+		}
+		self.released.notify_one();
 	}
 }
 
@@ -209,30 +354,78 @@ impl RuntimesCache {
 pub fn create_wasm_runtime_with_code(
 	wasm_method: WasmExecutionMethod,
 	heap_pages: u64,
+	allocation_strategy: AllocationStrategy,
 	code: &[u8],
 	host_functions: Vec<&'static dyn Function>,
 ) -> Result<Box<dyn WasmRuntime>, WasmError> {
 	match wasm_method {
 		WasmExecutionMethod::Interpreted =>
-			wasmi_execution::create_instance(code, heap_pages, host_functions)
+			wasmi_execution::create_instance(code, heap_pages, allocation_strategy, host_functions)
 				.map(|runtime| -> Box<dyn WasmRuntime> { Box::new(runtime) }),
 		#[cfg(feature = "wasmtime")]
 		WasmExecutionMethod::Compiled =>
-			wasmtime::create_instance(code, heap_pages, host_functions)
+			wasmtime::create_instance(code, heap_pages, allocation_strategy, host_functions)
 				.map(|runtime| -> Box<dyn WasmRuntime> { Box::new(runtime) }),
 	}
 }
 
+/// Create a wasm runtime with the given `code`, or the code in the storage of `ext` if `None`,
+/// with the given `instrumentation`.
+fn create_wasm_runtime<E: Externalities>(
+	ext: &E,
+	code: Option<&[u8]>,
+	wasm_method: WasmExecutionMethod,
+	heap_pages: u64,
+	allocation_strategy: AllocationStrategy,
+	instrumentation: Instrumentation,
+	host_functions: &[&'static dyn Function],
+) -> Result<Box<dyn WasmRuntime>, WasmError> {
+	let mut code = runtime_code(ext, code)?;
+	let mut host_functions = host_functions.to_vec();
+	// The code is metered first, so that the fuel consumed doesn't depend on the profiling.
+	if instrumentation.metered {
+		code = metering::instrument(&code)?;
+		host_functions.push(metering::GAS_FUNCTION);
+	}
+	if instrumentation.profiled {
+		code = profiling::instrument(&code)?;
+		host_functions.extend(profiling::HOST_FUNCTIONS.iter());
+	}
+	create_wasm_runtime_with_code(
+		wasm_method,
+		heap_pages,
+		allocation_strategy,
+		&code,
+		host_functions,
+	)
+}
+
+/// The given `code`, or the code in the storage of `ext` if `None`.
+fn runtime_code<E: Externalities>(ext: &E, code: Option<&[u8]>) -> Result<Vec<u8>, WasmError> {
+	match code {
+		Some(code) => Ok(code.to_vec()),
+		None => ext.original_storage(well_known_keys::CODE).ok_or(WasmError::CodeNotFound),
+	}
+}
+
 fn create_versioned_wasm_runtime<E: Externalities>(
 	ext: &mut E,
+	code: Option<&[u8]>,
 	wasm_method: WasmExecutionMethod,
 	heap_pages: u64,
-	host_functions: Vec<&'static dyn Function>,
-) -> Result<VersionedRuntime, WasmError> {
-	let code = ext
-		.original_storage(well_known_keys::CODE)
-		.ok_or(WasmError::CodeNotFound)?;
-	let mut runtime = create_wasm_runtime_with_code(wasm_method, heap_pages, &code, host_functions)?;
+	allocation_strategy: AllocationStrategy,
+	instrumentation: Instrumentation,
+	host_functions: &[&'static dyn Function],
+) -> Result<(VersionedRuntime, Box<dyn WasmRuntime>), WasmError> {
+	let mut runtime = create_wasm_runtime(
+		ext,
+		code,
+		wasm_method,
+		heap_pages,
+		allocation_strategy,
+		instrumentation,
+		host_functions,
+	)?;
 
 	// Call to determine runtime version.
 	let version_result = {
@@ -251,15 +444,47 @@ fn create_versioned_wasm_runtime<E: Externalities>(
 	let version = RuntimeVersion::decode(&mut encoded_version.as_slice())
 		.map_err(|_| WasmError::Instantiation("failed to decode \"Core_version\" result".into()))?;
 
-	Ok(VersionedRuntime {
-		runtime,
+	// The names are parsed once per runtime, rather than on each profiled call.
+	let function_names = if instrumentation.profiled {
+		runtime_code(ext, code)
+			.map(|code| profiling::function_names(&code))
+			.unwrap_or_default()
+	} else {
+		HashMap::new()
+	};
+
+	let versioned_runtime = VersionedRuntime {
 		version,
-	})
+		function_names,
+		pool: Mutex::new(InstancePool {
+			idle: Vec::new(),
+			count: 1,
+		}),
+		released: Condvar::new(),
+	};
+	Ok((versioned_runtime, runtime))
 }
 
 #[cfg(test)]
 mod tests {
+	use super::*;
 	use wasm_interface::HostFunctions;
+	use primitives::map;
+	use runtime_io::TestExternalities;
+
+	fn call_version<E: Externalities>(cache: &RuntimesCache, ext: &mut E, heap_pages: u64) {
+		let host_functions = runtime_io::SubstrateHostFunctions::host_functions();
+		cache.with_instance(
+			ext,
+			None,
+			WasmExecutionMethod::Interpreted,
+			heap_pages,
+			AllocationStrategy::default(),
+			Instrumentation::default(),
+			&host_functions,
+			|_runtime, versioned, _ext| Ok(Ok(versioned.version.clone())),
+		).unwrap();
+	}
 
 	#[test]
 	fn host_functions_are_equal() {
@@ -268,4 +493,143 @@ mod tests {
 		let equal = &host_functions[..] == &host_functions[..];
 		assert!(equal, "Host functions are not equal");
 	}
+
+	#[test]
+	fn runtimes_cache_evicts_least_recently_used_runtime() {
+		let cache = RuntimesCache::new(2, 1);
+		let mut ext = TestExternalities::new_with_code(test_runtime::WASM_BINARY, (map![], map![]));
+
+		call_version(&cache, &mut ext.ext(), 1024);
+		call_version(&cache, &mut ext.ext(), 2048);
+		call_version(&cache, &mut ext.ext(), 1024);
+		call_version(&cache, &mut ext.ext(), 4096);
+
+		let heap_pages = cache.runtimes.lock()
+			.iter()
+			.map(|cached| cached.key.2)
+			.collect::<Vec<_>>();
+		assert_eq!(heap_pages, vec![4096, 1024]);
+	}
+
+	#[test]
+	fn runtimes_cache_creates_runtime_again_with_other_host_functions() {
+		let cache = RuntimesCache::new(1, 1);
+		let mut ext = TestExternalities::new_with_code(test_runtime::WASM_BINARY, (map![], map![]));
+		let mut ext = ext.ext();
+		let host_functions = runtime_io::SubstrateHostFunctions::host_functions();
+		let mut more_host_functions = host_functions.clone();
+		more_host_functions.push(metering::GAS_FUNCTION);
+
+		for host_functions in &[&more_host_functions, &host_functions] {
+			cache.with_instance(
+				&mut ext,
+				None,
+				WasmExecutionMethod::Interpreted,
+				1024,
+				AllocationStrategy::default(),
+				Instrumentation::default(),
+				host_functions,
+				|runtime, _version, _ext| {
+					assert!(runtime.host_functions() == &host_functions[..]);
+					Ok(Ok(()))
+				},
+			).unwrap();
+		}
+
+		let runtimes = cache.runtimes.lock();
+		assert_eq!(runtimes.len(), 1);
+		assert!(runtimes[0].host_functions == host_functions);
+	}
+
+	#[test]
+	fn runtimes_cache_reuses_runtime_of_given_code() {
+		let cache = RuntimesCache::new(2, 2);
+		let mut ext = TestExternalities::default();
+		let host_functions = runtime_io::SubstrateHostFunctions::host_functions();
+		let mut ext = ext.ext();
+
+		for _ in 0..2 {
+			let version = cache.with_instance(
+				&mut ext,
+				Some(test_runtime::WASM_BINARY),
+				WasmExecutionMethod::Interpreted,
+				1024,
+				AllocationStrategy::default(),
+				Instrumentation::default(),
+				&host_functions,
+				|_runtime, versioned, _ext| Ok(Ok(versioned.version.clone())),
+			).unwrap();
+			assert_eq!(version, test_runtime::VERSION);
+		}
+
+		let runtimes = cache.runtimes.lock();
+		assert_eq!(runtimes.len(), 1);
+		let runtime = runtimes[0].runtime.lock().clone().unwrap().unwrap();
+		assert_eq!(runtime.pool.lock().count, 1);
+	}
+
+	#[test]
+	fn runtimes_cache_creates_instances_on_demand() {
+		let cache = RuntimesCache::new(1, 2);
+		let mut ext = TestExternalities::new_with_code(test_runtime::WASM_BINARY, (map![], map![]));
+		let host_functions = runtime_io::SubstrateHostFunctions::host_functions();
+		let mut ext = ext.ext();
+
+		cache.with_instance(
+			&mut ext,
+			None,
+			WasmExecutionMethod::Interpreted,
+			1024,
+			AllocationStrategy::default(),
+			Instrumentation::default(),
+			&host_functions,
+			|_runtime, _version, mut ext| {
+				// The only instance is in use, so a second one is created.
+				Ok(cache.with_instance(
+					&mut **ext,
+					None,
+					WasmExecutionMethod::Interpreted,
+					1024,
+					AllocationStrategy::default(),
+					Instrumentation::default(),
+					&host_functions,
+					|_runtime, _version, _ext| Ok(Ok(())),
+				))
+			},
+		).unwrap();
+
+		let runtime = cache.runtimes.lock()[0].runtime.lock().clone().unwrap().unwrap();
+		let pool = runtime.pool.lock();
+		assert_eq!(pool.count, 2);
+		assert_eq!(pool.idle.len(), 2);
+	}
+
+	#[test]
+	fn runtimes_cache_discards_panicked_instances() {
+		let cache = RuntimesCache::new(1, 1);
+		let mut ext = TestExternalities::new_with_code(test_runtime::WASM_BINARY, (map![], map![]));
+		let host_functions = runtime_io::SubstrateHostFunctions::host_functions();
+		let mut ext = ext.ext();
+
+		let result: Result<(), _> = cache.with_instance(
+			&mut ext,
+			None,
+			WasmExecutionMethod::Interpreted,
+			1024,
+			AllocationStrategy::default(),
+			Instrumentation::default(),
+			&host_functions,
+			|_runtime, _version, _ext| Err(Error::Runtime),
+		);
+		assert!(result.is_err());
+		{
+			let runtime = cache.runtimes.lock()[0].runtime.lock().clone().unwrap().unwrap();
+			let pool = runtime.pool.lock();
+			assert_eq!(pool.count, 0);
+			assert!(pool.idle.is_empty());
+		}
+
+		// A new instance is created for the next call.
+		call_version(&cache, &mut ext, 1024);
+	}
 }
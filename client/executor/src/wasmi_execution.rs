@@ -28,7 +28,7 @@ use crate::sandbox;
 use crate::allocator;
 use crate::wasm_utils::interpret_runtime_api_result;
 use crate::wasm_runtime::WasmRuntime;
-use log::{error, trace};
+use log::{debug, error, trace};
 use parity_wasm::elements::{deserialize_buffer, DataSegment, Instruction, Module as RawModule};
 use wasm_interface::{
 	FunctionContext, Pointer, WordSize, Sandbox, MemoryId, Result as WResult, Function,
@@ -46,12 +46,14 @@ impl<'a> FunctionExecutor<'a> {
 	fn new(
 		m: MemoryRef,
 		heap_base: u32,
+		allocation_strategy: allocator::AllocationStrategy,
 		t: Option<TableRef>,
 		host_functions: &'a [&'static dyn Function],
 	) -> Result<Self, Error> {
 		Ok(FunctionExecutor {
 			sandbox_store: sandbox::Store::new(),
-			heap: allocator::FreeingBumpHeapAllocator::new(heap_base),
+			heap: allocator::FreeingBumpHeapAllocator::new(heap_base)
+				.with_strategy(allocation_strategy),
 			memory: m,
 			table: t,
 			host_functions,
@@ -349,6 +351,7 @@ fn call_in_wasm_module(
 	method: &str,
 	data: &[u8],
 	host_functions: &[&'static dyn Function],
+	allocation_strategy: allocator::AllocationStrategy,
 ) -> Result<Vec<u8>, Error> {
 	// extract a reference to a linear memory, optional reference to a table
 	// and then initialize FunctionExecutor.
@@ -358,7 +361,13 @@ fn call_in_wasm_module(
 		.and_then(|e| e.as_table().cloned());
 	let heap_base = get_heap_base(module_instance)?;
 
-	let mut fec = FunctionExecutor::new(memory.clone(), heap_base, table, host_functions)?;
+	let mut fec = FunctionExecutor::new(
+		memory.clone(),
+		heap_base,
+		allocation_strategy,
+		table,
+		host_functions,
+	)?;
 
 	// Write the call data
 	let offset = fec.allocate_memory(data.len() as u32)?;
@@ -372,6 +381,7 @@ fn call_in_wasm_module(
 			&mut fec,
 		),
 	);
+	debug!(target: "wasm-heap", "Heap usage of `{}`: {}", method, fec.heap.stats());
 
 	match result {
 		Ok(Some(I64(r))) => {
@@ -526,7 +536,6 @@ impl StateSnapshot {
 }
 
 /// A runtime along with its initial state snapshot.
-#[derive(Clone)]
 pub struct WasmiRuntime {
 	/// A wasm module instance.
 	instance: ModuleRef,
@@ -534,8 +543,22 @@ pub struct WasmiRuntime {
 	state_snapshot: StateSnapshot,
 	/// The host functions registered for this instance.
 	host_functions: Vec<&'static dyn Function>,
+	/// The allocation strategy of the heap of the calls.
+	allocation_strategy: allocator::AllocationStrategy,
 }
 
+// SAFETY: the module instance and its memories, tables, globals and functions are reference
+// counted with `Rc`, which is not `Send` because two threads holding clones of the same `Rc`
+// could update its count concurrently. Here all the clones are owned by the runtime:
+// - `instance` and the references held by the module instance itself, which is only reachable
+//   through `instance` and never handed out by the runtime;
+// - the references taken by `call` for the `FunctionExecutor`, which are dropped before `call`
+//   returns.
+// `state_snapshot` holds plain data, and the host functions are `Sync`. As `WasmRuntime` isn't
+// `Sync` and `call` takes `&mut self`, the clones are only used by the thread owning the runtime,
+// and moving the runtime moves all of them together.
+unsafe impl Send for WasmiRuntime {}
+
 impl WasmRuntime for WasmiRuntime {
 	fn update_heap_pages(&mut self, heap_pages: u64) -> bool {
 		self.state_snapshot.heap_pages == heap_pages
@@ -559,13 +582,21 @@ impl WasmRuntime for WasmiRuntime {
 				error!(target: "wasm-executor", "snapshot restoration failed: {}", e);
 				e
 			})?;
-		call_in_wasm_module(ext, &self.instance, method, data, &self.host_functions)
+		call_in_wasm_module(
+			ext,
+			&self.instance,
+			method,
+			data,
+			&self.host_functions,
+			self.allocation_strategy,
+		)
 	}
 }
 
 pub fn create_instance(
 	code: &[u8],
 	heap_pages: u64,
+	allocation_strategy: allocator::AllocationStrategy,
 	host_functions: Vec<&'static dyn Function>,
 ) -> Result<WasmiRuntime, WasmError> {
 	let module = Module::from_buffer(&code).map_err(|_| WasmError::InvalidModule)?;
@@ -593,6 +624,7 @@ pub fn create_instance(
 		instance,
 		state_snapshot,
 		host_functions,
+		allocation_strategy,
 	})
 }
 
@@ -0,0 +1,215 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! On-disk cache of the runtimes compiled by Wasmtime.
+//!
+//! The cache is provided by Wasmtime itself: the compilation results are stored in the cache
+//! directory, keyed by the hash of the module code, the target and compiler settings, and the
+//! Wasmtime version. The entries that fail to decompress or decode are discarded and the module is
+//! compiled again. The compiled code doesn't depend on the number of heap pages, which is applied
+//! when the module is instantiated.
+//!
+//! The entries are additionally stored in a sub-directory named after the version of this crate,
+//! so that a different executor never loads the entries of another one.
+//!
+//! As an entry which is damaged on disk but still decodes would be loaded as is, the checksums of
+//! the entries are stored along with them, once they are written by a compilation. When the cache
+//! is set up, the entries whose checksum is missing or doesn't match are removed, so that the
+//! modules are compiled again.
+
+use crate::error::WasmError;
+use codec::{Decode, Encode};
+use log::{info, warn};
+use parking_lot::Mutex;
+use primitives::blake2_256;
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}};
+
+/// Name of the Wasmtime cache configuration file, written in the cache directory.
+const CONFIG_FILE_NAME: &str = "wasmtime-cache.toml";
+
+/// Name of the file of the checksums of the entries, written in the versioned cache directory.
+const CHECKSUMS_FILE_NAME: &str = "checksums";
+
+/// Checksums of the cache entries, by path relative to the versioned cache directory.
+type Checksums = BTreeMap<String, [u8; 32]>;
+
+/// The cache set up for the process.
+struct Cache {
+	/// The directory the cache was initialized with.
+	directory: PathBuf,
+	/// The directory of the entries.
+	versioned_directory: PathBuf,
+	checksums: Checksums,
+}
+
+lazy_static::lazy_static! {
+	/// The cache, if it has been initialized.
+	static ref CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+}
+
+/// Set up the on-disk cache of the runtimes compiled by Wasmtime, in the given directory.
+///
+/// The cache is global to the process and can only be initialized once; the following calls have
+/// no effect.
+pub fn init_compilation_cache(directory: &Path) -> Result<(), WasmError> {
+	let mut cache = CACHE.lock();
+	if let Some(Cache { directory: ref initialized, .. }) = *cache {
+		if initialized != directory {
+			warn!(
+				target: "wasmtime",
+				"Compilation cache is already initialized in {}, ignoring {}",
+				initialized.display(),
+				directory.display(),
+			);
+		}
+		return Ok(());
+	}
+
+	let cache_error = |e: std::io::Error| WasmError::CompilationCache(e.to_string());
+	let versioned_directory = directory.join(env!("CARGO_PKG_VERSION"));
+	fs::create_dir_all(&versioned_directory).map_err(cache_error)?;
+	let versioned_directory = versioned_directory.canonicalize().map_err(cache_error)?;
+	let checksums = verify_entries(&versioned_directory).map_err(cache_error)?;
+
+	let config_file = directory.join(CONFIG_FILE_NAME);
+	let config = format!(
+		"[cache]\nenabled = true\ndirectory = {:?}\n",
+		versioned_directory.to_string_lossy(),
+	);
+	fs::write(&config_file, config).map_err(cache_error)?;
+
+	// Wasmtime only accepts the cache to be initialized once, even if it failed.
+	let errors = wasmtime_environ::cache_init(true, Some(&config_file), None);
+	*cache = Some(Cache {
+		directory: directory.to_path_buf(),
+		versioned_directory: versioned_directory.clone(),
+		checksums,
+	});
+	if !errors.is_empty() {
+		return Err(WasmError::CompilationCache(errors.join(", ")));
+	}
+
+	info!(
+		target: "wasmtime",
+		"Caching compiled runtimes in {}",
+		versioned_directory.display(),
+	);
+	Ok(())
+}
+
+/// Stores the checksums of the entries written by a compilation, if the cache is set up.
+pub fn note_compiled() {
+	if let Some(ref mut cache) = *CACHE.lock() {
+		if let Err(e) = record_entries(&cache.versioned_directory, &mut cache.checksums) {
+			warn!(target: "wasmtime", "Failed to store the checksums of the compiled runtimes: {}", e);
+		}
+	}
+}
+
+/// Removes the entries of the cache whose checksum is missing or doesn't match, and returns the
+/// checksums of the remaining ones.
+fn verify_entries(directory: &Path) -> io::Result<Checksums> {
+	let stored = match fs::read(directory.join(CHECKSUMS_FILE_NAME)) {
+		Ok(encoded) => Checksums::decode(&mut &encoded[..]).unwrap_or_default(),
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => Checksums::new(),
+		Err(e) => return Err(e),
+	};
+	let mut checksums = Checksums::new();
+	for (name, path) in entries(directory)? {
+		let checksum = blake2_256(&fs::read(&path)?);
+		if stored.get(&name) == Some(&checksum) {
+			checksums.insert(name, checksum);
+		} else {
+			warn!(target: "wasmtime", "Removing damaged compiled runtime {}", path.display());
+			fs::remove_file(&path)?;
+		}
+	}
+	fs::write(directory.join(CHECKSUMS_FILE_NAME), checksums.encode())?;
+	Ok(checksums)
+}
+
+/// Adds the checksums of the new entries of the cache, and drops the ones of the removed entries.
+fn record_entries(directory: &Path, checksums: &mut Checksums) -> io::Result<()> {
+	let mut recorded = Checksums::new();
+	for (name, path) in entries(directory)? {
+		let checksum = match checksums.remove(&name) {
+			Some(checksum) => checksum,
+			None => blake2_256(&fs::read(&path)?),
+		};
+		recorded.insert(name, checksum);
+	}
+	if recorded != *checksums {
+		fs::write(directory.join(CHECKSUMS_FILE_NAME), recorded.encode())?;
+	}
+	*checksums = recorded;
+	Ok(())
+}
+
+/// Lists the entries of the cache, with their path relative to the cache directory. The files of
+/// the statistics of the entries and the files being written by Wasmtime are left out.
+fn entries(directory: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+	let mut entries = Vec::new();
+	let mut directories = vec![directory.to_path_buf()];
+	while let Some(current) = directories.pop() {
+		for entry in fs::read_dir(&current)? {
+			let path = entry?.path();
+			if path.is_dir() {
+				directories.push(path);
+				continue;
+			}
+			let name = match path.strip_prefix(directory) {
+				Ok(name) => name.to_string_lossy().into_owned(),
+				Err(_) => continue,
+			};
+			if name == CHECKSUMS_FILE_NAME || name.ends_with(".stats") || name.contains(".wip") {
+				continue;
+			}
+			entries.push((name, path));
+		}
+	}
+	Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn removes_entries_with_missing_or_wrong_checksum() {
+		let directory = tempfile::tempdir().unwrap();
+		let directory = directory.path();
+		fs::create_dir_all(directory.join("modules")).unwrap();
+		fs::write(directory.join("modules/a"), b"compiled a").unwrap();
+		fs::write(directory.join("modules/b"), b"compiled b").unwrap();
+		fs::write(directory.join("modules/b.stats"), b"usage of b").unwrap();
+
+		let mut checksums = Checksums::new();
+		record_entries(directory, &mut checksums).unwrap();
+		assert_eq!(checksums.keys().collect::<Vec<_>>(), vec!["modules/a", "modules/b"]);
+
+		fs::write(directory.join("modules/a"), b"damaged a").unwrap();
+		fs::write(directory.join("modules/c"), b"compiled c").unwrap();
+		let checksums = verify_entries(directory).unwrap();
+		assert_eq!(checksums.keys().collect::<Vec<_>>(), vec!["modules/b"]);
+		assert!(!directory.join("modules/a").exists());
+		assert!(directory.join("modules/b").exists());
+		assert!(directory.join("modules/b.stats").exists());
+		assert!(!directory.join("modules/c").exists());
+
+		// the checksums are stored on disk.
+		assert_eq!(verify_entries(directory).unwrap(), checksums);
+	}
+}
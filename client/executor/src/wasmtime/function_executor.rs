@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::allocator::FreeingBumpHeapAllocator;
+use crate::allocator::{AllocationStrategy, FreeingBumpHeapAllocator};
 use crate::error::{Error, Result};
 use crate::sandbox::{self, SandboxCapabilities, SupervisorFuncIndex};
 use crate::wasmtime::util::{
@@ -54,10 +54,10 @@ pub struct FunctionExecutorState {
 
 impl FunctionExecutorState {
 	/// Constructs a new `FunctionExecutorState`.
-	pub fn new(heap_base: u32) -> Self {
+	pub fn new(heap_base: u32, allocation_strategy: AllocationStrategy) -> Self {
 		FunctionExecutorState {
 			sandbox_store: sandbox::Store::new(),
-			heap: FreeingBumpHeapAllocator::new(heap_base),
+			heap: FreeingBumpHeapAllocator::new(heap_base).with_strategy(allocation_strategy),
 		}
 	}
 
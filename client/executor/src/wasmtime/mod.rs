@@ -16,9 +16,11 @@
 
 ///! Defines a `WasmRuntime` that uses the Wasmtime JIT to execute.
 
+mod cache;
 mod function_executor;
 mod runtime;
 mod trampoline;
 mod util;
 
 pub use runtime::create_instance;
+pub use cache::init_compilation_cache;
@@ -16,6 +16,7 @@
 
 //! Defines the compiled Wasm runtime that uses Wasmtime internally.
 
+use crate::allocator::AllocationStrategy;
 use crate::error::{Error, Result, WasmError};
 use crate::wasm_runtime::WasmRuntime;
 use crate::wasm_utils::interpret_runtime_api_result;
@@ -25,6 +26,7 @@ use crate::wasmtime::util::{cranelift_ir_signature, read_memory_into, write_memo
 use crate::Externalities;
 
 use cranelift_codegen::ir;
+use log::debug;
 use cranelift_codegen::isa::TargetIsa;
 use cranelift_entity::{EntityRef, PrimaryMap};
 use cranelift_frontend::FunctionBuilderContext;
@@ -50,8 +52,19 @@ pub struct WasmtimeRuntime {
 	heap_pages: u32,
 	/// The host functions registered for this instance.
 	host_functions: Vec<&'static dyn Function>,
+	/// The allocation strategy of the heap of the calls.
+	allocation_strategy: AllocationStrategy,
 }
 
+// SAFETY: the compiled module and the context share state through `Rc` (the compiler, the global
+// exports and the modules of the instances), and the instance handles point to the code memory of
+// the compiler. All of them are created by `create_instance` for this runtime only, and no clone
+// or pointer is handed out by the runtime. The thread-local state of Wasmtime, used to handle the
+// traps, is only set during `call`. As `WasmRuntime` isn't `Sync` and `call` takes `&mut self`,
+// that state is only used by the thread owning the runtime, and moving the runtime moves all of it
+// together.
+unsafe impl Send for WasmtimeRuntime {}
+
 impl WasmRuntime for WasmtimeRuntime {
 	fn update_heap_pages(&mut self, heap_pages: u64) -> bool {
 		match heap_pages_valid(heap_pages, self.max_heap_pages) {
@@ -75,6 +88,7 @@ impl WasmRuntime for WasmtimeRuntime {
 			method,
 			data,
 			self.heap_pages,
+			self.allocation_strategy,
 		)
 	}
 }
@@ -84,6 +98,7 @@ impl WasmRuntime for WasmtimeRuntime {
 pub fn create_instance(
 	code: &[u8],
 	heap_pages: u64,
+	allocation_strategy: AllocationStrategy,
 	host_functions: Vec<&'static dyn Function>,
 ) -> std::result::Result<WasmtimeRuntime, WasmError> {
 	let (compiled_module, context) = create_compiled_unit(code, &host_functions)?;
@@ -111,6 +126,7 @@ pub fn create_instance(
 		max_heap_pages,
 		heap_pages,
 		host_functions,
+		allocation_strategy,
 	})
 }
 
@@ -135,6 +151,7 @@ fn create_compiled_unit(
 	// Compile the wasm module.
 	let module = context.compile_module(&code)
 		.map_err(WasmError::WasmtimeSetup)?;
+	super::cache::note_compiled();
 
 	Ok((module, context))
 }
@@ -147,6 +164,7 @@ fn call_method(
 	method: &str,
 	data: &[u8],
 	heap_pages: u32,
+	allocation_strategy: AllocationStrategy,
 ) -> Result<Vec<u8>> {
 	// Old exports get clobbered in `InstanceHandle::new` if we don't explicitly remove them first.
 	//
@@ -167,7 +185,7 @@ fn call_method(
 
 	// Initialize the function executor state.
 	let heap_base = get_heap_base(&instance)?;
-	let executor_state = FunctionExecutorState::new(heap_base);
+	let executor_state = FunctionExecutorState::new(heap_base, allocation_strategy);
 	reset_env_state_and_take_trap(context, Some(executor_state))?;
 
 	// Write the input data into guest memory.
@@ -180,6 +198,10 @@ fn call_method(
 			.invoke(&mut instance, method, &args[..])
 			.map_err(Error::Wasmtime)
 	})?;
+	if let Some(executor_state) = get_env_state(context)?.executor_state.as_mut() {
+		let stats = executor_state.heap().stats();
+		debug!(target: "wasm-heap", "Heap usage of `{}`: {}", method, stats);
+	}
 	let trap_error = reset_env_state_and_take_trap(context, None)?;
 	let (output_ptr, output_len) = match outcome {
 		ActionOutcome::Returned { values } => match values.as_slice() {
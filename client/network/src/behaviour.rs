@@ -63,6 +63,7 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Behaviour<B, S, H> {
 		known_addresses: Vec<(PeerId, Multiaddr)>,
 		enable_mdns: bool,
 		allow_private_ipv4: bool,
+		enable_kademlia: bool,
 	) -> Self {
 		Behaviour {
 			substrate,
@@ -71,7 +72,8 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Behaviour<B, S, H> {
 				local_public_key,
 				known_addresses,
 				enable_mdns,
-				allow_private_ipv4
+				allow_private_ipv4,
+				enable_kademlia,
 			),
 			events: Vec::new(),
 		}
@@ -148,6 +150,7 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> NetworkBehaviourEventPr
 		for addr in &info.listen_addrs {
 			self.discovery.add_self_reported_address(&peer_id, addr.clone());
 		}
+		self.substrate.add_known_addresses(&peer_id, info.listen_addrs.into_iter());
 		self.substrate.add_discovered_nodes(iter::once(peer_id.clone()));
 	}
 }
@@ -163,6 +166,8 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> NetworkBehaviourEventPr
 				// implementation for `DebugInfoEvent`.
 			}
 			DiscoveryOut::Discovered(peer_id) => {
+				let addresses = libp2p::swarm::NetworkBehaviour::addresses_of_peer(&mut self.discovery, &peer_id);
+				self.substrate.add_known_addresses(&peer_id, addresses.into_iter());
 				self.substrate.add_discovered_nodes(iter::once(peer_id));
 			}
 			DiscoveryOut::ValueFound(results) => {
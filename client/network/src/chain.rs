@@ -20,10 +20,10 @@ use client::Client as SubstrateClient;
 use sp_blockchain::Error;
 use client_api::{ChangesProof, StorageProof, ClientInfo, CallExecutor};
 use consensus::{BlockImport, BlockStatus, Error as ConsensusError};
-use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, NumberFor};
 use sp_runtime::generic::{BlockId};
 use sp_runtime::Justification;
-use primitives::{H256, Blake2Hasher, storage::StorageKey};
+use primitives::{H256, Blake2Hasher, storage::{StorageKey, StorageOverlay, ChildrenStorageOverlay}};
 
 /// Local client abstraction for the network.
 pub trait Client<Block: BlockT>: Send + Sync {
@@ -74,6 +74,39 @@ pub trait Client<Block: BlockT>: Send + Sync {
 		key: &StorageKey
 	) -> Result<ChangesProof<Block::Header>, Error>;
 
+	/// Get a range of the state entries at a given block, starting at `start`, along with its
+	/// proof. The returned flag is `true` if there are no more entries after the range.
+	fn state_range(
+		&self,
+		block: &Block::Hash,
+		storage_key: Option<&[u8]>,
+		start: &[u8],
+		max_keys: usize,
+		max_size: usize,
+	) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, bool, StorageProof), Error>;
+
+	/// Check a range of state entries against the root of the trie they were read from.
+	fn check_state_range(
+		&self,
+		root: &Block::Hash,
+		start: &[u8],
+		entries: &[(Vec<u8>, Vec<u8>)],
+		complete: bool,
+		proof: StorageProof,
+	) -> Result<(), Error>;
+
+	/// Import the full state of a block which has been imported without state.
+	fn import_state(
+		&self,
+		block: Block::Hash,
+		top: StorageOverlay,
+		children: ChildrenStorageOverlay,
+	) -> Result<(), Error>;
+
+	/// Revert the `n` best blocks, down to the last finalized block at most. Returns the number
+	/// of blocks that have been reverted.
+	fn revert(&self, n: NumberFor<Block>) -> Result<NumberFor<Block>, Error>;
+
 	/// Returns `true` if the given `block` is a descendent of `base`.
 	fn is_descendent_of(&self, base: &Block::Hash, block: &Block::Hash) -> Result<bool, Error>;
 }
@@ -157,6 +190,42 @@ impl<B, E, Block, RA> Client<Block> for SubstrateClient<B, E, Block, RA> where
 		(self as &SubstrateClient<B, E, Block, RA>).key_changes_proof(first, last, min, max, storage_key, key)
 	}
 
+	fn state_range(
+		&self,
+		block: &Block::Hash,
+		storage_key: Option<&[u8]>,
+		start: &[u8],
+		max_keys: usize,
+		max_size: usize,
+	) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, bool, StorageProof), Error> {
+		(self as &SubstrateClient<B, E, Block, RA>)
+			.state_range(&BlockId::Hash(block.clone()), storage_key, start, max_keys, max_size)
+	}
+
+	fn check_state_range(
+		&self,
+		root: &Block::Hash,
+		start: &[u8],
+		entries: &[(Vec<u8>, Vec<u8>)],
+		complete: bool,
+		proof: StorageProof,
+	) -> Result<(), Error> {
+		(self as &SubstrateClient<B, E, Block, RA>).check_state_range(root, start, entries, complete, proof)
+	}
+
+	fn import_state(
+		&self,
+		block: Block::Hash,
+		top: StorageOverlay,
+		children: ChildrenStorageOverlay,
+	) -> Result<(), Error> {
+		(self as &SubstrateClient<B, E, Block, RA>).import_state(block, top, children)
+	}
+
+	fn revert(&self, n: NumberFor<Block>) -> Result<NumberFor<Block>, Error> {
+		(self as &SubstrateClient<B, E, Block, RA>).revert(n)
+	}
+
 	fn is_descendent_of(&self, base: &Block::Hash, block: &Block::Hash) -> Result<bool, Error> {
 		if base == block {
 			return Ok(false);
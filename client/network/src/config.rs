@@ -32,7 +32,11 @@ use libp2p::identity::{Keypair, ed25519};
 use libp2p::wasm_ext;
 use libp2p::{PeerId, Multiaddr, multiaddr};
 use core::{fmt, iter};
-use std::{error::Error, fs, io::{self, Write}, net::Ipv4Addr, path::{Path, PathBuf}, sync::Arc};
+use parking_lot::RwLock;
+use std::{
+	collections::HashSet, error::Error, fs, io::{self, Write}, net::Ipv4Addr,
+	path::{Path, PathBuf}, sync::Arc,
+};
 use zeroize::Zeroize;
 
 /// Network initialization parameters.
@@ -255,6 +259,9 @@ pub struct NetworkConfiguration {
 	pub reserved_nodes: Vec<String>,
 	/// The non-reserved peer mode.
 	pub non_reserved_mode: NonReservedPeerMode,
+	/// If `Some`, the node is part of a private network: only the peers on this allow-list can
+	/// connect to it, and peer discovery is disabled.
+	pub allowed_peers: Option<AllowedPeers>,
 	/// Client identifier. Sent over the wire for debugging purposes.
 	pub client_version: String,
 	/// Name of the node. Sent over the wire for debugging purposes.
@@ -263,6 +270,13 @@ pub struct NetworkConfiguration {
 	pub transport: TransportConfig,
 	/// Maximum number of peers to ask the same blocks in parallel.
 	pub max_parallel_downloads: u32,
+	/// Limits of the bandwidth used by the node.
+	pub bandwidth_limits: BandwidthLimits,
+	/// How full nodes synchronize with the chain.
+	pub sync_mode: SyncMode,
+	/// If true, the known peers, their addresses and reputations are saved in `net_config_path`
+	/// and restored on startup.
+	pub persist_peers: bool,
 }
 
 impl Default for NetworkConfiguration {
@@ -278,6 +292,7 @@ impl Default for NetworkConfiguration {
 			out_peers: 75,
 			reserved_nodes: Vec::new(),
 			non_reserved_mode: NonReservedPeerMode::Accept,
+			allowed_peers: None,
 			client_version: "unknown".into(),
 			node_name: "unknown".into(),
 			transport: TransportConfig::Normal {
@@ -286,6 +301,9 @@ impl Default for NetworkConfiguration {
 				wasm_external_transport: None,
 			},
 			max_parallel_downloads: 5,
+			bandwidth_limits: BandwidthLimits::default(),
+			sync_mode: SyncMode::Full,
+			persist_peers: false,
 		}
 	}
 }
@@ -367,6 +385,79 @@ impl NonReservedPeerMode {
 	}
 }
 
+/// How full nodes synchronize with the chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+	/// Download and execute all the blocks. This is the default.
+	Full,
+	/// Download the headers, then the state of a recent finalized block, then continue with a
+	/// full sync. Only used when the node has no state yet.
+	Warp,
+}
+
+/// Limits of the bandwidth used by the node, in bytes per second.
+///
+/// `None` (or zero) means unlimited. When the upload rate gets close to a limit, gossip messages
+/// are held back so that the remaining bandwidth is used by the sync.
+#[derive(Clone, Debug, Default)]
+pub struct BandwidthLimits {
+	/// Maximum upload rate of all the connections together.
+	pub upload: Option<u64>,
+	/// Maximum download rate of all the connections together.
+	pub download: Option<u64>,
+	/// Maximum upload rate to each peer.
+	pub peer_upload: Option<u64>,
+	/// Maximum download rate from each peer.
+	pub peer_download: Option<u64>,
+}
+
+/// The allow-list of a private network.
+///
+/// Clones share the same list, so that it can be updated while the network is running.
+#[derive(Clone, Debug, Default)]
+pub struct AllowedPeers(Arc<RwLock<HashSet<PeerId>>>);
+
+impl AllowedPeers {
+	/// Create an allow-list with the given peers.
+	pub fn new(peers: impl IntoIterator<Item = PeerId>) -> Self {
+		AllowedPeers(Arc::new(RwLock::new(peers.into_iter().collect())))
+	}
+
+	/// Load the allow-list from a file.
+	///
+	/// The file contains one base58-encoded `PeerId` per line. Empty lines and lines starting
+	/// with `#` are ignored.
+	pub fn from_file(path: &Path) -> io::Result<Self> {
+		let content = fs::read_to_string(path)?;
+		let peers = content.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.map(|line| line.parse::<PeerId>().map_err(|_| io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("Invalid peer id in {}: {}", path.display(), line),
+			)))
+			.collect::<io::Result<Vec<_>>>()?;
+		Ok(AllowedPeers::new(peers))
+	}
+
+	/// Returns true if the peer is allowed to connect.
+	pub fn contains(&self, peer_id: &PeerId) -> bool {
+		self.0.read().contains(peer_id)
+	}
+
+	/// Returns the allowed peers.
+	pub fn peers(&self) -> Vec<PeerId> {
+		self.0.read().iter().cloned().collect()
+	}
+
+	/// Replace the allowed peers.
+	///
+	/// Only applies to new connections.
+	pub fn set(&self, peers: impl IntoIterator<Item = PeerId>) {
+		*self.0.write() = peers.into_iter().collect();
+	}
+}
+
 /// The configuration of a node's secret key, describing the type of key
 /// and how it is obtained. A node's identity keypair is the result of
 /// the evaluation of the node key configuration.
@@ -540,4 +631,22 @@ mod tests {
 		let kp2 = NodeKeyConfig::Ed25519(Secret::New).into_keypair().unwrap();
 		assert!(secret_bytes(&kp1) != secret_bytes(&kp2));
 	}
+
+	#[test]
+	fn test_allowed_peers_file() {
+		let tmp = tempdir_with_prefix("allowed-peers");
+		let file = tmp.path().join("allowed");
+		let (peer1, peer2) = (PeerId::random(), PeerId::random());
+		std::fs::write(&file, format!("# Comment\n{}\n\n  {}  \n", peer1.to_base58(), peer2.to_base58())).unwrap();
+
+		let allowed = AllowedPeers::from_file(&file).unwrap();
+		assert!(allowed.contains(&peer1) && allowed.contains(&peer2));
+		assert!(!allowed.contains(&PeerId::random()));
+
+		allowed.clone().set(vec![peer1.clone()]);
+		assert!(allowed.contains(&peer1) && !allowed.contains(&peer2));
+
+		std::fs::write(&file, "not a peer id").unwrap();
+		assert_eq!(AllowedPeers::from_file(&file).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
 }
@@ -30,6 +30,9 @@
 //! order for nodes to propagate to us their view of the network. This is performed automatically
 //! by the `DiscoveryBehaviour`.
 //!
+//! Only bootstrap nodes are used on private networks, where mDNS and Kademlia random walks are
+//! disabled.
+//!
 //! Additionally, the `DiscoveryBehaviour` is also capable of storing and loading value in the
 //! network-wide DHT.
 //!
@@ -88,6 +91,9 @@ pub struct DiscoveryBehaviour<TSubstream> {
 	/// If false, `addresses_of_peer` won't return any private IPv4 address, except for the ones
 	/// stored in `user_defined`.
 	allow_private_ipv4: bool,
+	/// If false, we don't perform random Kademlia queries and don't report the nodes found
+	/// through Kademlia. The DHT can still be used to store and fetch records.
+	enable_kademlia: bool,
 }
 
 impl<TSubstream> DiscoveryBehaviour<TSubstream> {
@@ -99,6 +105,7 @@ impl<TSubstream> DiscoveryBehaviour<TSubstream> {
 		user_defined: Vec<(PeerId, Multiaddr)>,
 		enable_mdns: bool,
 		allow_private_ipv4: bool,
+		enable_kademlia: bool,
 	) -> Self {
 		if enable_mdns {
 			#[cfg(target_os = "unknown")]
@@ -121,6 +128,7 @@ impl<TSubstream> DiscoveryBehaviour<TSubstream> {
 			local_peer_id: local_public_key.into_peer_id(),
 			num_connections: 0,
 			allow_private_ipv4,
+			enable_kademlia,
 			#[cfg(not(target_os = "unknown"))]
 			mdns: if enable_mdns {
 				match Mdns::new() {
@@ -301,7 +309,7 @@ where
 		}
 
 		// Poll the stream that fires when we need to start a random Kademlia query.
-		loop {
+		while self.enable_kademlia {
 			match self.next_kad_random_query.poll() {
 				Ok(Async::NotReady) => break,
 				Ok(Async::Ready(_)) => {
@@ -334,8 +342,10 @@ where
 						return Async::Ready(NetworkBehaviourAction::GenerateEvent(ev));
 					}
 					KademliaEvent::RoutingUpdated { peer, .. } => {
-						let ev = DiscoveryOut::Discovered(peer);
-						return Async::Ready(NetworkBehaviourAction::GenerateEvent(ev));
+						if self.enable_kademlia {
+							let ev = DiscoveryOut::Discovered(peer);
+							return Async::Ready(NetworkBehaviourAction::GenerateEvent(ev));
+						}
 					}
 					KademliaEvent::GetClosestPeersResult(res) => {
 						match res {
@@ -480,7 +490,7 @@ mod tests {
 					upgrade::apply(stream, upgrade, endpoint, libp2p::core::upgrade::Version::V1)
 				});
 
-			let behaviour = DiscoveryBehaviour::new(keypair.public(), user_defined.clone(), false, true);
+			let behaviour = DiscoveryBehaviour::new(keypair.public(), user_defined.clone(), false, true, true);
 			let mut swarm = Swarm::new(transport, behaviour, keypair.public().into_peer_id());
 			let listen_addr: Multiaddr = format!("/memory/{}", rand::random::<u64>()).parse().unwrap();
 
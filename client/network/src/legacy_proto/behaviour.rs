@@ -369,6 +369,11 @@ impl<TSubstream> LegacyProto<TSubstream> {
 		self.peerset.debug_info()
 	}
 
+	/// Records addresses of a peer in the peer store of the peerset manager.
+	pub fn add_known_addresses(&mut self, peer_id: &PeerId, addresses: impl Iterator<Item = Multiaddr>) {
+		self.peerset.add_known_addresses(peer_id, addresses)
+	}
+
 	/// Function that is called when the peerset wants us to connect to a node.
 	fn peerset_report_connect(&mut self, peer_id: PeerId) {
 		let mut occ_entry = match self.peers.entry(peer_id) {
@@ -893,6 +898,7 @@ where
 					}
 				};
 
+				self.peerset.note_seen(&source);
 				debug!(target: "sub-libp2p", "External API <= Open({:?})", source);
 				let event = LegacyProtoOut::CustomProtocolOpen {
 					version,
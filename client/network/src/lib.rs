@@ -176,6 +176,7 @@ mod discovery;
 mod on_demand_layer;
 mod protocol;
 mod service;
+mod throttle;
 mod transport;
 mod utils;
 
@@ -42,8 +42,9 @@ use consensus_gossip::{ConsensusGossip, MessageRecipient as GossipMessageRecipie
 use light_dispatch::{LightDispatch, LightDispatchNetwork, RequestData};
 use specialization::NetworkSpecialization;
 use sync::{ChainSync, SyncState};
+use state_requests::StateRequests;
 use crate::service::{TransactionPool, ExHashT};
-use crate::config::{BoxFinalityProofRequestBuilder, Roles};
+use crate::config::{BoxFinalityProofRequestBuilder, Roles, SyncMode};
 use rustc_hex::ToHex;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
@@ -53,6 +54,7 @@ use log::{log, Level, trace, debug, warn, error};
 use crate::chain::{Client, FinalityProofProvider};
 use client_api::{FetchChecker, ChangesProof, StorageProof};
 use crate::error;
+use crate::throttle::Throttle;
 use util::LruHashSet;
 
 mod util;
@@ -62,6 +64,7 @@ pub mod event;
 pub mod light_dispatch;
 pub mod specialization;
 pub mod sync;
+mod state_requests;
 
 const REQUEST_TIMEOUT_SEC: u64 = 40;
 /// Interval at which we perform time based maintenance
@@ -75,9 +78,11 @@ const MAX_KNOWN_BLOCKS: usize = 1024; // ~32kb per peer + LruHashSet overhead
 const MAX_KNOWN_EXTRINSICS: usize = 4096; // ~128kb per peer + overhead
 
 /// Current protocol version.
-pub(crate) const CURRENT_VERSION: u32 = 5;
+pub(crate) const CURRENT_VERSION: u32 = 6;
 /// Lowest version we support
 pub(crate) const MIN_VERSION: u32 = 3;
+/// Lowest version supporting state requests.
+const STATE_REQUEST_VERSION: u32 = 6;
 
 // Maximum allowed entries in `BlockResponse`
 const MAX_BLOCK_DATA_RESPONSE: u32 = 128;
@@ -130,6 +135,8 @@ pub struct Protocol<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> {
 	transaction_pool: Arc<dyn TransactionPool<H, B>>,
 	/// When asked for a proof of finality, we use this struct to build one.
 	finality_proof_provider: Option<Arc<dyn FinalityProofProvider<B>>>,
+	/// Answers the state requests of warp syncing peers.
+	state_requests: StateRequests<B>,
 	/// Handles opening the unique substream and sending and receiving raw messages.
 	behaviour: LegacyProto<Substream<StreamMuxerBox>>,
 }
@@ -311,6 +318,10 @@ pub trait Context<B: BlockT> {
 	/// Send a consensus message to a peer.
 	fn send_consensus(&mut self, who: PeerId, messages: Vec<ConsensusMessage>);
 
+	/// Whether the upload to a peer exceeds its bandwidth limit. The consensus messages are always
+	/// sent, so it's up to the caller to hold back the ones which may be sent later.
+	fn is_upload_congested(&self, who: &PeerId) -> bool;
+
 	/// Send a chain-specific message to a peer.
 	fn send_chain_specific(&mut self, who: PeerId, message: Vec<u8>);
 }
@@ -370,6 +381,10 @@ impl<'a, B: BlockT + 'a, H: ExHashT + 'a> Context<B> for ProtocolContext<'a, B,
 		}
 	}
 
+	fn is_upload_congested(&self, who: &PeerId) -> bool {
+		self.context_data.throttle.is_upload_congested(who)
+	}
+
 	fn send_chain_specific(&mut self, who: PeerId, message: Vec<u8>) {
 		send_message::<B> (
 			self.behaviour,
@@ -386,6 +401,8 @@ struct ContextData<B: BlockT, H: ExHashT> {
 	peers: HashMap<PeerId, Peer<B, H>>,
 	stats: HashMap<&'static str, PacketStats>,
 	pub chain: Arc<dyn Client<B>>,
+	// Bandwidth limits, used to hold back gossip when the upload is congested.
+	throttle: Arc<Throttle>,
 }
 
 /// Configuration for the Substrate-specific part of the networking layer.
@@ -395,6 +412,10 @@ pub struct ProtocolConfig {
 	pub roles: Roles,
 	/// Maximum number of peers to ask the same blocks in parallel.
 	pub max_parallel_downloads: u32,
+	/// Bandwidth limits of the node.
+	pub throttle: Arc<Throttle>,
+	/// How full nodes synchronize with the chain.
+	pub sync_mode: SyncMode,
 }
 
 impl Default for ProtocolConfig {
@@ -402,6 +423,8 @@ impl Default for ProtocolConfig {
 		ProtocolConfig {
 			roles: Roles::FULL,
 			max_parallel_downloads: 5,
+			throttle: Arc::new(Throttle::new(&Default::default())),
+			sync_mode: SyncMode::Full,
 		}
 	}
 }
@@ -428,6 +451,7 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 			finality_proof_request_builder,
 			block_announce_validator,
 			config.max_parallel_downloads,
+			config.sync_mode,
 		);
 
 		let important_peers = {
@@ -443,15 +467,17 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 		let versions = &((MIN_VERSION as u8)..=(CURRENT_VERSION as u8)).collect::<Vec<u8>>();
 		let behaviour = LegacyProto::new(protocol_id, versions, peerset);
 
+		let state_requests = StateRequests::new(chain.clone());
 		let protocol = Protocol {
 			tick_timeout: Box::new(interval(TICK_TIMEOUT).map(|v| Ok::<_, ()>(v)).compat()),
 			propagate_timeout: Box::new(interval(PROPAGATE_TIMEOUT).map(|v| Ok::<_, ()>(v)).compat()),
-			config,
 			context_data: ContextData {
 				peers: HashMap::new(),
 				stats: HashMap::new(),
 				chain,
+				throttle: config.throttle.clone(),
 			},
+			config,
 			light_dispatch: LightDispatch::new(checker),
 			genesis_hash: info.chain.genesis_hash,
 			sync,
@@ -461,6 +487,7 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 			important_peers,
 			transaction_pool,
 			finality_proof_provider,
+			state_requests,
 			peerset_handle: peerset_handle.clone(),
 			behaviour,
 		};
@@ -493,6 +520,11 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 		self.behaviour.peerset_debug_info()
 	}
 
+	/// Records addresses of a peer, so that they are saved in the peer store.
+	pub fn add_known_addresses(&mut self, peer_id: &PeerId, addresses: impl Iterator<Item = Multiaddr>) {
+		self.behaviour.add_known_addresses(peer_id, addresses)
+	}
+
 	/// Returns the number of peers we're connected to.
 	pub fn num_connected_peers(&self) -> usize {
 		self.context_data.peers.values().count()
@@ -639,6 +671,10 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 				return self.on_finality_proof_response(who, response),
 			GenericMessage::RemoteReadChildRequest(request) =>
 				self.on_remote_read_child_request(who, request),
+			GenericMessage::StateRequest(request) =>
+				self.on_state_request(who, request),
+			GenericMessage::StateResponse(response) =>
+				self.on_state_response(who, response),
 			GenericMessage::Consensus(msg) => {
 				self.consensus_gossip.on_incoming(
 					&mut ProtocolContext::new(&mut self.context_data, &mut self.behaviour, &self.peerset_handle),
@@ -811,17 +847,23 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 			} else {
 				None
 			};
+			let body = if get_body {
+				match self.context_data.chain.body(&BlockId::Hash(hash)).unwrap_or(None) {
+					Some(body) => Some(body),
+					None => {
+						// The body has been pruned. Blocks without a body would be imported
+						// without being executed, so we stop here.
+						trace!(target: "sync", "Missing body of block #{} ({}) requested by {}", number, hash, peer);
+						break;
+					}
+				}
+			} else {
+				None
+			};
 			let block_data = message::generic::BlockData {
 				hash: hash,
 				header: if get_header { Some(header) } else { None },
-				body: if get_body {
-					self.context_data
-						.chain
-						.body(&BlockId::Hash(hash))
-						.unwrap_or(None)
-				} else {
-					None
-				},
+				body,
 				receipt: None,
 				message_queue: None,
 				justification,
@@ -1127,6 +1169,11 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 				continue;
 			}
 
+			// leave the bandwidth to the sync, the extrinsics will be sent on the next propagation
+			if self.context_data.throttle.is_upload_congested(who) {
+				continue;
+			}
+
 			let (hashes, to_send): (Vec<_>, Vec<_>) = extrinsics
 				.iter()
 				.filter(|&(ref hash, _)| peer.known_extrinsics.insert(hash.clone()))
@@ -1707,6 +1754,47 @@ impl<B: BlockT, S: NetworkSpecialization<B>, H: ExHashT> Protocol<B, S, H> {
 		}
 	}
 
+	fn on_state_request(
+		&mut self,
+		who: PeerId,
+		request: message::StateRequest<B::Hash>,
+	) {
+		trace!(target: "sync", "State request {} from {} ({:?} from {} at {})",
+			request.id,
+			who,
+			request.storage_key.as_ref().map(|k| k.to_hex::<String>()),
+			request.start.to_hex::<String>(),
+			request.block,
+		);
+		let id = request.id;
+		if !self.state_requests.push(who.clone(), request) {
+			debug!(target: "sync", "Too many pending state requests, dropping request {} from {}", id, who);
+		}
+	}
+
+	fn on_state_response(
+		&mut self,
+		who: PeerId,
+		response: message::StateResponse,
+	) {
+		trace!(target: "sync", "State response {} from {} with {} entries", response.id, who, response.entries.len());
+		for result in self.sync.on_state_data(who, response) {
+			match result {
+				Ok((id, req)) => send_request(
+					&mut self.behaviour,
+					&mut self.context_data.stats,
+					&mut self.context_data.peers,
+					&id,
+					GenericMessage::BlockRequest(req),
+				),
+				Err(sync::BadPeer(id, repu)) => {
+					self.behaviour.disconnect_peer(&id);
+					self.peerset_handle.report_peer(id, repu)
+				}
+			}
+		}
+	}
+
 	fn on_remote_body_response(
 		&mut self,
 		peer: PeerId,
@@ -1850,6 +1938,24 @@ Protocol<B, S, H> {
 				&id,
 				GenericMessage::FinalityProofRequest(r))
 		}
+		while let Some((id, response)) = self.state_requests.poll_response() {
+			send_message::<B>(
+				&mut self.behaviour,
+				&mut self.context_data.stats,
+				&id,
+				GenericMessage::StateResponse(response))
+		}
+		let peers = &self.context_data.peers;
+		let state_requests = self.sync.state_requests(|id| peers.get(id)
+			.map_or(false, |peer| peer.info.protocol_version >= STATE_REQUEST_VERSION)
+		).collect::<Vec<_>>();
+		for (id, r) in state_requests {
+			send_message::<B>(
+				&mut self.behaviour,
+				&mut self.context_data.stats,
+				&id,
+				GenericMessage::StateRequest(r))
+		}
 
 		let event = match self.behaviour.poll(params) {
 			Async::NotReady => return Async::NotReady,
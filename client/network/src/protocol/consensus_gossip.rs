@@ -203,6 +203,13 @@ fn propagate<'a, B: BlockT, I>(
 	};
 
 	for (id, ref mut peer) in peers.iter_mut() {
+		// The messages aren't marked as known by a congested peer, so that they are sent to it
+		// on a later propagation, at the latest on the periodic rebroadcast.
+		if protocol.is_upload_congested(id) {
+			trace!(target: "gossip", "Upload to {} is congested, holding back the messages", id);
+			continue;
+		}
+
 		let mut batch = Vec::new();
 		for (message_hash, topic, message) in messages.clone() {
 			let intent = match intent {
@@ -542,6 +549,11 @@ impl<B: BlockT> ConsensusGossip<B> {
 			Some(validator) => validator.message_allowed(),
 		};
 
+		if protocol.is_upload_congested(who) {
+			trace!(target: "gossip", "Upload to {} is congested, holding back the topic messages", who);
+			return;
+		}
+
 		if let Some(ref mut peer) = self.peers.get_mut(who) {
 			let mut batch = Vec::new();
 			for entry in self.messages.iter().filter(|m| m.topic == topic && m.message.engine_id == engine_id) {
@@ -664,6 +676,48 @@ mod tests {
 		}
 	}
 
+	#[derive(Default)]
+	struct TestContext {
+		congested: HashSet<PeerId>,
+		sent: Vec<(PeerId, Vec<ConsensusMessage>)>,
+	}
+
+	impl Context<Block> for TestContext {
+		fn report_peer(&mut self, _who: PeerId, _reputation: i32) {}
+
+		fn disconnect_peer(&mut self, _who: PeerId) {}
+
+		fn send_consensus(&mut self, who: PeerId, messages: Vec<ConsensusMessage>) {
+			if !messages.is_empty() {
+				self.sent.push((who, messages));
+			}
+		}
+
+		fn is_upload_congested(&self, who: &PeerId) -> bool {
+			self.congested.contains(who)
+		}
+
+		fn send_chain_specific(&mut self, _who: PeerId, _message: Vec<u8>) {}
+	}
+
+	#[test]
+	fn congested_peer_gets_message_later() {
+		let mut context = TestContext::default();
+		let mut consensus = ConsensusGossip::<Block>::new();
+		consensus.register_validator_internal([0, 0, 0, 0], Arc::new(AllowAll));
+		let peer = PeerId::random();
+		consensus.new_peer(&mut context, peer.clone(), Roles::FULL);
+
+		context.congested.insert(peer.clone());
+		let message = ConsensusMessage { data: vec![1, 2, 3], engine_id: [0, 0, 0, 0] };
+		consensus.multicast(&mut context, H256::default(), message.clone(), false);
+		assert!(context.sent.is_empty());
+
+		context.congested.clear();
+		consensus.rebroadcast(&mut context);
+		assert_eq!(context.sent, vec![(peer, vec![message])]);
+	}
+
 	#[test]
 	fn collects_garbage() {
 		struct AllowOne;
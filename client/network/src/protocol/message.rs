@@ -24,7 +24,7 @@ pub use self::generic::{
 	RemoteHeaderRequest, RemoteHeaderResponse,
 	RemoteChangesRequest, RemoteChangesResponse,
 	FinalityProofRequest, FinalityProofResponse,
-	FromBlock, RemoteReadChildRequest,
+	FromBlock, RemoteReadChildRequest, StateRequest,
 };
 use client_api::StorageProof;
 
@@ -135,6 +135,19 @@ pub struct RemoteReadResponse {
 	pub proof: StorageProof,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+/// State chunk response.
+pub struct StateResponse {
+	/// Id of a request this response was made for.
+	pub id: RequestId,
+	/// Entries of the state in key order, starting at the requested key.
+	pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+	/// `true` if there are no more entries after the sent ones.
+	pub complete: bool,
+	/// Proof of the range of entries.
+	pub proof: StorageProof,
+}
+
 /// Generic types.
 pub mod generic {
 	use codec::{Encode, Decode, Input, Output};
@@ -143,7 +156,7 @@ pub mod generic {
 	use super::{
 		RemoteReadResponse, Transactions, Direction,
 		RequestId, BlockAttributes, RemoteCallResponse, ConsensusEngineId,
-		BlockState, StorageProof,
+		BlockState, StorageProof, StateResponse,
 	};
 	/// Consensus is mostly opaque to us
 	#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
@@ -219,6 +232,10 @@ pub mod generic {
 		FinalityProofResponse(FinalityProofResponse<Hash>),
 		/// Batch of consensus protocol messages.
 		ConsensusBatch(Vec<ConsensusMessage>),
+		/// State chunk request.
+		StateRequest(StateRequest<Hash>),
+		/// State chunk response.
+		StateResponse(StateResponse),
 		/// Chain-specific message.
 		#[codec(index = "255")]
 		ChainSpecific(Vec<u8>),
@@ -246,6 +263,8 @@ pub mod generic {
 				Message::FinalityProofRequest(_) => "FinalityProofRequest",
 				Message::FinalityProofResponse(_) => "FinalityProofResponse",
 				Message::ConsensusBatch(_) => "ConsensusBatch",
+				Message::StateRequest(_) => "StateRequest",
+				Message::StateResponse(_) => "StateResponse",
 				Message::ChainSpecific(_) => "ChainSpecific",
 			}
 		}
@@ -449,4 +468,17 @@ pub mod generic {
 		/// Finality proof (if available).
 		pub proof: Option<Vec<u8>>,
 	}
+
+	#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+	/// Request for a chunk of the state of a block.
+	pub struct StateRequest<H> {
+		/// Unique request id.
+		pub id: RequestId,
+		/// Hash of the block to read the state of.
+		pub block: H,
+		/// Child storage key, if the entries of a child trie are requested.
+		pub storage_key: Option<Vec<u8>>,
+		/// Key of the first requested entry. The entries are sent in key order from there.
+		pub start: Vec<u8>,
+	}
 }
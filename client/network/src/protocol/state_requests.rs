@@ -0,0 +1,122 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Answers the state requests of warp syncing peers.
+//!
+//! Reading and proving a range of the state is too slow to be done on the network thread, so the
+//! requests are handed to a dedicated thread through a bounded queue. Requests arriving while the
+//! queue is full, and requests at blocks whose state is not available, are not answered: the
+//! peer eventually asks another peer.
+
+use std::sync::{Arc, mpsc::{sync_channel, SyncSender, TrySendError}};
+use std::thread;
+use futures::{prelude::*, sync::mpsc};
+use libp2p::PeerId;
+use log::{trace, warn};
+use rustc_hex::ToHex;
+use sp_runtime::traits::Block as BlockT;
+use crate::chain::Client;
+use crate::message::{self, StateResponse};
+
+/// Maximum number of entries of a state response.
+const MAX_STATE_RESPONSE_ENTRIES: usize = 4096;
+
+/// Maximum size of the entries of a state response.
+const MAX_STATE_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Maximum number of state requests waiting to be answered.
+const MAX_PENDING_STATE_REQUESTS: usize = 16;
+
+/// Handle to the thread answering state requests.
+pub struct StateRequests<B: BlockT> {
+	requests: SyncSender<(PeerId, message::StateRequest<B::Hash>)>,
+	responses: mpsc::UnboundedReceiver<(PeerId, StateResponse)>,
+}
+
+impl<B: BlockT> StateRequests<B> {
+	/// Spawn the thread answering the requests from the state of `chain`.
+	pub fn new(chain: Arc<dyn Client<B>>) -> Self {
+		let (requests, requests_rx) = sync_channel(MAX_PENDING_STATE_REQUESTS);
+		let (responses_tx, responses) = mpsc::unbounded();
+		let spawned = thread::Builder::new()
+			.name("state-requests".into())
+			.spawn(move || {
+				// the thread ends when the protocol, holding the sender, is dropped.
+				for (who, request) in requests_rx {
+					if let Some(response) = answer(&*chain, &who, request) {
+						if responses_tx.unbounded_send((who, response)).is_err() {
+							break;
+						}
+					}
+				}
+			});
+		if let Err(e) = spawned {
+			// requests then fail to be queued and are dropped.
+			warn!(target: "sync", "Failed to spawn the state requests thread: {}", e);
+		}
+		StateRequests { requests, responses }
+	}
+
+	/// Queue a request. Returns `false` if it has been dropped because too many requests are
+	/// waiting to be answered.
+	pub fn push(&self, who: PeerId, request: message::StateRequest<B::Hash>) -> bool {
+		match self.requests.try_send((who, request)) {
+			Ok(()) => true,
+			Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+		}
+	}
+
+	/// Returns the next answered request, if any.
+	pub fn poll_response(&mut self) -> Option<(PeerId, StateResponse)> {
+		match self.responses.poll() {
+			Ok(Async::Ready(Some(response))) => Some(response),
+			Ok(Async::Ready(None)) | Ok(Async::NotReady) | Err(()) => None,
+		}
+	}
+}
+
+fn answer<B: BlockT>(
+	chain: &dyn Client<B>,
+	who: &PeerId,
+	request: message::StateRequest<B::Hash>,
+) -> Option<StateResponse> {
+	let (entries, complete, proof) = match chain.state_range(
+		&request.block,
+		request.storage_key.as_ref().map(|k| &k[..]),
+		&request.start,
+		MAX_STATE_RESPONSE_ENTRIES,
+		MAX_STATE_RESPONSE_SIZE,
+	) {
+		Ok(range) => range,
+		Err(error) => {
+			trace!(target: "sync", "State request {} from {} at {} failed with: {}",
+				request.id,
+				who,
+				request.block,
+				error
+			);
+			return None
+		}
+	};
+	trace!(target: "sync", "Answering state request {} from {} ({:?} from {}) with {} entries",
+		request.id,
+		who,
+		request.storage_key.as_ref().map(|k| k.to_hex::<String>()),
+		request.start.to_hex::<String>(),
+		entries.len(),
+	);
+	Some(StateResponse { id: request.id, entries, complete, proof })
+}
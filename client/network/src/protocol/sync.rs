@@ -26,6 +26,15 @@
 //! the network, or whenever a block has been successfully verified, call the appropriate method in
 //! order to update it.
 //!
+//! # Warp sync
+//!
+//! With `SyncMode::Warp`, a full node without state first imports the headers up to the best block
+//! seen, along with the justifications of the blocks that require one, such as GRANDPA authority
+//! set changes. The finality of the imported headers is thus proven from genesis by the chain of
+//! authority sets. Once the last finalized block is recent enough for its state to be kept by the
+//! peers, the blocks above it are reverted, and its state is downloaded, checked against its state
+//! root and imported. The node then continues with a normal full sync from there.
+//!
 
 use blocks::BlockCollection;
 use client_api::ClientInfo;
@@ -35,9 +44,9 @@ use consensus::{BlockOrigin, BlockStatus,
 	import_queue::{IncomingBlock, BlockImportResult, BlockImportError}
 };
 use crate::{
-	config::{Roles, BoxFinalityProofRequestBuilder},
+	config::{Roles, BoxFinalityProofRequestBuilder, SyncMode},
 	message::{self, generic::FinalityProofRequest, BlockAnnounce, BlockAttributes, BlockRequest, BlockResponse,
-	FinalityProofResponse},
+	FinalityProofResponse, StateRequest, StateResponse},
 };
 use either::Either;
 use extra_requests::ExtraRequests;
@@ -48,10 +57,12 @@ use sp_runtime::{
 	generic::BlockId,
 	traits::{Block as BlockT, Header, NumberFor, Zero, One, CheckedSub, SaturatedConversion}
 };
+use state::StateSync;
 use std::{fmt, ops::Range, collections::{HashMap, HashSet, VecDeque}, sync::Arc};
 
 mod blocks;
 mod extra_requests;
+mod state;
 
 /// Maximum blocks to request in a single packet.
 const MAX_BLOCKS_TO_REQUEST: usize = 128;
@@ -69,6 +80,10 @@ const MAX_DOWNLOAD_AHEAD: u32 = 2048;
 /// so far behind.
 const MAJOR_SYNC_BLOCKS: u8 = 5;
 
+/// Warp sync downloads the state of the last finalized block once it is at most this many blocks
+/// behind the best block seen, so that the peers still keep its state.
+const WARP_SYNC_MAX_FINALITY_LAG: u32 = 128;
+
 /// Number of recently announced blocks to track for each peer.
 const ANNOUNCE_HISTORY_SIZE: usize = 64;
 
@@ -134,6 +149,16 @@ pub struct ChainSync<B: BlockT> {
 	block_announce_validator: Box<dyn BlockAnnounceValidator<B> + Send>,
 	/// Maximum number of peers to ask the same blocks in parallel.
 	max_parallel_downloads: u32,
+	/// Progress of warp sync, if it is enabled and not complete yet.
+	warp_sync: Option<WarpSync<B>>,
+}
+
+/// Progress of warp sync.
+enum WarpSync<B: BlockT> {
+	/// Importing headers, without bodies nor state, up to a recent block.
+	Headers,
+	/// Downloading the state of the best imported header.
+	State(StateSync<B>),
 }
 
 /// All the data we have about a Peer that we are trying to sync with
@@ -188,7 +213,9 @@ pub enum PeerSyncState<B: BlockT> {
 	/// Downloading justification for given block hash.
 	DownloadingJustification(B::Hash),
 	/// Downloading finality proof for given block hash.
-	DownloadingFinalityProof(B::Hash)
+	DownloadingFinalityProof(B::Hash),
+	/// Downloading a chunk of state during warp sync.
+	DownloadingState,
 }
 
 impl<B: BlockT> PeerSyncState<B> {
@@ -290,10 +317,24 @@ impl<B: BlockT> ChainSync<B> {
 		request_builder: Option<BoxFinalityProofRequestBuilder<B>>,
 		block_announce_validator: Box<dyn BlockAnnounceValidator<B> + Send>,
 		max_parallel_downloads: u32,
+		sync_mode: SyncMode,
 	) -> Self {
 		let mut required_block_attributes = BlockAttributes::HEADER | BlockAttributes::JUSTIFICATION;
 
-		if role.is_full() {
+		// Warp sync is only useful if we don't have any state beyond genesis yet.
+		let warp_sync = match sync_mode {
+			SyncMode::Warp if role.is_full() && (
+				info.chain.best_number.is_zero()
+					|| client.block_status(&BlockId::Hash(info.chain.best_hash)).ok()
+						== Some(BlockStatus::InChainPruned)
+			) => {
+				info!("Starting warp sync from block #{}", info.chain.best_number);
+				Some(WarpSync::Headers)
+			},
+			_ => None,
+		};
+
+		if role.is_full() && warp_sync.is_none() {
 			required_block_attributes |= BlockAttributes::BODY
 		}
 
@@ -314,6 +355,7 @@ impl<B: BlockT> ChainSync<B> {
 			is_idle: false,
 			block_announce_validator,
 			max_parallel_downloads,
+			warp_sync,
 		}
 	}
 
@@ -569,6 +611,9 @@ impl<B: BlockT> ChainSync<B> {
 			trace!(target: "sync", "Too many blocks in the queue.");
 			return Either::Left(std::iter::empty())
 		}
+		if let Some(WarpSync::State(_)) = self.warp_sync {
+			return Either::Left(std::iter::empty())
+		}
 		let major_sync = self.status().state == SyncState::Downloading;
 		let blocks = &mut self.blocks;
 		let attrs = &self.required_block_attributes;
@@ -606,7 +651,7 @@ impl<B: BlockT> ChainSync<B> {
 				blocks,
 				attrs,
 				max_parallel,
-				last_finalized
+				last_finalized,
 			) {
 				peer.state = PeerSyncState::DownloadingNew(range.start);
 				trace!(
@@ -638,6 +683,7 @@ impl<B: BlockT> ChainSync<B> {
 	pub fn on_block_data
 		(&mut self, who: PeerId, request: BlockRequest<B>, response: BlockResponse<B>) -> Result<OnBlockData<B>, BadPeer>
 	{
+		let allow_missing_state = self.warp_sync.is_some();
 		let new_blocks: Vec<IncomingBlock<B>> =
 			if let Some(peer) = self.peers.get_mut(&who) {
 				let mut blocks = response.blocks;
@@ -661,7 +707,7 @@ impl<B: BlockT> ChainSync<B> {
 									body: block_data.block.body,
 									justification: block_data.block.justification,
 									origin: block_data.origin,
-									allow_missing_state: false,
+									allow_missing_state,
 									import_existing: false,
 								}
 							}).collect()
@@ -736,7 +782,8 @@ impl<B: BlockT> ChainSync<B> {
 
 					| PeerSyncState::Available
 					| PeerSyncState::DownloadingJustification(..)
-					| PeerSyncState::DownloadingFinalityProof(..) => Vec::new()
+					| PeerSyncState::DownloadingFinalityProof(..)
+					| PeerSyncState::DownloadingState => Vec::new()
 				}
 			} else {
 				Vec::new()
@@ -837,6 +884,142 @@ impl<B: BlockT> ChainSync<B> {
 		Ok(OnBlockFinalityProof::Nothing)
 	}
 
+	/// Get an iterator over the state requests of warp sync.
+	///
+	/// Only the peers for which `supports_state` returns `true` are asked.
+	pub fn state_requests<'a>(
+		&'a mut self,
+		supports_state: impl Fn(&PeerId) -> bool + 'a,
+	) -> impl Iterator<Item = (PeerId, StateRequest<B::Hash>)> + 'a {
+		self.update_warp_sync();
+		let state = match &mut self.warp_sync {
+			Some(WarpSync::State(state)) => state,
+			_ => return Either::Left(std::iter::empty()),
+		};
+		for who in state.expire_requests() {
+			if let Some(peer) = self.peers.get_mut(&who) {
+				peer.state = PeerSyncState::Available;
+			}
+		}
+		let (_, number) = state.target();
+		Either::Right(self.peers.iter_mut().filter_map(move |(who, peer)| {
+			if !peer.state.is_available() || peer.best_number < number || !supports_state(who) {
+				return None
+			}
+			let request = state.next_request(who)?;
+			trace!(target: "sync", "New state request for {}: {:?}", who, request);
+			peer.state = PeerSyncState::DownloadingState;
+			Some((who.clone(), request))
+		}))
+	}
+
+	/// Handle a response to a state request that we made.
+	///
+	/// Once the whole state has been downloaded, it is imported and the sync restarts, which
+	/// may produce new block requests.
+	pub fn on_state_data(&mut self, who: PeerId, response: StateResponse)
+		-> Vec<Result<(PeerId, BlockRequest<B>), BadPeer>>
+	{
+		let mut output = Vec::new();
+		let client = &self.client;
+		let state = match &mut self.warp_sync {
+			Some(WarpSync::State(state)) => state,
+			_ => {
+				trace!(target: "sync", "Ignoring unexpected state response from {}", who);
+				return output
+			},
+		};
+		if let Some(peer) = self.peers.get_mut(&who) {
+			if peer.state == PeerSyncState::DownloadingState {
+				peer.state = PeerSyncState::Available;
+			}
+		}
+		self.is_idle = false;
+
+		let checked = state.on_response(who, response, |root, start, entries, complete, proof| {
+			client.check_state_range(root, start, entries, complete, proof).is_ok()
+		});
+		if let Err(bad_peer) = checked {
+			output.push(Err(bad_peer));
+			return output
+		}
+		if !state.is_complete() {
+			return output
+		}
+
+		let mut state = match self.warp_sync.take() {
+			Some(WarpSync::State(state)) => state,
+			_ => return output,
+		};
+		let entries = state.num_entries();
+		let (hash, top, children) = state.take_state();
+		match self.client.import_state(hash, top, children) {
+			Ok(()) => {
+				info!("Imported the {} state entries of block {}, continuing with full sync", entries, hash);
+				self.required_block_attributes |= BlockAttributes::BODY;
+				output.extend(self.restart());
+			},
+			Err(e) => {
+				// every chunk has been checked, so the peers which sent them are to blame.
+				warn!("Failed to import the state of block {}: {:?}", hash, e);
+				output.extend(state.on_import_failed().into_iter().map(Err));
+				self.warp_sync = Some(WarpSync::State(state));
+			},
+		}
+		output
+	}
+
+	/// Moves warp sync from the headers to the state download once the headers are imported and
+	/// a recent block is finalized.
+	fn update_warp_sync(&mut self) {
+		match self.warp_sync {
+			Some(WarpSync::Headers) => {},
+			_ => return,
+		}
+		let downloading_blocks = self.peers.values().any(|p| match p.state {
+			PeerSyncState::DownloadingNew(_) | PeerSyncState::DownloadingStale(_) => true,
+			_ => false,
+		});
+		if self.peers.is_empty() || !self.queue_blocks.is_empty() || downloading_blocks {
+			return
+		}
+
+		let info = self.client.info().chain;
+		let best_seen = self.peers.values().map(|p| p.best_number).max().unwrap_or_else(Zero::zero);
+		let headers_imported = info.best_number + MAJOR_SYNC_BLOCKS.into() >= best_seen;
+		let finality_lag = best_seen.checked_sub(&info.finalized_number).unwrap_or_else(Zero::zero);
+		if !headers_imported || info.finalized_number.is_zero() || finality_lag > WARP_SYNC_MAX_FINALITY_LAG.into() {
+			return
+		}
+
+		match self.client.block_status(&BlockId::Hash(info.finalized_hash)) {
+			Ok(BlockStatus::InChainWithState) => {
+				info!("Block #{} already has its state, continuing with full sync", info.finalized_number);
+				self.warp_sync = None;
+				self.required_block_attributes |= BlockAttributes::BODY;
+			},
+			_ => match self.client.header(&BlockId::Hash(info.finalized_hash)) {
+				Ok(Some(header)) => {
+					// the blocks above the finalized one are imported again with their bodies once
+					// the state is imported.
+					let above = info.best_number - info.finalized_number;
+					if let Err(e) = self.client.revert(above) {
+						warn!(target: "sync", "Error reverting to block #{}: {:?}", info.finalized_number, e);
+						return
+					}
+					info!("Downloading the state of finalized block #{} ({})", info.finalized_number, info.finalized_hash);
+					self.best_queued_hash = info.finalized_hash;
+					self.best_queued_number = info.finalized_number;
+					self.best_imported_number = info.finalized_number;
+					self.warp_sync = Some(WarpSync::State(StateSync::new(header)));
+				},
+				Ok(None) => {},
+				Err(e) => warn!(target: "sync", "Error reading header of block {}: {:?}", info.finalized_hash, e),
+			},
+		}
+		self.is_idle = false;
+	}
+
 	/// A batch of blocks have been processed, with or without errors.
 	///
 	/// Call this when a batch of blocks have been processed by the import
@@ -1122,6 +1305,9 @@ impl<B: BlockT> ChainSync<B> {
 		self.peers.remove(&who);
 		self.extra_justifications.peer_disconnected(&who);
 		self.extra_finality_proofs.peer_disconnected(&who);
+		if let Some(WarpSync::State(state)) = &mut self.warp_sync {
+			state.peer_disconnected(&who);
+		}
 		self.is_idle = false;
 	}
 
@@ -0,0 +1,325 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Download of the state of a block, used by warp sync.
+//!
+//! The entries of a trie are requested in key order, each response continuing after the last
+//! entry of the previous one. A response comes with a proof that it contains all the entries of
+//! the trie between the requested key and its last entry, and whether there are entries after
+//! them. The top trie is checked against the state root of the header, and each child trie against
+//! its root read from the top trie, so that a peer can neither alter nor omit entries.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use codec::Decode;
+use libp2p::PeerId;
+use log::{debug, trace, warn};
+use primitives::storage::{StorageOverlay, ChildrenStorageOverlay, well_known_keys};
+use sp_runtime::traits::{Block as BlockT, Header, NumberFor};
+use client_api::StorageProof;
+use crate::message::{self, RequestId, StateResponse};
+use super::BadPeer;
+
+/// Time after which a state request is considered lost and its chunk requested again.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Reputation change for peers which send us an invalid state chunk.
+const BAD_STATE_REPUTATION_CHANGE: i32 = -(1 << 29);
+
+/// A chunk of state: the child storage key, if any, and the key of its first entry.
+type Chunk = (Option<Vec<u8>>, Vec<u8>);
+
+/// The state of a block being downloaded from peers.
+pub struct StateSync<B: BlockT> {
+	/// Header of the block.
+	header: B::Header,
+	/// Chunks which have not been requested yet.
+	pending: VecDeque<Chunk>,
+	/// Chunks being downloaded, by peer.
+	in_flight: HashMap<PeerId, (RequestId, Instant, Chunk)>,
+	/// Entries of the top trie downloaded so far, excluding the roots of the child tries.
+	top: StorageOverlay,
+	/// Entries of the child tries downloaded so far.
+	children: ChildrenStorageOverlay,
+	/// Roots of the child tries, read from the top trie.
+	child_roots: HashMap<Vec<u8>, B::Hash>,
+	/// Peers which have sent entries of the state.
+	contributors: HashSet<PeerId>,
+	/// Id of the next request.
+	next_request_id: RequestId,
+}
+
+impl<B: BlockT> StateSync<B> {
+	/// Start downloading the state of the given block.
+	pub fn new(header: B::Header) -> Self {
+		let mut pending = VecDeque::new();
+		pending.push_back((None, Vec::new()));
+		StateSync {
+			header,
+			pending,
+			in_flight: HashMap::new(),
+			top: StorageOverlay::new(),
+			children: ChildrenStorageOverlay::new(),
+			child_roots: HashMap::new(),
+			contributors: HashSet::new(),
+			next_request_id: 0,
+		}
+	}
+
+	/// Hash and number of the block.
+	pub fn target(&self) -> (B::Hash, NumberFor<B>) {
+		(self.header.hash(), *self.header.number())
+	}
+
+	/// Returns `true` once all the chunks have been downloaded.
+	pub fn is_complete(&self) -> bool {
+		self.pending.is_empty() && self.in_flight.is_empty()
+	}
+
+	/// Number of entries downloaded so far.
+	pub fn num_entries(&self) -> usize {
+		self.top.len() + self.children.values().map(|child| child.len()).sum::<usize>()
+	}
+
+	/// Take the downloaded state, returning it along with the hash of the block.
+	pub fn take_state(&mut self) -> (B::Hash, StorageOverlay, ChildrenStorageOverlay) {
+		let top = std::mem::replace(&mut self.top, StorageOverlay::new());
+		let children = std::mem::replace(&mut self.children, ChildrenStorageOverlay::new());
+		(self.header.hash(), top, children)
+	}
+
+	/// Call when the downloaded state has failed to be imported. Every peer which has sent a part
+	/// of the state is reported, and the download starts again.
+	pub fn on_import_failed(&mut self) -> Vec<BadPeer> {
+		let contributors = std::mem::replace(&mut self.contributors, HashSet::new());
+		// request ids keep increasing, so that responses to the previous requests are ignored.
+		let next_request_id = self.next_request_id;
+		*self = StateSync::new(self.header.clone());
+		self.next_request_id = next_request_id;
+		contributors.into_iter().map(|who| BadPeer(who, BAD_STATE_REPUTATION_CHANGE)).collect()
+	}
+
+	/// Returns the peers whose request has timed out. Their chunks are requested again.
+	pub fn expire_requests(&mut self) -> Vec<PeerId> {
+		let now = Instant::now();
+		let expired = self.in_flight.iter()
+			.filter(|(_, (_, sent, _))| now.duration_since(*sent) > REQUEST_TIMEOUT)
+			.map(|(who, _)| who.clone())
+			.collect::<Vec<_>>();
+		for who in &expired {
+			trace!(target: "sync", "State request to {} has timed out", who);
+			self.peer_disconnected(who);
+		}
+		expired
+	}
+
+	/// Get the next request to send to the given peer, if any chunk is left to request.
+	pub fn next_request(&mut self, who: &PeerId) -> Option<message::StateRequest<B::Hash>> {
+		let (storage_key, start) = self.pending.pop_front()?;
+		let id = self.next_request_id;
+		self.next_request_id += 1;
+		self.in_flight.insert(who.clone(), (id, Instant::now(), (storage_key.clone(), start.clone())));
+		Some(message::generic::StateRequest {
+			id,
+			block: self.header.hash(),
+			storage_key,
+			start,
+		})
+	}
+
+	/// Handle a response to a state request. `check` verifies the proof of the range of entries
+	/// against the root of the trie they belong to.
+	///
+	/// Returns `Ok(false)` if the response is obsolete and has been ignored.
+	pub fn on_response<F>(&mut self, who: PeerId, response: StateResponse, check: F) -> Result<bool, BadPeer>
+		where F: FnOnce(&B::Hash, &[u8], &[(Vec<u8>, Vec<u8>)], bool, StorageProof) -> bool
+	{
+		let StateResponse { id, entries, complete, proof } = response;
+		match self.in_flight.get(&who) {
+			Some((expected, _, _)) if *expected == id => {},
+			_ => {
+				trace!(target: "sync", "Ignoring obsolete state response {} from {}", id, who);
+				return Ok(false)
+			},
+		}
+		let (_, _, (storage_key, start)) = self.in_flight.remove(&who)
+			.expect("Entry has been checked above; qed");
+
+		let root = match &storage_key {
+			None => *self.header.state_root(),
+			Some(storage_key) => self.child_roots.get(storage_key).cloned()
+				.expect("Child tries are only requested once their root is known; qed"),
+		};
+		// an incomplete response must make progress.
+		let valid = (complete || !entries.is_empty())
+			&& check(&root, &start, &entries, complete, proof);
+		if !valid {
+			debug!(target: "sync", "Invalid state chunk {:?} received from {}", (&storage_key, &start), who);
+			self.pending.push_front((storage_key, start));
+			return Err(BadPeer(who, BAD_STATE_REPUTATION_CHANGE))
+		}
+
+		trace!(target: "sync", "Received {} state entries from {:?} from {}", entries.len(), start, who);
+		self.contributors.insert(who);
+		if !complete {
+			let mut next = entries.last().map(|(key, _)| key.clone())
+				.expect("Incomplete responses are not empty; qed");
+			next.push(0);
+			self.pending.push_front((storage_key.clone(), next));
+		}
+
+		match storage_key {
+			None => for (key, value) in entries {
+				if well_known_keys::is_child_storage_key(&key) {
+					// The root of a child trie: it is computed again on import.
+					match B::Hash::decode(&mut &value[..]) {
+						Ok(child_root) => {
+							self.children.entry(key.clone()).or_default();
+							self.child_roots.insert(key.clone(), child_root);
+							self.pending.push_back((Some(key), Vec::new()));
+						},
+						Err(_) => warn!(target: "sync", "Invalid root of the child trie {:?}", key),
+					}
+				} else {
+					self.top.insert(key, value);
+				}
+			},
+			Some(storage_key) => self.children.entry(storage_key).or_default().extend(entries),
+		}
+		Ok(true)
+	}
+
+	/// Call when a peer has disconnected, so that its chunk is requested again.
+	pub fn peer_disconnected(&mut self, who: &PeerId) {
+		if let Some((_, _, chunk)) = self.in_flight.remove(who) {
+			self.pending.push_front(chunk);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::StateSync;
+	use crate::message::StateResponse;
+	use libp2p::PeerId;
+	use sp_runtime::generic::BlockId;
+	use test_client::{self, runtime::Block};
+
+	/// Answers the next request of `sync` with at most `max_keys` entries read from `client`.
+	fn respond(
+		client: &test_client::Client<test_client::Backend>,
+		sync: &mut StateSync<Block>,
+		who: &PeerId,
+		max_keys: usize,
+	) -> StateResponse {
+		let request = sync.next_request(who).unwrap();
+		let (entries, complete, proof) = client.state_range(
+			&BlockId::Hash(request.block),
+			request.storage_key.as_ref().map(|k| &k[..]),
+			&request.start,
+			max_keys,
+			usize::max_value(),
+		).unwrap();
+		StateResponse { id: request.id, entries, complete, proof }
+	}
+
+	fn on_response(
+		client: &test_client::Client<test_client::Backend>,
+		sync: &mut StateSync<Block>,
+		who: PeerId,
+		response: StateResponse,
+	) -> Result<bool, super::BadPeer> {
+		sync.on_response(who, response, |root, start, entries, complete, proof|
+			client.check_state_range(root, start, entries, complete, proof).is_ok()
+		)
+	}
+
+	#[test]
+	fn downloads_the_state_in_chunks() {
+		let client = test_client::new();
+		let header = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let mut sync = StateSync::<Block>::new(header);
+		let who = PeerId::random();
+
+		let mut responses = 0;
+		while !sync.is_complete() {
+			let response = respond(&client, &mut sync, &who, 2);
+			assert_eq!(on_response(&client, &mut sync, who.clone(), response), Ok(true));
+			responses += 1;
+		}
+
+		let state = client.state_range(&BlockId::Number(0), None, &[], usize::max_value(), usize::max_value())
+			.unwrap().0;
+		assert!(responses > 1);
+		assert_eq!(sync.num_entries(), state.len());
+		let (_, top, _) = sync.take_state();
+		assert_eq!(top.into_iter().collect::<Vec<_>>(), state);
+	}
+
+	#[test]
+	fn rejects_invalid_chunks() {
+		let client = test_client::new();
+		let header = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let mut sync = StateSync::<Block>::new(header);
+		let who = PeerId::random();
+
+		// an altered value.
+		let mut response = respond(&client, &mut sync, &who, 2);
+		response.entries[0].1.push(0);
+		assert!(on_response(&client, &mut sync, who.clone(), response).is_err());
+
+		// an omitted entry.
+		let mut response = respond(&client, &mut sync, &who, 2);
+		response.entries.remove(0);
+		assert!(on_response(&client, &mut sync, who.clone(), response).is_err());
+
+		// a truncated range claimed to be complete.
+		let mut response = respond(&client, &mut sync, &who, 2);
+		response.complete = true;
+		assert!(on_response(&client, &mut sync, who.clone(), response).is_err());
+
+		// an empty range claimed to be incomplete.
+		let mut response = respond(&client, &mut sync, &who, 2);
+		response.entries.clear();
+		assert!(on_response(&client, &mut sync, who.clone(), response).is_err());
+
+		// the chunk is still requested from the start.
+		assert_eq!(sync.next_request(&who).unwrap().start, Vec::<u8>::new());
+		assert_eq!(sync.num_entries(), 0);
+	}
+
+	#[test]
+	fn reports_contributors_when_import_fails() {
+		let client = test_client::new();
+		let header = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let mut sync = StateSync::<Block>::new(header);
+		let peers = vec![PeerId::random(), PeerId::random()];
+
+		for who in &peers {
+			let response = respond(&client, &mut sync, who, 2);
+			assert_eq!(on_response(&client, &mut sync, who.clone(), response), Ok(true));
+		}
+		assert!(sync.num_entries() > 0);
+
+		let mut reported = sync.on_import_failed().into_iter().map(|bad| bad.0).collect::<Vec<_>>();
+		reported.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+		let mut expected = peers.clone();
+		expected.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+		assert_eq!(reported, expected);
+		assert_eq!(sync.num_entries(), 0);
+		assert_eq!(sync.next_request(&peers[0]).unwrap().start, Vec::<u8>::new());
+	}
+}
@@ -50,6 +50,10 @@ use crate::protocol::consensus_gossip::{ConsensusGossip, MessageRecipient as Gos
 use crate::protocol::{event::Event, light_dispatch::{AlwaysBadChecker, RequestData}};
 use crate::protocol::specialization::NetworkSpecialization;
 use crate::protocol::sync::SyncState;
+use crate::throttle::Throttle;
+
+/// Name of the file of the peer store, in the network configuration directory.
+const PEER_STORE_FILE: &str = "peers.json";
 
 /// Minimum Requirements for a Hash within Networking
 pub trait ExHashT: std::hash::Hash + Eq + std::fmt::Debug + Clone + Send + Sync + 'static {}
@@ -167,6 +171,16 @@ impl<B: BlockT + 'static, S: NetworkSpecialization<B>, H: ExHashT> NetworkWorker
 				}
 			)?;
 
+		// Restore the peers known before the restart.
+		let peer_store = match params.network_config.net_config_path {
+			Some(ref path) if params.network_config.persist_peers =>
+				Some(peerset::PeerStore::open(Path::new(path).join(PEER_STORE_FILE))?),
+			_ => None,
+		};
+		if let Some(ref peer_store) = peer_store {
+			known_addresses.extend(peer_store.addresses().map(|(peer_id, addr)| (peer_id.clone(), addr.clone())));
+		}
+
 		// Initialize the reserved peers.
 		for reserved in params.network_config.reserved_nodes.iter() {
 			if let Ok((peer_id, addr)) = parse_str_addr(reserved) {
@@ -183,6 +197,7 @@ impl<B: BlockT + 'static, S: NetworkSpecialization<B>, H: ExHashT> NetworkWorker
 			bootnodes,
 			reserved_only: params.network_config.non_reserved_mode == NonReservedPeerMode::Deny,
 			reserved_nodes,
+			peer_store,
 		};
 
 		// Private and public keys configuration.
@@ -193,10 +208,13 @@ impl<B: BlockT + 'static, S: NetworkSpecialization<B>, H: ExHashT> NetworkWorker
 
 		let num_connected = Arc::new(AtomicUsize::new(0));
 		let is_major_syncing = Arc::new(AtomicBool::new(false));
+		let throttle = Arc::new(Throttle::new(&params.network_config.bandwidth_limits));
 		let (protocol, peerset_handle) = Protocol::new(
 			protocol::ProtocolConfig {
 				roles: params.roles,
 				max_parallel_downloads: params.network_config.max_parallel_downloads,
+				throttle: throttle.clone(),
+				sync_mode: params.network_config.sync_mode,
 			},
 			params.chain,
 			params.on_demand.as_ref().map(|od| od.checker().clone())
@@ -217,6 +235,8 @@ impl<B: BlockT + 'static, S: NetworkSpecialization<B>, H: ExHashT> NetworkWorker
 				params.network_config.client_version,
 				params.network_config.node_name
 			);
+			// Nodes of a private network only connect to the nodes they've been told about.
+			let is_private = params.network_config.allowed_peers.is_some();
 			let behaviour = Behaviour::new(
 				protocol,
 				user_agent,
@@ -224,12 +244,13 @@ impl<B: BlockT + 'static, S: NetworkSpecialization<B>, H: ExHashT> NetworkWorker
 				known_addresses,
 				match params.network_config.transport {
 					TransportConfig::MemoryOnly => false,
-					TransportConfig::Normal { enable_mdns, .. } => enable_mdns,
+					TransportConfig::Normal { enable_mdns, .. } => enable_mdns && !is_private,
 				},
 				match params.network_config.transport {
 					TransportConfig::MemoryOnly => false,
 					TransportConfig::Normal { allow_private_ipv4, .. } => allow_private_ipv4,
 				},
+				!is_private,
 			);
 			let (transport, bandwidth) = {
 				let (config_mem, config_wasm) = match params.network_config.transport {
@@ -237,7 +258,13 @@ impl<B: BlockT + 'static, S: NetworkSpecialization<B>, H: ExHashT> NetworkWorker
 					TransportConfig::Normal { wasm_external_transport, .. } =>
 						(false, wasm_external_transport)
 				};
-				transport::build_transport(local_identity, config_mem, config_wasm)
+				transport::build_transport(
+					local_identity,
+					config_mem,
+					config_wasm,
+					params.network_config.allowed_peers.clone(),
+					throttle,
+				)
 			};
 			(Swarm::<B, S, H>::new(transport, behaviour, local_peer_id.clone()), bandwidth)
 		};
@@ -319,6 +346,18 @@ impl<B: BlockT + 'static, S: NetworkSpecialization<B>, H: ExHashT> NetworkWorker
 		self.network_service.user_protocol().num_queued_blocks()
 	}
 
+	/// Returns the local `PeerId`.
+	pub fn local_peer_id(&self) -> &PeerId {
+		Swarm::<B, S, H>::local_peer_id(&self.network_service)
+	}
+
+	/// Returns the list of addresses we are listening on.
+	///
+	/// Does **NOT** include a trailing `/p2p/` with our `PeerId`.
+	pub fn listen_addresses(&self) -> impl Iterator<Item = &Multiaddr> {
+		Swarm::<B, S, H>::listeners(&self.network_service)
+	}
+
 	/// Adds an address for a node.
 	pub fn add_known_address(&mut self, peer_id: PeerId, addr: Multiaddr) {
 		self.network_service.add_known_address(peer_id, addr);
@@ -0,0 +1,341 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bandwidth throttling.
+//!
+//! The `Throttle` holds token buckets (one token per byte) limiting the upload and download
+//! rates of all the connections together and of each peer. The streams of the connections are
+//! wrapped in a `Throttled`, which waits for the buckets to refill before reading or writing.
+//!
+//! Since every message goes through the same streams, the protocol also asks the `Throttle`
+//! whether the upload is congested before sending gossip messages, leaving the remaining
+//! bandwidth to the sync.
+
+use crate::config::BandwidthLimits;
+use futures::prelude::*;
+use futures03::compat::Compat;
+use futures_timer::Delay;
+use libp2p::PeerId;
+use parking_lot::Mutex;
+use std::{cmp, collections::HashMap, io, sync::{Arc, Weak}, time::{Duration, Instant}};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The upload is congested when a bucket holds less than `1 / CONGESTION_RATIO` of its capacity.
+const CONGESTION_RATIO: u64 = 4;
+/// Number of tokens we wait for when a bucket is empty, so that we don't wake up for every byte.
+const MIN_CHUNK: u64 = 1024;
+/// Minimum time to wait when a bucket is empty.
+const MIN_WAIT: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+	Upload,
+	Download,
+}
+
+/// A token bucket, refilled at a constant rate. Its capacity is one second worth of tokens.
+#[derive(Debug)]
+struct TokenBucket {
+	/// Number of tokens added per second.
+	rate: u64,
+	/// Number of tokens in the bucket.
+	tokens: u64,
+	/// When the tokens have last been added.
+	last_refill: Instant,
+}
+
+impl TokenBucket {
+	fn new(rate: u64) -> Self {
+		TokenBucket { rate, tokens: rate, last_refill: Instant::now() }
+	}
+
+	fn refill(&mut self, now: Instant) {
+		let elapsed = now.duration_since(self.last_refill).as_micros();
+		let new_tokens = cmp::min(elapsed * u128::from(self.rate) / 1_000_000, u128::from(self.rate)) as u64;
+		// Keep `last_refill` unchanged if nothing has been added, so that we don't lose the
+		// fractions of tokens.
+		if new_tokens > 0 {
+			self.tokens = cmp::min(self.tokens + new_tokens, self.rate);
+			self.last_refill = now;
+		}
+	}
+
+	fn put_back(&mut self, tokens: u64) {
+		self.tokens = cmp::min(self.tokens + tokens, self.rate);
+	}
+
+	/// Time to wait until the bucket holds `tokens` tokens.
+	fn wait_time(&self, tokens: u64) -> Duration {
+		let missing = tokens.saturating_sub(self.tokens);
+		Duration::from_micros(missing.saturating_mul(1_000_000) / self.rate)
+	}
+
+	fn is_congested(&self) -> bool {
+		self.tokens < self.rate / CONGESTION_RATIO
+	}
+}
+
+/// The buckets limiting the upload and download rates.
+#[derive(Debug, Default)]
+struct Buckets {
+	upload: Option<Mutex<TokenBucket>>,
+	download: Option<Mutex<TokenBucket>>,
+}
+
+impl Buckets {
+	fn new(upload: Option<u64>, download: Option<u64>) -> Self {
+		let bucket = |rate: Option<u64>| rate.filter(|rate| *rate > 0).map(|rate| Mutex::new(TokenBucket::new(rate)));
+		Buckets { upload: bucket(upload), download: bucket(download) }
+	}
+
+	fn get(&self, direction: Direction) -> Option<&Mutex<TokenBucket>> {
+		match direction {
+			Direction::Upload => self.upload.as_ref(),
+			Direction::Download => self.download.as_ref(),
+		}
+	}
+}
+
+/// Limits the bandwidth of all the connections, globally and per peer.
+#[derive(Debug)]
+pub struct Throttle {
+	/// Buckets shared by all the connections.
+	global: Buckets,
+	peer_upload: Option<u64>,
+	peer_download: Option<u64>,
+	/// Buckets of each peer, shared by all the connections with this peer. They are dropped
+	/// together with the last connection.
+	peers: Mutex<HashMap<PeerId, Weak<Buckets>>>,
+}
+
+impl Throttle {
+	/// Creates a new `Throttle` enforcing the given limits.
+	pub fn new(limits: &BandwidthLimits) -> Self {
+		Throttle {
+			global: Buckets::new(limits.upload, limits.download),
+			peer_upload: limits.peer_upload,
+			peer_download: limits.peer_download,
+			peers: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Returns true if the upload rate to this peer is close to a limit, in which case messages
+	/// that aren't required by the sync should be held back.
+	pub fn is_upload_congested(&self, peer_id: &PeerId) -> bool {
+		let peer = self.peers.lock().get(peer_id).and_then(Weak::upgrade);
+		let now = Instant::now();
+		self.global.upload.iter()
+			.chain(peer.as_ref().and_then(|peer| peer.upload.as_ref()))
+			.any(|bucket| {
+				let mut bucket = bucket.lock();
+				bucket.refill(now);
+				bucket.is_congested()
+			})
+	}
+
+	fn peer_buckets(&self, peer_id: &PeerId) -> Arc<Buckets> {
+		let mut peers = self.peers.lock();
+		if let Some(buckets) = peers.get(peer_id).and_then(Weak::upgrade) {
+			return buckets;
+		}
+
+		// Forget about the peers we're no longer connected to.
+		peers.retain(|_, buckets| buckets.upgrade().is_some());
+		let buckets = Arc::new(Buckets::new(self.peer_upload, self.peer_download));
+		peers.insert(peer_id.clone(), Arc::downgrade(&buckets));
+		buckets
+	}
+
+	/// Takes up to `wanted` tokens from the global and peer buckets.
+	///
+	/// Returns the number of tokens taken, or the time to wait if a bucket is empty.
+	fn acquire(&self, peer: &Buckets, direction: Direction, wanted: usize) -> Result<usize, Duration> {
+		let now = Instant::now();
+		// The global bucket is always locked first.
+		let mut global = self.global.get(direction).map(|bucket| bucket.lock());
+		let mut peer = peer.get(direction).map(|bucket| bucket.lock());
+
+		let mut available = wanted as u64;
+		for bucket in global.iter_mut().chain(peer.iter_mut()) {
+			bucket.refill(now);
+			available = cmp::min(available, bucket.tokens);
+		}
+
+		if available == 0 {
+			let chunk = cmp::min(wanted as u64, MIN_CHUNK);
+			let wait = global.iter().chain(peer.iter())
+				.map(|bucket| bucket.wait_time(chunk))
+				.max()
+				.unwrap_or_default();
+			return Err(cmp::max(wait, MIN_WAIT));
+		}
+
+		for bucket in global.iter_mut().chain(peer.iter_mut()) {
+			bucket.tokens -= available;
+		}
+		Ok(available as usize)
+	}
+
+	/// Puts back the tokens that have been acquired but not used.
+	fn release(&self, peer: &Buckets, direction: Direction, tokens: usize) {
+		if tokens == 0 {
+			return;
+		}
+
+		for bucket in self.global.get(direction).into_iter().chain(peer.get(direction)) {
+			bucket.lock().put_back(tokens as u64);
+		}
+	}
+}
+
+/// A stream whose reads and writes are limited by a `Throttle`.
+pub struct Throttled<S> {
+	inner: S,
+	throttle: Arc<Throttle>,
+	peer: Arc<Buckets>,
+	/// Fires when the download buckets might have been refilled.
+	read_delay: Option<Compat<Delay>>,
+	/// Fires when the upload buckets might have been refilled.
+	write_delay: Option<Compat<Delay>>,
+}
+
+impl<S> Throttled<S> {
+	/// Wraps the stream of a connection with the given peer.
+	pub fn new(throttle: Arc<Throttle>, peer_id: &PeerId, inner: S) -> Self {
+		let peer = throttle.peer_buckets(peer_id);
+		Throttled { inner, throttle, peer, read_delay: None, write_delay: None }
+	}
+
+	/// Returns the number of bytes that can be transferred, or a `WouldBlock` error after having
+	/// registered the current task to be woken up once the buckets have been refilled.
+	fn poll_acquire(&mut self, direction: Direction, wanted: usize) -> io::Result<usize> {
+		let delay = match direction {
+			Direction::Upload => &mut self.write_delay,
+			Direction::Download => &mut self.read_delay,
+		};
+
+		loop {
+			if let Some(pending) = delay.as_mut() {
+				match pending.poll()? {
+					Async::Ready(()) => *delay = None,
+					Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
+				}
+			}
+
+			match self.throttle.acquire(&self.peer, direction, wanted) {
+				Ok(tokens) => return Ok(tokens),
+				Err(wait) => *delay = Some(Compat::new(Delay::new(wait))),
+			}
+		}
+	}
+}
+
+impl<S: io::Read> io::Read for Throttled<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return self.inner.read(buf);
+		}
+
+		let tokens = self.poll_acquire(Direction::Download, buf.len())?;
+		let result = self.inner.read(&mut buf[..tokens]);
+		let used = *result.as_ref().unwrap_or(&0);
+		self.throttle.release(&self.peer, Direction::Download, tokens - used);
+		result
+	}
+}
+
+impl<S: AsyncRead> AsyncRead for Throttled<S> {}
+
+impl<S: io::Write> io::Write for Throttled<S> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return self.inner.write(buf);
+		}
+
+		let tokens = self.poll_acquire(Direction::Upload, buf.len())?;
+		let result = self.inner.write(&buf[..tokens]);
+		let used = *result.as_ref().unwrap_or(&0);
+		self.throttle.release(&self.peer, Direction::Upload, tokens - used);
+		result
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+impl<S: AsyncWrite> AsyncWrite for Throttled<S> {
+	fn shutdown(&mut self) -> Poll<(), io::Error> {
+		self.inner.shutdown()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn limits(upload: Option<u64>, peer_upload: Option<u64>) -> BandwidthLimits {
+		BandwidthLimits { upload, peer_upload, ..Default::default() }
+	}
+
+	#[test]
+	fn global_limit_is_enforced() {
+		let throttle = Throttle::new(&limits(Some(10_000), None));
+		let peer = throttle.peer_buckets(&PeerId::random());
+
+		assert_eq!(throttle.acquire(&peer, Direction::Upload, 50_000), Ok(10_000));
+		assert!(throttle.acquire(&peer, Direction::Upload, 1).is_err());
+		assert_eq!(throttle.acquire(&peer, Direction::Download, 50_000), Ok(50_000));
+
+		throttle.release(&peer, Direction::Upload, 1_000);
+		assert_eq!(throttle.acquire(&peer, Direction::Upload, 50_000), Ok(1_000));
+	}
+
+	#[test]
+	fn peer_limit_is_shared_by_connections() {
+		let throttle = Throttle::new(&limits(None, Some(10_000)));
+		let peer_id = PeerId::random();
+		let (peer1, peer2) = (throttle.peer_buckets(&peer_id), throttle.peer_buckets(&peer_id));
+		let other = throttle.peer_buckets(&PeerId::random());
+
+		assert_eq!(throttle.acquire(&peer1, Direction::Upload, 6_000), Ok(6_000));
+		assert_eq!(throttle.acquire(&peer2, Direction::Upload, 6_000), Ok(4_000));
+		assert_eq!(throttle.acquire(&other, Direction::Upload, 6_000), Ok(6_000));
+	}
+
+	#[test]
+	fn upload_congestion() {
+		let throttle = Throttle::new(&limits(None, Some(10_000)));
+		let peer_id = PeerId::random();
+		let peer = throttle.peer_buckets(&peer_id);
+
+		assert!(!throttle.is_upload_congested(&peer_id));
+		assert_eq!(throttle.acquire(&peer, Direction::Upload, 9_000), Ok(9_000));
+		assert!(throttle.is_upload_congested(&peer_id));
+		assert!(!throttle.is_upload_congested(&PeerId::random()));
+
+		// The buckets are dropped with the last connection.
+		drop(peer);
+		assert!(!throttle.is_upload_congested(&peer_id));
+	}
+
+	#[test]
+	fn unlimited_throttle() {
+		let throttle = Throttle::new(&BandwidthLimits::default());
+		let peer = throttle.peer_buckets(&PeerId::random());
+		assert_eq!(throttle.acquire(&peer, Direction::Upload, usize::max_value()), Ok(usize::max_value()));
+	}
+}
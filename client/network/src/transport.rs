@@ -25,6 +25,7 @@ use libp2p::{tcp, dns, websocket, noise};
 use libp2p::core::{either::EitherError, either::EitherOutput};
 use libp2p::core::{self, upgrade, transport::boxed::Boxed, transport::OptionalTransport, muxing::StreamMuxerBox};
 use std::{io, sync::Arc, time::Duration, usize};
+use crate::{config::AllowedPeers, throttle::{Throttle, Throttled}};
 
 pub use self::bandwidth::BandwidthSinks;
 
@@ -33,12 +34,19 @@ pub use self::bandwidth::BandwidthSinks;
 /// If `memory_only` is true, then only communication within the same process are allowed. Only
 /// addresses with the format `/memory/...` are allowed.
 ///
+/// If `allowed_peers` is `Some`, the connections with the peers that aren't on the allow-list are
+/// rejected as soon as the remote is authenticated.
+///
+/// The reads and writes of the connections are limited by the `throttle`.
+///
 /// Returns a `BandwidthSinks` object that allows querying the average bandwidth produced by all
 /// the connections spawned with this transport.
 pub fn build_transport(
 	keypair: identity::Keypair,
 	memory_only: bool,
-	wasm_external_transport: Option<wasm_ext::ExtTransport>
+	wasm_external_transport: Option<wasm_ext::ExtTransport>,
+	allowed_peers: Option<AllowedPeers>,
+	throttle: Arc<Throttle>,
 ) -> (Boxed<(PeerId, StreamMuxerBox), io::Error>, Arc<bandwidth::BandwidthSinks>) {
 	// Build configuration objects for encryption mechanisms.
 	#[cfg(not(target_os = "unknown"))]
@@ -113,6 +121,21 @@ pub fn build_transport(
 			.and_then(|(id, stream)| Ok((stream, id)))
 	});
 
+	// Private network authorization
+	let transport = transport.and_then(move |(stream, peer_id), _| match allowed_peers {
+		Some(ref allowed_peers) if !allowed_peers.contains(&peer_id) => Err(io::Error::new(
+			io::ErrorKind::PermissionDenied,
+			format!("{:?} is not allowed to connect to the private network", peer_id),
+		)),
+		_ => Ok((stream, peer_id)),
+	});
+
+	// Bandwidth throttling
+	let transport = transport.and_then(move |(stream, peer_id), _| {
+		let stream = Throttled::new(throttle.clone(), &peer_id, stream);
+		Ok::<_, io::Error>((stream, peer_id))
+	});
+
 	// Multiplexing
 	let transport = transport.and_then(move |(stream, peer_id), endpoint| {
 			let peer_id2 = peer_id.clone();
@@ -130,3 +153,56 @@ pub fn build_transport(
 
 	(transport, sinks)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::BandwidthLimits;
+	use libp2p::core::transport::ListenerEvent;
+
+	/// Connects a dialer to a listener that only allows the peers of `allowed`, and returns the
+	/// outcome of the upgrade on the listener side.
+	fn connect(dialer: identity::Keypair, allowed: Vec<PeerId>) -> Result<PeerId, io::Error> {
+		let throttle = Arc::new(Throttle::new(&BandwidthLimits::default()));
+		let (listener, _) = build_transport(
+			identity::Keypair::generate_ed25519(),
+			true,
+			None,
+			Some(AllowedPeers::new(allowed)),
+			throttle.clone(),
+		);
+		let (dialer, _) = build_transport(dialer, true, None, None, throttle);
+
+		let addr: libp2p::Multiaddr = format!("/memory/{}", rand::random::<u64>()).parse().unwrap();
+		let inbound = listener.listen_on(addr.clone()).unwrap()
+			.filter_map(|event| match event {
+				ListenerEvent::Upgrade { upgrade, .. } => Some(upgrade),
+				_ => None,
+			})
+			.into_future()
+			.map_err(|(e, _)| e)
+			.and_then(|(upgrade, _)| upgrade.expect("the listener yields the dialer's connection"))
+			.map(|(peer_id, _)| peer_id);
+		// The outcome of the dialer is irrelevant: it is disconnected if it isn't allowed.
+		let outbound = dialer.dial(addr).unwrap().then(|_| Ok::<_, io::Error>(()));
+
+		let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+		runtime.block_on(inbound.join(outbound)).map(|(peer_id, _)| peer_id)
+	}
+
+	#[test]
+	fn allowed_peers_are_accepted() {
+		let dialer = identity::Keypair::generate_ed25519();
+		let dialer_id = dialer.public().into_peer_id();
+
+		assert_eq!(connect(dialer, vec![dialer_id.clone()]).unwrap(), dialer_id);
+	}
+
+	#[test]
+	fn peers_not_on_the_list_are_rejected() {
+		let dialer = identity::Keypair::generate_ed25519();
+
+		let err = connect(dialer, vec![PeerId::random()]).unwrap_err();
+		assert!(err.to_string().contains("is not allowed to connect to the private network"));
+	}
+}
@@ -18,20 +18,25 @@
 //! connected to.
 
 mod peersstate;
+mod store;
 
-use std::{collections::{HashSet, HashMap}, collections::VecDeque, time::Instant};
+use std::{collections::{HashSet, HashMap}, collections::VecDeque, time::{Duration, Instant}};
 use futures::{prelude::*, channel::mpsc};
-use libp2p::PeerId;
-use log::{debug, error, trace};
+use libp2p::{Multiaddr, PeerId};
+use log::{debug, error, trace, warn};
 use serde_json::json;
 use std::{pin::Pin, task::Context, task::Poll};
 
+pub use store::{PeerStore, StoredPeer};
+
 /// We don't accept nodes whose reputation is under this value.
 const BANNED_THRESHOLD: i32 = 82 * (i32::min_value() / 100);
 /// Reputation change for a node when we get disconnected from it.
 const DISCONNECT_REPUTATION_CHANGE: i32 = -10;
 /// Reserved peers group ID
 const RESERVED_NODES: &'static str = "reserved";
+/// Interval at which the peer store is saved.
+const PEER_STORE_SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 #[derive(Debug)]
 enum Action {
@@ -145,6 +150,25 @@ pub struct PeersetConfig {
 	/// > **Note**: Keep in mind that the networking has to know an address for these nodes,
 	/// >			otherwise it will not be able to connect to them.
 	pub reserved_nodes: Vec<PeerId>,
+
+	/// Store of the known peers. If any, the peers and their reputations are restored from it,
+	/// and it is regularly saved.
+	pub peer_store: Option<PeerStore>,
+}
+
+/// Moves a reputation towards zero, as happens every second.
+///
+/// If we multiply each second the reputation by `k` (where `k` is between 0 and 1), it takes
+/// `ln(0.5) / ln(k)` seconds to reduce the reputation by half. We use `k = 0.98`, so we divide by
+/// `50`. With that value, it takes 34.3 seconds to reduce the reputation by half.
+fn reput_tick(reput: i32) -> i32 {
+	let mut diff = reput / 50;
+	if diff == 0 && reput < 0 {
+		diff = -1;
+	} else if diff == 0 && reput > 0 {
+		diff = 1;
+	}
+	reput.saturating_sub(diff)
 }
 
 /// Side of the peer set manager owned by the network. In other words, the "receiving" side.
@@ -166,6 +190,8 @@ pub struct Peerset {
 	created: Instant,
 	/// Last time when we updated the reputations of connected nodes.
 	latest_time_update: Instant,
+	/// Store of the known peers, and last time when it was saved.
+	peer_store: Option<(PeerStore, Instant)>,
 }
 
 impl Peerset {
@@ -185,8 +211,31 @@ impl Peerset {
 			message_queue: VecDeque::new(),
 			created: Instant::now(),
 			latest_time_update: Instant::now(),
+			peer_store: None,
 		};
 
+		if let Some(peer_store) = config.peer_store {
+			// Reputations keep decaying while the node is stopped.
+			let elapsed = store::now().saturating_sub(peer_store.saved());
+			for (peer_id, stored) in peer_store.peers() {
+				let mut reputation = stored.reputation;
+				for _ in 0..elapsed {
+					if reputation == 0 {
+						break
+					}
+					reputation = reput_tick(reputation);
+				}
+				let mut entry = match peerset.data.peer(peer_id) {
+					peersstate::Peer::Unknown(entry) => entry.discover(),
+					peersstate::Peer::NotConnected(entry) => entry,
+					peersstate::Peer::Connected(_) => continue,
+				};
+				entry.set_reputation(reputation);
+			}
+			debug!(target: "peerset", "Restored {} peers from the peer store", peer_store.peers().count());
+			peerset.peer_store = Some((peer_store, Instant::now()));
+		}
+
 		peerset.data.set_priority_group(RESERVED_NODES, config.reserved_nodes.into_iter().collect());
 		for peer_id in config.bootnodes {
 			if let peersstate::Peer::Unknown(entry) = peerset.data.peer(&peer_id) {
@@ -289,22 +338,8 @@ impl Peerset {
 		};
 
 		// For each elapsed second, move the node reputation towards zero.
-		// If we multiply each second the reputation by `k` (where `k` is between 0 and 1), it
-		// takes `ln(0.5) / ln(k)` seconds to reduce the reputation by half. Use this formula to
-		// empirically determine a value of `k` that looks correct.
 		for _ in 0..secs_diff {
 			for peer in self.data.peers().cloned().collect::<Vec<_>>() {
-				// We use `k = 0.98`, so we divide by `50`. With that value, it takes 34.3 seconds
-				// to reduce the reputation by half.
-				fn reput_tick(reput: i32) -> i32 {
-					let mut diff = reput / 50;
-					if diff == 0 && reput < 0 {
-						diff = -1;
-					} else if diff == 0 && reput > 0 {
-						diff = 1;
-					}
-					reput.saturating_sub(diff)
-				}
 				match self.data.peer(&peer) {
 					peersstate::Peer::Connected(mut peer) =>
 						peer.set_reputation(reput_tick(peer.reputation())),
@@ -314,6 +349,40 @@ impl Peerset {
 				}
 			}
 		}
+
+		if self.peer_store.as_ref().map_or(false, |(_, saved)| saved.elapsed() >= PEER_STORE_SAVE_INTERVAL) {
+			self.save_peer_store();
+		}
+	}
+
+	/// Saves the peer store, if any, with the current reputations.
+	fn save_peer_store(&mut self) {
+		let data = &mut self.data;
+		if let Some((peer_store, saved)) = self.peer_store.as_mut() {
+			let result = peer_store.save(|peer_id| match data.peer(peer_id) {
+				peersstate::Peer::Connected(entry) => Some(entry.reputation()),
+				peersstate::Peer::NotConnected(entry) => Some(entry.reputation()),
+				peersstate::Peer::Unknown(_) => None,
+			});
+			if let Err(e) = result {
+				warn!(target: "peerset", "Failed to save the peer store: {}", e);
+			}
+			*saved = Instant::now();
+		}
+	}
+
+	/// Records addresses of a peer in the peer store, if any.
+	pub fn add_known_addresses(&mut self, peer_id: &PeerId, addresses: impl Iterator<Item = Multiaddr>) {
+		if let Some((peer_store, _)) = self.peer_store.as_mut() {
+			peer_store.add_addresses(peer_id, addresses);
+		}
+	}
+
+	/// Records in the peer store, if any, that we have successfully connected to a peer.
+	pub fn note_seen(&mut self, peer_id: &PeerId) {
+		if let Some((peer_store, _)) = self.peer_store.as_mut() {
+			peer_store.note_seen(peer_id);
+		}
 	}
 
 	/// Try to fill available out slots with nodes.
@@ -471,6 +540,12 @@ impl Peerset {
 	}
 }
 
+impl Drop for Peerset {
+	fn drop(&mut self) {
+		self.save_peer_store();
+	}
+}
+
 impl Stream for Peerset {
 	type Item = Message;
 
@@ -540,6 +615,7 @@ mod tests {
 			bootnodes: vec![bootnode],
 			reserved_only: true,
 			reserved_nodes: Vec::new(),
+			peer_store: None,
 		};
 
 		let (peerset, handle) = Peerset::from_config(config);
@@ -568,6 +644,7 @@ mod tests {
 			bootnodes: vec![bootnode.clone()],
 			reserved_only: false,
 			reserved_nodes: Vec::new(),
+			peer_store: None,
 		};
 
 		let (mut peerset, _handle) = Peerset::from_config(config);
@@ -595,6 +672,7 @@ mod tests {
 			bootnodes: vec![bootnode.clone()],
 			reserved_only: false,
 			reserved_nodes: vec![],
+			peer_store: None,
 		};
 
 		let (mut peerset, _handle) = Peerset::from_config(config);
@@ -616,6 +694,7 @@ mod tests {
 			bootnodes: vec![],
 			reserved_only: false,
 			reserved_nodes: vec![],
+			peer_store: None,
 		});
 
 		// We ban a node by setting its reputation under the threshold.
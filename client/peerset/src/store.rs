@@ -0,0 +1,208 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! On-disk store of the known peers, so that their reputations and addresses survive restarts.
+//!
+//! The store is a JSON file, rewritten as a whole every time it is saved.
+
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+use libp2p::{Multiaddr, PeerId};
+use log::warn;
+use serde::{Serialize, Deserialize};
+
+/// Maximum number of peers kept in the store. The peers seen the least recently are dropped first.
+const MAX_STORED_PEERS: usize = 1000;
+/// Maximum number of addresses kept for each peer.
+const MAX_STORED_ADDRESSES: usize = 10;
+/// Peers which have not been seen for that long are dropped from the store.
+const EXPIRATION_SECS: u64 = 30 * 24 * 3600;
+
+/// What we know about a peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoredPeer {
+	/// Reputation of the peer when the store was saved.
+	pub reputation: i32,
+	/// When we were last connected to the peer, in seconds since the UNIX epoch.
+	pub last_seen: u64,
+	/// Known addresses of the peer, most recent first.
+	pub addresses: Vec<Multiaddr>,
+}
+
+/// Format of the file.
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+	/// When the file was saved, in seconds since the UNIX epoch.
+	saved: u64,
+	/// Known peers, by base-58 encoded peer id.
+	peers: HashMap<String, FilePeer>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FilePeer {
+	reputation: i32,
+	last_seen: u64,
+	addresses: Vec<String>,
+}
+
+/// Known peers, loaded from and saved to a file.
+#[derive(Debug)]
+pub struct PeerStore {
+	path: PathBuf,
+	/// When the store was last saved, in seconds since the UNIX epoch.
+	saved: u64,
+	peers: HashMap<PeerId, StoredPeer>,
+}
+
+/// Current time, in seconds since the UNIX epoch.
+pub(crate) fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl PeerStore {
+	/// Opens the store at the given path.
+	///
+	/// The store starts empty if the file doesn't exist yet, or if it can't be decoded.
+	pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		let mut store = PeerStore { path, saved: now(), peers: HashMap::new() };
+
+		let content = match fs::read(&store.path) {
+			Ok(content) => content,
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(store),
+			Err(e) => return Err(e),
+		};
+		let file: StoreFile = match serde_json::from_slice(&content) {
+			Ok(file) => file,
+			Err(e) => {
+				warn!(target: "peerset", "Ignoring invalid peer store {}: {}", store.path.display(), e);
+				return Ok(store)
+			}
+		};
+
+		store.saved = file.saved;
+		for (peer_id, peer) in file.peers {
+			let peer_id = match peer_id.parse::<PeerId>() {
+				Ok(peer_id) => peer_id,
+				Err(_) => continue,
+			};
+			store.peers.insert(peer_id, StoredPeer {
+				reputation: peer.reputation,
+				last_seen: peer.last_seen,
+				addresses: peer.addresses.iter().filter_map(|a| a.parse().ok()).collect(),
+			});
+		}
+		Ok(store)
+	}
+
+	/// When the store was last saved, in seconds since the UNIX epoch.
+	pub fn saved(&self) -> u64 {
+		self.saved
+	}
+
+	/// Returns the known peers.
+	pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &StoredPeer)> {
+		self.peers.iter()
+	}
+
+	/// Returns all the known addresses of all the known peers.
+	pub fn addresses(&self) -> impl Iterator<Item = (&PeerId, &Multiaddr)> {
+		self.peers.iter().flat_map(|(peer_id, peer)| peer.addresses.iter().map(move |a| (peer_id, a)))
+	}
+
+	/// Records addresses of a peer.
+	pub fn add_addresses(&mut self, peer_id: &PeerId, addresses: impl Iterator<Item = Multiaddr>) {
+		let peer = self.peers.entry(peer_id.clone()).or_default();
+		for address in addresses {
+			peer.addresses.retain(|a| a != &address);
+			peer.addresses.insert(0, address);
+		}
+		peer.addresses.truncate(MAX_STORED_ADDRESSES);
+	}
+
+	/// Records that we are connected to a peer.
+	pub fn note_seen(&mut self, peer_id: &PeerId) {
+		self.peers.entry(peer_id.clone()).or_default().last_seen = now();
+	}
+
+	/// Saves the store, with the given current reputations of the peers.
+	pub fn save(&mut self, mut reputation: impl FnMut(&PeerId) -> Option<i32>) -> io::Result<()> {
+		self.saved = now();
+		for (peer_id, peer) in self.peers.iter_mut() {
+			if let Some(value) = reputation(peer_id) {
+				peer.reputation = value;
+			}
+		}
+
+		let saved = self.saved;
+		self.peers.retain(|_, peer| peer.last_seen + EXPIRATION_SECS > saved || peer.reputation < 0);
+		if self.peers.len() > MAX_STORED_PEERS {
+			let mut last_seen = self.peers.values().map(|peer| peer.last_seen).collect::<Vec<_>>();
+			last_seen.sort_unstable_by(|a, b| b.cmp(a));
+			let oldest = last_seen[MAX_STORED_PEERS - 1];
+			self.peers.retain(|_, peer| peer.last_seen >= oldest);
+		}
+
+		let file = StoreFile {
+			saved,
+			peers: self.peers.iter().map(|(peer_id, peer)| (peer_id.to_base58(), FilePeer {
+				reputation: peer.reputation,
+				last_seen: peer.last_seen,
+				addresses: peer.addresses.iter().map(|a| a.to_string()).collect(),
+			})).collect(),
+		};
+		let content = serde_json::to_vec(&file)
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+		// Write to a temporary file first, so that a crash doesn't leave a truncated store.
+		let tmp_path = self.path.with_extension("tmp");
+		fs::write(&tmp_path, content)?;
+		fs::rename(&tmp_path, &self.path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::PeerStore;
+	use libp2p::PeerId;
+
+	#[test]
+	fn store_roundtrip() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("peers.json");
+		let peer_id = PeerId::random();
+		let address: libp2p::Multiaddr = "/ip4/127.0.0.1/tcp/30333".parse().unwrap();
+
+		let mut store = PeerStore::open(&path).unwrap();
+		assert_eq!(store.peers().count(), 0);
+		store.add_addresses(&peer_id, vec![address.clone(), address.clone()].into_iter());
+		store.note_seen(&peer_id);
+		store.save(|_| Some(-100)).unwrap();
+
+		let store = PeerStore::open(&path).unwrap();
+		let (stored_id, stored) = store.peers().next().unwrap();
+		assert_eq!(stored_id, &peer_id);
+		assert_eq!(stored.reputation, -100);
+		assert_eq!(stored.addresses, vec![address]);
+	}
+
+	#[test]
+	fn invalid_store_is_ignored() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("peers.json");
+		std::fs::write(&path, b"not json").unwrap();
+		assert_eq!(PeerStore::open(&path).unwrap().peers().count(), 0);
+	}
+}
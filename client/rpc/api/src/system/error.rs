@@ -17,17 +17,45 @@
 //! System RPC module errors.
 
 use crate::system::helpers::Health;
+use crate::policy::UnsafeRpcError;
 use jsonrpc_core as rpc;
 
 /// System RPC Result type.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// System RPC future Result type.
+pub type FutureResult<T> = Box<dyn rpc::futures::Future<Item = T, Error = Error> + Send>;
+
 /// System RPC errors.
 #[derive(Debug, derive_more::Display, derive_more::From)]
 pub enum Error {
 	/// Provided block range couldn't be resolved to a list of blocks.
 	#[display(fmt = "Node is not fully functional: {}", _0)]
 	NotHealthy(Health),
+	/// Peer argument is malformatted.
+	#[display(fmt = "Malformatted peer argument: {}", _0)]
+	#[from(ignore)]
+	MalformattedPeerArg(String),
+	/// Non-reserved peer mode is not recognized.
+	#[display(fmt = "Unknown non-reserved peer mode: {}", _0)]
+	#[from(ignore)]
+	UnknownNonReservedPeerMode(String),
+	/// Error while reading the database.
+	#[display(fmt = "Database error: {}", _0)]
+	#[from(ignore)]
+	Database(String),
+	/// Too many heavy requests are being answered.
+	#[display(fmt = "Too many requests are being processed, retry later")]
+	Busy,
+	/// The new runtime failed the upgrade checks.
+	#[display(fmt = "Runtime upgrade rejected: {}", _0)]
+	#[from(ignore)]
+	RuntimeUpgradeRejected(String),
+	/// Call to an unsafe RPC was denied.
+	UnsafeRpcCalled(UnsafeRpcError),
+	/// The request was dropped before being answered.
+	#[display(fmt = "Request was dropped before being answered")]
+	RequestDropped,
 }
 
 impl std::error::Error for Error {}
@@ -43,6 +71,33 @@ impl From<Error> for rpc::Error {
 				message: format!("{}", e),
 				data: serde_json::to_value(h).ok(),
 			},
+			Error::MalformattedPeerArg(_) => rpc::Error {
+				code: rpc::ErrorCode::ServerError(BASE_ERROR + 2),
+				message: format!("{}", e),
+				data: None,
+			},
+			Error::UnknownNonReservedPeerMode(ref mode) => rpc::Error {
+				code: rpc::ErrorCode::InvalidParams,
+				message: format!("{}", e),
+				data: Some(mode.clone().into()),
+			},
+			Error::Database(_) => rpc::Error {
+				code: rpc::ErrorCode::ServerError(BASE_ERROR + 3),
+				message: format!("{}", e),
+				data: None,
+			},
+			Error::Busy => rpc::Error {
+				code: rpc::ErrorCode::ServerError(BASE_ERROR + 4),
+				message: format!("{}", e),
+				data: None,
+			},
+			Error::RuntimeUpgradeRejected(_) => rpc::Error {
+				code: rpc::ErrorCode::ServerError(BASE_ERROR + 5),
+				message: format!("{}", e),
+				data: None,
+			},
+			Error::UnsafeRpcCalled(e) => e.into(),
+			Error::RequestDropped => rpc::Error::internal_error(),
 		}
 	}
 }
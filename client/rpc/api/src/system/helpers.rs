@@ -19,6 +19,7 @@
 use std::fmt;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, map::Map};
+use runtime_version::RuntimeVersion;
 
 /// Node properties
 pub type Properties = Map<String, Value>;
@@ -74,6 +75,92 @@ pub struct PeerInfo<Hash, Number> {
 	pub best_number: Number,
 }
 
+/// Usage of the database returned by the RPC.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseUsage {
+	/// Accesses to each column.
+	pub columns: Vec<DatabaseColumnUsage>,
+	/// Number of commits.
+	pub commits: u64,
+	/// Total duration of the commits, in milliseconds.
+	pub commits_duration_ms: u64,
+	/// Histogram of the durations of the commits.
+	pub commit_latency: Vec<LatencyBucket>,
+	/// Memory used by the state cache, in bytes.
+	pub state_cache_size: usize,
+	/// Number of state reads served by the cache.
+	pub state_cache_hits: u64,
+	/// Number of state reads which had to go to the database.
+	pub state_cache_misses: u64,
+	/// Memory used by the trie node cache, in bytes.
+	pub trie_cache_size: usize,
+	/// Number of trie node reads served by the cache.
+	pub trie_cache_hits: u64,
+	/// Number of trie node reads which had to go to the database.
+	pub trie_cache_misses: u64,
+}
+
+/// Accesses to a database column.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseColumnUsage {
+	/// Name of the column.
+	pub name: String,
+	/// Number of reads since the node has started.
+	pub reads: u64,
+	/// Number of bytes read since the node has started.
+	pub bytes_read: u64,
+	/// Number of writes since the node has started.
+	pub writes: u64,
+	/// Number of bytes written since the node has started.
+	pub bytes_written: u64,
+	/// Number of reads per second, recently.
+	pub reads_per_sec: u64,
+	/// Number of bytes read per second, recently.
+	pub bytes_read_per_sec: u64,
+	/// Number of writes per second, recently.
+	pub writes_per_sec: u64,
+	/// Number of bytes written per second, recently.
+	pub bytes_written_per_sec: u64,
+}
+
+/// Bucket of a latency histogram.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyBucket {
+	/// Upper bound of the bucket in milliseconds, `None` for the last bucket.
+	pub max_ms: Option<u64>,
+	/// Number of samples in the bucket.
+	pub count: u64,
+}
+
+/// Size of a database column.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseColumnSize {
+	/// Name of the column.
+	pub name: String,
+	/// Number of keys.
+	pub keys: u64,
+	/// Size of the keys and values, in bytes.
+	pub bytes: u64,
+}
+
+/// Outcome of a dry run of the upgrade to a new runtime.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeUpgradeCheck<Hash> {
+	/// Hash of the best block the new runtime was executed on.
+	pub best_hash: Hash,
+	/// Version of the current runtime.
+	pub current_version: RuntimeVersion,
+	/// Version of the new runtime.
+	pub new_version: RuntimeVersion,
+	/// Number of inherent extrinsics applied in the block built with the new runtime.
+	pub inherent_extrinsics: usize,
+}
+
 /// The role the node is running as
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum NodeRole {
@@ -103,6 +190,18 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn should_serialize_database_column_size() {
+		assert_eq!(
+			::serde_json::to_string(&DatabaseColumnSize {
+				name: "state".into(),
+				keys: 2,
+				bytes: 100,
+			}).unwrap(),
+			r#"{"name":"state","keys":2,"bytes":100}"#,
+		);
+	}
+
 	#[test]
 	fn should_serialize_peer_info() {
 		assert_eq!(
@@ -21,10 +21,14 @@ pub mod helpers;
 
 use crate::helpers::Receiver;
 use jsonrpc_derive::rpc;
+use primitives::Bytes;
 
-use self::error::Result;
+use self::error::{FutureResult, Result};
 
-pub use self::helpers::{Properties, SystemInfo, Health, PeerInfo, NodeRole};
+pub use self::helpers::{
+	Properties, SystemInfo, Health, PeerInfo, NodeRole, DatabaseUsage, DatabaseColumnUsage,
+	LatencyBucket, DatabaseColumnSize, RuntimeUpgradeCheck,
+};
 pub use self::gen_client::Client as SystemClient;
 
 /// Substrate system RPC API
@@ -68,4 +72,56 @@ pub trait SystemApi<Hash, Number> {
 	/// Returns the roles the node is running as.
 	#[rpc(name = "system_nodeRoles", returns = "Vec<NodeRole>")]
 	fn system_node_roles(&self) -> Receiver<Vec<NodeRole>>;
+
+	/// Returns the multiaddresses that the local node is listening on.
+	///
+	/// The addresses include a trailing `/p2p/` with the local `PeerId`, and are thus suitable to
+	/// be passed to `system_addReservedPeer` or as a bootnode address.
+	#[rpc(name = "system_localListenAddresses", returns = "Vec<String>")]
+	fn system_local_listen_addresses(&self) -> Receiver<Vec<String>>;
+
+	/// Returns the usage statistics of the database, or `null` if the node doesn't keep them.
+	#[rpc(name = "system_databaseUsage", returns = "Option<DatabaseUsage>")]
+	fn system_database_usage(&self) -> Receiver<Option<DatabaseUsage>>;
+
+	/// Returns the number of keys and the size of each column of the database.
+	///
+	/// This goes through the whole database and may take a long time, so this method is unsafe
+	/// and is denied when called externally, unless explicitly allowed.
+	#[rpc(name = "system_databaseColumnSizes", returns = "Vec<DatabaseColumnSize>")]
+	fn system_database_column_sizes(&self) -> FutureResult<Vec<DatabaseColumnSize>>;
+
+	/// Adds a reserved peer. The string parameter should encode a `p2p` multiaddr.
+	///
+	/// `/ip4/198.51.100.19/tcp/30333/p2p/QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV`
+	/// is an example of a valid multiaddr with the `PeerId` attached.
+	///
+	/// This method is unsafe and is denied when called externally, unless explicitly allowed.
+	#[rpc(name = "system_addReservedPeer", returns = "()")]
+	fn system_add_reserved_peer(&self, peer: String) -> FutureResult<()>;
+
+	/// Removes a reserved peer. The string should encode only the `PeerId`,
+	/// e.g. `QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV`.
+	///
+	/// This method is unsafe and is denied when called externally, unless explicitly allowed.
+	#[rpc(name = "system_removeReservedPeer", returns = "()")]
+	fn system_remove_reserved_peer(&self, peer_id: String) -> FutureResult<()>;
+
+	/// Sets the policy for connections to non-reserved peers. The mode is either `accept`
+	/// or `deny`. In the latter case the node disconnects from all non-reserved peers and only
+	/// maintains connections to the reserved ones.
+	///
+	/// This method is unsafe and is denied when called externally, unless explicitly allowed.
+	#[rpc(name = "system_setNonReservedPeerMode", returns = "()")]
+	fn system_set_non_reserved_peer_mode(&self, mode: String) -> FutureResult<()>;
+
+	/// Dry runs the upgrade to the given Wasm runtime code on top of the best block.
+	///
+	/// The new runtime must be instantiable, keep the spec name of the current runtime and
+	/// increase its spec version, and must initialize and finalize a block with the inherents.
+	/// Nothing is written to the database.
+	///
+	/// This method is unsafe and is denied when called externally, unless explicitly allowed.
+	#[rpc(name = "system_checkRuntimeUpgrade", returns = "RuntimeUpgradeCheck<Hash>")]
+	fn system_check_runtime_upgrade(&self, code: Bytes) -> FutureResult<RuntimeUpgradeCheck<Hash>>;
 }
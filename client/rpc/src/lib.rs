@@ -22,7 +22,7 @@
 
 mod metadata;
 
-pub use api::Subscriptions;
+pub use api::{DenyUnsafe, Subscriptions};
 pub use self::metadata::Metadata;
 pub use rpc::IoHandlerExtension as RpcExtension;
 
@@ -252,7 +252,7 @@ impl<B, E, Block, RA> StateBackend<B, E, Block, RA> for FullState<B, E, Block, R
 						&method,
 						&*call_data,
 						ExecutionStrategy::NativeElseWasm,
-						None,
+						Some(self.client.execution_extensions().call_extensions()),
 					)
 					.map(Into::into))
 				.map_err(client_err)))
@@ -20,18 +20,24 @@
 mod tests;
 
 use futures::{channel::{mpsc, oneshot}, compat::Compat};
-use api::Receiver;
+use primitives::Bytes;
+use api::{DenyUnsafe, Receiver};
+use rpc::futures::Future;
 use sp_runtime::traits::{self, Header as HeaderT};
-use self::error::Result;
+use self::error::{Error, FutureResult, Result};
 
 pub use api::system::*;
-pub use self::helpers::{Properties, SystemInfo, Health, PeerInfo, NodeRole};
+pub use self::helpers::{
+	Properties, SystemInfo, Health, PeerInfo, NodeRole, DatabaseUsage, DatabaseColumnUsage,
+	LatencyBucket, DatabaseColumnSize, RuntimeUpgradeCheck,
+};
 pub use self::gen_client::Client as SystemClient;
 
 /// System API implementation
 pub struct System<B: traits::Block> {
 	info: SystemInfo,
 	send_back: mpsc::UnboundedSender<Request<B>>,
+	deny_unsafe: DenyUnsafe,
 }
 
 /// Request to be processed.
@@ -43,7 +49,21 @@ pub enum Request<B: traits::Block> {
 	/// Must return the state of the network.
 	NetworkState(oneshot::Sender<rpc::Value>),
 	/// Must return the node role.
-	NodeRoles(oneshot::Sender<Vec<NodeRole>>)
+	NodeRoles(oneshot::Sender<Vec<NodeRole>>),
+	/// Must return the list of multiaddresses the node is listening on.
+	LocalListenAddresses(oneshot::Sender<Vec<String>>),
+	/// Must return the usage statistics of the database.
+	DatabaseUsage(oneshot::Sender<Option<DatabaseUsage>>),
+	/// Must return the size of each column of the database.
+	DatabaseColumnSizes(oneshot::Sender<Result<Vec<DatabaseColumnSize>>>),
+	/// Must add the given `p2p` multiaddr as a reserved peer.
+	NetworkAddReservedPeer(String, oneshot::Sender<Result<()>>),
+	/// Must remove the peer with the given `PeerId` from the reserved peers.
+	NetworkRemoveReservedPeer(String, oneshot::Sender<Result<()>>),
+	/// Must apply the given policy for connections to non-reserved peers.
+	NetworkSetNonReservedPeerMode(String, oneshot::Sender<Result<()>>),
+	/// Must dry run the upgrade to the given Wasm runtime code on top of the best block.
+	CheckRuntimeUpgrade(Vec<u8>, oneshot::Sender<Result<RuntimeUpgradeCheck<B::Hash>>>),
 }
 
 impl<B: traits::Block> System<B> {
@@ -51,14 +71,32 @@ impl<B: traits::Block> System<B> {
 	///
 	/// The `send_back` will be used to transmit some of the requests. The user is responsible for
 	/// reading from that channel and answering the requests.
+	///
+	/// The `deny_unsafe` tells whether the methods that modify the node state are denied.
 	pub fn new(
 		info: SystemInfo,
-		send_back: mpsc::UnboundedSender<Request<B>>
+		send_back: mpsc::UnboundedSender<Request<B>>,
+		deny_unsafe: DenyUnsafe,
 	) -> Self {
 		System {
 			info,
 			send_back,
+			deny_unsafe,
+		}
+	}
+
+	/// Sends an unsafe request to be answered by the user, unless unsafe methods are denied.
+	fn unsafe_request<T: Send + 'static>(
+		&self,
+		request: impl FnOnce(oneshot::Sender<Result<T>>) -> Request<B>,
+	) -> FutureResult<T> {
+		if let Err(err) = self.deny_unsafe.check_if_safe() {
+			return Box::new(rpc::futures::future::err(err.into()));
 		}
+
+		let (tx, rx) = oneshot::channel();
+		let _ = self.send_back.unbounded_send(request(tx));
+		Box::new(Compat::new(rx).map_err(|_| Error::RequestDropped).and_then(|result| result))
 	}
 }
 
@@ -102,4 +140,36 @@ impl<B: traits::Block> SystemApi<B::Hash, <B::Header as HeaderT>::Number> for Sy
 		let _ = self.send_back.unbounded_send(Request::NodeRoles(tx));
 		Receiver(Compat::new(rx))
 	}
+
+	fn system_local_listen_addresses(&self) -> Receiver<Vec<String>> {
+		let (tx, rx) = oneshot::channel();
+		let _ = self.send_back.unbounded_send(Request::LocalListenAddresses(tx));
+		Receiver(Compat::new(rx))
+	}
+
+	fn system_database_usage(&self) -> Receiver<Option<DatabaseUsage>> {
+		let (tx, rx) = oneshot::channel();
+		let _ = self.send_back.unbounded_send(Request::DatabaseUsage(tx));
+		Receiver(Compat::new(rx))
+	}
+
+	fn system_database_column_sizes(&self) -> FutureResult<Vec<DatabaseColumnSize>> {
+		self.unsafe_request(Request::DatabaseColumnSizes)
+	}
+
+	fn system_add_reserved_peer(&self, peer: String) -> FutureResult<()> {
+		self.unsafe_request(|tx| Request::NetworkAddReservedPeer(peer, tx))
+	}
+
+	fn system_remove_reserved_peer(&self, peer_id: String) -> FutureResult<()> {
+		self.unsafe_request(|tx| Request::NetworkRemoveReservedPeer(peer_id, tx))
+	}
+
+	fn system_set_non_reserved_peer_mode(&self, mode: String) -> FutureResult<()> {
+		self.unsafe_request(|tx| Request::NetworkSetNonReservedPeerMode(mode, tx))
+	}
+
+	fn system_check_runtime_upgrade(&self, code: Bytes) -> FutureResult<RuntimeUpgradeCheck<B::Hash>> {
+		self.unsafe_request(|tx| Request::CheckRuntimeUpgrade(code.0, tx))
+	}
 }
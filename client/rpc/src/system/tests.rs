@@ -42,6 +42,10 @@ impl Default for Status {
 }
 
 fn api<T: Into<Option<Status>>>(sync: T) -> System<Block> {
+	api_with_policy(sync, DenyUnsafe::No)
+}
+
+fn api_with_policy<T: Into<Option<Status>>>(sync: T, deny_unsafe: DenyUnsafe) -> System<Block> {
 	let status = sync.into().unwrap_or_default();
 	let should_have_peers = !status.is_dev;
 	let (tx, rx) = mpsc::unbounded();
@@ -83,6 +87,50 @@ fn api<T: Into<Option<Status>>>(sync: T) -> System<Block> {
 				Request::NodeRoles(sender) => {
 					let _ = sender.send(vec![NodeRole::Authority]);
 				}
+				Request::LocalListenAddresses(sender) => {
+					let _ = sender.send(vec![
+						"/ip4/198.51.100.19/tcp/30333/p2p/QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV".into(),
+					]);
+				}
+				Request::DatabaseUsage(sender) => {
+					let _ = sender.send(None);
+				}
+				Request::DatabaseColumnSizes(sender) => {
+					let _ = sender.send(Ok(vec![DatabaseColumnSize {
+						name: "state".into(),
+						keys: 2,
+						bytes: 100,
+					}]));
+				}
+				Request::NetworkAddReservedPeer(peer, sender) => {
+					let _ = match network::config::parse_str_addr(&peer) {
+						Ok(_) => sender.send(Ok(())),
+						Err(s) => sender.send(Err(error::Error::MalformattedPeerArg(format!("{:?}", s)))),
+					};
+				}
+				Request::NetworkRemoveReservedPeer(peer, sender) => {
+					let _ = match peer.parse::<PeerId>() {
+						Ok(_) => sender.send(Ok(())),
+						Err(s) => sender.send(Err(error::Error::MalformattedPeerArg(format!("{:?}", s)))),
+					};
+				}
+				Request::NetworkSetNonReservedPeerMode(mode, sender) => {
+					let _ = match network::config::NonReservedPeerMode::parse(&mode) {
+						Some(_) => sender.send(Ok(())),
+						None => sender.send(Err(error::Error::UnknownNonReservedPeerMode(mode))),
+					};
+				}
+				Request::CheckRuntimeUpgrade(code, sender) => {
+					let _ = match code.is_empty() {
+						false => sender.send(Ok(RuntimeUpgradeCheck {
+							best_hash: Default::default(),
+							current_version: Default::default(),
+							new_version: Default::default(),
+							inherent_extrinsics: 1,
+						})),
+						true => sender.send(Err(error::Error::RuntimeUpgradeRejected("empty code".into()))),
+					};
+				}
 			};
 
 			future::ready(())
@@ -93,7 +141,7 @@ fn api<T: Into<Option<Status>>>(sync: T) -> System<Block> {
 		impl_version: "0.2.0".into(),
 		chain_name: "testchain".into(),
 		properties: Default::default(),
-	}, tx)
+	}, tx, deny_unsafe)
 }
 
 fn wait_receiver<T>(rx: Receiver<T>) -> T {
@@ -232,3 +280,96 @@ fn system_node_roles() {
 		vec![NodeRole::Authority]
 	);
 }
+
+#[test]
+fn system_local_listen_addresses() {
+	assert_eq!(
+		wait_receiver(api(None).system_local_listen_addresses()),
+		vec!["/ip4/198.51.100.19/tcp/30333/p2p/QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV".to_owned()]
+	);
+}
+
+#[test]
+fn system_network_add_reserved() {
+	let good_peer_id = "/ip4/198.51.100.19/tcp/30333/p2p/QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV";
+	let bad_peer_id = "/ip4/198.51.100.19/tcp/30333";
+	let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+	let good = runtime.block_on(api(None).system_add_reserved_peer(good_peer_id.into()));
+	assert_matches!(good, Ok(()));
+	let bad = runtime.block_on(api(None).system_add_reserved_peer(bad_peer_id.into()));
+	assert_matches!(bad, Err(error::Error::MalformattedPeerArg(_)));
+}
+
+#[test]
+fn system_network_remove_reserved() {
+	let good_peer_id = "QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV";
+	let bad_peer_id = "/ip4/198.51.100.19/tcp/30333/p2p/QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV";
+	let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+	let good = runtime.block_on(api(None).system_remove_reserved_peer(good_peer_id.into()));
+	assert_matches!(good, Ok(()));
+	let bad = runtime.block_on(api(None).system_remove_reserved_peer(bad_peer_id.into()));
+	assert_matches!(bad, Err(error::Error::MalformattedPeerArg(_)));
+}
+
+#[test]
+fn system_network_set_non_reserved_peer_mode() {
+	let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+	let good = runtime.block_on(api(None).system_set_non_reserved_peer_mode("deny".into()));
+	assert_matches!(good, Ok(()));
+	let bad = runtime.block_on(api(None).system_set_non_reserved_peer_mode("maybe".into()));
+	assert_matches!(bad, Err(error::Error::UnknownNonReservedPeerMode(_)));
+}
+
+#[test]
+fn system_database_usage_works() {
+	let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+	assert_eq!(wait_receiver(api(None).system_database_usage()), None);
+	assert_eq!(
+		runtime.block_on(api(None).system_database_column_sizes()).unwrap(),
+		vec![DatabaseColumnSize { name: "state".into(), keys: 2, bytes: 100 }],
+	);
+}
+
+#[test]
+fn system_check_runtime_upgrade() {
+	let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+	let good = runtime.block_on(api(None).system_check_runtime_upgrade(vec![1, 2, 3].into()));
+	assert_eq!(good.unwrap().inherent_extrinsics, 1);
+	let bad = runtime.block_on(api(None).system_check_runtime_upgrade(Vec::new().into()));
+	assert_matches!(bad, Err(error::Error::RuntimeUpgradeRejected(_)));
+}
+
+#[test]
+fn system_unsafe_methods_are_denied() {
+	let peer = "/ip4/198.51.100.19/tcp/30333/p2p/QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV";
+	let api = api_with_policy(None, DenyUnsafe::Yes);
+	let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+	assert_matches!(
+		runtime.block_on(api.system_add_reserved_peer(peer.into())),
+		Err(error::Error::UnsafeRpcCalled(_))
+	);
+	assert_matches!(
+		runtime.block_on(api.system_remove_reserved_peer("QmSk5HQbn6LhUwDiNMseVUjuRYhEtYj4aUZ6WfWoGURpdV".into())),
+		Err(error::Error::UnsafeRpcCalled(_))
+	);
+	assert_matches!(
+		runtime.block_on(api.system_set_non_reserved_peer_mode("deny".into())),
+		Err(error::Error::UnsafeRpcCalled(_))
+	);
+	assert_matches!(
+		runtime.block_on(api.system_database_column_sizes()),
+		Err(error::Error::UnsafeRpcCalled(_))
+	);
+	assert_matches!(
+		runtime.block_on(api.system_check_runtime_upgrade(vec![1, 2, 3].into())),
+		Err(error::Error::UnsafeRpcCalled(_))
+	);
+	// safe methods are still available
+	assert_eq!(wait_receiver(api.system_local_listen_addresses()).len(), 1);
+}
@@ -0,0 +1,109 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+use log::warn;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Runs the tasks which take too long to be run on the network future, such as the heavy RPC
+/// requests, one at a time on a dedicated thread.
+///
+/// At most `max_pending` tasks are queued or running. The thread ends when the `BlockingTasks`
+/// is dropped, after running the queued tasks.
+pub struct BlockingTasks {
+	sender: Option<SyncSender<Task>>,
+	pending: Arc<AtomicUsize>,
+	max_pending: usize,
+}
+
+impl BlockingTasks {
+	/// Spawns the thread running the tasks.
+	pub fn new(name: &str, max_pending: usize) -> Self {
+		let (sender, receiver) = sync_channel::<Task>(max_pending);
+		let pending = Arc::new(AtomicUsize::new(0));
+		let thread_pending = pending.clone();
+		let spawned = thread::Builder::new()
+			.name(name.into())
+			.spawn(move || for task in receiver {
+				task();
+				thread_pending.fetch_sub(1, Ordering::SeqCst);
+			});
+		let sender = match spawned {
+			Ok(_) => Some(sender),
+			Err(e) => {
+				warn!("Failed to spawn the {} thread: {}", name, e);
+				None
+			},
+		};
+		BlockingTasks { sender, pending, max_pending }
+	}
+
+	/// Queues a task, run with the given `input`. If too many tasks are pending, the task is not
+	/// run and `input` is returned, e.g. to answer a request with an error.
+	///
+	/// The input is dropped if the thread has stopped, e.g. because a task panicked.
+	pub fn spawn<T: Send + 'static>(&self, input: T, task: impl FnOnce(T) + Send + 'static) -> Result<(), T> {
+		let sender = match &self.sender {
+			Some(sender) => sender,
+			None => return Err(input),
+		};
+		// the tasks are only queued from the owner of `self`, so the count can't be raised
+		// concurrently.
+		if self.pending.load(Ordering::SeqCst) >= self.max_pending {
+			return Err(input)
+		}
+		self.pending.fetch_add(1, Ordering::SeqCst);
+		if sender.try_send(Box::new(move || task(input))).is_err() {
+			self.pending.fetch_sub(1, Ordering::SeqCst);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::BlockingTasks;
+	use std::sync::mpsc::channel;
+
+	#[test]
+	fn runs_tasks_and_rejects_them_when_busy() {
+		let tasks = BlockingTasks::new("test-blocking-tasks", 2);
+		let (block_tx, block_rx) = channel::<()>();
+		let (done_tx, done_rx) = channel();
+
+		// the first task blocks the thread, the second one waits in the queue.
+		assert_eq!(tasks.spawn(done_tx.clone(), move |done| {
+			block_rx.recv().unwrap();
+			done.send(1).unwrap();
+		}), Ok(()));
+		assert_eq!(tasks.spawn(done_tx.clone(), |done| done.send(2).unwrap()), Ok(()));
+		assert_eq!(tasks.spawn(4, |_| panic!("Rejected tasks are not run")), Err(4));
+
+		block_tx.send(()).unwrap();
+		assert_eq!(done_rx.recv().unwrap(), 1);
+		assert_eq!(done_rx.recv().unwrap(), 2);
+
+		// room is made once the tasks have run.
+		while tasks.spawn(done_tx.clone(), |done| done.send(3).unwrap()).is_err() {
+			std::thread::yield_now();
+		}
+		assert_eq!(done_rx.recv().unwrap(), 3);
+	}
+}
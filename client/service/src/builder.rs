@@ -16,8 +16,9 @@
 
 use crate::{Service, NetworkStatus, NetworkState, error::Error, DEFAULT_PROTOCOL_ID};
 use crate::{SpawnTaskHandle, start_rpc_servers, build_network_future, TransactionPoolAdapter};
+use crate::runtime_allowed_peers;
 use crate::status_sinks;
-use crate::config::{Configuration, DatabaseConfig};
+use crate::config::Configuration;
 use client_api::{
 	self,
 	BlockchainEvents,
@@ -140,6 +141,30 @@ type TLightCallExecutor<TBl, TExecDisp> = client::light::call_executor::GenesisC
 	>,
 >;
 
+/// Creates the executor of the runtime according to the configuration.
+fn new_executor<TExecDisp: NativeExecutionDispatch, TCfg, TGen, TCSExt>(
+	config: &Configuration<TCfg, TGen, TCSExt>,
+) -> NativeExecutor<TExecDisp> {
+	#[cfg(feature = "wasmtime")]
+	{
+		// the interpreted runtimes are not compiled, so they have nothing to cache.
+		if config.wasm_method == sc_executor::WasmExecutionMethod::Compiled {
+			if let Some(ref path) = config.wasmtime_cache_path {
+				if let Err(e) = sc_executor::init_compilation_cache(path) {
+					warn!("Compiled runtimes won't be cached: {}", e);
+				}
+			}
+		}
+	}
+
+	NativeExecutor::new(
+		config.wasm_method,
+		config.default_heap_pages,
+	)
+		.with_runtimes_cache_limits(config.max_runtimes, config.max_runtime_instances)
+		.with_allocation_strategy(config.allocation_strategy)
+}
+
 impl<TCfg, TGen, TCSExt> ServiceBuilder<(), (), TCfg, TGen, TCSExt, (), (), (), (), (), (), (), (), (), ()>
 where TGen: RuntimeGenesis, TCSExt: Extension {
 	/// Start the service builder with a configuration.
@@ -159,7 +184,7 @@ where TGen: RuntimeGenesis, TCSExt: Extension {
 		Arc<dyn FinalityProofProvider<TBl>>,
 		(),
 		(),
-		(),
+		NoopRpcExtensionBuilder<()>,
 		TFullBackend<TBl>,
 	>, Error> {
 		let keystore = Keystore::open(
@@ -167,10 +192,7 @@ where TGen: RuntimeGenesis, TCSExt: Extension {
 			config.keystore_password.clone()
 		)?;
 
-		let executor = NativeExecutor::<TExecDisp>::new(
-			config.wasm_method,
-			config.default_heap_pages,
-		);
+		let executor = new_executor::<TExecDisp, _, _, _>(&config);
 
 		let fork_blocks = config.chain_spec
 			.extensions()
@@ -183,22 +205,24 @@ where TGen: RuntimeGenesis, TCSExt: Extension {
 				state_cache_size: config.state_cache_size,
 				state_cache_child_ratio:
 					config.state_cache_child_ratio.map(|v| (v, 100)),
+				trie_cache_size: config.trie_cache_size,
 				pruning: config.pruning.clone(),
-				source: match &config.database {
-					DatabaseConfig::Path { path, cache_size } =>
-						client_db::DatabaseSettingsSrc::Path {
-							path: path.clone(),
-							cache_size: cache_size.clone().map(|u| u as usize),
-						},
-					DatabaseConfig::Custom(db) =>
-						client_db::DatabaseSettingsSrc::Custom(db.clone()),
-				},
+				block_pruning: config.block_pruning,
+				state_pinning: config.state_pinning,
+				header_pruning: config.header_pruning,
+				source: config.database.to_settings_src(),
 			};
 
-			let extensions = client_api::execution_extensions::ExecutionExtensions::new(
+			let mut extensions = client_api::execution_extensions::ExecutionExtensions::new(
 				config.execution_strategies.clone(),
 				Some(keystore.clone()),
-			);
+			).with_fuel_limits(config.execution_fuel_limits.clone());
+			if let Some(ref path) = config.divergence_reports_path {
+				extensions = extensions.with_divergence_reports(path.clone());
+			}
+			if let Some(ref path) = config.wasm_profiles_path {
+				extensions = extensions.with_wasm_profiles(path.clone());
+			}
 
 			client_db::new_client(
 				db_config,
@@ -223,7 +247,7 @@ where TGen: RuntimeGenesis, TCSExt: Extension {
 			finality_proof_provider: None,
 			network_protocol: (),
 			transaction_pool: Arc::new(()),
-			rpc_extensions: Default::default(),
+			rpc_extensions: NoopRpcExtensionBuilder(()),
 			remote_backend: None,
 			dht_event_tx: None,
 			marker: PhantomData,
@@ -247,7 +271,7 @@ where TGen: RuntimeGenesis, TCSExt: Extension {
 		Arc<dyn FinalityProofProvider<TBl>>,
 		(),
 		(),
-		(),
+		NoopRpcExtensionBuilder<()>,
 		TLightBackend<TBl>,
 	>, Error> {
 		let keystore = Keystore::open(
@@ -255,26 +279,19 @@ where TGen: RuntimeGenesis, TCSExt: Extension {
 			config.keystore_password.clone()
 		)?;
 
-		let executor = NativeExecutor::<TExecDisp>::new(
-			config.wasm_method,
-			config.default_heap_pages,
-		);
+		let executor = new_executor::<TExecDisp, _, _, _>(&config);
 
 		let db_storage = {
 			let db_settings = client_db::DatabaseSettings {
 				state_cache_size: config.state_cache_size,
 				state_cache_child_ratio:
 					config.state_cache_child_ratio.map(|v| (v, 100)),
+				trie_cache_size: config.trie_cache_size,
 				pruning: config.pruning.clone(),
-				source: match &config.database {
-					DatabaseConfig::Path { path, cache_size } =>
-						client_db::DatabaseSettingsSrc::Path {
-							path: path.clone(),
-							cache_size: cache_size.clone().map(|u| u as usize),
-						},
-					DatabaseConfig::Custom(db) =>
-						client_db::DatabaseSettingsSrc::Custom(db.clone()),
-				},
+				block_pruning: config.block_pruning,
+				state_pinning: config.state_pinning,
+				header_pruning: config.header_pruning,
+				source: config.database.to_settings_src(),
 			};
 			client_db::light::LightStorage::new(db_settings)?
 		};
@@ -301,7 +318,7 @@ where TGen: RuntimeGenesis, TCSExt: Extension {
 			finality_proof_provider: None,
 			network_protocol: (),
 			transaction_pool: Arc::new(()),
-			rpc_extensions: Default::default(),
+			rpc_extensions: NoopRpcExtensionBuilder(()),
 			remote_backend: Some(remote_blockchain),
 			dht_event_tx: None,
 			marker: PhantomData,
@@ -592,6 +609,10 @@ impl<TBl, TRtApi, TCfg, TGen, TCSExt, TCl, TFchr, TSc, TImpQu, TFprb, TFpp, TNet
 	}
 
 	/// Defines the RPC extensions to use.
+	///
+	/// The same extensions are exposed on every RPC interface, regardless of whether unsafe
+	/// RPC methods are allowed on it. Use `with_rpc_extensions_builder` if the extensions
+	/// contain unsafe methods.
 	pub fn with_rpc_extensions<URpc>(
 		self,
 		rpc_ext_builder: impl FnOnce(
@@ -602,7 +623,29 @@ impl<TBl, TRtApi, TCfg, TGen, TCSExt, TCl, TFchr, TSc, TImpQu, TFprb, TFpp, TNet
 			Option<Arc<dyn RemoteBlockchain<TBl>>>,
 		) -> Result<URpc, Error>,
 	) -> Result<ServiceBuilder<TBl, TRtApi, TCfg, TGen, TCSExt, TCl, TFchr, TSc, TImpQu, TFprb, TFpp,
-		TNetP, TExPool, URpc, Backend>, Error>
+		TNetP, TExPool, NoopRpcExtensionBuilder<URpc>, Backend>, Error>
+	where TSc: Clone, TFchr: Clone {
+		self.with_rpc_extensions_builder(|client, pool, backend, fetcher, remote_blockchain|
+			rpc_ext_builder(client, pool, backend, fetcher, remote_blockchain)
+				.map(NoopRpcExtensionBuilder)
+		)
+	}
+
+	/// Defines the builder of the RPC extensions to use.
+	///
+	/// The extensions are built for every RPC interface the node is listening on, given
+	/// the `DenyUnsafe` policy of that interface.
+	pub fn with_rpc_extensions_builder<UExtBuilder>(
+		self,
+		rpc_ext_builder: impl FnOnce(
+			Arc<TCl>,
+			Arc<TExPool>,
+			Arc<Backend>,
+			Option<TFchr>,
+			Option<Arc<dyn RemoteBlockchain<TBl>>>,
+		) -> Result<UExtBuilder, Error>,
+	) -> Result<ServiceBuilder<TBl, TRtApi, TCfg, TGen, TCSExt, TCl, TFchr, TSc, TImpQu, TFprb, TFpp,
+		TNetP, TExPool, UExtBuilder, Backend>, Error>
 	where TSc: Clone, TFchr: Clone {
 		let rpc_extensions = rpc_ext_builder(
 			self.client.clone(),
@@ -658,6 +701,44 @@ impl<TBl, TRtApi, TCfg, TGen, TCSExt, TCl, TFchr, TSc, TImpQu, TFprb, TFpp, TNet
 	}
 }
 
+/// Builds the RPC extensions given the `DenyUnsafe` policy of the RPC interface they are
+/// exposed on.
+///
+/// At service definition time we don't know whether a particular interface is safe or not,
+/// so the extensions are built lazily whenever the RPC handler for an interface is created.
+pub trait RpcExtensionBuilder {
+	/// The type of the RPC extension that will be built.
+	type Output: rpc::RpcExtension<rpc::Metadata>;
+
+	/// Returns an instance of the RPC extension for the given `DenyUnsafe` policy.
+	fn build(&self, deny_unsafe: rpc::DenyUnsafe) -> Self::Output;
+}
+
+impl<F, R> RpcExtensionBuilder for F where
+	F: Fn(rpc::DenyUnsafe) -> R,
+	R: rpc::RpcExtension<rpc::Metadata>,
+{
+	type Output = R;
+
+	fn build(&self, deny_unsafe: rpc::DenyUnsafe) -> Self::Output {
+		(*self)(deny_unsafe)
+	}
+}
+
+/// An `RpcExtensionBuilder` that ignores the `DenyUnsafe` policy and always returns a clone
+/// of the same RPC extension.
+pub struct NoopRpcExtensionBuilder<R>(pub R);
+
+impl<R> RpcExtensionBuilder for NoopRpcExtensionBuilder<R> where
+	R: rpc::RpcExtension<rpc::Metadata> + Clone,
+{
+	type Output = R;
+
+	fn build(&self, _deny_unsafe: rpc::DenyUnsafe) -> Self::Output {
+		self.0.clone()
+	}
+}
+
 /// Implemented on `ServiceBuilder`. Allows running block commands, such as import/export/validate
 /// components to the builder.
 pub trait ServiceBuilderCommand {
@@ -690,6 +771,12 @@ pub trait ServiceBuilderCommand {
 		self,
 		block: BlockId<Self::Block>
 	) -> Box<dyn Future<Item = (), Error = Error> + Send>;
+
+	/// Dry runs the upgrade to the given Wasm runtime `code` on top of the best block.
+	fn check_runtime_upgrade(
+		&self,
+		code: &[u8]
+	) -> Result<(), Error>;
 }
 
 impl<TBl, TRtApi, TCfg, TGen, TCSExt, TBackend, TExec, TSc, TImpQu, TNetP, TExPool, TRpc>
@@ -730,7 +817,7 @@ ServiceBuilder<
 	TExPool: 'static
 		+ TransactionPool<Block=TBl, Hash = <TBl as BlockT>::Hash>
 		+ TransactionPoolMaintainer<Block=TBl, Hash = <TBl as BlockT>::Hash>,
-	TRpc: rpc::RpcExtension<rpc::Metadata> + Clone,
+	TRpc: RpcExtensionBuilder,
 {
 	/// Builds the service.
 	pub fn build(self) -> Result<Service<
@@ -818,6 +905,40 @@ ServiceBuilder<
 		let block_announce_validator =
 			Box::new(consensus_common::block_validation::DefaultBlockAnnounceValidator::new(client.clone()));
 
+		if let (Some(allowed_peers), false) = (config.network.allowed_peers.clone(), config.roles.is_light()) {
+			// Keep the allow-list of the private network in sync with the one stored on chain.
+			// The peers given in the configuration are always allowed.
+			let configured_peers = allowed_peers.peers();
+			let runtime_peers = runtime_allowed_peers(&*client, &BlockId::Hash(chain_info.finalized_hash))?;
+			// An empty allow-list would reject every connection.
+			if configured_peers.is_empty() && runtime_peers.as_ref().map_or(true, Vec::is_empty) {
+				return Err(Error::Other(
+					"The runtime doesn't provide any allowed peer of the private network, \
+					they must be given with `--allowed-peers`".into()
+				));
+			}
+			if let Some(peers) = runtime_peers {
+				allowed_peers.set(configured_peers.iter().cloned().chain(peers));
+			}
+
+			let client_ = client.clone();
+			let update = move |at: BlockId<TBl>| match runtime_allowed_peers(&*client_, &at) {
+				Ok(Some(peers)) => allowed_peers.set(configured_peers.iter().cloned().chain(peers)),
+				Ok(None) => {},
+				Err(e) => warn!("Unable to fetch the allowed peers from the runtime: {:?}", e),
+			};
+
+			let events = client.finality_notification_stream()
+				.map(|v| Ok::<_, ()>(v)).compat()
+				.for_each(move |notification| {
+					update(BlockId::Hash(notification.hash));
+					Ok(())
+				})
+				.select(exit.clone())
+				.then(|_| Ok(()));
+			let _ = to_spawn_tx.unbounded_send(Box::new(events));
+		}
+
 		let network_params = network::config::Params {
 			roles: config.roles,
 			network_config: config.network.clone(),
@@ -925,10 +1046,11 @@ ServiceBuilder<
 			let bandwidth_download = net_status.average_download_per_sec;
 			let bandwidth_upload = net_status.average_upload_per_sec;
 
-			let used_state_cache_size = match info.used_state_cache_size {
-				Some(size) => size,
-				None => 0,
-			};
+			let usage = info.usage.unwrap_or_default();
+			let used_state_cache_size = usage.state_cache.used_size;
+			let state_cache_hit_ratio = usage.state_cache.hit_ratio().unwrap_or(0.0);
+			let db_io = usage.per_second();
+			let db_commits = usage.commit_latency.commits;
 
 			// get cpu usage and memory usage of this process
 			let (cpu_usage, memory) = if let Some(self_pid) = self_pid {
@@ -953,6 +1075,12 @@ ServiceBuilder<
 				"bandwidth_download" => bandwidth_download,
 				"bandwidth_upload" => bandwidth_upload,
 				"used_state_cache_size" => used_state_cache_size,
+				"state_cache_hit_ratio" => state_cache_hit_ratio,
+				"db_reads_per_sec" => db_io.reads,
+				"db_bytes_read_per_sec" => db_io.bytes_read,
+				"db_writes_per_sec" => db_io.writes,
+				"db_bytes_written_per_sec" => db_io.bytes_written,
+				"db_commits" => db_commits,
 			);
 			record_metrics!(
 				"peers".to_owned() => num_peers,
@@ -963,7 +1091,13 @@ ServiceBuilder<
 				"finalized_height".to_owned() => finalized_number,
 				"bandwidth_download".to_owned() => bandwidth_download,
 				"bandwidth_upload".to_owned() => bandwidth_upload,
-				"used_state_cache_size".to_owned() => used_state_cache_size
+				"used_state_cache_size".to_owned() => used_state_cache_size,
+				"state_cache_hit_ratio".to_owned() => state_cache_hit_ratio,
+				"db_reads_per_sec".to_owned() => db_io.reads,
+				"db_bytes_read_per_sec".to_owned() => db_io.bytes_read,
+				"db_writes_per_sec".to_owned() => db_io.writes,
+				"db_bytes_written_per_sec".to_owned() => db_io.bytes_written,
+				"db_commits".to_owned() => db_commits
 			);
 
 			Ok(())
@@ -985,7 +1119,7 @@ ServiceBuilder<
 
 		// RPC
 		let (system_rpc_tx, system_rpc_rx) = futures03::channel::mpsc::unbounded();
-		let gen_handler = || {
+		let gen_handler = |deny_unsafe: rpc::DenyUnsafe| {
 			use rpc::{chain, state, author, system};
 
 			let system_info = rpc::system::SystemInfo {
@@ -1030,17 +1164,17 @@ ServiceBuilder<
 				subscriptions,
 				keystore.clone(),
 			);
-			let system = system::System::new(system_info, system_rpc_tx.clone());
+			let system = system::System::new(system_info, system_rpc_tx.clone(), deny_unsafe);
 
 			rpc_servers::rpc_handler((
 				state::StateApi::to_delegate(state),
 				chain::ChainApi::to_delegate(chain),
 				author::AuthorApi::to_delegate(author),
 				system::SystemApi::to_delegate(system),
-				rpc_extensions.clone(),
+				rpc_extensions.build(deny_unsafe),
 			))
 		};
-		let rpc_handlers = gen_handler();
+		let rpc_handlers = gen_handler(rpc::DenyUnsafe::No);
 		let rpc = start_rpc_servers(&config, gen_handler)?;
 
 
@@ -19,6 +19,7 @@
 use crate::error;
 use crate::builder::{ServiceBuilderCommand, ServiceBuilder};
 use crate::error::Error;
+use crate::config::{Configuration, DatabaseConfig};
 use chain_spec::{ChainSpec, RuntimeGenesis, Extension};
 use log::{warn, info};
 use futures::{future, prelude::*};
@@ -49,6 +50,52 @@ pub fn build_spec<G, E>(spec: ChainSpec<G, E>, raw: bool) -> error::Result<Strin
 	Ok(spec.to_json(raw)?)
 }
 
+/// Check the integrity of the database of a full node, reverting the chain to the last
+/// consistent block if `repair` is set.
+///
+/// Returns `true` if the database is consistent, once repaired if requested.
+pub fn check_database<TBl, C, G, E>(config: &Configuration<C, G, E>, repair: bool) -> error::Result<bool> where
+	TBl: BlockT<Hash = <Blake2Hasher as Hasher>::Out>,
+{
+	let db_settings = client_db::DatabaseSettings {
+		state_cache_size: config.state_cache_size,
+		state_cache_child_ratio: config.state_cache_child_ratio.map(|v| (v, 100)),
+		trie_cache_size: config.trie_cache_size,
+		pruning: config.pruning.clone(),
+		block_pruning: config.block_pruning,
+		state_pinning: config.state_pinning,
+		header_pruning: config.header_pruning,
+		source: config.database.to_settings_src(),
+	};
+	// no block is imported, so blocks are never canonicalized.
+	let backend = client_db::Backend::<TBl>::new(db_settings, 0)?;
+
+	let report = backend.check_integrity()?;
+	for inconsistency in &report.inconsistencies {
+		warn!("{}", inconsistency);
+	}
+	if report.inconsistencies.is_empty() {
+		info!("Database is consistent up to the best block #{}", report.best_number);
+		return Ok(true);
+	}
+	info!("Database is consistent up to block #{}", report.last_consistent);
+	if !repair {
+		return Ok(false);
+	}
+
+	let reverted = backend.repair(&report)?;
+	info!("Reverted {} blocks", reverted);
+	Ok(backend.check_integrity()?.inconsistencies.is_empty())
+}
+
+/// Copy a database into another, column by column. The destination database must be empty.
+pub fn migrate_database(from: &DatabaseConfig, to: &DatabaseConfig) -> error::Result<()> {
+	client_db::migrate_database(&from.to_settings_src(), &to.to_settings_src(), |column, copied| {
+		info!("Copied {} entries of column {}", copied, column);
+	})?;
+	Ok(())
+}
+
 impl<
 	TBl, TRtApi, TCfg, TGen, TCSExt, TBackend,
 	TExec, TFchr, TSc, TImpQu, TFprb, TFpp, TNetP,
@@ -293,6 +340,22 @@ impl<
 		Ok(())
 	}
 
+	fn check_runtime_upgrade(
+		&self,
+		code: &[u8]
+	) -> Result<(), Error> {
+		let check = crate::check_runtime_upgrade(&*self.client, code)?;
+
+		info!(
+			"The runtime can be upgraded from {} to {} on top of the best block ({}).",
+			check.current_version,
+			check.new_version,
+			check.best_hash,
+		);
+		info!("Applied {} inherent extrinsics.", check.inherent_extrinsics);
+		Ok(())
+	}
+
 	fn check_block(
 		self,
 		block_id: BlockId<TBl>
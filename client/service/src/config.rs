@@ -17,9 +17,12 @@
 //! Service configuration.
 
 pub use client::ExecutionStrategies;
-pub use client_db::{kvdb::KeyValueDB, PruningMode};
+pub use client_api::execution_extensions::ExecutionFuelLimits;
+pub use client_db::{kvdb::KeyValueDB, PruningMode, BlockPruning, StatePinning, HeaderPruning, DatabaseKind};
 pub use network::config::{ExtTransport, NetworkConfiguration, Roles};
-pub use sc_executor::WasmExecutionMethod;
+pub use sc_executor::{
+	AllocationStrategy, WasmExecutionMethod, DEFAULT_MAX_RUNTIMES, DEFAULT_MAX_RUNTIME_INSTANCES,
+};
 
 use std::{path::PathBuf, net::SocketAddr, sync::Arc};
 pub use txpool::txpool::Options as TransactionPoolOptions;
@@ -53,8 +56,16 @@ pub struct Configuration<C, G, E = NoExtension> {
 	pub state_cache_size: usize,
 	/// Size in percent of cache size dedicated to child tries
 	pub state_cache_child_ratio: Option<usize>,
+	/// Size of the trie node cache in Bytes
+	pub trie_cache_size: usize,
 	/// Pruning settings.
 	pub pruning: PruningMode,
+	/// Block body pruning settings.
+	pub block_pruning: BlockPruning,
+	/// Selects the blocks whose state is archived.
+	pub state_pinning: StatePinning,
+	/// Light client header pruning settings.
+	pub header_pruning: HeaderPruning,
 	/// Chain configuration.
 	pub chain_spec: ChainSpec<G, E>,
 	/// Custom configuration.
@@ -65,6 +76,11 @@ pub struct Configuration<C, G, E = NoExtension> {
 	pub wasm_method: WasmExecutionMethod,
 	/// Execution strategies.
 	pub execution_strategies: ExecutionStrategies,
+	/// Maximum number of instructions executed in Wasm by the runtime calls.
+	pub execution_fuel_limits: ExecutionFuelLimits,
+	/// Directory of the reports of the divergences between the native and Wasm executions, when
+	/// both are used. `None` if disabled.
+	pub divergence_reports_path: Option<PathBuf>,
 	/// RPC over HTTP binding address. `None` if disabled.
 	pub rpc_http: Option<SocketAddr>,
 	/// RPC over Websockets binding address. `None` if disabled.
@@ -73,6 +89,8 @@ pub struct Configuration<C, G, E = NoExtension> {
 	pub rpc_ws_max_connections: Option<usize>,
 	/// CORS settings for HTTP & WS servers. `None` if all origins are allowed.
 	pub rpc_cors: Option<Vec<String>>,
+	/// RPC methods to expose (by default only a safe subset or all of them).
+	pub rpc_methods: RpcMethods,
 	/// Grafana data source http port. `None` if disabled.
 	pub grafana_port: Option<SocketAddr>,
 	/// Telemetry service URL. `None` if disabled.
@@ -82,6 +100,15 @@ pub struct Configuration<C, G, E = NoExtension> {
 	pub telemetry_external_transport: Option<ExtTransport>,
 	/// The default number of 64KB pages to allocate for Wasm execution
 	pub default_heap_pages: Option<u64>,
+	/// The allocation strategy of the heap of the Wasm execution of the offchain workers and RPC
+	/// calls. The other calls always use `AllocationStrategy::Bump`.
+	pub allocation_strategy: AllocationStrategy,
+	/// Maximum number of runtimes kept in the runtimes cache.
+	pub max_runtimes: usize,
+	/// Maximum number of instances of each runtime, i.e. of calls into a runtime executed in parallel.
+	pub max_runtime_instances: usize,
+	/// Directory of the on-disk cache of the runtimes compiled by Wasmtime. `None` if disabled.
+	pub wasmtime_cache_path: Option<PathBuf>,
 	/// Should offchain workers be executed.
 	pub offchain_worker: bool,
 	/// Sentry mode is enabled, the node's role is AUTHORITY but it should not
@@ -104,6 +131,8 @@ pub struct Configuration<C, G, E = NoExtension> {
 	pub tracing_targets: Option<String>,
 	/// Tracing receiver
 	pub tracing_receiver: sc_tracing::TracingReceiver,
+	/// Directory of the profiles of the Wasm execution of the imported blocks. `None` if disabled.
+	pub wasm_profiles_path: Option<PathBuf>,
 }
 
 /// Configuration of the database of the client.
@@ -115,12 +144,48 @@ pub enum DatabaseConfig {
 		path: PathBuf,
 		/// Cache Size for internal database in MiB
 		cache_size: Option<u32>,
+		/// Kind of the database.
+		kind: DatabaseKind,
 	},
 
 	/// A custom implementation of an already-open database.
 	Custom(Arc<dyn KeyValueDB>),
 }
 
+impl DatabaseConfig {
+	/// Returns where the client database finds the database.
+	pub(crate) fn to_settings_src(&self) -> client_db::DatabaseSettingsSrc {
+		match self {
+			DatabaseConfig::Path { path, cache_size, kind } =>
+				client_db::DatabaseSettingsSrc::Path {
+					path: path.clone(),
+					cache_size: cache_size.clone().map(|u| u as usize),
+					kind: *kind,
+				},
+			DatabaseConfig::Custom(db) =>
+				client_db::DatabaseSettingsSrc::Custom(db.clone()),
+		}
+	}
+}
+
+/// Available RPC methods.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RpcMethods {
+	/// Expose every RPC method only when RPC is listening on `localhost`,
+	/// otherwise serve only safe RPC methods.
+	Auto,
+	/// Allow only a safe subset of RPC methods.
+	Safe,
+	/// Expose every RPC method (even potentially unsafe ones).
+	Unsafe,
+}
+
+impl Default for RpcMethods {
+	fn default() -> RpcMethods {
+		RpcMethods::Auto
+	}
+}
+
 impl<C, G, E> Configuration<C, G, E> where
 	C: Default,
 	G: RuntimeGenesis,
@@ -142,21 +207,34 @@ impl<C, G, E> Configuration<C, G, E> where
 			database: DatabaseConfig::Path {
 				path: Default::default(),
 				cache_size: Default::default(),
+				kind: Default::default(),
 			},
 			state_cache_size: Default::default(),
 			state_cache_child_ratio: Default::default(),
+			// same as the `--trie-cache-size` default.
+			trie_cache_size: 64 * 1024 * 1024,
 			custom: Default::default(),
 			pruning: PruningMode::default(),
+			block_pruning: BlockPruning::default(),
+			state_pinning: StatePinning::default(),
+			header_pruning: HeaderPruning::default(),
 			wasm_method: WasmExecutionMethod::Interpreted,
 			execution_strategies: Default::default(),
+			execution_fuel_limits: Default::default(),
+			divergence_reports_path: None,
 			rpc_http: None,
 			rpc_ws: None,
 			rpc_ws_max_connections: None,
 			rpc_cors: Some(vec![]),
+			rpc_methods: Default::default(),
 			grafana_port: None,
 			telemetry_endpoints: None,
 			telemetry_external_transport: None,
 			default_heap_pages: None,
+			allocation_strategy: Default::default(),
+			max_runtimes: DEFAULT_MAX_RUNTIMES,
+			max_runtime_instances: DEFAULT_MAX_RUNTIME_INSTANCES,
+			wasmtime_cache_path: None,
 			offchain_worker: Default::default(),
 			sentry_mode: false,
 			force_authoring: false,
@@ -165,6 +243,7 @@ impl<C, G, E> Configuration<C, G, E> where
 			dev_key_seed: None,
 			tracing_targets: Default::default(),
 			tracing_receiver: Default::default(),
+			wasm_profiles_path: None,
 		};
 		configuration.network.boot_nodes = configuration.chain_spec.boot_nodes().to_vec();
 
@@ -26,6 +26,7 @@ pub mod error;
 
 mod builder;
 mod status_sinks;
+mod blocking_tasks;
 
 use std::io;
 use std::marker::PhantomData;
@@ -50,11 +51,16 @@ use log::{log, warn, debug, error, Level};
 use codec::{Encode, Decode};
 use primitives::{Blake2Hasher, H256};
 use sp_runtime::generic::BlockId;
-use sp_runtime::traits::{NumberFor, Block as BlockT};
+use sp_runtime::traits::{NumberFor, Block as BlockT, Header as HeaderT, ProvideRuntimeApi};
+use sp_allowed_peers::AllowedPeersApi;
 
 pub use self::error::Error;
-pub use self::builder::{ServiceBuilder, ServiceBuilderCommand};
-pub use config::{Configuration, Roles, PruningMode};
+pub use self::builder::{
+	ServiceBuilder, ServiceBuilderCommand, RpcExtensionBuilder, NoopRpcExtensionBuilder,
+};
+pub use config::{
+	Configuration, Roles, PruningMode, BlockPruning, StatePinning, HeaderPruning, DatabaseKind, RpcMethods,
+};
 pub use chain_spec::{ChainSpec, Properties, RuntimeGenesis, Extension as ChainSpecExtension};
 pub use txpool_api::{TransactionPool, TransactionPoolMaintainer, InPoolTransaction, IntoPoolError};
 pub use txpool::txpool::Options as TransactionPoolOptions;
@@ -69,6 +75,10 @@ pub use futures::future::Executor;
 
 const DEFAULT_PROTOCOL_ID: &str = "sup";
 
+/// Minimal number of blocks the node must be behind the best seen block for the database to be
+/// switched to the bulk import mode.
+const BULK_IMPORT_GAP: u32 = 1024;
+
 /// Substrate service.
 pub struct Service<TBl, TCl, TSc, TNetStatus, TNet, TTxPool, TOc> {
 	client: Arc<TCl>,
@@ -346,12 +356,31 @@ impl<TBl, TCl, TSc, TNetStatus, TNet, TTxPool, TOc> Executor<Box<dyn Future<Item
 	}
 }
 
+/// Dry runs the upgrade to the given Wasm runtime `code` on top of the best block of `client`.
+///
+/// The block is built with the timestamp inherent only, the other inherents are left out.
+pub(crate) fn check_runtime_upgrade<B: BlockT>(
+	client: &impl client::CheckRuntimeUpgrade<B>,
+	code: &[u8],
+) -> sp_blockchain::Result<client::RuntimeUpgradeCheck<B>> {
+	let providers = inherents::InherentDataProviders::new();
+	let inherent_data = providers.register_provider(sp_timestamp::InherentDataProvider)
+		.and_then(|_| providers.create_inherent_data())
+		.map_err(|e| sp_blockchain::Error::Msg(e.into_string()))?;
+	client.check_runtime_upgrade(code, inherent_data)
+}
+
+/// Maximum number of the RPC requests which are too slow to be answered on the network future,
+/// such as `system_databaseColumnSizes` or `system_checkRuntimeUpgrade`, being processed at once.
+const MAX_PENDING_BLOCKING_REQUESTS: usize = 4;
+
 /// Builds a never-ending future that continuously polls the network.
 ///
 /// The `status_sink` contain a list of senders to send a periodic network status to.
 fn build_network_future<
 	B: BlockT,
-	C: client::BlockchainEvents<B>,
+	C: client::BlockchainEvents<B> + client::UsageProvider<B> + client::BulkImport
+		+ client::CheckRuntimeUpgrade<B> + Send + Sync + 'static,
 	S: network::specialization::NetworkSpecialization<B>,
 	H: network::ExHashT
 > (
@@ -372,11 +401,21 @@ fn build_network_future<
 	let mut finality_notification_stream = client.finality_notification_stream().fuse()
 		.map(|v| Ok::<_, ()>(v)).compat();
 
+	let mut best_number = client.usage_info().chain.best_number;
+	let mut bulk_import = false;
+	let blocking_tasks = blocking_tasks::BlockingTasks::new(
+		"rpc-blocking-tasks",
+		MAX_PENDING_BLOCKING_REQUESTS,
+	);
+
 	futures::future::poll_fn(move || {
 		let before_polling = Instant::now();
 
 		// We poll `imported_blocks_stream`.
 		while let Ok(Async::Ready(Some(notification))) = imported_blocks_stream.poll() {
+			if notification.is_new_best {
+				best_number = *notification.header.number();
+			}
 			network.on_block_imported(notification.hash, notification.header, Vec::new(), notification.is_new_best);
 		}
 
@@ -389,6 +428,18 @@ fn build_network_future<
 			network.on_block_finalized(notification.hash, notification.header);
 		}
 
+		// The database is switched to the bulk import mode while the node is far behind the chain.
+		let catching_up = network.sync_state() == network::SyncState::Downloading &&
+			network.best_seen_block().map_or(false, |best_seen|
+				best_seen > best_number && best_seen - best_number >= BULK_IMPORT_GAP.into()
+			);
+		if catching_up != bulk_import {
+			bulk_import = catching_up;
+			if let Err(e) = client.set_bulk_import(bulk_import) {
+				warn!("Failed to switch the bulk import mode of the database: {:?}", e);
+			}
+		}
+
 		// Poll the RPC requests and answer them.
 		while let Ok(Async::Ready(Some(request))) = rpc_rx.poll() {
 			match request {
@@ -430,6 +481,114 @@ fn build_network_future<
 
 					let _ = sender.send(node_roles);
 				}
+				rpc::system::Request::LocalListenAddresses(sender) => {
+					let peer_id = network.local_peer_id().clone().into();
+					let p2p_proto_suffix = network::multiaddr::Protocol::P2p(peer_id);
+					let addresses = network.listen_addresses()
+						.map(|addr| addr.clone().with(p2p_proto_suffix.clone()).to_string())
+						.collect();
+					let _ = sender.send(addresses);
+				}
+				rpc::system::Request::DatabaseUsage(sender) => {
+					let _ = sender.send(client.usage_info().usage.map(|usage| rpc::system::DatabaseUsage {
+						columns: usage.columns.into_iter().map(|column| rpc::system::DatabaseColumnUsage {
+							name: column.name.into(),
+							reads: column.total.reads,
+							bytes_read: column.total.bytes_read,
+							writes: column.total.writes,
+							bytes_written: column.total.bytes_written,
+							reads_per_sec: column.per_second.reads,
+							bytes_read_per_sec: column.per_second.bytes_read,
+							writes_per_sec: column.per_second.writes,
+							bytes_written_per_sec: column.per_second.bytes_written,
+						}).collect(),
+						commits: usage.commit_latency.commits,
+						commits_duration_ms: usage.commit_latency.total.as_millis() as u64,
+						commit_latency: usage.commit_latency.buckets.into_iter()
+							.map(|(max_ms, count)| rpc::system::LatencyBucket { max_ms, count })
+							.collect(),
+						state_cache_size: usage.state_cache.used_size,
+						state_cache_hits: usage.state_cache.hits,
+						state_cache_misses: usage.state_cache.misses,
+						trie_cache_size: usage.trie_cache.used_size,
+						trie_cache_hits: usage.trie_cache.hits,
+						trie_cache_misses: usage.trie_cache.misses,
+					}));
+				}
+				rpc::system::Request::DatabaseColumnSizes(sender) => {
+					// Going through the whole database takes a while, so it isn't done here.
+					let client = client.clone();
+					let queued = blocking_tasks.spawn(sender, move |sender| {
+						let sizes = client.column_sizes()
+							.map(|sizes| sizes.unwrap_or_default().into_iter()
+								.map(|size| rpc::system::DatabaseColumnSize {
+									name: size.name.into(),
+									keys: size.keys,
+									bytes: size.bytes,
+								})
+								.collect()
+							)
+							.map_err(|e| rpc::system::error::Error::Database(e.to_string()));
+						let _ = sender.send(sizes);
+					});
+					if let Err(sender) = queued {
+						let _ = sender.send(Err(rpc::system::error::Error::Busy));
+					}
+				}
+				rpc::system::Request::NetworkAddReservedPeer(peer_addr, sender) => {
+					let x = network.service().add_reserved_peer(peer_addr)
+						.map_err(rpc::system::error::Error::MalformattedPeerArg);
+					let _ = sender.send(x);
+				}
+				rpc::system::Request::NetworkRemoveReservedPeer(peer_id, sender) => {
+					let _ = match peer_id.parse::<network::PeerId>() {
+						Ok(peer_id) => {
+							network.service().remove_reserved_peer(peer_id);
+							sender.send(Ok(()))
+						}
+						Err(e) => sender.send(Err(
+							rpc::system::error::Error::MalformattedPeerArg(e.to_string())
+						)),
+					};
+				}
+				rpc::system::Request::NetworkSetNonReservedPeerMode(mode, sender) => {
+					use network::config::NonReservedPeerMode;
+
+					let _ = match NonReservedPeerMode::parse(&mode) {
+						Some(NonReservedPeerMode::Accept) => {
+							network.service().accept_unreserved_peers();
+							sender.send(Ok(()))
+						}
+						Some(NonReservedPeerMode::Deny) => {
+							network.service().deny_unreserved_peers();
+							sender.send(Ok(()))
+						}
+						None => sender.send(Err(
+							rpc::system::error::Error::UnknownNonReservedPeerMode(mode)
+						)),
+					};
+				}
+				rpc::system::Request::CheckRuntimeUpgrade(code, sender) => {
+					// Executing the new runtime takes a while, so it isn't done here.
+					let client = client.clone();
+					let queued = blocking_tasks.spawn(sender, move |sender| {
+						let check = check_runtime_upgrade(&*client, &code)
+							.map(|check| rpc::system::RuntimeUpgradeCheck {
+								best_hash: check.best_hash,
+								current_version: check.current_version,
+								new_version: check.new_version,
+								inherent_extrinsics: check.inherent_extrinsics,
+							})
+							.map_err(|e| rpc::system::error::Error::RuntimeUpgradeRejected(match e {
+								sp_blockchain::Error::RuntimeUpgradeRejected(reason) => reason,
+								e => e.to_string(),
+							}));
+						let _ = sender.send(check);
+					});
+					if let Err(sender) = queued {
+						let _ = sender.send(Err(rpc::system::error::Error::Busy));
+					}
+				}
 			};
 		}
 
@@ -510,7 +669,7 @@ impl<TBl, TCl, TSc, TNetStatus, TNet, TTxPool, TOc> Drop for
 
 /// Starts RPC servers that run in their own thread, and returns an opaque object that keeps them alive.
 #[cfg(not(target_os = "unknown"))]
-fn start_rpc_servers<C, G, E, H: FnMut() -> rpc_servers::RpcHandler<rpc::Metadata>>(
+fn start_rpc_servers<C, G, E, H: FnMut(rpc::DenyUnsafe) -> rpc_servers::RpcHandler<rpc::Metadata>>(
 	config: &Configuration<C, G, E>,
 	mut gen_handler: H
 ) -> Result<Box<dyn std::any::Any + Send + Sync>, error::Error> {
@@ -532,10 +691,23 @@ fn start_rpc_servers<C, G, E, H: FnMut() -> rpc_servers::RpcHandler<rpc::Metadat
 		})
 	}
 
+	fn deny_unsafe(addr: &SocketAddr, methods: &config::RpcMethods) -> rpc::DenyUnsafe {
+		let is_exposed_addr = !addr.ip().is_loopback();
+		match (is_exposed_addr, methods) {
+			| (_, config::RpcMethods::Unsafe)
+			| (false, config::RpcMethods::Auto) => rpc::DenyUnsafe::No,
+			_ => rpc::DenyUnsafe::Yes,
+		}
+	}
+
 	Ok(Box::new((
 		maybe_start_server(
 			config.rpc_http,
-			|address| rpc_servers::start_http(address, config.rpc_cors.as_ref(), gen_handler()),
+			|address| rpc_servers::start_http(
+				address,
+				config.rpc_cors.as_ref(),
+				gen_handler(deny_unsafe(&address, &config.rpc_methods)),
+			),
 		)?,
 		maybe_start_server(
 			config.rpc_ws,
@@ -543,7 +715,7 @@ fn start_rpc_servers<C, G, E, H: FnMut() -> rpc_servers::RpcHandler<rpc::Metadat
 				address,
 				config.rpc_ws_max_connections,
 				config.rpc_cors.as_ref(),
-				gen_handler(),
+				gen_handler(deny_unsafe(&address, &config.rpc_methods)),
 			),
 		)?.map(Mutex::new),
 	)))
@@ -551,7 +723,7 @@ fn start_rpc_servers<C, G, E, H: FnMut() -> rpc_servers::RpcHandler<rpc::Metadat
 
 /// Starts RPC servers that run in their own thread, and returns an opaque object that keeps them alive.
 #[cfg(target_os = "unknown")]
-fn start_rpc_servers<C, G, E, H: FnMut() -> rpc_servers::RpcHandler<rpc::Metadata>>(
+fn start_rpc_servers<C, G, E, H: FnMut(rpc::DenyUnsafe) -> rpc_servers::RpcHandler<rpc::Metadata>>(
 	_: &Configuration<C, G, E>,
 	_: H
 ) -> Result<Box<dyn std::any::Any + Send + Sync>, error::Error> {
@@ -579,6 +751,47 @@ impl RpcSession {
 	}
 }
 
+/// Returns the allow-list of the private network stored on chain, or `None` if the runtime
+/// doesn't provide one.
+///
+/// The runtime API is called by name, so that the runtimes which don't implement it don't have to
+/// provide a stub for the service to be built.
+fn runtime_allowed_peers<B, E, Block, RA>(
+	client: &Client<B, E, Block, RA>,
+	at: &BlockId<Block>,
+) -> Result<Option<Vec<PeerId>>, sp_blockchain::Error> where
+	B: client_api::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+	Block: BlockT<Hash = H256>,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api:
+		sp_api::ApiExt<Block, Error = sp_blockchain::Error>,
+{
+	if !client.runtime_api().has_api::<dyn AllowedPeersApi<Block, Error = ()>>(at)? {
+		return Ok(None);
+	}
+
+	let encoded = client.executor().call(
+		at,
+		"AllowedPeersApi_allowed_peers",
+		&[],
+		client::ExecutionStrategy::AlwaysWasm,
+		None,
+	)?;
+	let peers = <Vec<Vec<u8>>>::decode(&mut &encoded[..])
+		.map_err(|e| sp_blockchain::Error::Msg(format!("Invalid allowed peers: {}", e.what())))?
+		.into_iter()
+		.filter_map(|peer| match PeerId::from_bytes(peer) {
+			Ok(peer_id) => Some(peer_id),
+			Err(peer) => {
+				warn!("Ignoring invalid allowed peer id: {:?}", peer);
+				None
+			},
+		})
+		.collect();
+	Ok(Some(peers))
+}
+
 /// Transaction pool adapter.
 pub struct TransactionPoolAdapter<C, P> {
 	imports_external_transactions: bool,
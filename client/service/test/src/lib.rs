@@ -34,7 +34,7 @@ use service::{
 	Error,
 };
 use network::{multiaddr, Multiaddr};
-use network::config::{NetworkConfiguration, TransportConfig, NodeKeyConfig, Secret, NonReservedPeerMode};
+use network::config::{NetworkConfiguration, TransportConfig, NodeKeyConfig, Secret, NonReservedPeerMode, SyncMode};
 use sp_runtime::{generic::BlockId, traits::Block as BlockT};
 use txpool_api::TransactionPool;
 
@@ -155,6 +155,7 @@ fn node_config<G, E: Clone> (
 		out_peers: 450,
 		reserved_nodes: vec![],
 		non_reserved_mode: NonReservedPeerMode::Accept,
+		allowed_peers: None,
 		client_version: "network/test/0.1".to_owned(),
 		node_name: "unknown".to_owned(),
 		transport: TransportConfig::Normal {
@@ -163,6 +164,9 @@ fn node_config<G, E: Clone> (
 			wasm_external_transport: None,
 		},
 		max_parallel_downloads: NetworkConfiguration::default().max_parallel_downloads,
+		bandwidth_limits: Default::default(),
+		sync_mode: SyncMode::Full,
+		persist_peers: false,
 	};
 
 	Configuration {
@@ -177,24 +181,36 @@ fn node_config<G, E: Clone> (
 		config_dir: Some(root.clone()),
 		database: DatabaseConfig::Path {
 			path: root.join("db"),
-			cache_size: None
+			cache_size: None,
+			kind: Default::default(),
 		},
 		state_cache_size: 16777216,
+		trie_cache_size: 16777216,
 		state_cache_child_ratio: None,
 		pruning: Default::default(),
+		block_pruning: Default::default(),
+		state_pinning: Default::default(),
+		header_pruning: Default::default(),
 		chain_spec: (*spec).clone(),
 		custom: Default::default(),
 		name: format!("Node {}", index),
 		wasm_method: service::config::WasmExecutionMethod::Interpreted,
 		execution_strategies: Default::default(),
+		execution_fuel_limits: Default::default(),
+		divergence_reports_path: None,
 		rpc_http: None,
 		rpc_ws: None,
 		rpc_ws_max_connections: None,
+		rpc_methods: Default::default(),
 		rpc_cors: None,
 		grafana_port: None,
 		telemetry_endpoints: None,
 		telemetry_external_transport: None,
 		default_heap_pages: None,
+		allocation_strategy: Default::default(),
+		max_runtimes: service::config::DEFAULT_MAX_RUNTIMES,
+		max_runtime_instances: service::config::DEFAULT_MAX_RUNTIME_INSTANCES,
+		wasmtime_cache_path: None,
 		offchain_worker: false,
 		sentry_mode: false,
 		force_authoring: false,
@@ -202,6 +218,7 @@ fn node_config<G, E: Clone> (
 		dev_key_seed: key_seed,
 		tracing_targets: None,
 		tracing_receiver: Default::default(),
+		wasm_profiles_path: None,
 	}
 }
 
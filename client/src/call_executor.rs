@@ -205,6 +205,30 @@ impl<B, E, Block> CallExecutor<Block, Blake2Hasher> for LocalCallExecutor<B, E>
 		version.map_err(|e| sp_blockchain::Error::VersionInvalid(format!("{:?}", e)).into())
 	}
 
+	fn call_with_code(
+		&self,
+		id: &BlockId<Block>,
+		code: &[u8],
+		overlay: &mut OverlayedChanges,
+		method: &str,
+		call_data: &[u8],
+	) -> sp_blockchain::Result<Vec<u8>> {
+		let state = self.backend.state_at(*id)?;
+
+		let mut ext = Ext::new(
+			overlay,
+			&state,
+			self.backend.changes_trie_storage(),
+			None,
+		);
+		let result = self.executor.call_with_code(&mut ext, code, method, call_data);
+		{
+			let _lock = self.backend.get_import_lock().read();
+			self.backend.destroy_state(state)?;
+		}
+		result.map_err(|e| sp_blockchain::Error::Execution(Box::new(e)))
+	}
+
 	fn call_at_state<
 		S: state_machine::Backend<Blake2Hasher>,
 		F: FnOnce(
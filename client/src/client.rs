@@ -28,7 +28,7 @@ use hash_db::{Hasher, Prefix};
 use primitives::{
 	Blake2Hasher, H256, ChangesTrieConfiguration, convert_hash,
 	NeverNativeValue, ExecutionContext, NativeOrEncoded,
-	storage::{StorageKey, StorageData, well_known_keys},
+	storage::{StorageKey, StorageData, StorageOverlay, ChildrenStorageOverlay, well_known_keys},
 	traits::CodeExecutor,
 };
 use sc_telemetry::{telemetry, SUBSTRATE_INFO};
@@ -45,6 +45,7 @@ use state_machine::{
 	prove_read, prove_child_read, ChangesTrieRootsStorage, ChangesTrieStorage,
 	ChangesTrieTransaction, ChangesTrieConfigurationRange, key_changes, key_changes_proof,
 	OverlayedChanges, BackendTrustLevel, StorageProof, merge_storage_proofs,
+	prove_range_read, read_range_proof_check,
 };
 use executor::{RuntimeVersion, RuntimeInfo};
 use consensus::{
@@ -60,6 +61,7 @@ use sp_blockchain::{self as blockchain,
 };
 
 use sp_api::{CallRuntimeAt, ConstructRuntimeApi, Core as CoreApi, ProofRecorder, InitializeBlock};
+use inherents::InherentData;
 use block_builder::BlockBuilderApi;
 
 pub use client_api::{
@@ -69,8 +71,8 @@ pub use client_api::{
 	},
 	client::{
 		ImportNotifications, FinalityNotification, FinalityNotifications, BlockImportNotification,
-		ClientInfo, BlockchainEvents, BlockBody, ProvideUncles, ForkBlocks,
-		BlockOf,
+		ClientInfo, BlockchainEvents, BlockBody, ProvideUncles, ForkBlocks, UsageProvider, ColumnSize,
+		BlockOf, BulkImport, CheckRuntimeUpgrade, RuntimeUpgradeCheck,
 	},
 	execution_extensions::{ExecutionExtensions, ExecutionStrategies},
 	notifications::{StorageNotifications, StorageEventStream},
@@ -344,6 +346,65 @@ impl<B, E, Block, RA> Client<B, E, Block, RA> where
 				.map_err(Into::into))
 	}
 
+	/// Reads the entries of the state at a given block in key order, starting at `start`
+	/// (inclusive), and returns them along with a proof of the range. Entries are read from the
+	/// child trie under `child_storage_key` if it is given.
+	///
+	/// At most `max_keys` entries, and at most `max_size` bytes unless the first entry is larger,
+	/// are returned. The returned flag is `true` if there are no more entries after them.
+	pub fn state_range(
+		&self,
+		id: &BlockId<Block>,
+		child_storage_key: Option<&[u8]>,
+		start: &[u8],
+		max_keys: usize,
+		max_size: usize,
+	) -> sp_blockchain::Result<(Vec<(Vec<u8>, Vec<u8>)>, bool, StorageProof)> {
+		let state = self.state_at(id)?;
+		prove_range_read(state, child_storage_key, start, max_keys, max_size)
+			.map_err(sp_blockchain::Error::from_state)
+	}
+
+	/// Checks a range of state entries returned by `state_range` against the root of the trie
+	/// they were read from: the state root of the block, or the root of the child trie.
+	pub fn check_state_range(
+		&self,
+		root: &Block::Hash,
+		start: &[u8],
+		entries: &[(Vec<u8>, Vec<u8>)],
+		complete: bool,
+		proof: StorageProof,
+	) -> sp_blockchain::Result<()> {
+		read_range_proof_check::<Blake2Hasher>(*root, proof, start, entries, complete)
+			.map_err(|_| Error::InvalidStateProof)
+	}
+
+	/// Imports the full state of a block whose header has been imported without state, e.g.
+	/// a block downloaded during warp sync. Fails if the state root does not match the header.
+	pub fn import_state(
+		&self,
+		hash: Block::Hash,
+		top: StorageOverlay,
+		children: ChildrenStorageOverlay,
+	) -> sp_blockchain::Result<()> {
+		let header = self.backend.blockchain().header(BlockId::Hash(hash))?
+			.ok_or_else(|| Error::UnknownBlock(format!("{}", hash)))?;
+		let new_state = if self.backend.blockchain().info().best_hash == hash {
+			NewBlockState::Best
+		} else {
+			NewBlockState::Normal
+		};
+
+		self.lock_import_and_run(|operation| {
+			let state_root = operation.op.reset_storage(top, children)?;
+			if state_root != *header.state_root() {
+				return Err(Error::InvalidStateRoot);
+			}
+
+			operation.op.set_block_data(header, None, None, new_state)
+		})
+	}
+
 	/// Execute a call to a contract on top of state in a block of given hash
 	/// AND returning execution proof.
 	///
@@ -1006,7 +1067,10 @@ impl<B, E, Block, RA> Client<B, E, Block, RA> where
 							_ => get_execution_manager(self.execution_extensions().strategies().importing),
 						},
 						None,
-						None,
+						Some(self.execution_extensions().block_execution_extensions(
+							*import_headers.post().number(),
+							hash,
+						)),
 					)?;
 
 				overlay.commit_prospective();
@@ -1147,7 +1211,7 @@ impl<B, E, Block, RA> Client<B, E, Block, RA> where
 		let info = self.backend.blockchain().info();
 		ClientInfo {
 			chain: info,
-			used_state_cache_size: self.backend.used_state_cache_size(),
+			usage: self.backend.usage_info(),
 		}
 	}
 
@@ -1286,6 +1350,101 @@ impl<B, E, Block, RA> ProvideUncles<Block> for Client<B, E, Block, RA> where
 	}
 }
 
+impl<B, E, Block, RA> UsageProvider<Block> for Client<B, E, Block, RA> where
+	B: backend::Backend<Block, Blake2Hasher>,
+	E: CallExecutor<Block, Blake2Hasher>,
+	Block: BlockT<Hash=H256>,
+{
+	fn usage_info(&self) -> ClientInfo<Block> {
+		self.info()
+	}
+
+	fn column_sizes(&self) -> sp_blockchain::Result<Option<Vec<ColumnSize>>> {
+		self.backend.column_sizes()
+	}
+}
+
+impl<B, E, Block, RA> BulkImport for Client<B, E, Block, RA> where
+	B: backend::Backend<Block, Blake2Hasher>,
+	E: CallExecutor<Block, Blake2Hasher>,
+	Block: BlockT<Hash=H256>,
+{
+	fn set_bulk_import(&self, enabled: bool) -> sp_blockchain::Result<()> {
+		self.backend.set_bulk_import(enabled)
+	}
+}
+
+impl<B, E, Block, RA> CheckRuntimeUpgrade<Block> for Client<B, E, Block, RA> where
+	B: backend::Backend<Block, Blake2Hasher>,
+	E: CallExecutor<Block, Blake2Hasher>,
+	Block: BlockT<Hash=H256>,
+{
+	fn check_runtime_upgrade(
+		&self,
+		code: &[u8],
+		inherent_data: InherentData,
+	) -> sp_blockchain::Result<RuntimeUpgradeCheck<Block>> {
+		let info = self.info().chain;
+		let at = BlockId::Hash(info.best_hash);
+		let rejected = |reason: String| sp_blockchain::Error::RuntimeUpgradeRejected(reason);
+
+		// All the calls are executed on top of the changes of the previous ones, as they would
+		// be when building a block.
+		let mut overlay = OverlayedChanges::default();
+		let mut call = |method: &str, call_data: &[u8]| self.executor
+			.call_with_code(&at, code, &mut overlay, method, call_data)
+			.map_err(|e| rejected(format!("`{}` failed: {}", method, e)));
+
+		let current_version = self.runtime_version_at(&at)?;
+		let new_version = RuntimeVersion::decode(&mut &call("Core_version", &[])?[..])
+			.map_err(|e| rejected(format!("Invalid runtime version: {}", e.what())))?;
+		if new_version.spec_name != current_version.spec_name {
+			return Err(rejected(format!(
+				"The spec name `{}` differs from the current `{}`",
+				new_version.spec_name,
+				current_version.spec_name,
+			)));
+		}
+		if new_version.spec_version <= current_version.spec_version {
+			return Err(rejected(format!(
+				"The spec version {} isn't greater than the current {}",
+				new_version.spec_version,
+				current_version.spec_version,
+			)));
+		}
+
+		let header = <Block::Header as HeaderT>::new(
+			info.best_number + One::one(),
+			Default::default(),
+			Default::default(),
+			info.best_hash,
+			Default::default(),
+		);
+		call("Core_initialize_block", &header.encode())?;
+
+		let inherent_extrinsics = <Vec<Block::Extrinsic>>::decode(
+			&mut &call("BlockBuilder_inherent_extrinsics", &inherent_data.encode())?[..]
+		).map_err(|e| rejected(format!("Invalid inherent extrinsics: {}", e.what())))?;
+		for extrinsic in &inherent_extrinsics {
+			let result = sp_runtime::ApplyExtrinsicResult::decode(
+				&mut &call("BlockBuilder_apply_extrinsic", &extrinsic.encode())?[..]
+			).map_err(|e| rejected(format!("Invalid result of an inherent extrinsic: {}", e.what())))?;
+			if let Err(e) = result {
+				return Err(rejected(format!("Inherent extrinsic is invalid: {:?}", e)));
+			}
+		}
+
+		call("BlockBuilder_finalize_block", &[])?;
+
+		Ok(RuntimeUpgradeCheck {
+			best_hash: info.best_hash,
+			current_version,
+			new_version,
+			inherent_extrinsics: inherent_extrinsics.len(),
+		})
+	}
+}
+
 impl<B, E, Block, RA> ChainHeaderBackend<Block> for Client<B, E, Block, RA> where
 	B: backend::Backend<Block, Blake2Hasher>,
 	E: CallExecutor<Block, Blake2Hasher> + Send + Sync,
@@ -1773,7 +1932,10 @@ pub(crate) mod tests {
 	use test_client::{
 		prelude::*,
 		client_ext::ClientExt,
-		client_db::{Backend, DatabaseSettings, DatabaseSettingsSrc, PruningMode},
+		client_db::{
+			Backend, DatabaseSettings, DatabaseSettingsSrc, DatabaseKind, PruningMode, BlockPruning, StatePinning,
+			HeaderPruning,
+		},
 		runtime::{self, Block, Transfer, RuntimeApi, TestAPI},
 	};
 
@@ -2658,11 +2820,16 @@ pub(crate) mod tests {
 		let backend = Arc::new(Backend::new(
 			DatabaseSettings {
 				state_cache_size: 1 << 20,
+				trie_cache_size: 1 << 20,
 				state_cache_child_ratio: None,
 				pruning: PruningMode::ArchiveAll,
+				block_pruning: BlockPruning::ArchiveAll,
+				state_pinning: StatePinning::None,
+				header_pruning: HeaderPruning::default(),
 				source: DatabaseSettingsSrc::Path {
 					path: tmp.path().into(),
 					cache_size: None,
+					kind: DatabaseKind::RocksDb,
 				}
 			},
 			u64::max_value(),
@@ -2751,11 +2918,16 @@ pub(crate) mod tests {
 		let backend = Arc::new(Backend::new(
 				DatabaseSettings {
 					state_cache_size: 1 << 20,
+					trie_cache_size: 1 << 20,
 					state_cache_child_ratio: None,
 					pruning: PruningMode::keep_blocks(1),
+					block_pruning: BlockPruning::ArchiveAll,
+					state_pinning: StatePinning::None,
+					header_pruning: HeaderPruning::default(),
 					source: DatabaseSettingsSrc::Path {
 						path: tmp.path().into(),
 						cache_size: None,
+						kind: DatabaseKind::RocksDb,
 					}
 				},
 				u64::max_value(),
@@ -2843,4 +3015,94 @@ pub(crate) mod tests {
 		check_block_b1.parent_hash = H256::random();
 		assert_eq!(client.check_block(check_block_b1.clone()).unwrap(), ImportResult::UnknownParent);
 	}
+
+	#[test]
+	fn check_runtime_upgrade_rejects_invalid_code() {
+		let client = test_client::new();
+
+		match client.check_runtime_upgrade(&[1, 2, 3], Default::default()) {
+			Err(Error::RuntimeUpgradeRejected(reason)) =>
+				assert!(reason.starts_with("`Core_version` failed"), "{}", reason),
+			res => panic!(
+				"Expected the upgrade to be rejected, got {:?}",
+				res.map(|check| check.new_version),
+			),
+		}
+	}
+
+	/// Wraps the `Core_version` export of the given runtime `code`, so that it reports the given
+	/// `spec_version` instead of its own.
+	fn with_spec_version(code: &[u8], spec_version: u32) -> Vec<u8> {
+		use parity_wasm::elements::{
+			self, Func, FuncBody, ImportCountType, Instruction, Instructions, Internal, Local,
+			ValueType,
+		};
+
+		// The encoded version starts with the spec name, the impl name and the authoring version.
+		let offset = runtime::VERSION.spec_name.encode().len()
+			+ runtime::VERSION.impl_name.encode().len()
+			+ runtime::VERSION.authoring_version.encode().len();
+
+		let mut module: elements::Module = elements::deserialize_buffer(code).unwrap();
+		let imported_functions = module.import_count(ImportCountType::Function) as u32;
+		let functions = module.function_section().unwrap().entries().to_vec();
+		let wrapper = imported_functions + functions.len() as u32;
+		let export = module.export_section_mut().unwrap().entries_mut()
+			.iter_mut()
+			.find(|export| export.field() == "Core_version")
+			.unwrap();
+		let version = match *export.internal() {
+			Internal::Function(index) => index,
+			ref internal => panic!("`Core_version` isn't a function: {:?}", internal),
+		};
+		*export.internal_mut() = Internal::Function(wrapper);
+
+		let type_ref = functions[(version - imported_functions) as usize].type_ref();
+		module.function_section_mut().unwrap().entries_mut().push(Func::new(type_ref));
+		module.code_section_mut().unwrap().bodies_mut().push(FuncBody::new(
+			vec![Local::new(1, ValueType::I64)],
+			Instructions::new(vec![
+				Instruction::GetLocal(0),
+				Instruction::GetLocal(1),
+				Instruction::Call(version),
+				// The pointer to the encoded version is in the lower 32 bits of the result.
+				Instruction::TeeLocal(2),
+				Instruction::I32WrapI64,
+				Instruction::I32Const(spec_version as i32),
+				Instruction::I32Store(0, offset as u32),
+				Instruction::GetLocal(2),
+				Instruction::End,
+			]),
+		));
+		elements::serialize(module).unwrap()
+	}
+
+	#[test]
+	fn check_runtime_upgrade_accepts_bumped_spec_version() {
+		let client = test_client::new();
+		let code = client.code_at(&BlockId::Number(0)).unwrap();
+		let code = with_spec_version(&code, runtime::VERSION.spec_version + 1);
+
+		let check = client.check_runtime_upgrade(&code, Default::default()).unwrap();
+		assert_eq!(check.best_hash, client.info().chain.genesis_hash);
+		assert_eq!(check.current_version.spec_version, runtime::VERSION.spec_version);
+		assert_eq!(check.new_version.spec_version, runtime::VERSION.spec_version + 1);
+		assert_eq!(check.new_version.spec_name, runtime::VERSION.spec_name);
+		assert_eq!(check.inherent_extrinsics, 0);
+	}
+
+	#[test]
+	fn check_runtime_upgrade_rejects_same_spec_version() {
+		let client = test_client::new();
+		let code = client.code_at(&BlockId::Number(0)).unwrap();
+
+		match client.check_runtime_upgrade(&code, Default::default()) {
+			Err(Error::RuntimeUpgradeRejected(reason)) =>
+				assert!(reason.starts_with("The spec version"), "{}", reason),
+			res => panic!(
+				"Expected the upgrade to be rejected, got {:?}",
+				res.map(|check| check.new_version),
+			),
+		}
+	}
 }
@@ -37,6 +37,7 @@ use client_api::{
 	blockchain::{
 		self, BlockStatus, HeaderBackend, well_known_cache_keys::Id as CacheKeyId
 	},
+	UsageInfo,
 };
 use crate::leaves::LeafSet;
 
@@ -681,7 +682,7 @@ where
 		&self.blockchain
 	}
 
-	fn used_state_cache_size(&self) -> Option<usize> {
+	fn usage_info(&self) -> Option<UsageInfo> {
 		None
 	}
 
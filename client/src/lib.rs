@@ -98,7 +98,8 @@ pub use crate::{
 		new_in_mem,
 		BlockBody, ImportNotifications, FinalityNotifications, BlockchainEvents,
 		BlockImportNotification, Client, ClientInfo, ExecutionStrategies, FinalityNotification,
-		LongestChain, BlockOf, ProvideUncles, ForkBlocks, apply_aux,
+		LongestChain, BlockOf, ProvideUncles, ForkBlocks, UsageProvider, BulkImport, apply_aux,
+		CheckRuntimeUpgrade, RuntimeUpgradeCheck,
 	},
 	leaves::LeafSet,
 };
@@ -38,6 +38,7 @@ use client_api::{
 		HeaderBackend as BlockchainHeaderBackend, well_known_cache_keys,
 	},
 	light::Storage as BlockchainStorage,
+	UsageInfo,
 };
 use crate::light::blockchain::Blockchain;
 use hash_db::Hasher;
@@ -185,7 +186,7 @@ impl<S, Block, H> ClientBackend<Block, H> for Backend<S, H> where
 		&self.blockchain
 	}
 
-	fn used_state_cache_size(&self) -> Option<usize> {
+	fn usage_info(&self) -> Option<UsageInfo> {
 		None
 	}
 
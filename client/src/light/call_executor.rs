@@ -152,6 +152,20 @@ impl<Block, B, Local> CallExecutor<Block, Blake2Hasher> for
 		}
 	}
 
+	fn call_with_code(
+		&self,
+		id: &BlockId<Block>,
+		code: &[u8],
+		overlay: &mut OverlayedChanges,
+		method: &str,
+		call_data: &[u8],
+	) -> ClientResult<Vec<u8>> {
+		match self.backend.is_local_state_available(id) {
+			true => self.local.call_with_code(id, code, overlay, method, call_data),
+			false => Err(ClientError::NotAvailableOnLightClient),
+		}
+	}
+
 	fn call_at_state<
 		S: StateBackend<Blake2Hasher>,
 		FF: FnOnce(
@@ -345,6 +359,17 @@ mod tests {
 			unreachable!()
 		}
 
+		fn call_with_code(
+			&self,
+			_id: &BlockId<Block>,
+			_code: &[u8],
+			_overlay: &mut OverlayedChanges,
+			_method: &str,
+			_call_data: &[u8],
+		) -> Result<Vec<u8>, ClientError> {
+			unreachable!()
+		}
+
 		fn call_at_state<
 			S: state_machine::Backend<Blake2Hasher>,
 			F: FnOnce(
@@ -18,6 +18,11 @@
 //! this module is a `ChangeSet` which is basically a list of key-value pairs (trie nodes) that
 //! were added or deleted during block execution.
 //!
+//! A node may be referenced several times, e.g. by identical child tries, and is then listed once
+//! for each reference. The node changes of a `CommitSet` are reference changes: the backing
+//! database is expected to count the references of each node and to remove it along with its last
+//! reference.
+//!
 //! # Canonicalization.
 //! Canonicalization window tracks a tree of blocks identified by header hash. The in-memory
 //! overlay allows to get any node that was inserted in any of the blocks within the window.
@@ -27,15 +32,23 @@
 //!
 //! # Pruning.
 //! See `RefWindow` for pruning algorithm details. `StateDb` prunes on each canonicalization until pruning
-//! constraints are satisfied.
+//! constraints are satisfied. A canonicalized block is kept while it is within `max_blocks` of the
+//! last canonicalized block or has been canonicalized less than `max_age` seconds ago.
+//!
+//! # Archived blocks.
+//! The state of some blocks may be archived, so that it is never pruned. `StateDb` only keeps the
+//! registry of the archived blocks: the nodes of their state must be copied by the caller under
+//! `archived_node_key` before the block is registered with `note_archived`, while the block is
+//! pinned.
 
 mod noncanonical;
 mod pruning;
 #[cfg(test)] mod test;
 
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 use parking_lot::RwLock;
-use codec::Codec;
+use codec::{Codec, Decode, Encode};
 use std::collections::{HashMap, hash_map::Entry};
 use noncanonical::NonCanonicalOverlay;
 use pruning::RefWindow;
@@ -45,6 +58,8 @@ const PRUNING_MODE: &[u8] = b"mode";
 const PRUNING_MODE_ARCHIVE: &[u8] = b"archive";
 const PRUNING_MODE_ARCHIVE_CANON: &[u8] = b"archive_canonical";
 const PRUNING_MODE_CONSTRAINED: &[u8] = b"constrained";
+const ARCHIVED_BLOCKS: &[u8] = b"archived_blocks";
+const ARCHIVED_NODE: &[u8] = b"archived_node";
 
 /// Database value type.
 pub type DBValue = Vec<u8>;
@@ -114,9 +129,9 @@ impl<E: fmt::Debug> fmt::Debug for Error<E> {
 /// A set of state node changes.
 #[derive(Default, Debug, Clone)]
 pub struct ChangeSet<H: Hash> {
-	/// Inserted nodes.
+	/// Inserted nodes, once for each added reference.
 	pub inserted: Vec<(H, DBValue)>,
-	/// Deleted nodes.
+	/// Deleted nodes, once for each removed reference.
 	pub deleted: Vec<H>,
 }
 
@@ -137,6 +152,9 @@ pub struct Constraints {
 	pub max_blocks: Option<u32>,
 	/// Maximum memory in the pruning overlay.
 	pub max_mem: Option<usize>,
+	/// Minimum time, in seconds, during which the state of a canonicalized block is kept, even
+	/// if it is older than `max_blocks`.
+	pub max_age: Option<u64>,
 }
 
 /// Pruning mode.
@@ -156,6 +174,7 @@ impl PruningMode {
 		PruningMode::Constrained(Constraints {
 			max_blocks: Some(n),
 			max_mem: None,
+			max_age: None,
 		})
 	}
 
@@ -189,11 +208,23 @@ fn to_meta_key<S: Codec>(suffix: &[u8], data: &S) -> Vec<u8> {
 	buffer
 }
 
+/// Returns the meta key under which an archived state node is stored.
+pub fn archived_node_key(key: &[u8]) -> Vec<u8> {
+	to_meta_key(ARCHIVED_NODE, &key)
+}
+
+/// Current time in seconds since the UNIX epoch.
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 struct StateDbSync<BlockHash: Hash, Key: Hash> {
 	mode: PruningMode,
 	non_canonical: NonCanonicalOverlay<BlockHash, Key>,
 	pruning: Option<RefWindow<BlockHash, Key>>,
 	pinned: HashMap<BlockHash, u32>,
+	/// Numbers of the blocks whose state is archived.
+	archived: HashMap<BlockHash, u64>,
 }
 
 impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
@@ -212,12 +243,17 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 			PruningMode::Constrained(_) => Some(RefWindow::new(db)?),
 			PruningMode::ArchiveAll | PruningMode::ArchiveCanonical => None,
 		};
+		let archived = match db.get_meta(&to_meta_key(ARCHIVED_BLOCKS, &())).map_err(Error::Db)? {
+			Some(archived) => Vec::<(BlockHash, u64)>::decode(&mut archived.as_slice())?.into_iter().collect(),
+			None => Default::default(),
+		};
 
 		Ok(StateDbSync {
 			mode,
 			non_canonical,
 			pruning,
 			pinned: Default::default(),
+			archived,
 		})
 	}
 
@@ -260,6 +296,14 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 		}
 	}
 
+	pub fn reset_last_canonicalized<E: fmt::Debug>(&mut self, hash: &BlockHash, number: u64) -> Result<CommitSet<Key>, Error<E>> {
+		match self.mode {
+			PruningMode::ArchiveAll => Ok(CommitSet::default()),
+			PruningMode::Constrained(_) | PruningMode::ArchiveCanonical =>
+				self.non_canonical.reset_last_canonicalized(hash, number),
+		}
+	}
+
 	pub fn canonicalize_block<E: fmt::Debug>(&mut self, hash: &BlockHash) -> Result<CommitSet<Key>, Error<E>> {
 		let mut commit = CommitSet::default();
 		if self.mode == PruningMode::ArchiveAll {
@@ -273,10 +317,11 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 			}
 			Err(e) => return Err(e),
 		};
+		let now = now();
 		if let Some(ref mut pruning) = self.pruning {
-			pruning.note_canonical(&hash, &mut commit);
+			pruning.note_canonical(&hash, &mut commit, now);
 		}
-		self.prune(&mut commit);
+		self.prune(&mut commit, now);
 		Ok(commit)
 	}
 
@@ -284,7 +329,14 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 		return self.non_canonical.last_canonicalized_block_number()
 	}
 
+	pub fn noncanonical_blocks(&self) -> Vec<(u64, BlockHash, BlockHash)> {
+		self.non_canonical.blocks()
+	}
+
 	pub fn is_pruned(&self, hash: &BlockHash, number: u64) -> bool {
+		if self.archived.contains_key(hash) {
+			return false;
+		}
 		match self.mode {
 			PruningMode::ArchiveAll => false,
 			PruningMode::ArchiveCanonical | PruningMode::Constrained(_) => {
@@ -297,7 +349,7 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 		}
 	}
 
-	fn prune(&mut self, commit: &mut CommitSet<Key>) {
+	fn prune(&mut self, commit: &mut CommitSet<Key>, now: u64) {
 		if let (&mut Some(ref mut pruning), &PruningMode::Constrained(ref constraints)) = (&mut self.pruning, &self.mode) {
 			loop {
 				if pruning.window_size() <= constraints.max_blocks.unwrap_or(0) as u64 {
@@ -308,6 +360,12 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 					break;
 				}
 
+				if let (Some(max_age), Some(canonicalized)) = (constraints.max_age, pruning.next_canonicalized()) {
+					if now.saturating_sub(canonicalized) < max_age {
+						break;
+					}
+				}
+
 				let pinned = &self.pinned;
 				if pruning.next_hash().map_or(false, |h| pinned.contains_key(&h)) {
 					break;
@@ -345,6 +403,9 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 					}
 					*refs += 1;
 					Ok(())
+				} else if self.archived.contains_key(hash) {
+					// archived state is never pruned.
+					Ok(())
 				} else {
 					Err(PinError::InvalidBlock)
 				}
@@ -352,6 +413,25 @@ impl<BlockHash: Hash, Key: Hash> StateDbSync<BlockHash, Key> {
 		}
 	}
 
+	pub fn note_archived(&mut self, hash: &BlockHash, number: u64) -> CommitSet<Key> {
+		let mut commit = CommitSet::default();
+		if self.archived.insert(hash.clone(), number).is_none() {
+			trace!(target: "state-db", "Archived block: {:?}", hash);
+		}
+		commit.meta.inserted.push((to_meta_key(ARCHIVED_BLOCKS, &()), self.archived_list().encode()));
+		commit
+	}
+
+	pub fn is_archived(&self, hash: &BlockHash) -> bool {
+		self.archived.contains_key(hash)
+	}
+
+	fn archived_list(&self) -> Vec<(BlockHash, u64)> {
+		let mut archived: Vec<_> = self.archived.iter().map(|(h, n)| (h.clone(), *n)).collect();
+		archived.sort_by_key(|(_, n)| *n);
+		archived
+	}
+
 	pub fn unpin(&mut self, hash: &BlockHash) {
 		match self.pinned.entry(hash.clone()) {
 			Entry::Occupied(mut entry) => {
@@ -418,6 +498,13 @@ impl<BlockHash: Hash, Key: Hash> StateDb<BlockHash, Key> {
 		self.db.write().insert_block(hash, number, parent_hash, changeset)
 	}
 
+	/// Make the given block the last canonicalized one, so that the state of its child may be
+	/// inserted even though the state of the block itself is unknown. Fails if there are
+	/// non-canonical blocks.
+	pub fn reset_last_canonicalized<E: fmt::Debug>(&self, hash: &BlockHash, number: u64) -> Result<CommitSet<Key>, Error<E>> {
+		self.db.write().reset_last_canonicalized(hash, number)
+	}
+
 	/// Finalize a previously inserted block.
 	pub fn canonicalize_block<E: fmt::Debug>(&self, hash: &BlockHash) -> Result<CommitSet<Key>, Error<E>> {
 		self.db.write().canonicalize_block(hash)
@@ -433,6 +520,23 @@ impl<BlockHash: Hash, Key: Hash> StateDb<BlockHash, Key> {
 		self.db.write().unpin(hash)
 	}
 
+	/// Registers the block as archived, so that its state is never reported as pruned. The nodes
+	/// of the state must have been copied under `archived_node_key` beforehand. Returns the database
+	/// commit, which takes effect immediately.
+	pub fn note_archived(&self, hash: &BlockHash, number: u64) -> CommitSet<Key> {
+		self.db.write().note_archived(hash, number)
+	}
+
+	/// Is the state of the block archived?
+	pub fn is_archived(&self, hash: &BlockHash) -> bool {
+		self.db.read().is_archived(hash)
+	}
+
+	/// Returns the hashes and numbers of the archived blocks, ordered by number.
+	pub fn archived_blocks(&self) -> Vec<(BlockHash, u64)> {
+		self.db.read().archived_list()
+	}
+
 	/// Get a value from non-canonical/pruning overlay or the backing DB.
 	pub fn get<D: NodeDb>(&self, key: &Key, db: &D) -> Result<Option<DBValue>, Error<D::Error>>
 		where Key: AsRef<D::Key>
@@ -457,6 +561,11 @@ impl<BlockHash: Hash, Key: Hash> StateDb<BlockHash, Key> {
 		return self.db.read().is_pruned(hash, number)
 	}
 
+	/// Returns the number, hash and parent hash of the blocks in the non-canonical journal.
+	pub fn noncanonical_blocks(&self) -> Vec<(u64, BlockHash, BlockHash)> {
+		self.db.read().noncanonical_blocks()
+	}
+
 	/// Apply all pending changes
 	pub fn apply_pending(&self) {
 		self.db.write().apply_pending();
@@ -559,6 +668,7 @@ mod tests {
 		let (db, _) = make_test_db(PruningMode::Constrained(Constraints {
 			max_blocks: Some(0),
 			max_mem: None,
+			max_age: None,
 		}));
 		assert!(db.data_eq(&make_db(&[21, 3, 922, 94])));
 	}
@@ -568,6 +678,7 @@ mod tests {
 		let (db, sdb) = make_test_db(PruningMode::Constrained(Constraints {
 			max_blocks: Some(1),
 			max_mem: None,
+			max_age: None,
 		}));
 		assert!(sdb.is_pruned(&H256::from_low_u64_be(0), 0));
 		assert!(sdb.is_pruned(&H256::from_low_u64_be(1), 1));
@@ -581,6 +692,7 @@ mod tests {
 		let (db, sdb) = make_test_db(PruningMode::Constrained(Constraints {
 			max_blocks: Some(2),
 			max_mem: None,
+			max_age: None,
 		}));
 		assert!(sdb.is_pruned(&H256::from_low_u64_be(0), 0));
 		assert!(sdb.is_pruned(&H256::from_low_u64_be(1), 1));
@@ -589,6 +701,40 @@ mod tests {
 		assert!(db.data_eq(&make_db(&[1, 21, 3, 921, 922, 93, 94])));
 	}
 
+	#[test]
+	fn prune_window_keeps_recent_blocks() {
+		let (db, sdb) = make_test_db(PruningMode::Constrained(Constraints {
+			max_blocks: Some(0),
+			max_mem: None,
+			max_age: Some(3600),
+		}));
+		assert!(!sdb.is_pruned(&H256::from_low_u64_be(1), 1));
+		assert!(!sdb.is_pruned(&H256::from_low_u64_be(21), 2));
+		assert!(db.data_eq(&make_db(&[1, 21, 3, 91, 921, 922, 93, 94])));
+	}
+
+	#[test]
+	fn archived_block_is_not_pruned() {
+		let (mut db, sdb) = make_test_db(PruningMode::Constrained(Constraints {
+			max_blocks: Some(0),
+			max_mem: None,
+			max_age: None,
+		}));
+		let hash = H256::from_low_u64_be(1);
+		assert!(sdb.is_pruned(&hash, 1));
+		assert!(sdb.pin(&hash).is_err());
+
+		db.commit(&sdb.note_archived(&hash, 1));
+		assert!(!sdb.is_pruned(&hash, 1));
+		assert!(sdb.pin(&hash).is_ok());
+		sdb.unpin(&hash);
+
+		// the registry survives restarts.
+		let sdb: StateDb<H256, H256> = StateDb::new(PruningMode::keep_blocks(0), &db).unwrap();
+		assert_eq!(sdb.archived_blocks(), vec![(hash, 1)]);
+		assert!(!sdb.is_pruned(&hash, 1));
+	}
+
 	#[test]
 	fn detects_incompatible_mode() {
 		let mut db = make_db(&[]);
@@ -603,7 +749,7 @@ mod tests {
 			)
 			.unwrap(),
 		);
-		let new_mode = PruningMode::Constrained(Constraints { max_blocks: Some(2), max_mem: None });
+		let new_mode = PruningMode::Constrained(Constraints { max_blocks: Some(2), max_mem: None, max_age: None });
 		let state_db: Result<StateDb<H256, H256>, _> = StateDb::new(new_mode, &db);
 		assert!(state_db.is_err());
 	}
@@ -259,6 +259,21 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		}
 	}
 
+	/// Make the given block the last canonicalized one, so that the state of its child may be
+	/// inserted even though the state of the block itself is unknown. This is used when the
+	/// state of a block is imported instead of being built from the state of its ancestors.
+	/// Fails if there are non-canonical blocks.
+	pub fn reset_last_canonicalized<E: fmt::Debug>(&mut self, hash: &BlockHash, number: u64) -> Result<CommitSet<Key>, Error<E>> {
+		if !self.levels.is_empty() {
+			return Err(Error::InvalidBlock);
+		}
+		let mut commit = CommitSet::default();
+		let last_canonicalized = (hash.clone(), number);
+		commit.meta.inserted.push((to_meta_key(LAST_CANONICAL, &()), last_canonicalized.encode()));
+		self.last_canonicalized = Some(last_canonicalized);
+		Ok(commit)
+	}
+
 	fn front_block_number(&self) -> u64 {
 		self.last_canonicalized.as_ref().map(|&(_, n)| n + 1).unwrap_or(0)
 	}
@@ -275,6 +290,17 @@ impl<BlockHash: Hash, Key: Hash> NonCanonicalOverlay<BlockHash, Key> {
 		self.last_canonicalized.as_ref().map(|&(ref h, _)| h.clone())
 	}
 
+	/// Returns the number, hash and parent hash of every block in the overlay.
+	pub fn blocks(&self) -> Vec<(u64, BlockHash, BlockHash)> {
+		let front = self.front_block_number();
+		let parents = &self.parents;
+		self.levels.iter().enumerate().flat_map(|(index, level)| level.iter().map(move |overlay| (
+			front + index as u64,
+			overlay.hash.clone(),
+			parents.get(&overlay.hash).expect("there is a parent entry for each entry in levels; qed").clone(),
+		))).collect()
+	}
+
 	pub fn top_level(&self) -> Vec<(BlockHash, u64)> {
 		let start = self.last_canonicalized_block_number().unwrap_or(0);
 		self.levels
@@ -508,6 +534,47 @@ mod tests {
 		overlay.canonicalize::<io::Error>(&h2, &mut commit).unwrap();
 	}
 
+	#[test]
+	fn insert_after_reset_last_canonicalized() {
+		let h1 = H256::random();
+		let h10 = H256::random();
+		let h11 = H256::random();
+		let mut db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		db.commit(&overlay.insert::<io::Error>(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap());
+		assert!(overlay.reset_last_canonicalized::<io::Error>(&h10, 10).is_err());
+
+		let mut commit = CommitSet::default();
+		overlay.canonicalize::<io::Error>(&h1, &mut commit).unwrap();
+		db.commit(&commit);
+		overlay.apply_pending();
+		assert!(overlay.insert::<io::Error>(&h11, 11, &h10, make_changeset(&[2], &[])).is_err());
+
+		db.commit(&overlay.reset_last_canonicalized::<io::Error>(&h10, 10).unwrap());
+		db.commit(&overlay.insert::<io::Error>(&h11, 11, &h10, make_changeset(&[2], &[])).unwrap());
+		assert!(contains(&overlay, 2));
+
+		let overlay2 = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		assert_eq!(overlay2.last_canonicalized, Some((h10, 10)));
+		assert!(overlay2.have_block(&h11));
+	}
+
+	#[test]
+	fn lists_blocks() {
+		let h1 = H256::random();
+		let h2_1 = H256::random();
+		let h2_2 = H256::random();
+		let mut db = make_db(&[]);
+		let mut overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		db.commit(&overlay.insert::<io::Error>(&h1, 1, &H256::default(), make_changeset(&[1], &[])).unwrap());
+		db.commit(&overlay.insert::<io::Error>(&h2_1, 2, &h1, make_changeset(&[2], &[])).unwrap());
+		db.commit(&overlay.insert::<io::Error>(&h2_2, 2, &h1, make_changeset(&[3], &[])).unwrap());
+		overlay.apply_pending();
+
+		let overlay = NonCanonicalOverlay::<H256, H256>::new(&db).unwrap();
+		assert_eq!(overlay.blocks(), vec![(1, h1, H256::default()), (2, h2_1, h1), (2, h2_2, h1)]);
+	}
+
 	#[test]
 	fn insert_canonicalize_one() {
 		let h1 = H256::random();
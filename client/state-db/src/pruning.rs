@@ -19,23 +19,29 @@
 //! For each block we maintain a list of nodes pending deletion.
 //! There is also a global index of node key to block number.
 //! If a node is re-inserted into the window it gets removed from
-//! the death list.
-//! The changes are journaled in the DB.
-
-use std::collections::{HashMap, HashSet, VecDeque};
+//! the death list, and the insertion is dropped from the commit: the node
+//! keeps the reference that was about to be removed.
+//! A node may be deleted several times, once for each of its references,
+//! e.g. when identical child tries are removed.
+//! The changes are journaled in the DB, along with the time at which each block has been
+//! canonicalized.
+
+use std::collections::{HashMap, VecDeque, hash_map::Entry};
 use codec::{Encode, Decode};
 use crate::{CommitSet, Error, MetaDb, to_meta_key, Hash};
 use log::{trace, warn};
 
 const LAST_PRUNED: &[u8] = b"last_pruned";
 const PRUNING_JOURNAL: &[u8] = b"pruning_journal";
+const PRUNING_JOURNAL_TIME: &[u8] = b"pruning_journal_time";
 
 /// See module documentation.
 pub struct RefWindow<BlockHash: Hash, Key: Hash> {
 	/// A queue of keys that should be deleted for each block in the pruning window.
 	death_rows: VecDeque<DeathRow<BlockHash, Key>>,
-	/// An index that maps each key from `death_rows` to block number.
-	death_index: HashMap<Key, u64>,
+	/// An index that maps each key from `death_rows` to the block numbers, once for each
+	/// pending deletion, in ascending order.
+	death_index: HashMap<Key, Vec<u64>>,
 	/// Block number that corresponts to the front of `death_rows`
 	pending_number: u64,
 	/// Number of call of `note_canonical` after
@@ -50,7 +56,10 @@ pub struct RefWindow<BlockHash: Hash, Key: Hash> {
 struct DeathRow<BlockHash: Hash, Key: Hash> {
 	hash: BlockHash,
 	journal_key: Vec<u8>,
-	deleted: HashSet<Key>,
+	/// Deleted keys, with the number of deleted references.
+	deleted: HashMap<Key, u32>,
+	/// When the block has been canonicalized, in seconds since the UNIX epoch.
+	canonicalized: u64,
 }
 
 #[derive(Encode, Decode)]
@@ -64,6 +73,10 @@ fn to_journal_key(block: u64) -> Vec<u8> {
 	to_meta_key(PRUNING_JOURNAL, &block)
 }
 
+fn to_journal_time_key(block: u64) -> Vec<u8> {
+	to_meta_key(PRUNING_JOURNAL_TIME, &block)
+}
+
 impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 	pub fn new<D: MetaDb>(db: &D) -> Result<RefWindow<BlockHash, Key>, Error<D::Error>> {
 		let last_pruned = db.get_meta(&to_meta_key(LAST_PRUNED, &()))
@@ -87,8 +100,13 @@ impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 			match db.get_meta(&journal_key).map_err(|e| Error::Db(e))? {
 				Some(record) => {
 					let record: JournalRecord<BlockHash, Key> = Decode::decode(&mut record.as_slice())?;
+					// the time is missing from the journals written by older versions.
+					let canonicalized = match db.get_meta(&to_journal_time_key(block)).map_err(|e| Error::Db(e))? {
+						Some(time) => u64::decode(&mut time.as_slice())?,
+						None => 0,
+					};
 					trace!(target: "state-db", "Pruning journal entry {} ({} inserted, {} deleted)", block, record.inserted.len(), record.deleted.len());
-					pruning.import(&record.hash, journal_key, record.inserted.into_iter(), record.deleted);
+					pruning.import(&record.hash, journal_key, record.inserted.into_iter(), record.deleted, canonicalized);
 				},
 				None => break,
 			}
@@ -97,26 +115,65 @@ impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 		Ok(pruning)
 	}
 
-	fn import<I: IntoIterator<Item=Key>>(&mut self, hash: &BlockHash, journal_key: Vec<u8>, inserted: I, deleted: Vec<Key>) {
+	/// Returns the re-inserted keys which took the place of a pending deletion, with the number
+	/// of such deletions.
+	fn import<I: IntoIterator<Item=Key>>(
+		&mut self,
+		hash: &BlockHash,
+		journal_key: Vec<u8>,
+		inserted: I,
+		deleted: Vec<Key>,
+		canonicalized: u64,
+	) -> HashMap<Key, u32> {
 		// remove all re-inserted keys from death rows
+		let mut reinserted = HashMap::new();
 		for k in inserted {
-			if let Some(block) = self.death_index.remove(&k) {
-				self.death_rows[(block - self.pending_number) as usize].deleted.remove(&k);
+			if self.cancel_deletion(&k) {
+				*reinserted.entry(k).or_insert(0) += 1;
 			}
 		}
 
 		// add new keys
 		let imported_block = self.pending_number + self.death_rows.len() as u64;
-		for k in deleted.iter() {
-			self.death_index.insert(k.clone(), imported_block);
+		let mut row = HashMap::new();
+		for k in deleted {
+			self.death_index.entry(k.clone()).or_insert_with(Vec::new).push(imported_block);
+			*row.entry(k).or_insert(0) += 1;
 		}
 		self.death_rows.push_back(
 			DeathRow {
 				hash: hash.clone(),
-				deleted: deleted.into_iter().collect(),
+				deleted: row,
 				journal_key: journal_key,
+				canonicalized,
 			}
 		);
+		reinserted
+	}
+
+	/// Cancels the latest pending deletion of the key, unless its block is being pruned.
+	fn cancel_deletion(&mut self, key: &Key) -> bool {
+		let first_kept = self.pending_number + self.pending_prunings as u64;
+		let block = match self.death_index.entry(key.clone()) {
+			Entry::Occupied(mut entry) => match entry.get().last().cloned() {
+				Some(block) if block >= first_kept => {
+					entry.get_mut().pop();
+					if entry.get().is_empty() {
+						entry.remove();
+					}
+					block
+				},
+				_ => return false,
+			},
+			Entry::Vacant(_) => return false,
+		};
+		if let Entry::Occupied(mut entry) = self.death_rows[(block - self.pending_number) as usize].deleted.entry(key.clone()) {
+			*entry.get_mut() -= 1;
+			if *entry.get() == 0 {
+				entry.remove();
+			}
+		}
+		true
 	}
 
 	pub fn window_size(&self) -> u64 {
@@ -127,6 +184,11 @@ impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 		self.death_rows.get(self.pending_prunings).map(|r| r.hash.clone())
 	}
 
+	/// When the next block to prune has been canonicalized, in seconds since the UNIX epoch.
+	pub fn next_canonicalized(&self) -> Option<u64> {
+		self.death_rows.get(self.pending_prunings).map(|r| r.canonicalized)
+	}
+
 	pub fn mem_used(&self) -> usize {
 		0
 	}
@@ -144,17 +206,20 @@ impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 		if let Some(pruned) = self.death_rows.get(self.pending_prunings) {
 			trace!(target: "state-db", "Pruning {:?} ({} deleted)", pruned.hash, pruned.deleted.len());
 			let index = self.pending_number + self.pending_prunings as u64;
-			commit.data.deleted.extend(pruned.deleted.iter().cloned());
+			commit.data.deleted.extend(pruned.deleted.iter()
+				.flat_map(|(k, refs)| std::iter::repeat(k).take(*refs as usize).cloned()));
 			commit.meta.inserted.push((to_meta_key(LAST_PRUNED, &()), index.encode()));
 			commit.meta.deleted.push(pruned.journal_key.clone());
+			commit.meta.deleted.push(to_journal_time_key(index));
 			self.pending_prunings += 1;
 		} else {
 			warn!(target: "state-db", "Trying to prune when there's nothing to prune");
 		}
 	}
 
-	/// Add a change set to the window. Creates a journal record and pushes it to `commit`
-	pub fn note_canonical(&mut self, hash: &BlockHash, commit: &mut CommitSet<Key>) {
+	/// Add a change set to the window. Creates a journal record and pushes it to `commit`.
+	/// `now` is the current time in seconds since the UNIX epoch.
+	pub fn note_canonical(&mut self, hash: &BlockHash, commit: &mut CommitSet<Key>, now: u64) {
 		trace!(target: "state-db", "Adding to pruning window: {:?} ({} inserted, {} deleted)", hash, commit.data.inserted.len(), commit.data.deleted.len());
 		let inserted = commit.data.inserted.iter().map(|(k, _)| k.clone()).collect();
 		let deleted = ::std::mem::replace(&mut commit.data.deleted, Vec::new());
@@ -166,7 +231,16 @@ impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 		let block = self.pending_number + self.death_rows.len() as u64;
 		let journal_key = to_journal_key(block);
 		commit.meta.inserted.push((journal_key.clone(), journal_record.encode()));
-		self.import(&journal_record.hash, journal_key, journal_record.inserted.into_iter(), journal_record.deleted);
+		commit.meta.inserted.push((to_journal_time_key(block), now.encode()));
+		let mut reinserted = self.import(&journal_record.hash, journal_key, journal_record.inserted.into_iter(), journal_record.deleted, now);
+		// the re-inserted nodes are still in the database and keep their reference.
+		commit.data.inserted.retain(|(k, _)| match reinserted.get_mut(k) {
+			Some(refs) if *refs > 0 => {
+				*refs -= 1;
+				false
+			},
+			_ => true,
+		});
 		self.pending_canonicalizations += 1;
 	}
 
@@ -176,8 +250,14 @@ impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 		for _ in 0 .. self.pending_prunings {
 			let pruned = self.death_rows.pop_front().expect("pending_prunings is always < death_rows.len()");
 			trace!(target: "state-db", "Applying pruning {:?} ({} deleted)", pruned.hash, pruned.deleted.len());
-			for k in pruned.deleted.iter() {
-				self.death_index.remove(&k);
+			let pruned_number = self.pending_number;
+			for k in pruned.deleted.keys() {
+				if let Entry::Occupied(mut entry) = self.death_index.entry(k.clone()) {
+					entry.get_mut().retain(|block| *block != pruned_number);
+					if entry.get().is_empty() {
+						entry.remove();
+					}
+				}
 			}
 			self.pending_number += 1;
 		}
@@ -192,7 +272,10 @@ impl<BlockHash: Hash, Key: Hash> RefWindow<BlockHash, Key> {
 		// deleted in case transaction fails and `revert_pending` is called.
 		self.death_rows.truncate(self.death_rows.len() - self.pending_canonicalizations);
 		let new_max_block = self.death_rows.len() as u64 + self.pending_number;
-		self.death_index.retain(|_, block| *block < new_max_block);
+		self.death_index.retain(|_, blocks| {
+			blocks.retain(|block| *block < new_max_block);
+			!blocks.is_empty()
+		});
 		self.pending_canonicalizations = 0;
 		self.pending_prunings = 0;
 	}
@@ -240,7 +323,7 @@ mod tests {
 		let mut pruning: RefWindow<H256, H256> = RefWindow::new(&db).unwrap();
 		let mut commit = make_commit(&[4, 5], &[1, 3]);
 		let h = H256::random();
-		pruning.note_canonical(&h, &mut commit);
+		pruning.note_canonical(&h, &mut commit, 0);
 		db.commit(&commit);
 		assert!(pruning.have_block(&h));
 		pruning.apply_pending();
@@ -268,10 +351,10 @@ mod tests {
 		let mut db = make_db(&[1, 2, 3]);
 		let mut pruning: RefWindow<H256, H256> = RefWindow::new(&db).unwrap();
 		let mut commit = make_commit(&[4], &[1]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		let mut commit = make_commit(&[5], &[2]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		pruning.apply_pending();
 		assert!(db.data_eq(&make_db(&[1, 2, 3, 4, 5])));
@@ -296,10 +379,10 @@ mod tests {
 		let mut db = make_db(&[1, 2, 3]);
 		let mut pruning: RefWindow<H256, H256> = RefWindow::new(&db).unwrap();
 		let mut commit = make_commit(&[4], &[1]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		let mut commit = make_commit(&[5], &[2]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		assert!(db.data_eq(&make_db(&[1, 2, 3, 4, 5])));
 		let mut commit = CommitSet::default();
@@ -319,13 +402,13 @@ mod tests {
 		let mut db = make_db(&[1, 2, 3]);
 		let mut pruning: RefWindow<H256, H256> = RefWindow::new(&db).unwrap();
 		let mut commit = make_commit(&[], &[2]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		let mut commit = make_commit(&[2], &[]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		let mut commit = make_commit(&[], &[2]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		assert!(db.data_eq(&make_db(&[1, 2, 3])));
 		pruning.apply_pending();
@@ -347,18 +430,69 @@ mod tests {
 		assert_eq!(pruning.pending_number, 3);
 	}
 
+	#[test]
+	fn shared_node_survives() {
+		let mut db = make_db(&[1, 2, 3]);
+		let mut pruning: RefWindow<H256, H256> = RefWindow::new(&db).unwrap();
+		// two identical child tries are created, then removed one after the other.
+		let mut commit = make_commit(&[4, 4], &[]);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
+		db.commit(&commit);
+		let mut commit = make_commit(&[], &[4]);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
+		db.commit(&commit);
+		let mut commit = make_commit(&[], &[4]);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
+		db.commit(&commit);
+		pruning.apply_pending();
+		assert_eq!(pruning.death_index.get(&H256::from_low_u64_be(4)), Some(&vec![1, 2]));
+
+		check_journal(&pruning, &db);
+
+		let mut commit = CommitSet::default();
+		pruning.prune_one(&mut commit);
+		pruning.prune_one(&mut commit);
+		db.commit(&commit);
+		pruning.apply_pending();
+		assert!(db.data_eq(&make_db(&[1, 2, 3, 4])));
+		let mut commit = CommitSet::default();
+		pruning.prune_one(&mut commit);
+		db.commit(&commit);
+		pruning.apply_pending();
+		assert!(db.data_eq(&make_db(&[1, 2, 3])));
+		assert!(pruning.death_index.is_empty());
+	}
+
+	#[test]
+	fn reinsertion_cancels_one_deletion() {
+		let mut db = make_db(&[1, 2, 3]);
+		let mut pruning: RefWindow<H256, H256> = RefWindow::new(&db).unwrap();
+		let mut commit = make_commit(&[], &[2, 2]);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
+		db.commit(&commit);
+		let mut commit = make_commit(&[2], &[]);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
+		// the node keeps the reference which was about to be removed.
+		assert!(commit.data.inserted.is_empty());
+		db.commit(&commit);
+		pruning.apply_pending();
+		assert_eq!(pruning.death_rows[0].deleted.get(&H256::from_low_u64_be(2)), Some(&1));
+
+		check_journal(&pruning, &db);
+	}
+
 	#[test]
 	fn reinserted_survivew_pending() {
 		let mut db = make_db(&[1, 2, 3]);
 		let mut pruning: RefWindow<H256, H256> = RefWindow::new(&db).unwrap();
 		let mut commit = make_commit(&[], &[2]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		let mut commit = make_commit(&[2], &[]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		let mut commit = make_commit(&[], &[2]);
-		pruning.note_canonical(&H256::random(), &mut commit);
+		pruning.note_canonical(&H256::random(), &mut commit, 0);
 		db.commit(&commit);
 		assert!(db.data_eq(&make_db(&[1, 2, 3])));
 
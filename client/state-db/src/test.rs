@@ -24,6 +24,8 @@ use crate::{DBValue, ChangeSet, CommitSet, MetaDb, NodeDb};
 pub struct TestDb {
 	pub data: HashMap<H256, DBValue>,
 	pub meta: HashMap<Vec<u8>, DBValue>,
+	/// Number of references of the nodes referenced more than once.
+	pub refs: HashMap<H256, u32>,
 }
 
 impl MetaDb for TestDb {
@@ -45,10 +47,24 @@ impl NodeDb for TestDb {
 
 impl TestDb {
 	pub fn commit(&mut self, commit: &CommitSet<H256>) {
-		self.data.extend(commit.data.inserted.iter().cloned());
+		for (k, v) in commit.data.inserted.iter() {
+			if self.data.contains_key(k) {
+				*self.refs.entry(*k).or_insert(1) += 1;
+			} else {
+				self.data.insert(*k, v.clone());
+			}
+		}
 		self.meta.extend(commit.meta.inserted.iter().cloned());
 		for k in commit.data.deleted.iter() {
-			self.data.remove(k);
+			match self.refs.get_mut(k) {
+				Some(refs) if *refs > 2 => *refs -= 1,
+				Some(_) => {
+					self.refs.remove(k);
+				},
+				None => {
+					self.data.remove(k);
+				},
+			}
 		}
 		self.meta.extend(commit.meta.inserted.iter().cloned());
 		for k in commit.meta.deleted.iter() {
@@ -89,6 +105,7 @@ pub fn make_db(inserted: &[u64]) -> TestDb {
 			})
 			.collect(),
 		meta: Default::default(),
+		refs: Default::default(),
 	}
 }
 
@@ -0,0 +1,32 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime Api to provide the allow-list of a private network.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use rstd::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+	/// The allowed peers api.
+	///
+	/// This api is used by nodes of a private network to retrieve the identities of the
+	/// peers that are allowed to connect to the network.
+	pub trait AllowedPeersApi {
+		/// Retrieve the allowed peers, as the bytes representation of their `PeerId`.
+		fn allowed_peers() -> Vec<Vec<u8>>;
+	}
+}
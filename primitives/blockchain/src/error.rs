@@ -90,6 +90,9 @@ pub enum Error {
 	/// Invalid remote CHT-based proof.
 	#[display(fmt = "Remote node has responded with invalid header proof")]
 	InvalidCHTProof,
+	/// Invalid remote state proof.
+	#[display(fmt = "Remote node has responded with invalid state proof")]
+	InvalidStateProof,
 	/// Remote fetch has been cancelled.
 	#[display(fmt = "Remote data fetch has been cancelled")]
 	RemoteFetchCancelled,
@@ -123,6 +126,10 @@ pub enum Error {
 	/// Invalid calculated state root on block import.
 	#[display(fmt = "Calculated state root does not match.")]
 	InvalidStateRoot,
+	/// A new runtime failed the checks of its dry run.
+	#[display(fmt = "Runtime upgrade rejected: {}", _0)]
+	#[from(ignore)]
+	RuntimeUpgradeRejected(String),
 	/// A convenience variant for String
 	#[display(fmt = "{}", _0)]
 	Msg(String),
@@ -79,6 +79,27 @@ externalities::decl_extension! {
 	pub struct KeystoreExt(BareCryptoStorePtr);
 }
 
+externalities::decl_extension! {
+	/// The maximum number of instructions a runtime call may execute, to register/retrieve from the
+	/// externalities. The fuel is only metered in Wasm, so the limited calls are always executed
+	/// in Wasm.
+	pub struct FuelLimitExt(u64);
+}
+
+externalities::decl_extension! {
+	/// The file the profile of the Wasm execution of a runtime call is appended to, in the
+	/// collapsed stacks format, to register/retrieve from the externalities. The profiles are only
+	/// recorded in Wasm, so the profiled calls are always executed in Wasm.
+	pub struct WasmProfileExt(std::path::PathBuf);
+}
+
+externalities::decl_extension! {
+	/// Marks a runtime call whose result is not part of the consensus, made by an offchain worker
+	/// or through the `state_call` RPC, to register/retrieve from the externalities. Only these calls
+	/// may use an allocation strategy that deviates from the other nodes.
+	pub struct OffchainCallExt(());
+}
+
 /// Code execution engine.
 pub trait CodeExecutor: Sized + Send + Sync {
 	/// Externalities error type.
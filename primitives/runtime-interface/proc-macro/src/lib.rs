@@ -77,14 +77,15 @@ mod utils;
 /// // Be aware that this module is not `public`, the visibility of the module is determined based
 /// // on the visibility of the trait declaration.
 /// mod interface {
+///     // Every trait method is renamed to `FUNCTION_NAME_version_VERSION`.
 ///     trait Interface {
-///         fn call_some_complex_code(data: &[u8]) -> Vec<u8>;
-///         fn set_or_clear(&mut self, optional: Option<Vec<u8>>);
+///         fn call_some_complex_code_version_1(data: &[u8]) -> Vec<u8>;
+///         fn set_or_clear_version_1(&mut self, optional: Option<Vec<u8>>);
 ///     }
 ///
 ///     impl Interface for &mut dyn externalities::Externalities {
-///         fn call_some_complex_code(data: &[u8]) -> Vec<u8> { Vec::new() }
-///         fn set_or_clear(&mut self, optional: Option<Vec<u8>>) {
+///         fn call_some_complex_code_version_1(data: &[u8]) -> Vec<u8> { Vec::new() }
+///         fn set_or_clear_version_1(&mut self, optional: Option<Vec<u8>>) {
 ///             match optional {
 ///                 Some(value) => self.set_storage([1, 2, 3, 4].to_vec(), value),
 ///                 None => self.clear_storage(&[1, 2, 3, 4]),
@@ -92,12 +93,21 @@ mod utils;
 ///         }
 ///     }
 ///
+///     // The bare functions call the latest version of the function.
 ///     pub fn call_some_complex_code(data: &[u8]) -> Vec<u8> {
-///         <&mut dyn externalities::Externalities as Interface>::call_some_complex_code(data)
+///         call_some_complex_code_version_1(data)
 ///     }
 ///
 ///     pub fn set_or_clear(optional: Option<Vec<u8>>) {
-///         externalities::with_externalities(|mut ext| Interface::set_or_clear(&mut ext, optional))
+///         set_or_clear_version_1(optional)
+///     }
+///
+///     fn call_some_complex_code_version_1(data: &[u8]) -> Vec<u8> {
+///         <&mut dyn externalities::Externalities as Interface>::call_some_complex_code_version_1(data)
+///     }
+///
+///     fn set_or_clear_version_1(optional: Option<Vec<u8>>) {
+///         externalities::with_externalities(|mut ext| Interface::set_or_clear_version_1(&mut ext, optional))
 ///             .expect("`set_or_clear` called outside of an Externalities-provided environment.")
 ///     }
 ///
@@ -106,7 +116,7 @@ mod utils;
 ///     /// arguments from wasm to native and calls the corresponding native function.
 ///     ///
 ///     /// This type needs to be passed to the wasm executor, so that the host functions will be
-///     /// registered in the executor.
+///     /// registered in the executor. Every version of every function is registered.
 ///     pub struct HostFunctions;
 /// }
 /// ```
@@ -166,6 +176,36 @@ mod utils;
 /// representation. On the host each argument is converted back to the native representation and
 /// the native implementation is called. Any return value is handled in the same way.
 ///
+/// # Versions
+///
+/// A function can be changed in an incompatible way, e.g. by changing its signature, without
+/// breaking the runtimes that call its previous version. The new version is declared as a new
+/// method with the same name and the `#[version(VERSION)]` attribute. A method without this
+/// attribute declares the version `1`, and the versions of a function need to be contiguous.
+///
+/// ```
+/// # use runtime_interface::runtime_interface;
+///
+/// #[runtime_interface]
+/// trait Interface {
+///     fn call_some_complex_code(data: &[u8]) -> Vec<u8> {
+///         Vec::new()
+///     }
+///
+///     #[version(2)]
+///     fn call_some_complex_code(data: &[u8], length: u32) -> Vec<u8> {
+///         vec![0; length as usize]
+///     }
+/// }
+/// ```
+///
+/// Every version is exported as a host function of its own, e.g.
+/// `ext_interface_call_some_complex_code_version_1` and
+/// `ext_interface_call_some_complex_code_version_2`, and `HostFunctions` provides the
+/// implementations of all versions. The generated `call_some_complex_code` bare function calls the
+/// latest version, both on native and in wasm, so newly compiled runtimes only import the latest
+/// version of every function.
+///
 /// # Wasm only interfaces
 ///
 /// Some interfaces are only required from within the wasm runtime e.g. the allocator interface.
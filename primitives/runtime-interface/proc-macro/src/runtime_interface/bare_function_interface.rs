@@ -22,19 +22,25 @@
 //! exported into wasm and convert back the result.
 //!
 //! [`generate`](bare_function_interface::generate) is the entry point for generating for each
-//! trait method one bare function.
+//! function of the interface one bare function.
 //!
 //! [`function_for_method`](bare_function_interface::function_for_method) generates the bare
-//! function per trait method. Each bare function contains both implementations. The implementations
-//! are feature-gated, so that one is compiled for the native and the other for the wasm side.
+//! function per function of the interface, for its latest version. Each bare function contains
+//! both implementations. The implementations are feature-gated, so that one is compiled for the
+//! native and the other for the wasm side. On the native side, the bare function calls the native
+//! implementation of the latest version, generated by
+//! [`function_std_impl`](bare_function_interface::function_std_impl) for every version of the
+//! function.
 
 use crate::utils::{
 	generate_crate_access, create_exchangeable_host_function_ident, get_function_arguments,
-	get_function_argument_names, get_trait_methods,
+	get_function_argument_names, get_runtime_interface, create_function_ident_with_version,
+	is_version_attribute,
 };
 
 use syn::{
-	Ident, ItemTrait, TraitItemMethod, FnArg, Signature, Result, spanned::Spanned, parse_quote,
+	Ident, ItemTrait, TraitItemMethod, FnArg, Signature, Result, Error, spanned::Spanned,
+	parse_quote,
 };
 
 use proc_macro2::{TokenStream, Span};
@@ -43,23 +49,37 @@ use quote::{quote, quote_spanned};
 
 use std::iter;
 
-/// Generate one bare function per trait method. The name of the bare function is equal to the name
-/// of the trait method.
+/// Generate one bare function per function of the interface. The name of the bare function is equal
+/// to the name of the trait method. Additionally, the native implementation of every version is
+/// generated.
 pub fn generate(trait_def: &ItemTrait, is_wasm_only: bool) -> Result<TokenStream> {
 	let trait_name = &trait_def.ident;
-	get_trait_methods(trait_def).try_fold(TokenStream::new(), |mut t, m| {
-		t.extend(function_for_method(trait_name, m, is_wasm_only)?);
+	let runtime_interface = get_runtime_interface(trait_def)?;
+
+	let bare_functions = runtime_interface.latest_versions()
+		.try_fold(TokenStream::new(), |mut t, (latest_version, m)| {
+			t.extend(function_for_method(m, latest_version, is_wasm_only)?);
+			Ok::<_, Error>(t)
+		})?;
+
+	runtime_interface.all_versions().try_fold(bare_functions, |mut t, (version, m)| {
+		t.extend(function_std_impl(trait_name, m, version, is_wasm_only)?);
 		Ok(t)
 	})
 }
 
 /// Generates the bare function implementation for the given method for the host and wasm side.
 fn function_for_method(
-	trait_name: &Ident,
 	method: &TraitItemMethod,
+	latest_version: u32,
 	is_wasm_only: bool,
 ) -> Result<TokenStream> {
-	let std_impl = function_std_impl(trait_name, method, is_wasm_only)?;
+	// The native implementation is not callable when this is a wasm only interface.
+	let std_impl = if is_wasm_only {
+		quote!()
+	} else {
+		function_std_latest_impl(method, latest_version)?
+	};
 	let no_std_impl = function_no_std_impl(method)?;
 
 	Ok(
@@ -78,7 +98,7 @@ fn function_no_std_impl(method: &TraitItemMethod) -> Result<TokenStream> {
 	let args = get_function_arguments(&method.sig);
 	let arg_names = get_function_argument_names(&method.sig);
 	let return_value = &method.sig.output;
-	let attrs = &method.attrs;
+	let attrs = method.attrs.iter().filter(|a| !is_version_attribute(a));
 
 	Ok(
 		quote! {
@@ -92,13 +112,36 @@ fn function_no_std_impl(method: &TraitItemMethod) -> Result<TokenStream> {
 	)
 }
 
-/// Generates the bare function implementation for `cfg(feature = "std")`.
+/// Generates the bare function implementation for `cfg(feature = "std")` that calls the native
+/// implementation of the latest version.
+fn function_std_latest_impl(method: &TraitItemMethod, latest_version: u32) -> Result<TokenStream> {
+	let function_name = &method.sig.ident;
+	let args = get_function_arguments(&method.sig);
+	let arg_names = get_function_argument_names(&method.sig);
+	let return_value = &method.sig.output;
+	let attrs = method.attrs.iter().filter(|a| !is_version_attribute(a));
+	let latest_function_name = create_function_ident_with_version(function_name, latest_version);
+
+	Ok(
+		quote_spanned! { method.span() =>
+			#[cfg(feature = "std")]
+			#( #attrs )*
+			pub fn #function_name( #( #args, )* ) #return_value {
+				#latest_function_name( #( #arg_names, )* )
+			}
+		}
+	)
+}
+
+/// Generates the native implementation of the given version of the method for
+/// `cfg(feature = "std")`.
 fn function_std_impl(
 	trait_name: &Ident,
 	method: &TraitItemMethod,
+	version: u32,
 	is_wasm_only: bool,
 ) -> Result<TokenStream> {
-	let function_name = &method.sig.ident;
+	let function_name = create_function_ident_with_version(&method.sig.ident, version);
 	let crate_ = generate_crate_access();
 	let args = get_function_arguments(&method.sig).map(FnArg::Typed).chain(
 		// Add the function context as last parameter when this is a wasm only interface.
@@ -115,16 +158,14 @@ fn function_std_impl(
 		).take(1),
 	);
 	let return_value = &method.sig.output;
-	let attrs = &method.attrs;
-	// Don't make the function public accessible when this is a wasm only interface.
-	let vis = if is_wasm_only { quote!() } else { quote!(pub) };
-	let call_to_trait = generate_call_to_trait(trait_name, method, is_wasm_only);
+	let attrs = method.attrs.iter().filter(|a| !is_version_attribute(a));
+	let call_to_trait = generate_call_to_trait(trait_name, method, version, is_wasm_only);
 
 	Ok(
 		quote_spanned! { method.span() =>
 			#[cfg(feature = "std")]
 			#( #attrs )*
-			#vis fn #function_name( #( #args, )* ) #return_value {
+			fn #function_name( #( #args, )* ) #return_value {
 				#call_to_trait
 			}
 		}
@@ -135,13 +176,14 @@ fn function_std_impl(
 fn generate_call_to_trait(
 	trait_name: &Ident,
 	method: &TraitItemMethod,
+	version: u32,
 	is_wasm_only: bool,
 ) -> TokenStream {
 	let crate_ = generate_crate_access();
-	let method_name = &method.sig.ident;
+	let method_name = create_function_ident_with_version(&method.sig.ident, version);
 	let expect_msg = format!(
 		"`{}` called outside of an Externalities-provided environment.",
-		method_name,
+		method.sig.ident,
 	);
 	let arg_names = get_function_argument_names(&method.sig);
 
@@ -16,11 +16,17 @@
 
 //! Checks the trait declaration, makes the trait declaration module local, removes all method
 //! default implementations and implements the trait for `&mut dyn Externalities`.
+//!
+//! Every version of a function is declared by its own trait method, so the trait methods are
+//! renamed to `FUNCTION_NAME_version_VERSION`.
 
-use crate::utils::{generate_crate_access, get_function_argument_types_without_ref};
+use crate::utils::{
+	generate_crate_access, get_function_argument_types_without_ref, get_trait_methods,
+	get_method_version, create_function_ident_with_version, is_version_attribute,
+};
 
 use syn::{
-	ItemTrait, TraitItemMethod, Result, TraitItem, Error, fold::{self, Fold}, spanned::Spanned,
+	ItemTrait, TraitItemMethod, Result, Error, fold::{self, Fold}, spanned::Spanned,
 	Visibility, Receiver, Type, Generics,
 };
 
@@ -83,7 +89,15 @@ impl ToEssentialTraitDef {
 }
 
 impl Fold for ToEssentialTraitDef {
-	fn fold_trait_item_method(&mut self, mut method: TraitItemMethod) -> TraitItemMethod {
+	fn fold_trait_item_method(&mut self, method: TraitItemMethod) -> TraitItemMethod {
+		let mut method = match to_versioned_method(method.clone()) {
+			Ok(method) => method,
+			Err(e) => {
+				self.errors.push(e);
+				method
+			}
+		};
+
 		if method.default.take().is_none() {
 			self.push_error(&method, "Methods need to have an implementation.");
 		}
@@ -121,13 +135,9 @@ impl Fold for ToEssentialTraitDef {
 fn impl_trait_for_externalities(trait_def: &ItemTrait, is_wasm_only: bool) -> Result<TokenStream> {
 	let trait_ = &trait_def.ident;
 	let crate_ = generate_crate_access();
-	let methods = trait_def
-		.items
-		.iter()
-		.filter_map(|i| match i {
-			TraitItem::Method(ref method) => Some(method),
-			_ => None,
-		});
+	let methods = get_trait_methods(trait_def)
+		.map(|m| to_versioned_method(m.clone()))
+		.collect::<Result<Vec<_>>>()?;
 
 	let impl_type = if is_wasm_only {
 		quote!( &mut dyn #crate_::wasm_interface::FunctionContext )
@@ -144,3 +154,13 @@ fn impl_trait_for_externalities(trait_def: &ItemTrait, is_wasm_only: bool) -> Re
 		}
 	)
 }
+
+/// Renames the given trait method to the name of its version and removes its `version` attribute.
+fn to_versioned_method(mut method: TraitItemMethod) -> Result<TraitItemMethod> {
+	let version = get_method_version(&method)?;
+
+	method.sig.ident = create_function_ident_with_version(&method.sig.ident, version);
+	method.attrs.retain(|a| !is_version_attribute(a));
+
+	Ok(method)
+}
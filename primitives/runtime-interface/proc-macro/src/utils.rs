@@ -20,17 +20,56 @@ use proc_macro2::{TokenStream, Span};
 
 use syn::{
 	Ident, Error, Signature, Pat, PatType, FnArg, Type, token, TraitItemMethod, ItemTrait,
-	TraitItem, parse_quote, spanned::Spanned,
+	TraitItem, parse_quote, spanned::Spanned, Result, Meta, NestedMeta, Lit, Attribute,
 };
 
 use proc_macro_crate::crate_name;
 
-use std::env;
+use std::{env, collections::BTreeMap};
 
 use quote::quote;
 
 use inflector::Inflector;
 
+/// Name of the attribute that sets the version of a runtime interface function.
+const VERSION_ATTRIBUTE: &str = "version";
+
+/// A runtime interface function with all its versions.
+pub struct RuntimeInterfaceFunction<'a> {
+	/// The versions of the function, each declared by its own trait method.
+	versions: BTreeMap<u32, &'a TraitItemMethod>,
+}
+
+impl<'a> RuntimeInterfaceFunction<'a> {
+	/// Returns the latest version of the function and the trait method declaring it.
+	pub fn latest_version(&self) -> (u32, &'a TraitItemMethod) {
+		let (version, method) = self.versions
+			.iter()
+			.next_back()
+			.expect("A function is only created with at least one version; qed");
+
+		(*version, *method)
+	}
+}
+
+/// A runtime interface with all the versions of its functions.
+pub struct RuntimeInterface<'a> {
+	/// The functions of the interface, by their name.
+	functions: BTreeMap<Ident, RuntimeInterfaceFunction<'a>>,
+}
+
+impl<'a> RuntimeInterface<'a> {
+	/// Returns an iterator over the latest version of every function.
+	pub fn latest_versions(&self) -> impl Iterator<Item = (u32, &'a TraitItemMethod)> + '_ {
+		self.functions.values().map(|f| f.latest_version())
+	}
+
+	/// Returns an iterator over all the versions of every function.
+	pub fn all_versions(&self) -> impl Iterator<Item = (u32, &'a TraitItemMethod)> + '_ {
+		self.functions.values().flat_map(|f| f.versions.iter().map(|(v, m)| (*v, *m)))
+	}
+}
+
 /// Generates the include for the runtime-interface crate.
 pub fn generate_runtime_interface_include() -> TokenStream {
 	if env::var("CARGO_PKG_NAME").unwrap() == "sp-runtime-interface" {
@@ -66,18 +105,24 @@ pub fn create_exchangeable_host_function_ident(name: &Ident) -> Ident {
 	Ident::new(&format!("host_{}", name), Span::call_site())
 }
 
-/// Create the host function identifier for the given function name.
-pub fn create_host_function_ident(name: &Ident, trait_name: &Ident) -> Ident {
+/// Create the host function identifier for the given function name and version.
+pub fn create_host_function_ident(name: &Ident, version: u32, trait_name: &Ident) -> Ident {
 	Ident::new(
 		&format!(
-			"ext_{}_{}_version_1",
+			"ext_{}_{}_version_{}",
 			trait_name.to_string().to_snake_case(),
 			name,
+			version,
 		),
 		Span::call_site(),
 	)
 }
 
+/// Create the identifier of the given version of the function with the given name.
+pub fn create_function_ident_with_version(name: &Ident, version: u32) -> Ident {
+	Ident::new(&format!("{}_version_{}", name, version), name.span())
+}
+
 /// Returns the function arguments of the given `Signature`, minus any `self` arguments.
 pub fn get_function_arguments<'a>(sig: &'a Signature) -> impl Iterator<Item = PatType> + 'a {
 	sig.inputs
@@ -160,3 +205,91 @@ pub fn get_trait_methods<'a>(trait_def: &'a ItemTrait) -> impl Iterator<Item = &
 			_ => None,
 		})
 }
+
+/// Returns if the given `Attribute` is the `version` attribute.
+pub fn is_version_attribute(attr: &Attribute) -> bool {
+	attr.path.is_ident(VERSION_ATTRIBUTE)
+}
+
+/// Parse the given attribute as `VERSION_ATTRIBUTE`.
+fn parse_version_attribute(version: &Attribute) -> Result<u32> {
+	let meta = version.parse_meta()?;
+
+	let err = Err(Error::new(
+			meta.span(),
+			&format!(
+				"Unexpected `{version}` attribute. The supported format is `{version}(1)`",
+				version = VERSION_ATTRIBUTE,
+			)
+		)
+	);
+
+	match meta {
+		Meta::List(ref list) => {
+			if list.nested.len() != 1 {
+				err
+			} else if let Some(NestedMeta::Lit(Lit::Int(i))) = list.nested.first() {
+				match i.base10_parse()? {
+					0 => Err(Error::new(i.span(), "Versions start at `1`.")),
+					version => Ok(version),
+				}
+			} else {
+				err
+			}
+		},
+		_ => err,
+	}
+}
+
+/// Returns the version of the given trait method, which is `1` if it has no `version` attribute.
+pub fn get_method_version(method: &TraitItemMethod) -> Result<u32> {
+	let mut versions = method.attrs.iter().filter(|a| is_version_attribute(a));
+
+	match (versions.next(), versions.next()) {
+		(None, _) => Ok(1),
+		(Some(version), None) => parse_version_attribute(version),
+		(Some(_), Some(duplicate)) => Err(
+			Error::new(duplicate.span(), "Duplicated `version` attribute.")
+		),
+	}
+}
+
+/// Collects the functions of the given runtime interface trait definition with all their versions.
+///
+/// Every version of a function is declared by its own trait method. The versions of a function need
+/// to be unique and contiguous, starting at `1`.
+pub fn get_runtime_interface<'a>(trait_def: &'a ItemTrait) -> Result<RuntimeInterface<'a>> {
+	let mut functions = BTreeMap::<_, RuntimeInterfaceFunction>::new();
+
+	for method in get_trait_methods(trait_def) {
+		let version = get_method_version(method)?;
+
+		let function = functions
+			.entry(method.sig.ident.clone())
+			.or_insert_with(|| RuntimeInterfaceFunction { versions: BTreeMap::new() });
+
+		if function.versions.insert(version, method).is_some() {
+			return Err(
+				Error::new(
+					method.span(),
+					format!("Duplicated version `{}` of this function.", version),
+				)
+			)
+		}
+	}
+
+	for function in functions.values() {
+		let (latest_version, method) = function.latest_version();
+
+		if let Some(missing) = (1..latest_version).find(|v| !function.versions.contains_key(v)) {
+			return Err(
+				Error::new(
+					method.span(),
+					format!("Version `{}` of this function is missing.", missing),
+				)
+			)
+		}
+	}
+
+	Ok(RuntimeInterface { functions })
+}
@@ -0,0 +1,11 @@
+use runtime_interface::runtime_interface;
+
+#[runtime_interface]
+trait Test {
+	fn test() {}
+
+	#[version(1)]
+	fn test() {}
+}
+
+fn main() {}
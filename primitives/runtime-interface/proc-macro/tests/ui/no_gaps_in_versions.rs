@@ -0,0 +1,11 @@
+use runtime_interface::runtime_interface;
+
+#[runtime_interface]
+trait Test {
+	fn test() {}
+
+	#[version(3)]
+	fn test() {}
+}
+
+fn main() {}
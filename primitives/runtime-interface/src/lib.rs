@@ -209,4 +209,20 @@ mod tests {
 	fn test_overwrite_native_function_implementation() {
 		call_wasm_method::<HostFunctions>("test_overwrite_native_function_implementation");
 	}
+
+	#[test]
+	fn test_versioning_calls_the_latest_version() {
+		call_wasm_method::<HostFunctions>("test_versioning_calls_the_latest_version");
+	}
+
+	#[test]
+	fn host_functions_provide_all_versions() {
+		let names = HostFunctions::host_functions()
+			.iter()
+			.map(|f| f.name().to_string())
+			.collect::<Vec<_>>();
+
+		assert!(names.contains(&"ext_test_api_test_versioning_version_1".to_string()));
+		assert!(names.contains(&"ext_test_api_test_versioning_version_2".to_string()));
+	}
 }
@@ -82,6 +82,17 @@ pub trait TestApi {
 	fn overwrite_native_function_implementation() -> bool {
 		false
 	}
+
+	/// Checks the given `data`, the first version of the function accepts `42` and `50`.
+	fn test_versioning(data: u32) -> bool {
+		data == 42 || data == 50
+	}
+
+	/// Checks the given `data`, the second version of the function only accepts `42`.
+	#[version(2)]
+	fn test_versioning(data: u32) -> bool {
+		data == 42
+	}
 }
 
 /// Two random external functions from the old runtime interface.
@@ -191,4 +202,10 @@ wasm_export_functions! {
 
 		assert!(test_api::overwrite_native_function_implementation());
 	}
+
+	fn test_versioning_calls_the_latest_version() {
+		assert!(test_api::test_versioning(42));
+		// Only the first version accepts `50`.
+		assert!(!test_api::test_versioning(50));
+	}
 }
@@ -0,0 +1,225 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reports of the divergences between the native and the Wasm executions of a call.
+//!
+//! When a call is executed with `ExecutionStrategy::Both` and a `DivergenceReportExt` is
+//! registered, both the return values and the storage changes of the two executions are compared.
+//! On any difference, a report is written to the directory of the extension.
+
+use std::{
+	collections::{BTreeSet, HashMap}, fmt::{self, Write as _}, fs, io, path::PathBuf,
+	time::{SystemTime, UNIX_EPOCH},
+};
+use codec::{Decode, Encode};
+use primitives::{hexdisplay::HexDisplay, storage::well_known_keys::EXTRINSIC_INDEX};
+use crate::{CallResult, overlayed_changes::{OverlayedChangeSet, OverlayedValue}};
+
+externalities::decl_extension! {
+	/// The extension enabling the divergence reports, to register in the state machine.
+	pub struct DivergenceReportExt(DivergenceReporter);
+}
+
+/// Writes the reports of the divergences between the native and the Wasm executions.
+pub struct DivergenceReporter {
+	/// The directory the reports are written to.
+	directory: PathBuf,
+	/// Description of the block the calls are executed at.
+	block: String,
+}
+
+impl DivergenceReporter {
+	/// Create a reporter writing the reports of the calls executed at `block` to `directory`.
+	pub fn new(directory: PathBuf, block: String) -> Self {
+		DivergenceReporter { directory, block }
+	}
+
+	/// Write the report of the executions of `method` if they diverged, and return its path.
+	pub(crate) fn report_if_diverged<R: Encode + PartialEq, E: fmt::Debug>(
+		&self,
+		method: &str,
+		native: (&CallResult<R, E>, &OverlayedChangeSet),
+		wasm: (&CallResult<R, E>, &OverlayedChangeSet),
+	) -> io::Result<Option<PathBuf>> {
+		let results_equal = match (native.0, wasm.0) {
+			(Ok(native), Ok(wasm)) => native == wasm,
+			(Err(_), Err(_)) => true,
+			_ => false,
+		};
+		let top_diff = diff(&native.1.top, &wasm.1.top);
+		let children_keys = native.1.children.keys()
+			.chain(wasm.1.children.keys())
+			.collect::<BTreeSet<_>>();
+		let empty = HashMap::new();
+		let children_diff = children_keys.into_iter()
+			.map(|storage_key| (storage_key, diff(
+				native.1.children.get(storage_key).unwrap_or(&empty),
+				wasm.1.children.get(storage_key).unwrap_or(&empty),
+			)))
+			.filter(|(_, diff)| !diff.is_empty())
+			.collect::<Vec<_>>();
+
+		if results_equal && top_diff.is_empty() && children_diff.is_empty() {
+			return Ok(None);
+		}
+
+		let mut report = String::new();
+		let _ = writeln!(
+			report,
+			"Divergence between the native and Wasm executions of `{}` at {}.\n",
+			method,
+			self.block,
+		);
+		let _ = writeln!(report, "Native result: {:?}", native.0);
+		let _ = writeln!(report, "Wasm result: {:?}\n", wasm.0);
+		let _ = writeln!(
+			report,
+			"Extrinsic index: native {}, wasm {}",
+			extrinsic_index(native.1),
+			extrinsic_index(wasm.1),
+		);
+		write_diff(&mut report, "Storage changes", &top_diff);
+		for (storage_key, diff) in children_diff {
+			write_diff(
+				&mut report,
+				&format!("Child storage {} changes", HexDisplay::from(storage_key)),
+				&diff,
+			);
+		}
+
+		fs::create_dir_all(&self.directory)?;
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_nanos())
+			.unwrap_or_default();
+		let path = self.directory.join(format!("divergence-{}-{}.txt", method, timestamp));
+		fs::write(&path, report)?;
+		Ok(Some(path))
+	}
+}
+
+/// A key changed differently by the two executions, with the native and Wasm changes.
+type KeyDiff<'a> = (&'a [u8], Option<&'a OverlayedValue>, Option<&'a OverlayedValue>);
+
+/// The keys changed differently by the two executions, sorted.
+fn diff<'a>(
+	native: &'a HashMap<Vec<u8>, OverlayedValue>,
+	wasm: &'a HashMap<Vec<u8>, OverlayedValue>,
+) -> Vec<KeyDiff<'a>> {
+	native.keys()
+		.chain(wasm.keys())
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.filter_map(|key| {
+			let native = native.get(key);
+			let wasm = wasm.get(key);
+			if native.map(|v| &v.value) == wasm.map(|v| &v.value) {
+				None
+			} else {
+				Some((&key[..], native, wasm))
+			}
+		})
+		.collect()
+}
+
+fn write_diff(report: &mut String, title: &str, diff: &[KeyDiff]) {
+	if diff.is_empty() {
+		return;
+	}
+
+	let _ = writeln!(report, "\n{}:", title);
+	for (key, native, wasm) in diff {
+		let _ = writeln!(report, "{}", HexDisplay::from(key));
+		let _ = writeln!(report, "\tnative: {}", DisplayChange(*native));
+		let _ = writeln!(report, "\twasm: {}", DisplayChange(*wasm));
+	}
+}
+
+/// The index of the extrinsic being executed, according to the changes.
+fn extrinsic_index(changes: &OverlayedChangeSet) -> String {
+	changes.top.get(EXTRINSIC_INDEX)
+		.and_then(|value| value.value.as_ref())
+		.and_then(|value| u32::decode(&mut &value[..]).ok())
+		.map(|index| index.to_string())
+		.unwrap_or_else(|| "none".into())
+}
+
+/// Displays the change of a key, along with the extrinsics which made it if known.
+struct DisplayChange<'a>(Option<&'a OverlayedValue>);
+
+impl<'a> fmt::Display for DisplayChange<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.0 {
+			None => write!(f, "unchanged"),
+			Some(change) => {
+				match change.value {
+					Some(ref value) => write!(f, "{}", HexDisplay::from(value))?,
+					None => write!(f, "deleted")?,
+				}
+				match change.extrinsics {
+					Some(ref extrinsics) => write!(f, " (by extrinsics {:?})", extrinsics),
+					None => Ok(()),
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use primitives::NativeOrEncoded;
+	use crate::NeverNativeValue;
+
+	fn changes(values: &[(&str, Option<&str>)]) -> OverlayedChangeSet {
+		OverlayedChangeSet {
+			top: values.iter()
+				.map(|(key, value)| (key.as_bytes().to_vec(), OverlayedValue {
+					value: value.map(|value| value.as_bytes().to_vec()),
+					extrinsics: None,
+				}))
+				.collect(),
+			children: Default::default(),
+		}
+	}
+
+	#[test]
+	fn reports_only_diverging_executions() {
+		let directory = tempfile::tempdir().unwrap();
+		let reporter = DivergenceReporter::new(directory.path().to_path_buf(), "block #1".into());
+		let result: CallResult<NeverNativeValue, u8> = Ok(NativeOrEncoded::Encoded(vec![42]));
+		let native_changes = changes(&[("same", Some("1")), ("diverging", Some("2"))]);
+
+		let report = reporter.report_if_diverged(
+			"test",
+			(&result, &native_changes),
+			(&result, &native_changes),
+		).unwrap();
+		assert_eq!(report, None);
+
+		let wasm_changes = changes(&[("same", Some("1")), ("diverging", None)]);
+		let path = reporter.report_if_diverged(
+			"test",
+			(&result, &native_changes),
+			(&result, &wasm_changes),
+		).unwrap().unwrap();
+		let report = fs::read_to_string(path).unwrap();
+		assert!(report.contains("at block #1"));
+		assert!(report.contains(&HexDisplay::from(&b"diverging".to_vec()).to_string()));
+		assert!(!report.contains(&HexDisplay::from(&b"same".to_vec()).to_string()));
+		assert!(report.contains("wasm: deleted"));
+	}
+}
@@ -18,7 +18,7 @@
 
 #![warn(missing_docs)]
 
-use std::{fmt, result, collections::HashMap, panic::UnwindSafe, marker::PhantomData};
+use std::{fmt, result, any::TypeId, collections::HashMap, panic::UnwindSafe, marker::PhantomData};
 use log::{warn, trace};
 use hash_db::Hasher;
 use codec::{Decode, Encode, Codec};
@@ -31,6 +31,7 @@ use externalities::Extensions;
 
 pub mod backend;
 mod changes_trie;
+mod divergence;
 mod error;
 mod ext;
 mod testing;
@@ -65,6 +66,7 @@ pub use proving_backend::{
 pub use trie_backend_essence::{TrieBackendStorage, Storage};
 pub use trie_backend::TrieBackend;
 pub use error::{Error, ExecutionError};
+pub use divergence::{DivergenceReportExt, DivergenceReporter};
 
 type CallResult<R, E> = Result<NativeOrEncoded<R>, E>;
 
@@ -310,6 +312,15 @@ impl<'a, B, H, N, T, Exec> StateMachine<'a, B, H, N, T, Exec> where
 		);
 
 		if was_native {
+			let reporting_divergence = self.extensions
+				.get_mut(TypeId::of::<DivergenceReportExt>())
+				.is_some();
+			let native_prospective = if reporting_divergence {
+				Some(self.overlay.prospective.clone())
+			} else {
+				None
+			};
+
 			self.overlay.prospective = orig_prospective.clone();
 			let (wasm_result, _, wasm_storage_delta, wasm_changes_delta) = self.execute_aux(
 				compute_tx,
@@ -317,6 +328,10 @@ impl<'a, B, H, N, T, Exec> StateMachine<'a, B, H, N, T, Exec> where
 				native_call,
 			);
 
+			if let Some(native_prospective) = native_prospective {
+				self.report_divergence(&result, &native_prospective, &wasm_result);
+			}
+
 			if (result.is_ok() && wasm_result.is_ok()
 				&& result.as_ref().ok() == wasm_result.as_ref().ok())
 				|| result.is_err() && wasm_result.is_err()
@@ -330,6 +345,41 @@ impl<'a, B, H, N, T, Exec> StateMachine<'a, B, H, N, T, Exec> where
 		}
 	}
 
+	/// Write a report if the native and Wasm executions diverged, the Wasm changes being in the
+	/// overlay.
+	fn report_divergence<R: Encode + PartialEq>(
+		&mut self,
+		native_result: &CallResult<R, Exec::Error>,
+		native_prospective: &OverlayedChangeSet,
+		wasm_result: &CallResult<R, Exec::Error>,
+	) {
+		let reporter = match self.extensions
+			.get_mut(TypeId::of::<DivergenceReportExt>())
+			.and_then(|ext| ext.downcast_mut::<DivergenceReportExt>())
+		{
+			Some(reporter) => reporter,
+			None => return,
+		};
+
+		match reporter.report_if_diverged(
+			self.method,
+			(native_result, native_prospective),
+			(wasm_result, &self.overlay.prospective),
+		) {
+			Ok(Some(path)) => warn!(
+				"Native and Wasm executions of {} diverged, see {}",
+				self.method,
+				path.display(),
+			),
+			Ok(None) => (),
+			Err(e) => warn!(
+				"Native and Wasm executions of {} may have diverged, failed to write the report: {}",
+				self.method,
+				e,
+			),
+		}
+	}
+
 	fn execute_call_with_native_else_wasm_strategy<R, NC>(
 		&mut self,
 		compute_tx: bool,
@@ -619,6 +669,60 @@ where
 	Ok(proving_backend.extract_proof())
 }
 
+/// Generate a proof of a range of storage entries.
+///
+/// The entries of the trie, or of the child trie under `storage_key`, are read in order starting
+/// at `start` (inclusive), until `max_keys` entries or `max_size` bytes have been read. At least
+/// one entry is read if there is any. Returns the entries, `true` if they are the last entries of
+/// the trie, and the proof, which also covers the absence of other entries in the range.
+pub fn prove_range_read<B, H>(
+	mut backend: B,
+	storage_key: Option<&[u8]>,
+	start: &[u8],
+	max_keys: usize,
+	max_size: usize,
+) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, bool, StorageProof), Box<dyn Error>>
+where
+	B: Backend<H>,
+	H: Hasher,
+	H::Out: Ord + Codec,
+{
+	let trie_backend = backend.as_trie_backend()
+		.ok_or_else(|| Box::new(ExecutionError::UnableToGenerateProof) as Box<dyn Error>)?;
+	prove_range_read_on_trie_backend(trie_backend, storage_key, start, max_keys, max_size)
+}
+
+/// Generate a proof of a range of storage entries on pre-created trie backend.
+pub fn prove_range_read_on_trie_backend<S, H>(
+	trie_backend: &TrieBackend<S, H>,
+	storage_key: Option<&[u8]>,
+	start: &[u8],
+	max_keys: usize,
+	max_size: usize,
+) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, bool, StorageProof), Box<dyn Error>>
+where
+	S: trie_backend_essence::TrieBackendStorage<H>,
+	H: Hasher,
+	H::Out: Ord + Codec,
+{
+	let proving_backend = proving_backend::ProvingBackend::<_, H>::new(trie_backend);
+	let mut entries = Vec::new();
+	let mut size = 0;
+	let mut complete = true;
+	proving_backend.for_key_values_from(storage_key, start, |key, value| {
+		let entry_size = key.len() + value.len();
+		if !entries.is_empty() && (entries.len() >= max_keys || size + entry_size > max_size) {
+			// The next entry is visited nonetheless, so that the proof shows where the range ends.
+			complete = false;
+			return false;
+		}
+		size += entry_size;
+		entries.push((key.to_vec(), value.to_vec()));
+		true
+	}).map_err(|e| Box::new(e) as Box<dyn Error>)?;
+	Ok((entries, complete, proving_backend.extract_proof()))
+}
+
 /// Check storage read proof, generated by `prove_read` call.
 pub fn read_proof_check<H, I>(
 	root: H::Out,
@@ -666,6 +770,42 @@ where
 	Ok(result)
 }
 
+/// Check a range proof, generated by `prove_range_read` call.
+///
+/// Succeeds if `entries` are exactly the entries of the trie with the given `root` starting at
+/// `start`, and if `complete` is `true` only when there is no entry after them.
+pub fn read_range_proof_check<H>(
+	root: H::Out,
+	proof: StorageProof,
+	start: &[u8],
+	entries: &[(Vec<u8>, Vec<u8>)],
+	complete: bool,
+) -> Result<(), Box<dyn Error>>
+where
+	H: Hasher,
+	H::Out: Ord + Codec,
+{
+	let proving_backend = create_proof_check_backend::<H>(root, proof)?;
+	let mut expected = entries.iter();
+	let mut matches = true;
+	let mut has_more = false;
+	proving_backend.for_key_values_from(None, start, |key, value| match expected.next() {
+		Some((expected_key, expected_value)) => {
+			matches = expected_key.as_slice() == key && expected_value.as_slice() == value;
+			matches
+		},
+		None => {
+			has_more = true;
+			false
+		},
+	}).map_err(|e| Box::new(e) as Box<dyn Error>)?;
+
+	if !matches || expected.next().is_some() || complete == has_more {
+		return Err(Box::new(ExecutionError::InvalidProof));
+	}
+	Ok(())
+}
+
 /// Check storage read proof on pre-created proving backend.
 pub fn read_proof_check_on_proving_backend<H>(
 	proving_backend: &TrieBackend<MemoryDB<H>, H>,
@@ -1004,6 +1144,60 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn prove_range_read_and_proof_check_works() {
+		let remote_backend = trie_backend::tests::test_trie();
+		let remote_root = remote_backend.storage_root(::std::iter::empty()).0;
+		let mut expected = remote_backend.pairs();
+		expected.sort();
+
+		// read the whole trie by ranges of 10 entries
+		let mut entries = Vec::new();
+		let mut start = Vec::new();
+		loop {
+			let (range, complete, proof) = prove_range_read_on_trie_backend(
+				&remote_backend,
+				None,
+				&start,
+				10,
+				usize::max_value(),
+			).unwrap();
+			assert!(range.len() <= 10);
+			read_range_proof_check::<Blake2Hasher>(remote_root, proof.clone(), &start, &range, complete)
+				.unwrap();
+
+			// omitting an entry or ending the range early is detected
+			if range.len() > 1 {
+				let mut omitted = range.clone();
+				omitted.remove(range.len() / 2);
+				assert!(read_range_proof_check::<Blake2Hasher>(
+					remote_root, proof.clone(), &start, &omitted, complete,
+				).is_err());
+			}
+			assert!(read_range_proof_check::<Blake2Hasher>(
+				remote_root, proof, &start, &range, !complete,
+			).is_err());
+
+			start = range.last().unwrap().0.clone();
+			start.push(0);
+			entries.extend(range);
+			if complete {
+				break;
+			}
+		}
+		assert_eq!(entries, expected);
+
+		// the size limit is applied after the first entry
+		let (range, complete, _) = prove_range_read_on_trie_backend(
+			&remote_backend,
+			None,
+			&[],
+			10,
+			0,
+		).unwrap();
+		assert_eq!((range.len(), complete), (1, false));
+	}
+
 	#[test]
 	fn prove_read_and_proof_check_works() {
 		// fetch read proof from 'remote' full node
@@ -224,6 +224,18 @@ impl<'a, S: 'a + TrieBackendStorage<H>, H: 'a + Hasher> ProvingBackend<'a, S, H>
 		ProvingBackend(TrieBackend::new(recorder, root))
 	}
 
+	/// Execute given closure for the key and values of the trie, or of the child trie under
+	/// `storage_key`, in order starting at `start`, until the closure returns `false`. The
+	/// nodes visited by the iteration are recorded.
+	pub fn for_key_values_from<F: FnMut(&[u8], &[u8]) -> bool>(
+		&self,
+		storage_key: Option<&[u8]>,
+		start: &[u8],
+		f: F,
+	) -> Result<(), String> {
+		self.0.for_key_values_from(storage_key, start, f)
+	}
+
 	/// Extracting the gathered unordered proof.
 	pub fn extract_proof(&self) -> StorageProof {
 		let trie_nodes = self.0.essence().backend_storage().proof_recorder
@@ -42,6 +42,17 @@ impl<S: TrieBackendStorage<H>, H: Hasher> TrieBackend<S, H> where H::Out: Codec
 		&self.essence
 	}
 
+	/// Execute given closure for the key and values of the trie, or of the child trie under
+	/// `storage_key`, in order starting at `start`, until the closure returns `false`.
+	pub fn for_key_values_from<F: FnMut(&[u8], &[u8]) -> bool>(
+		&self,
+		storage_key: Option<&[u8]>,
+		start: &[u8],
+		f: F,
+	) -> Result<(), String> {
+		self.essence.for_key_values_from(storage_key, start, f)
+	}
+
 	/// Get backend storage reference.
 	pub fn backend_storage(&self) -> &S {
 		self.essence.backend_storage()
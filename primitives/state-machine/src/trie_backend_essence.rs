@@ -186,6 +186,57 @@ impl<S: TrieBackendStorage<H>, H: Hasher> TrieBackendEssence<S, H> where H::Out:
 		self.keys_values_with_prefix_inner(&self.root, prefix, f)
 	}
 
+	/// Execute given closure for the key and values of the trie, or of the child trie under
+	/// `storage_key`, in order starting at `start` (inclusive), until the closure returns `false`.
+	///
+	/// Unlike the other iteration methods, the errors of the trie are returned, so that the
+	/// missing nodes of a partial trie are detected.
+	pub fn for_key_values_from<F: FnMut(&[u8], &[u8]) -> bool>(
+		&self,
+		storage_key: Option<&[u8]>,
+		start: &[u8],
+		mut f: F,
+	) -> Result<(), String> {
+		let root = match storage_key {
+			None => self.root.clone(),
+			Some(storage_key) => {
+				let root_vec = self.storage(storage_key)?
+					.unwrap_or(default_child_trie_root::<Layout<H>>(storage_key).encode());
+				let mut root = H::Out::default();
+				if root_vec.len() != root.as_ref().len() {
+					return Err(format!("Invalid child trie root of {:?}", storage_key));
+				}
+				root.as_mut().copy_from_slice(&root_vec);
+				root
+			},
+		};
+
+		let mut read_overlay = S::Overlay::default();
+		let eph = Ephemeral {
+			storage: &self.storage,
+			overlay: &mut read_overlay,
+		};
+
+		let mut iter = move || -> Result<(), Box<TrieError<H::Out>>> {
+			let trie = TrieDB::<H>::new(&eph, &root)?;
+			let mut iter = trie.iter()?;
+
+			iter.seek(start)?;
+
+			for x in iter {
+				let (key, value) = x?;
+
+				if !f(&key, &value) {
+					break;
+				}
+			}
+
+			Ok(())
+		};
+
+		iter().map_err(|e| format!("Trie lookup error: {}", e))
+	}
+
 }
 
 pub(crate) struct Ephemeral<'a, S: 'a + TrieBackendStorage<H>, H: 'a + Hasher> {
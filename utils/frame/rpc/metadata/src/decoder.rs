@@ -0,0 +1,390 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage keys and values, and events decoding driven by the runtime metadata.
+
+use codec::{Compact, Decode};
+use frame_metadata::{
+	DecodeDifferent, EventMetadata, META_RESERVED, ModuleMetadata, RuntimeMetadata,
+	RuntimeMetadataPrefixed, StorageEntryModifier, StorageEntryType, StorageHasher,
+};
+use serde_json::{json, Value};
+use sp_core::{blake2_128, blake2_256, twox_128, twox_256, twox_64};
+
+use crate::{Error, types::{Type, TypeRegistry}};
+
+/// Runtime metadata along with the types needed to decode the data it describes.
+pub struct RuntimeDecoder {
+	modules: Vec<ModuleMetadata>,
+	types: TypeRegistry,
+}
+
+/// A storage query, built from the metadata of a storage entry.
+#[derive(Debug)]
+pub struct StorageQuery {
+	/// The storage key to query.
+	pub key: Vec<u8>,
+	value: Type,
+	default: Option<Vec<u8>>,
+	is_linked: bool,
+}
+
+impl StorageQuery {
+	/// Decode the value found under the storage key.
+	///
+	/// Returns `null` if there's no value and the entry doesn't have a default one.
+	pub fn decode(&self, value: Option<&[u8]>) -> Result<Value, Error> {
+		let mut input = match value.or_else(|| self.default.as_ref().map(|d| &d[..])) {
+			Some(input) => input,
+			None => return Ok(Value::Null),
+		};
+		let value = self.value.decode(&mut input)?;
+		// Values of linked maps are followed by the linkage to the neighbour keys.
+		if !self.is_linked && !input.is_empty() {
+			return Err(Error::TrailingBytes(input.len()));
+		}
+		Ok(value)
+	}
+}
+
+impl RuntimeDecoder {
+	/// Create decoder from the SCALE-encoded `RuntimeMetadataPrefixed`.
+	pub fn new(metadata: &[u8], types: TypeRegistry) -> Result<Self, Error> {
+		let RuntimeMetadataPrefixed(reserved, metadata) = Decode::decode(&mut &metadata[..])?;
+		match metadata {
+			RuntimeMetadata::V9(metadata) if reserved == META_RESERVED => Ok(RuntimeDecoder {
+				modules: decoded(metadata.modules)?,
+				types,
+			}),
+			_ => Err(Error::UnsupportedMetadata),
+		}
+	}
+
+	/// Build the query for the storage entry given as `Module.Item` and the JSON
+	/// representation of its keys.
+	pub fn storage_query(&self, entry: &str, keys: &[Value]) -> Result<StorageQuery, Error> {
+		let unknown_entry = || Error::UnknownStorageEntry(entry.into());
+		let mut path = entry.splitn(2, '.');
+		let (module_name, item_name) = match (path.next(), path.next()) {
+			(Some(module), Some(item)) => (module, item),
+			_ => return Err(unknown_entry()),
+		};
+		let storage = self.modules.iter()
+			.find(|module| as_decoded(&module.name).map_or(false, |name| name == module_name))
+			.and_then(|module| module.storage.as_ref())
+			.ok_or_else(unknown_entry)?;
+		let storage = as_decoded(storage)?;
+		let entry_metadata = as_decoded(&storage.entries)?
+			.iter()
+			.find(|entry| as_decoded(&entry.name).map_or(false, |name| name == item_name))
+			.ok_or_else(unknown_entry)?;
+
+		let mut storage_key = twox_128(as_decoded(&storage.prefix)?.as_bytes()).to_vec();
+		storage_key.extend_from_slice(&twox_128(item_name.as_bytes()));
+
+		let mut hash_key = |hasher: &StorageHasher, ty: &DecodeDifferent<&'static str, String>, value: &Value| {
+			let mut encoded = Vec::new();
+			self.types.parse(as_decoded(ty)?).encode_to(value, &mut encoded)?;
+			storage_key.extend_from_slice(&hash(hasher, &encoded));
+			Ok::<_, Error>(())
+		};
+		let (value, is_linked) = match (&entry_metadata.ty, keys) {
+			(StorageEntryType::Plain(value), []) => (value, false),
+			(StorageEntryType::Map { hasher, key, value, is_linked }, [key_value]) => {
+				hash_key(hasher, key, key_value)?;
+				(value, *is_linked)
+			},
+			(StorageEntryType::DoubleMap { hasher, key1, key2, value, key2_hasher }, [key1_value, key2_value]) => {
+				hash_key(hasher, key1, key1_value)?;
+				hash_key(key2_hasher, key2, key2_value)?;
+				(value, false)
+			},
+			(ty, _) => return Err(Error::InvalidKeysCount {
+				expected: match ty {
+					StorageEntryType::Plain(_) => 0,
+					StorageEntryType::Map { .. } => 1,
+					StorageEntryType::DoubleMap { .. } => 2,
+				},
+				provided: keys.len(),
+			}),
+		};
+
+		Ok(StorageQuery {
+			key: storage_key,
+			value: self.types.parse(as_decoded(value)?),
+			default: match entry_metadata.modifier {
+				StorageEntryModifier::Default => Some(as_decoded(&entry_metadata.default)?.clone()),
+				StorageEntryModifier::Optional => None,
+			},
+			is_linked,
+		})
+	}
+
+	/// Decode the SCALE-encoded `Vec<EventRecord>`, as stored in the `System::Events` storage value.
+	///
+	/// Every record is represented as an object with the `phase`, the `module` and `event` names,
+	/// the decoded event `args` and the `topics`.
+	pub fn decode_events(&self, mut input: &[u8]) -> Result<Vec<Value>, Error> {
+		// The outer event enum has the `System` event first, followed by the events of the
+		// other modules in the order of their declaration.
+		let modules = self.modules.iter()
+			.filter_map(|module| module.event.as_ref().map(|event| (&module.name, event)))
+			.map(|(name, event)| Ok((as_decoded(name)?, as_decoded(event)?)))
+			.collect::<Result<Vec<(&String, &Vec<EventMetadata>)>, Error>>()?;
+		let (system, others): (Vec<_>, Vec<_>) = modules.into_iter().partition(|(name, _)| *name == "System");
+		let modules = system.into_iter().chain(others).collect::<Vec<_>>();
+		let topic = Type::Vec(Box::new(self.types.parse("Hash")));
+
+		let len = <Compact<u32>>::decode(&mut input)?.0;
+		let mut events = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			let phase = match u8::decode(&mut input)? {
+				0 => json!({ "applyExtrinsic": u32::decode(&mut input)? }),
+				1 => json!("finalization"),
+				_ => return Err(codec::Error::from("Invalid phase").into()),
+			};
+			let module_index = u8::decode(&mut input)?;
+			let (module, module_events) = modules.get(module_index as usize)
+				.ok_or(Error::UnknownEvent(module_index, None))?;
+			let event_index = u8::decode(&mut input)?;
+			let event = module_events.get(event_index as usize)
+				.ok_or(Error::UnknownEvent(module_index, Some(event_index)))?;
+			let args = as_decoded(&event.arguments)?
+				.iter()
+				.map(|arg| self.types.parse(arg).decode(&mut input))
+				.collect::<Result<Vec<_>, _>>()?;
+			let topics = topic.decode(&mut input)?;
+
+			events.push(json!({
+				"phase": phase,
+				"module": module,
+				"event": as_decoded(&event.name)?,
+				"args": args,
+				"topics": topics,
+			}));
+		}
+
+		Ok(events)
+	}
+}
+
+fn decoded<B, O>(value: DecodeDifferent<B, O>) -> Result<O, Error> {
+	match value {
+		DecodeDifferent::Decoded(value) => Ok(value),
+		DecodeDifferent::Encode(_) => Err(Error::UnsupportedMetadata),
+	}
+}
+
+fn as_decoded<B, O>(value: &DecodeDifferent<B, O>) -> Result<&O, Error> {
+	match value {
+		DecodeDifferent::Decoded(value) => Ok(value),
+		DecodeDifferent::Encode(_) => Err(Error::UnsupportedMetadata),
+	}
+}
+
+fn hash(hasher: &StorageHasher, data: &[u8]) -> Vec<u8> {
+	match hasher {
+		StorageHasher::Blake2_128 => blake2_128(data).to_vec(),
+		StorageHasher::Blake2_256 => blake2_256(data).to_vec(),
+		StorageHasher::Twox128 => twox_128(data).to_vec(),
+		StorageHasher::Twox256 => twox_256(data).to_vec(),
+		StorageHasher::Twox64Concat => twox_64(data).iter().chain(data).cloned().collect(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Encode;
+	use frame_metadata::{RuntimeMetadataV9, StorageEntryMetadata, StorageMetadata};
+
+	fn string(s: &str) -> DecodeDifferent<&'static str, String> {
+		DecodeDifferent::Decoded(s.into())
+	}
+
+	fn entry(name: &str, modifier: StorageEntryModifier, ty: StorageEntryType, default: Vec<u8>) -> StorageEntryMetadata {
+		StorageEntryMetadata {
+			name: string(name),
+			modifier,
+			ty,
+			default: DecodeDifferent::Decoded(default),
+			documentation: DecodeDifferent::Decoded(vec![]),
+		}
+	}
+
+	fn event(name: &str, arguments: &[&str]) -> EventMetadata {
+		EventMetadata {
+			name: string(name),
+			arguments: DecodeDifferent::Decoded(arguments.iter().map(|arg| arg.to_string()).collect()),
+			documentation: DecodeDifferent::Decoded(vec![]),
+		}
+	}
+
+	fn module(name: &str, storage: Option<StorageMetadata>, events: Option<Vec<EventMetadata>>) -> ModuleMetadata {
+		ModuleMetadata {
+			name: string(name),
+			storage: storage.map(DecodeDifferent::Decoded),
+			calls: None,
+			event: events.map(DecodeDifferent::Decoded),
+			constants: DecodeDifferent::Decoded(vec![]),
+			errors: DecodeDifferent::Decoded(vec![]),
+		}
+	}
+
+	fn decoder() -> RuntimeDecoder {
+		let metadata: RuntimeMetadataPrefixed = RuntimeMetadataV9 {
+			modules: DecodeDifferent::Decoded(vec![
+				module("Timestamp", Some(StorageMetadata {
+					prefix: string("Timestamp"),
+					entries: DecodeDifferent::Decoded(vec![
+						entry("Now", StorageEntryModifier::Default, StorageEntryType::Plain(string("T::Moment")), 0u64.encode()),
+					]),
+				}), None),
+				module("Balances", Some(StorageMetadata {
+					prefix: string("Balances"),
+					entries: DecodeDifferent::Decoded(vec![
+						entry("FreeBalance", StorageEntryModifier::Default, StorageEntryType::Map {
+							hasher: StorageHasher::Blake2_256,
+							key: string("T::AccountId"),
+							value: string("T::Balance"),
+							is_linked: false,
+						}, 0u128.encode()),
+						entry("Locks", StorageEntryModifier::Optional, StorageEntryType::DoubleMap {
+							hasher: StorageHasher::Twox128,
+							key1: string("T::AccountId"),
+							key2: string("u32"),
+							value: string("Vec<u8>"),
+							key2_hasher: StorageHasher::Twox64Concat,
+						}, vec![]),
+					]),
+				}), Some(vec![event("Transfer", &["AccountId", "AccountId", "Balance"])])),
+				module("System", None, Some(vec![
+					event("ExtrinsicSuccess", &["DispatchInfo"]),
+					event("ExtrinsicFailed", &["DispatchError", "DispatchInfo"]),
+				])),
+			]),
+		}.into();
+
+		RuntimeDecoder::new(&metadata.encode(), TypeRegistry::default()).unwrap()
+	}
+
+	fn matches_err<T>(result: Result<T, Error>, check: impl Fn(&Error) -> bool) -> bool {
+		result.err().map_or(false, |e| check(&e))
+	}
+
+	fn prefix(module: &str, item: &str) -> Vec<u8> {
+		twox_128(module.as_bytes()).iter().chain(&twox_128(item.as_bytes())).cloned().collect()
+	}
+
+	#[test]
+	fn should_build_storage_keys() {
+		let decoder = decoder();
+		let account = [1u8; 32];
+		let account_json = json!(format!("0x{}", "01".repeat(32)));
+
+		assert_eq!(decoder.storage_query("Timestamp.Now", &[]).unwrap().key, prefix("Timestamp", "Now"));
+
+		let mut expected = prefix("Balances", "FreeBalance");
+		expected.extend_from_slice(&blake2_256(&account));
+		assert_eq!(decoder.storage_query("Balances.FreeBalance", &[account_json.clone()]).unwrap().key, expected);
+
+		let mut expected = prefix("Balances", "Locks");
+		expected.extend_from_slice(&twox_128(&account));
+		expected.extend_from_slice(&twox_64(&5u32.encode()));
+		expected.extend_from_slice(&5u32.encode());
+		assert_eq!(decoder.storage_query("Balances.Locks", &[account_json, json!(5)]).unwrap().key, expected);
+	}
+
+	#[test]
+	fn should_reject_invalid_storage_queries() {
+		let decoder = decoder();
+
+		assert!(matches_err(decoder.storage_query("Balances", &[]), |e| match e {
+			Error::UnknownStorageEntry(_) => true,
+			_ => false,
+		}));
+		assert!(matches_err(decoder.storage_query("Balances.Unknown", &[]), |e| match e {
+			Error::UnknownStorageEntry(_) => true,
+			_ => false,
+		}));
+		assert!(matches_err(decoder.storage_query("Balances.FreeBalance", &[]), |e| match e {
+			Error::InvalidKeysCount { expected: 1, provided: 0 } => true,
+			_ => false,
+		}));
+		assert!(matches_err(decoder.storage_query("Balances.FreeBalance", &[json!("0x01")]), |e| match e {
+			Error::InvalidValue(_) => true,
+			_ => false,
+		}));
+	}
+
+	#[test]
+	fn should_decode_storage_values() {
+		let decoder = decoder();
+
+		let now = decoder.storage_query("Timestamp.Now", &[]).unwrap();
+		assert_eq!(now.decode(None).unwrap(), json!(0));
+		assert_eq!(now.decode(Some(&42u64.encode())).unwrap(), json!(42));
+		assert!(now.decode(Some(&42u128.encode())).is_err());
+
+		let locks = decoder.storage_query("Balances.Locks", &[json!(format!("0x{}", "01".repeat(32))), json!(5)]).unwrap();
+		assert_eq!(locks.decode(None).unwrap(), Value::Null);
+		assert_eq!(locks.decode(Some(&vec![1u8, 2].encode())).unwrap(), json!("0x0102"));
+	}
+
+	#[test]
+	fn should_decode_events() {
+		let decoder = decoder();
+		let dispatch_info = (10u32, 0u8, true);
+		let mut encoded = Compact(2u32).encode();
+		// System::ExtrinsicSuccess during the first extrinsic.
+		(0u8, 0u32, 0u8, 0u8, dispatch_info, Vec::<[u8; 32]>::new()).encode_to(&mut encoded);
+		// Balances::Transfer at finalization.
+		(1u8, 1u8, 0u8, [1u8; 32], [2u8; 32], 100u128, vec![[3u8; 32]]).encode_to(&mut encoded);
+
+		assert_eq!(decoder.decode_events(&encoded).unwrap(), vec![
+			json!({
+				"phase": { "applyExtrinsic": 0 },
+				"module": "System",
+				"event": "ExtrinsicSuccess",
+				"args": [{ "weight": 10, "class": "Normal", "paysFee": true }],
+				"topics": [],
+			}),
+			json!({
+				"phase": "finalization",
+				"module": "Balances",
+				"event": "Transfer",
+				"args": [
+					format!("0x{}", "01".repeat(32)),
+					format!("0x{}", "02".repeat(32)),
+					"100",
+				],
+				"topics": [format!("0x{}", "03".repeat(32))],
+			}),
+		]);
+	}
+
+	#[test]
+	fn should_reject_unknown_events() {
+		let decoder = decoder();
+		let mut encoded = Compact(1u32).encode();
+		(1u8, 2u8, 0u8, Vec::<[u8; 32]>::new()).encode_to(&mut encoded);
+
+		assert!(matches_err(decoder.decode_events(&encoded), |e| match e {
+			Error::UnknownEvent(2, None) => true,
+			_ => false,
+		}));
+	}
+}
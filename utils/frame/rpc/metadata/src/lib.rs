@@ -0,0 +1,229 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! RPC methods returning storage values and events decoded to JSON.
+//!
+//! The runtime metadata describes storage entries and events by their type names. Given a
+//! [`TypeRegistry`] that resolves these names, the methods of this crate decode the SCALE-encoded
+//! data to JSON, so that clients may query the chain state without a SCALE type registry of
+//! their own.
+
+#![warn(missing_docs)]
+
+mod decoder;
+mod types;
+
+use std::sync::Arc;
+
+use client::{CallExecutor, Client};
+use client_api::backend::Backend;
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+use serde_json::Value;
+use sp_api::Metadata as MetadataRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::{
+	Blake2Hasher, H256,
+	storage::{StorageData, StorageKey},
+};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, ProvideRuntimeApi},
+};
+
+pub use self::gen_client::Client as MetadataClient;
+pub use self::decoder::{RuntimeDecoder, StorageQuery};
+pub use self::types::{Type, TypeRegistry};
+
+const RUNTIME_ERROR: i64 = 1;
+const DECODE_ERROR: i64 = 2;
+const STORAGE_ERROR: i64 = 3;
+
+/// The storage entry of the events deposited in a block.
+const EVENTS_STORAGE_ENTRY: &str = "System.Events";
+
+/// Metadata-driven decoding RPC methods.
+#[rpc]
+pub trait MetadataApi<BlockHash> {
+	/// Returns the decoded value of a storage entry at a given block (best block by default).
+	///
+	/// The entry is given as `Module.Item` (e.g. `Balances.FreeBalance`) and the `keys` are
+	/// the JSON representations of the map keys, if the entry is a map.
+	#[rpc(name = "metadata_storage")]
+	fn storage(&self, entry: String, keys: Option<Vec<Value>>, at: Option<BlockHash>) -> Result<Value>;
+
+	/// Returns the decoded events deposited in a given block (best block by default).
+	#[rpc(name = "metadata_events")]
+	fn events(&self, at: Option<BlockHash>) -> Result<Vec<Value>>;
+}
+
+/// Metadata-driven decoding errors.
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum Error {
+	/// The metadata refers to a type that isn't known to the type registry.
+	#[display(fmt = "Unknown type: {}", _0)]
+	#[from(ignore)]
+	UnknownType(String),
+	/// The JSON value doesn't match the expected type.
+	#[display(fmt = "Value doesn't match the expected type: {}", _0)]
+	#[from(ignore)]
+	InvalidValue(Value),
+	/// The SCALE-encoded data doesn't match the expected type.
+	#[display(fmt = "Decoding failed: {:?}", _0)]
+	Codec(codec::Error),
+	/// The data has more bytes than the expected type.
+	#[display(fmt = "{} bytes left after decoding", _0)]
+	#[from(ignore)]
+	TrailingBytes(usize),
+	/// The metadata version is not supported.
+	#[display(fmt = "Unsupported metadata version")]
+	#[from(ignore)]
+	UnsupportedMetadata,
+	/// There's no such storage entry in the metadata.
+	#[display(fmt = "Unknown storage entry: {}", _0)]
+	#[from(ignore)]
+	UnknownStorageEntry(String),
+	/// The number of keys doesn't match the storage entry kind.
+	#[display(fmt = "Expected {} storage keys, {} provided", expected, provided)]
+	#[from(ignore)]
+	InvalidKeysCount {
+		/// Number of keys of the storage entry.
+		expected: usize,
+		/// Number of keys provided.
+		provided: usize,
+	},
+	/// There's no such event (module index, event index) in the metadata.
+	#[display(fmt = "Unknown event: module {}, event {:?}", _0, _1)]
+	#[from(ignore)]
+	UnknownEvent(u8, Option<u8>),
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for RpcError {
+	fn from(e: Error) -> Self {
+		let code = match e {
+			Error::InvalidValue(_)
+				| Error::UnknownStorageEntry(_)
+				| Error::InvalidKeysCount { .. } => ErrorCode::InvalidParams,
+			_ => ErrorCode::ServerError(DECODE_ERROR),
+		};
+		RpcError {
+			code,
+			message: format!("{}", e),
+			data: None,
+		}
+	}
+}
+
+/// Something that provides the storage of a given block.
+pub trait StorageAt<Block: BlockT> {
+	/// Returns the value under the key in that block.
+	fn storage_at(&self, at: &BlockId<Block>, key: &StorageKey) -> sp_blockchain::Result<Option<StorageData>>;
+}
+
+impl<B, E, Block, RA> StorageAt<Block> for Client<B, E, Block, RA> where
+	B: Backend<Block, Blake2Hasher>,
+	E: CallExecutor<Block, Blake2Hasher>,
+	Block: BlockT<Hash=H256>,
+{
+	fn storage_at(&self, at: &BlockId<Block>, key: &StorageKey) -> sp_blockchain::Result<Option<StorageData>> {
+		self.storage(at, key)
+	}
+}
+
+/// An implementation of metadata-driven decoding RPC methods.
+pub struct Metadata<C, B> {
+	client: Arc<C>,
+	types: TypeRegistry,
+	_marker: std::marker::PhantomData<B>,
+}
+
+impl<C, B> Metadata<C, B> {
+	/// Create new `Metadata` with the given reference to the client and the default type registry.
+	pub fn new(client: Arc<C>) -> Self {
+		Self::with_types(client, Default::default())
+	}
+
+	/// Create new `Metadata` with the given reference to the client and type registry.
+	pub fn with_types(client: Arc<C>, types: TypeRegistry) -> Self {
+		Metadata {
+			client,
+			types,
+			_marker: Default::default(),
+		}
+	}
+}
+
+impl<C, Block> Metadata<C, Block> where
+	Block: BlockT,
+	C: ProvideRuntimeApi + HeaderBackend<Block> + StorageAt<Block>,
+	C::Api: MetadataRuntimeApi<Block>,
+{
+	fn decoder(&self, at: &BlockId<Block>) -> Result<RuntimeDecoder> {
+		let metadata = self.client.runtime_api().metadata(at).map_err(|e| RpcError {
+			code: ErrorCode::ServerError(RUNTIME_ERROR),
+			message: "Unable to query the runtime metadata.".into(),
+			data: Some(format!("{:?}", e).into()),
+		})?;
+		Ok(RuntimeDecoder::new(&metadata, self.types.clone())?)
+	}
+
+	fn storage_value(&self, at: &BlockId<Block>, key: Vec<u8>) -> Result<Option<StorageData>> {
+		self.client.storage_at(at, &StorageKey(key)).map_err(|e| RpcError {
+			code: ErrorCode::ServerError(STORAGE_ERROR),
+			message: "Unable to query the storage.".into(),
+			data: Some(format!("{:?}", e).into()),
+		})
+	}
+
+	fn block_or_best(&self, at: Option<Block::Hash>) -> BlockId<Block> {
+		BlockId::hash(at.unwrap_or_else(||
+			// If the block hash is not supplied assume the best block.
+			self.client.info().best_hash))
+	}
+}
+
+impl<C, Block> MetadataApi<<Block as BlockT>::Hash> for Metadata<C, Block> where
+	Block: BlockT,
+	C: Send + Sync + 'static,
+	C: ProvideRuntimeApi + HeaderBackend<Block> + StorageAt<Block>,
+	C::Api: MetadataRuntimeApi<Block>,
+{
+	fn storage(
+		&self,
+		entry: String,
+		keys: Option<Vec<Value>>,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Value> {
+		let at = self.block_or_best(at);
+		let query = self.decoder(&at)?.storage_query(&entry, &keys.unwrap_or_default())?;
+		let value = self.storage_value(&at, query.key.clone())?;
+		Ok(query.decode(value.as_ref().map(|value| &value.0[..]))?)
+	}
+
+	fn events(&self, at: Option<<Block as BlockT>::Hash>) -> Result<Vec<Value>> {
+		let at = self.block_or_best(at);
+		let decoder = self.decoder(&at)?;
+		// The key is built from the storage prefix in the metadata, and the call fails if the
+		// metadata doesn't describe the events, rather than returning none of them.
+		let key = decoder.storage_query(EVENTS_STORAGE_ENTRY, &[])?.key;
+		match self.storage_value(&at, key)? {
+			Some(events) => Ok(decoder.decode_events(&events.0)?),
+			None => Ok(Vec::new()),
+		}
+	}
+}
@@ -0,0 +1,497 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Types described by the metadata type names and their conversion between SCALE and JSON.
+
+use std::{collections::HashMap, convert::TryFrom};
+
+use codec::{Compact, Decode, Encode, Input};
+use serde_json::{Map, Value};
+use sp_core::Bytes;
+
+use crate::Error;
+
+/// A SCALE-encoded type that can be converted to and from JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+	/// `bool`.
+	Bool,
+	/// `u8`.
+	U8,
+	/// `u16`.
+	U16,
+	/// `u32`.
+	U32,
+	/// `u64`.
+	U64,
+	/// `u128`, represented as a decimal string.
+	U128,
+	/// `i8`.
+	I8,
+	/// `i16`.
+	I16,
+	/// `i32`.
+	I32,
+	/// `i64`.
+	I64,
+	/// `i128`, represented as a decimal string.
+	I128,
+	/// An UTF-8 string.
+	Str,
+	/// Variable length bytes, represented as a hex string.
+	Bytes,
+	/// Fixed length bytes (hashes, account ids, ...), represented as a hex string.
+	FixedBytes(usize),
+	/// Compact encoded unsigned integer.
+	Compact(Box<Type>),
+	/// An optional value, represented as `null` or the value itself.
+	Option(Box<Type>),
+	/// Variable length sequence, represented as an array.
+	Vec(Box<Type>),
+	/// Fixed length sequence, represented as an array.
+	Array(Box<Type>, usize),
+	/// A tuple, represented as an array. The empty tuple is represented as `null`.
+	Tuple(Vec<Type>),
+	/// A map encoded as a sequence of key-value pairs, represented as an array of pairs.
+	Map(Box<Type>, Box<Type>),
+	/// A struct, represented as an object.
+	Struct(Vec<(String, Type)>),
+	/// An enum without fields, represented as the variant name.
+	Enum(Vec<String>),
+	/// A type that couldn't be resolved from its name.
+	Unknown(String),
+}
+
+impl Type {
+	fn is_unsigned(&self) -> bool {
+		match self {
+			Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::U128 => true,
+			_ => false,
+		}
+	}
+
+	/// Decode a value of this type from the input and convert it to JSON.
+	pub fn decode<I: Input>(&self, input: &mut I) -> Result<Value, Error> {
+		Ok(match self {
+			Type::Bool => bool::decode(input)?.into(),
+			Type::U8 => u8::decode(input)?.into(),
+			Type::U16 => u16::decode(input)?.into(),
+			Type::U32 => u32::decode(input)?.into(),
+			Type::U64 => u64::decode(input)?.into(),
+			Type::U128 => u128::decode(input)?.to_string().into(),
+			Type::I8 => i8::decode(input)?.into(),
+			Type::I16 => i16::decode(input)?.into(),
+			Type::I32 => i32::decode(input)?.into(),
+			Type::I64 => i64::decode(input)?.into(),
+			Type::I128 => i128::decode(input)?.to_string().into(),
+			Type::Str => String::decode(input)?.into(),
+			Type::Bytes => bytes_to_json(Vec::<u8>::decode(input)?),
+			Type::FixedBytes(len) => {
+				let mut bytes = vec![0; *len];
+				input.read(&mut bytes)?;
+				bytes_to_json(bytes)
+			},
+			Type::Compact(inner) => {
+				let value = <Compact<u128>>::decode(input)?.0;
+				match **inner {
+					Type::U128 => value.to_string().into(),
+					_ => u64::try_from(value).map_err(|_| codec::Error::from("Compact value out of range"))?.into(),
+				}
+			},
+			Type::Option(inner) => match u8::decode(input)? {
+				0 => Value::Null,
+				1 => inner.decode(input)?,
+				_ => return Err(codec::Error::from("Invalid Option variant").into()),
+			},
+			Type::Vec(inner) => {
+				let len = <Compact<u32>>::decode(input)?.0;
+				Value::Array((0..len).map(|_| inner.decode(input)).collect::<Result<_, _>>()?)
+			},
+			Type::Array(inner, len) =>
+				Value::Array((0..*len).map(|_| inner.decode(input)).collect::<Result<_, _>>()?),
+			Type::Tuple(types) if types.is_empty() => Value::Null,
+			Type::Tuple(types) =>
+				Value::Array(types.iter().map(|ty| ty.decode(input)).collect::<Result<_, _>>()?),
+			Type::Map(key, value) => {
+				let len = <Compact<u32>>::decode(input)?.0;
+				Value::Array((0..len)
+					.map(|_| Ok(Value::Array(vec![key.decode(input)?, value.decode(input)?])))
+					.collect::<Result<_, Error>>()?)
+			},
+			Type::Struct(fields) => Value::Object(fields
+				.iter()
+				.map(|(name, ty)| Ok((name.clone(), ty.decode(input)?)))
+				.collect::<Result<Map<_, _>, Error>>()?),
+			Type::Enum(variants) => {
+				let index = u8::decode(input)?;
+				variants.get(index as usize)
+					.ok_or_else(|| codec::Error::from("Invalid enum variant"))?
+					.clone()
+					.into()
+			},
+			Type::Unknown(name) => return Err(Error::UnknownType(name.clone())),
+		})
+	}
+
+	/// Encode the JSON representation of a value of this type.
+	pub fn encode_to(&self, value: &Value, dest: &mut Vec<u8>) -> Result<(), Error> {
+		let invalid = || Error::InvalidValue(value.clone());
+		let unsigned = || match value {
+			Value::Number(n) => n.as_u64().map(u128::from),
+			Value::String(s) => s.parse().ok(),
+			_ => None,
+		}.ok_or_else(invalid);
+		let signed = || match value {
+			Value::Number(n) => n.as_i64().map(i128::from),
+			Value::String(s) => s.parse().ok(),
+			_ => None,
+		}.ok_or_else(invalid);
+		let bytes = || serde_json::from_value::<Bytes>(value.clone()).map(|bytes| bytes.0).map_err(|_| invalid());
+		let array = |len: Option<usize>| match value {
+			Value::Array(values) if len.map_or(true, |len| len == values.len()) => Ok(values),
+			_ => Err(invalid()),
+		};
+
+		match self {
+			Type::Bool => value.as_bool().ok_or_else(invalid)?.encode_to(dest),
+			Type::U8 => u8::try_from(unsigned()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::U16 => u16::try_from(unsigned()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::U32 => u32::try_from(unsigned()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::U64 => u64::try_from(unsigned()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::U128 => unsigned()?.encode_to(dest),
+			Type::I8 => i8::try_from(signed()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::I16 => i16::try_from(signed()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::I32 => i32::try_from(signed()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::I64 => i64::try_from(signed()?).map_err(|_| invalid())?.encode_to(dest),
+			Type::I128 => signed()?.encode_to(dest),
+			Type::Str => value.as_str().ok_or_else(invalid)?.encode_to(dest),
+			Type::Bytes => bytes()?.encode_to(dest),
+			Type::FixedBytes(len) => {
+				let bytes = bytes()?;
+				if bytes.len() != *len {
+					return Err(invalid());
+				}
+				dest.extend_from_slice(&bytes);
+			},
+			Type::Compact(_) => Compact(unsigned()?).encode_to(dest),
+			Type::Option(inner) => match value {
+				Value::Null => dest.push(0),
+				value => {
+					dest.push(1);
+					inner.encode_to(value, dest)?;
+				},
+			},
+			Type::Vec(inner) => {
+				let values = array(None)?;
+				Compact(values.len() as u32).encode_to(dest);
+				for value in values {
+					inner.encode_to(value, dest)?;
+				}
+			},
+			Type::Array(inner, len) => for value in array(Some(*len))? {
+				inner.encode_to(value, dest)?;
+			},
+			Type::Tuple(types) if types.is_empty() => if !value.is_null() {
+				return Err(invalid());
+			},
+			Type::Tuple(types) => for (ty, value) in types.iter().zip(array(Some(types.len()))?) {
+				ty.encode_to(value, dest)?;
+			},
+			Type::Map(key, value_ty) => {
+				let pairs = array(None)?;
+				Compact(pairs.len() as u32).encode_to(dest);
+				for pair in pairs {
+					match pair {
+						Value::Array(pair) if pair.len() == 2 => {
+							key.encode_to(&pair[0], dest)?;
+							value_ty.encode_to(&pair[1], dest)?;
+						},
+						_ => return Err(invalid()),
+					}
+				}
+			},
+			Type::Struct(fields) => {
+				let object = value.as_object().ok_or_else(invalid)?;
+				for (name, ty) in fields {
+					ty.encode_to(object.get(name).ok_or_else(invalid)?, dest)?;
+				}
+			},
+			Type::Enum(variants) => {
+				let name = value.as_str().ok_or_else(invalid)?;
+				let index = variants.iter().position(|variant| variant == name).ok_or_else(invalid)?;
+				(index as u8).encode_to(dest);
+			},
+			Type::Unknown(name) => return Err(Error::UnknownType(name.clone())),
+		}
+
+		Ok(())
+	}
+}
+
+fn bytes_to_json(bytes: Vec<u8>) -> Value {
+	serde_json::to_value(Bytes(bytes)).expect("Bytes are always serializable; qed")
+}
+
+/// Resolves the type names found in the metadata into [`Type`]s.
+///
+/// Generic wrappers (`Vec`, `Option`, `Compact`, tuples, arrays, ...) and primitives are
+/// understood natively, paths like `T::` or `<T as Trait>::` are ignored and every other
+/// name is looked up in the registered types.
+#[derive(Debug, Clone)]
+pub struct TypeRegistry {
+	types: HashMap<String, Type>,
+}
+
+impl Default for TypeRegistry {
+	/// Registry with the types used by the FRAME modules of the node runtime.
+	fn default() -> Self {
+		let mut registry = TypeRegistry::empty();
+		for (name, definition) in &[
+			("AccountId", "[u8; 32]"),
+			("AccountIndex", "u32"),
+			("Balance", "u128"),
+			("BalanceOf", "Balance"),
+			("BlockNumber", "u32"),
+			("Hash", "H256"),
+			("Index", "u32"),
+			("Moment", "u64"),
+			("Weight", "u32"),
+			("Gas", "u64"),
+			("EventIndex", "u32"),
+			("SessionIndex", "u32"),
+			("EraIndex", "u32"),
+			("MemberCount", "u32"),
+			("ProposalIndex", "u32"),
+			("ReferendumIndex", "u32"),
+			("PropIndex", "u32"),
+			("Percent", "u8"),
+			("Permill", "u32"),
+			("Perbill", "u32"),
+			("Perquintill", "u64"),
+			("AuthorityId", "[u8; 32]"),
+			("AuthorityWeight", "u64"),
+			("AuthorityList", "Vec<(AuthorityId, AuthorityWeight)>"),
+		] {
+			registry.register_alias(*name, definition);
+		}
+		registry.register("DispatchClass", Type::Enum(vec!["Normal".into(), "Operational".into()]));
+		registry.register("DispatchInfo", Type::Struct(vec![
+			("weight".into(), registry.parse("Weight")),
+			("class".into(), registry.parse("DispatchClass")),
+			("paysFee".into(), Type::Bool),
+		]));
+		registry.register("DispatchError", Type::Struct(vec![
+			("module".into(), registry.parse("Option<u8>")),
+			("error".into(), Type::U8),
+		]));
+		registry
+	}
+}
+
+impl TypeRegistry {
+	/// Registry that only knows about primitives and generic wrappers.
+	pub fn empty() -> Self {
+		TypeRegistry { types: HashMap::new() }
+	}
+
+	/// Register (or replace) the type with given name.
+	pub fn register(&mut self, name: impl Into<String>, ty: Type) {
+		self.types.insert(name.into(), ty);
+	}
+
+	/// Register (or replace) the type with given name as an alias of another type name.
+	///
+	/// The definition is resolved against the types registered so far.
+	pub fn register_alias(&mut self, name: impl Into<String>, definition: &str) {
+		let ty = self.parse(definition);
+		self.register(name, ty);
+	}
+
+	/// Resolve the type with given name.
+	///
+	/// Parts that can't be resolved are returned as [`Type::Unknown`].
+	pub fn parse(&self, name: &str) -> Type {
+		let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+		self.parse_normalized(&name)
+	}
+
+	fn parse_normalized(&self, full_name: &str) -> Type {
+		let unknown = || Type::Unknown(full_name.into());
+
+		if full_name.starts_with('(') && full_name.ends_with(')') {
+			return Type::Tuple(split_top_level(&full_name[1..full_name.len() - 1])
+				.into_iter()
+				.map(|name| self.parse_normalized(name))
+				.collect());
+		}
+
+		if full_name.starts_with('[') && full_name.ends_with(']') {
+			let inner = &full_name[1..full_name.len() - 1];
+			return match inner.rfind(';').map(|pos| (&inner[..pos], inner[pos + 1..].parse())) {
+				Some((name, Ok(len))) => match self.parse_normalized(name) {
+					Type::U8 => Type::FixedBytes(len),
+					ty => Type::Array(Box::new(ty), len),
+				},
+				_ => unknown(),
+			};
+		}
+
+		let name = strip_path(full_name);
+		let (base, params) = match name.find('<') {
+			Some(pos) if name.ends_with('>') => (&name[..pos], split_top_level(&name[pos + 1..name.len() - 1])),
+			_ => (name, Vec::new()),
+		};
+		let params = params.into_iter().map(|name| self.parse_normalized(name)).collect::<Vec<_>>();
+
+		match (base, params.as_slice()) {
+			("Vec", [Type::U8]) => Type::Bytes,
+			("Vec", [ty]) | ("VecDeque", [ty]) | ("BTreeSet", [ty]) => Type::Vec(Box::new(ty.clone())),
+			("Option", [ty]) => Type::Option(Box::new(ty.clone())),
+			("Compact", [ty]) if ty.is_unsigned() => Type::Compact(Box::new(ty.clone())),
+			("Box", [ty]) => ty.clone(),
+			("BTreeMap", [key, value]) => Type::Map(Box::new(key.clone()), Box::new(value.clone())),
+			("PhantomData", _) => Type::Tuple(Vec::new()),
+			_ => self.types.get(base).cloned().or_else(|| primitive(base)).unwrap_or_else(unknown),
+		}
+	}
+}
+
+fn primitive(name: &str) -> Option<Type> {
+	Some(match name {
+		"bool" => Type::Bool,
+		"u8" => Type::U8,
+		"u16" => Type::U16,
+		"u32" => Type::U32,
+		"u64" => Type::U64,
+		"u128" => Type::U128,
+		"i8" => Type::I8,
+		"i16" => Type::I16,
+		"i32" => Type::I32,
+		"i64" => Type::I64,
+		"i128" => Type::I128,
+		"str" | "String" | "Text" => Type::Str,
+		"H160" => Type::FixedBytes(20),
+		"H256" => Type::FixedBytes(32),
+		"H512" => Type::FixedBytes(64),
+		_ => return None,
+	})
+}
+
+/// Strip the path from the type name (`T::Balance` -> `Balance`).
+fn strip_path(name: &str) -> &str {
+	let mut depth = 0;
+	let mut start = 0;
+	let bytes = name.as_bytes();
+	for (i, c) in bytes.iter().enumerate() {
+		match c {
+			b'<' | b'(' | b'[' => depth += 1,
+			b'>' | b')' | b']' => depth -= 1,
+			b':' if depth == 0 && bytes.get(i + 1) == Some(&b':') => start = i + 2,
+			_ => {},
+		}
+	}
+	&name[start..]
+}
+
+/// Split the list of type names on commas that aren't nested in other types.
+fn split_top_level(list: &str) -> Vec<&str> {
+	let mut depth = 0;
+	let mut start = 0;
+	let mut names = Vec::new();
+	for (i, c) in list.char_indices() {
+		match c {
+			'<' | '(' | '[' => depth += 1,
+			'>' | ')' | ']' => depth -= 1,
+			',' if depth == 0 => {
+				names.push(&list[start..i]);
+				start = i + 1;
+			},
+			_ => {},
+		}
+	}
+	names.push(&list[start..]);
+	names.into_iter().filter(|name| !name.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn roundtrip(name: &str, value: Value) -> Vec<u8> {
+		let ty = TypeRegistry::default().parse(name);
+		let mut encoded = Vec::new();
+		ty.encode_to(&value, &mut encoded).unwrap();
+		assert_eq!(ty.decode(&mut &encoded[..]).unwrap(), value);
+		encoded
+	}
+
+	#[test]
+	fn should_parse_type_names() {
+		let registry = TypeRegistry::default();
+
+		assert_eq!(registry.parse("T::Balance"), Type::U128);
+		assert_eq!(registry.parse("BalanceOf<T>"), Type::U128);
+		assert_eq!(registry.parse("<T as system::Trait>::AccountId"), Type::FixedBytes(32));
+		assert_eq!(registry.parse("Vec<u8>"), Type::Bytes);
+		assert_eq!(registry.parse("Compact<T::BlockNumber>"), Type::Compact(Box::new(Type::U32)));
+		assert_eq!(
+			registry.parse("Vec<(T::AccountId, Option<u64>)>"),
+			Type::Vec(Box::new(Type::Tuple(vec![
+				Type::FixedBytes(32),
+				Type::Option(Box::new(Type::U64)),
+			]))),
+		);
+		assert_eq!(registry.parse("[u32; 4]"), Type::Array(Box::new(Type::U32), 4));
+		assert_eq!(registry.parse("()"), Type::Tuple(Vec::new()));
+		assert_eq!(registry.parse("Compact<Foo>"), Type::Unknown("Compact<Foo>".into()));
+		assert_eq!(
+			registry.parse("Vec<Foo>"),
+			Type::Vec(Box::new(Type::Unknown("Foo".into()))),
+		);
+	}
+
+	#[test]
+	fn should_convert_values() {
+		assert_eq!(roundtrip("u32", json!(42)), 42u32.encode());
+		assert_eq!(roundtrip("Balance", json!("340282366920938463463374607431768211455")), u128::max_value().encode());
+		assert_eq!(roundtrip("Compact<u64>", json!(1_000)), Compact(1_000u64).encode());
+		assert_eq!(roundtrip("Option<bool>", json!(false)), Some(false).encode());
+		assert_eq!(roundtrip("Option<u8>", json!(null)), None::<u8>.encode());
+		assert_eq!(roundtrip("Vec<u8>", json!("0x0102")), vec![1u8, 2].encode());
+		assert_eq!(roundtrip("H256", json!(format!("0x{}", "01".repeat(32)))), [1u8; 32].encode());
+		assert_eq!(roundtrip("(u8, String)", json!([1, "one"])), (1u8, "one").encode());
+		assert_eq!(roundtrip("BTreeMap<u8, u16>", json!([[1, 2]])), vec![(1u8, 2u16)].encode());
+		assert_eq!(
+			roundtrip("DispatchInfo", json!({ "weight": 10, "class": "Operational", "paysFee": true })),
+			(10u32, 1u8, true).encode(),
+		);
+	}
+
+	#[test]
+	fn should_reject_mismatching_values() {
+		let registry = TypeRegistry::default();
+		let encode = |name, value| registry.parse(name).encode_to(&value, &mut Vec::new());
+
+		assert!(encode("u8", json!(256)).is_err());
+		assert!(encode("H256", json!("0x01")).is_err());
+		assert!(encode("(u8, u8)", json!([1])).is_err());
+		match encode("Foo", json!(1)) {
+			Err(Error::UnknownType(name)) => assert_eq!(name, "Foo"),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
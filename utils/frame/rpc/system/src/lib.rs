@@ -16,9 +16,9 @@
 
 //! System SRML specific RPC methods.
 
-use std::sync::Arc;
+use std::{cell::RefCell, sync::Arc};
 
-use codec::{self, Codec, Decode, Encode};
+use codec::{self, Codec, Compact, Decode, Encode};
 use client::{
 	light::blockchain::{future_header, RemoteBlockchain},
 	light::fetcher::{Fetcher, RemoteCallRequest},
@@ -29,15 +29,24 @@ use jsonrpc_core::{
 };
 use jsonrpc_derive::rpc;
 use futures::future::{ready, TryFutureExt};
+use serde::{Serialize, Deserialize};
+use sp_api::{CallRuntimeAt, Core, InitializeBlock, Metadata};
+use sp_block_builder::BlockBuilder;
 use sp_blockchain::{
 	HeaderBackend,
 	Error as ClientError
 };
 use sp_runtime::{
+	ApplyExtrinsicResult,
 	generic::BlockId,
-	traits,
+	traits::{self, Header as HeaderT},
+};
+use sp_core::{Bytes, ExecutionContext, hexdisplay::HexDisplay, twox_128};
+use sc_rpc_api::DenyUnsafe;
+use state_machine::OverlayedChanges;
+use frame_metadata::{
+	DecodeDifferent, META_RESERVED, RuntimeMetadata, RuntimeMetadataPrefixed, StorageEntryType,
 };
-use sp_core::hexdisplay::HexDisplay;
 use txpool_api::{TransactionPool, InPoolTransaction};
 
 pub use frame_system_rpc_runtime_api::AccountNonceApi;
@@ -48,7 +57,7 @@ pub type FutureResult<T> = Box<dyn Future<Item = T, Error = Error> + Send>;
 
 /// System RPC methods.
 #[rpc]
-pub trait SystemApi<AccountId, Index> {
+pub trait SystemApi<BlockHash, AccountId, Index> {
 	/// Returns the next valid index (aka nonce) for given account.
 	///
 	/// This method takes into consideration all pending transactions
@@ -56,34 +65,96 @@ pub trait SystemApi<AccountId, Index> {
 	/// it fallbacks to query the index from the runtime (aka. state nonce).
 	#[rpc(name = "system_accountNextIndex", alias("account_nextIndex"))]
 	fn nonce(&self, account: AccountId) -> FutureResult<Index>;
+
+	/// Dry run an extrinsic at a given block (best block by default).
+	///
+	/// The extrinsic is applied on top of the block state, as if it was the first extrinsic
+	/// of the next block. Nothing is persisted.
+	///
+	/// This method is unsafe and is denied when called externally, unless explicitly allowed.
+	#[rpc(name = "system_dryRun")]
+	fn dry_run(&self, extrinsic: Bytes, at: Option<BlockHash>) -> FutureResult<DryRunResult>;
+}
+
+/// What would happen if an extrinsic were included in a block.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DryRunOutcome {
+	/// The extrinsic would be included and dispatched successfully.
+	Success,
+	/// The extrinsic would be included, but its dispatch would fail.
+	#[serde(rename_all = "camelCase")]
+	DispatchError {
+		/// Module index, matching the metadata module index.
+		module: Option<u8>,
+		/// Module specific error value.
+		error: u8,
+	},
+	/// The extrinsic is invalid and would not be included in a block.
+	Invalid(String),
+}
+
+impl From<ApplyExtrinsicResult> for DryRunOutcome {
+	fn from(result: ApplyExtrinsicResult) -> Self {
+		match result {
+			Ok(Ok(())) => DryRunOutcome::Success,
+			Ok(Err(e)) => DryRunOutcome::DispatchError { module: e.module, error: e.error },
+			Err(e) => DryRunOutcome::Invalid(format!("{:?}", e)),
+		}
+	}
+}
+
+/// Result of a dry run of an extrinsic.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunResult {
+	/// What would happen if the extrinsic were included in a block.
+	pub outcome: DryRunOutcome,
+	/// The SCALE-encoded `ApplyExtrinsicResult` returned by the runtime.
+	pub result: Bytes,
+	/// Weight consumed by the extrinsic, as accounted by the `System` module.
+	///
+	/// `None` if the runtime metadata doesn't describe the `System.AllExtrinsicsWeight` storage.
+	pub weight: Option<u32>,
+	/// The SCALE-encoded `Vec<EventRecord>` of events deposited by the extrinsic.
+	///
+	/// `None` if the runtime metadata doesn't describe the `System.Events` storage.
+	pub events: Option<Bytes>,
 }
 
 const RUNTIME_ERROR: i64 = 1;
+const DECODE_ERROR: i64 = 2;
 
 /// An implementation of System-specific RPC methods on full client.
 pub struct FullSystem<P: TransactionPool, C, B> {
 	client: Arc<C>,
 	pool: Arc<P>,
+	deny_unsafe: DenyUnsafe,
 	_marker: std::marker::PhantomData<B>,
 }
 
 impl<P: TransactionPool, C, B> FullSystem<P, C, B> {
 	/// Create new `FullSystem` given client and transaction pool.
-	pub fn new(client: Arc<C>, pool: Arc<P>) -> Self {
+	pub fn new(client: Arc<C>, pool: Arc<P>, deny_unsafe: DenyUnsafe) -> Self {
 		FullSystem {
 			client,
 			pool,
+			deny_unsafe,
 			_marker: Default::default(),
 		}
 	}
 }
 
-impl<P, C, Block, AccountId, Index> SystemApi<AccountId, Index> for FullSystem<P, C, Block>
+impl<P, C, Block, AccountId, Index> SystemApi<<Block as traits::Block>::Hash, AccountId, Index>
+	for FullSystem<P, C, Block>
 where
 	C: traits::ProvideRuntimeApi,
+	C: CallRuntimeAt<Block>,
 	C: HeaderBackend<Block>,
 	C: Send + Sync + 'static,
 	C::Api: AccountNonceApi<Block, AccountId, Index>,
+	C::Api: Core<Block, Error = <C as CallRuntimeAt<Block>>::Error>,
+	C::Api: Metadata<Block>,
 	P: TransactionPool + 'static,
 	Block: traits::Block,
 	AccountId: Clone + std::fmt::Display + Codec,
@@ -106,6 +177,177 @@ where
 
 		Box::new(result(get_nonce()))
 	}
+
+	fn dry_run(
+		&self,
+		extrinsic: Bytes,
+		at: Option<<Block as traits::Block>::Hash>,
+	) -> FutureResult<DryRunResult> {
+		if let Err(err) = self.deny_unsafe.check_if_safe() {
+			return Box::new(result(Err(err.into())));
+		}
+
+		let dry_run = || {
+			let at = BlockId::<Block>::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+			let runtime_error = |message: &str, e: &dyn std::fmt::Debug| Error {
+				code: ErrorCode::ServerError(RUNTIME_ERROR),
+				message: message.into(),
+				data: Some(format!("{:?}", e).into()),
+			};
+
+			let version = self.client.runtime_version_at(&at)
+				.map_err(|e| runtime_error("Unable to query runtime version.", &e))?;
+			if !version.has_api_with::<dyn BlockBuilder<Block, Error = ()>, _>(|v| v >= 4) {
+				return Err(runtime_error("Runtime does not support dry running extrinsics.", &version));
+			}
+
+			let parent = self.client.header(at.clone())
+				.map_err(|e| runtime_error("Unable to query block header.", &e))?
+				.ok_or_else(|| runtime_error("Unknown block.", &at))?;
+			let header = <<Block as traits::Block>::Header as HeaderT>::new(
+				*parent.number() + traits::One::one(),
+				Default::default(),
+				Default::default(),
+				parent.hash(),
+				Default::default(),
+			);
+
+			// The weight and the events are read from the storage of the `System` module, found
+			// through the metadata, as the runtime doesn't expose them otherwise.
+			let metadata = self.client.runtime_api().metadata(&at).ok();
+			let storage_key = |item: &str| metadata.as_ref()
+				.and_then(|metadata| system_storage_key(metadata, item));
+			let events_key = storage_key("Events");
+			let weight_key = storage_key("AllExtrinsicsWeight");
+
+			// Initialize the next block and apply the extrinsic on top of it, using our own
+			// overlay, so that we could inspect the storage changes afterwards.
+			let api = self.client.runtime_api();
+			let changes = RefCell::new(OverlayedChanges::default());
+			let call = |function: &'static str, args: Vec<u8>| self.client
+				.call_api_at::<(), fn() -> Result<(), String>, _>(
+					&*api,
+					&at,
+					function,
+					args,
+					&changes,
+					InitializeBlock::Skip,
+					None,
+					ExecutionContext::BlockConstruction,
+					&None,
+				)
+				.map(|result| result.into_encoded())
+				.map_err(|e| runtime_error("Unable to dry run extrinsic.", &e));
+
+			call("Core_initialize_block", header.encode())?;
+			let events_before = events_key.as_ref().map(|key| overlay_value(&changes, key));
+			let weight_before = weight_key.as_ref().map(|key| overlay_value(&changes, key));
+
+			let encoded_result = call("BlockBuilder_apply_extrinsic", extrinsic.0)?;
+			let events_after = events_key.as_ref().map(|key| overlay_value(&changes, key));
+			let weight_after = weight_key.as_ref().map(|key| overlay_value(&changes, key));
+
+			let decode_error = |message: &str, e: codec::Error| Error {
+				code: ErrorCode::ServerError(DECODE_ERROR),
+				message: message.into(),
+				data: Some(format!("{:?}", e).into()),
+			};
+			let apply_result = ApplyExtrinsicResult::decode(&mut &encoded_result[..])
+				.map_err(|e| decode_error("Unable to decode dry run result.", e))?;
+			let events = match (events_before, events_after) {
+				(Some(before), Some(after)) => Some(
+					appended_events(before.as_ref().map(|e| &e[..]), after.as_ref().map(|e| &e[..]))
+						.map_err(|e| decode_error("Unable to decode dry run events.", e))?
+				),
+				_ => None,
+			};
+			let decode_weight = |weight: Option<Vec<u8>>| weight
+				.map(|weight| u32::decode(&mut &weight[..]))
+				.transpose()
+				.map(Option::unwrap_or_default)
+				.map_err(|e| decode_error("Unable to decode dry run weight.", e));
+			let weight = match (weight_before, weight_after) {
+				(Some(before), Some(after)) =>
+					Some(decode_weight(after)?.saturating_sub(decode_weight(before)?)),
+				_ => None,
+			};
+
+			Ok(DryRunResult {
+				outcome: apply_result.into(),
+				result: encoded_result.into(),
+				weight,
+				events: events.map(Into::into),
+			})
+		};
+
+		Box::new(result(dry_run()))
+	}
+}
+
+/// Returns the key of a plain storage value of the `System` module, if the SCALE-encoded runtime
+/// metadata describes it.
+fn system_storage_key(metadata: &[u8], item: &str) -> Option<Vec<u8>> {
+	fn decoded<B, O>(value: &DecodeDifferent<B, O>) -> Option<&O> {
+		match value {
+			DecodeDifferent::Decoded(value) => Some(value),
+			DecodeDifferent::Encode(_) => None,
+		}
+	}
+
+	let metadata = match RuntimeMetadataPrefixed::decode(&mut &metadata[..]).ok()? {
+		RuntimeMetadataPrefixed(META_RESERVED, RuntimeMetadata::V9(metadata)) => metadata,
+		_ => return None,
+	};
+	let storage = decoded(&metadata.modules)?
+		.iter()
+		.find(|module| decoded(&module.name).map_or(false, |name| name == "System"))?
+		.storage
+		.as_ref()
+		.and_then(decoded)?;
+	let prefix = decoded(&storage.prefix)?;
+	decoded(&storage.entries)?
+		.iter()
+		.find(|entry| decoded(&entry.name).map_or(false, |name| name == item))
+		.filter(|entry| match entry.ty {
+			StorageEntryType::Plain(_) => true,
+			_ => false,
+		})
+		.map(|_| [twox_128(prefix.as_bytes()), twox_128(item.as_bytes())].concat())
+}
+
+/// Reads a storage value from the overlay.
+///
+/// Values that haven't been touched during the dry run are reported as missing, which is fine
+/// for the values we're interested in: they are reset when a block is initialized or finalized.
+fn overlay_value(changes: &RefCell<OverlayedChanges>, key: &[u8]) -> Option<Vec<u8>> {
+	changes.borrow().storage(key).and_then(|value| value.map(|value| value.to_vec()))
+}
+
+/// Returns the SCALE-encoded vector of events that were appended to the `System::Events`
+/// storage value.
+///
+/// Events are only ever appended to the storage value, so the records deposited in between are
+/// the trailing bytes of the `after` value. This doesn't require knowing the runtime event type.
+fn appended_events(before: Option<&[u8]>, after: Option<&[u8]>) -> Result<Vec<u8>, codec::Error> {
+	fn split_len(encoded: Option<&[u8]>) -> Result<(u32, &[u8]), codec::Error> {
+		match encoded {
+			Some(mut encoded) if !encoded.is_empty() => {
+				let len = <Compact<u32>>::decode(&mut encoded)?;
+				Ok((len.0, encoded))
+			},
+			_ => Ok((0, &[])),
+		}
+	}
+
+	let (before_len, before_records) = split_len(before)?;
+	let (after_len, after_records) = split_len(after)?;
+	if after_len < before_len || !after_records.starts_with(before_records) {
+		return Err("Events were modified, not appended".into());
+	}
+
+	let mut events = Compact(after_len - before_len).encode();
+	events.extend_from_slice(&after_records[before_records.len()..]);
+	Ok(events)
 }
 
 /// An implementation of System-specific RPC methods on light client.
@@ -133,7 +375,8 @@ impl<P: TransactionPool, C, F, Block> LightSystem<P, C, F, Block> {
 	}
 }
 
-impl<P, C, F, Block, AccountId, Index> SystemApi<AccountId, Index> for LightSystem<P, C, F, Block>
+impl<P, C, F, Block, AccountId, Index> SystemApi<<Block as traits::Block>::Hash, AccountId, Index>
+	for LightSystem<P, C, F, Block>
 where
 	P: TransactionPool + 'static,
 	C: HeaderBackend<Block>,
@@ -178,6 +421,18 @@ where
 
 		Box::new(future_nonce)
 	}
+
+	fn dry_run(
+		&self,
+		_extrinsic: Bytes,
+		_at: Option<<Block as traits::Block>::Hash>,
+	) -> FutureResult<DryRunResult> {
+		Box::new(result(Err(Error {
+			code: ErrorCode::MethodNotFound,
+			message: "Unable to dry run extrinsic.".into(),
+			data: Some("Dry run is not supported by light clients".into()),
+		})))
+	}
 }
 
 /// Adjust account nonce from state, so that tx with the nonce will be
@@ -223,7 +478,9 @@ fn adjust_nonce<P, AccountId, Index>(
 mod tests {
 	use super::*;
 
+	use assert_matches::assert_matches;
 	use futures::executor::block_on;
+	use sp_runtime::transaction_validity::InvalidTransaction;
 	use test_client::{
 		runtime::Transfer,
 		AccountKeyring,
@@ -252,7 +509,7 @@ mod tests {
 		let ext1 = new_transaction(1);
 		block_on(pool.submit_one(&BlockId::number(0), ext1)).unwrap();
 
-		let accounts = FullSystem::new(client, pool);
+		let accounts = FullSystem::new(client, pool, DenyUnsafe::No);
 
 		// when
 		let nonce = accounts.nonce(AccountKeyring::Alice.into());
@@ -260,4 +517,123 @@ mod tests {
 		// then
 		assert_eq!(nonce.wait().unwrap(), 2);
 	}
+
+	#[test]
+	fn dry_run_should_deny_unsafe() {
+		// given
+		let _ = env_logger::try_init();
+		let client = Arc::new(test_client::new());
+		let pool = Arc::new(BasicPool::new(Default::default(), FullChainApi::new(client.clone())));
+
+		let accounts = FullSystem::new(client, pool, DenyUnsafe::Yes);
+
+		// when
+		let res = accounts.dry_run(vec![].into(), None);
+
+		// then
+		assert_matches!(res.wait(), Err(Error { code: ErrorCode::MethodNotFound, .. }));
+	}
+
+	#[test]
+	fn dry_run_should_work() {
+		// given
+		let _ = env_logger::try_init();
+		let client = Arc::new(test_client::new());
+		let pool = Arc::new(BasicPool::new(Default::default(), FullChainApi::new(client.clone())));
+
+		let accounts = FullSystem::new(client, pool, DenyUnsafe::No);
+
+		let tx = Transfer {
+			from: AccountKeyring::Alice.into(),
+			to: AccountKeyring::Bob.into(),
+			amount: 5,
+			nonce: 0,
+		}.into_signed_tx();
+
+		// when
+		let res = accounts.dry_run(tx.encode().into(), None).wait().unwrap();
+
+		// then
+		assert_eq!(res.outcome, DryRunOutcome::Success);
+		let apply_res: ApplyExtrinsicResult = Decode::decode(&mut &res.result[..]).unwrap();
+		assert_eq!(apply_res, Ok(Ok(())));
+		// The test runtime doesn't provide its metadata.
+		assert_eq!(res.weight, None);
+		assert_eq!(res.events, None);
+	}
+
+	#[test]
+	fn dry_run_should_report_invalid_extrinsic() {
+		// given
+		let _ = env_logger::try_init();
+		let client = Arc::new(test_client::new());
+		let pool = Arc::new(BasicPool::new(Default::default(), FullChainApi::new(client.clone())));
+
+		let accounts = FullSystem::new(client, pool, DenyUnsafe::No);
+
+		let tx = Transfer {
+			from: AccountKeyring::Alice.into(),
+			to: AccountKeyring::Bob.into(),
+			amount: 5,
+			nonce: 100,
+		}.into_signed_tx();
+
+		// when
+		let res = accounts.dry_run(tx.encode().into(), None).wait().unwrap();
+
+		// then
+		let apply_res: ApplyExtrinsicResult = Decode::decode(&mut &res.result[..]).unwrap();
+		assert_eq!(apply_res, Err(InvalidTransaction::Stale.into()));
+		assert_matches!(res.outcome, DryRunOutcome::Invalid(_));
+	}
+
+	#[test]
+	fn appended_events_should_return_new_records() {
+		let before = vec![1u16, 2].encode();
+		let after = vec![1u16, 2, 3, 4].encode();
+
+		assert_eq!(
+			appended_events(Some(&before), Some(&after)).unwrap(),
+			vec![3u16, 4].encode(),
+		);
+		assert_eq!(appended_events(None, Some(&after)).unwrap(), after);
+		assert_eq!(appended_events(None, None).unwrap(), Vec::<u16>::new().encode());
+		assert!(appended_events(Some(&after), Some(&before)).is_err());
+	}
+
+	#[test]
+	fn system_storage_keys_are_found_in_metadata() {
+		use frame_metadata::{
+			ModuleMetadata, RuntimeMetadataV9, StorageEntryMetadata, StorageEntryModifier, StorageMetadata,
+		};
+
+		let string = |s: &str| DecodeDifferent::Decoded(s.to_string());
+		let metadata: RuntimeMetadataPrefixed = RuntimeMetadataV9 {
+			modules: DecodeDifferent::Decoded(vec![ModuleMetadata {
+				name: string("System"),
+				storage: Some(DecodeDifferent::Decoded(StorageMetadata {
+					prefix: string("System"),
+					entries: DecodeDifferent::Decoded(vec![StorageEntryMetadata {
+						name: string("Events"),
+						modifier: StorageEntryModifier::Default,
+						ty: StorageEntryType::Plain(string("Vec<EventRecord<T::Event, T::Hash>>")),
+						default: DecodeDifferent::Decoded(vec![0]),
+						documentation: DecodeDifferent::Decoded(vec![]),
+					}]),
+				})),
+				calls: None,
+				event: None,
+				constants: DecodeDifferent::Decoded(vec![]),
+				errors: DecodeDifferent::Decoded(vec![]),
+			}]),
+		}.into();
+		let metadata = metadata.encode();
+
+		assert_eq!(
+			system_storage_key(&metadata, "Events"),
+			Some([twox_128(b"System"), twox_128(b"Events")].concat()),
+		);
+		assert_eq!(system_storage_key(&metadata, "AllExtrinsicsWeight"), None);
+		assert_eq!(system_storage_key(&[], "Events"), None);
+	}
 }